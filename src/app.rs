@@ -1,8 +1,9 @@
 use crate::{
-    panel::app_settings_panel::AppSettingsPanel, panel::demo_panel::DemoPanel,
-    panel::demo_settings_panel::DemoSettingsPanel,
+    ecs::schedule::Schedule, ecs::world::World, panel::app_settings_panel::AppSettingsPanel,
+    panel::demo_panel::DemoPanel, panel::demo_settings_panel::DemoSettingsPanel,
     panel::entity_property_panel::EntityPropertyPanel,
     panel::scene_hierarchy_panel::SceneHierarchyPanel, panel::top_panel::TopPanel, panel::Panel,
+    simulation::Simulation,
 };
 
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
@@ -32,6 +33,15 @@ pub struct Pathfinding {
 
     #[serde(skip)]
     entity_property_panel: EntityPropertyPanel,
+
+    #[serde(skip)]
+    world: World,
+
+    #[serde(skip)]
+    simulation: Simulation,
+
+    #[serde(skip, default = "Simulation::schedule")]
+    schedule: Schedule<Simulation>,
 }
 
 impl Default for Pathfinding {
@@ -46,6 +56,9 @@ impl Default for Pathfinding {
             app_settings_panel: AppSettingsPanel::default(),
             scene_hierarchy_panel: SceneHierarchyPanel::default(),
             entity_property_panel: EntityPropertyPanel::default(),
+            world: World::default(),
+            simulation: Simulation::default(),
+            schedule: Simulation::schedule(),
         }
     }
 }
@@ -78,6 +91,10 @@ impl eframe::App for Pathfinding {
         // For inspiration and more examples, go to https://emilk.github.io/egui
         ctx.request_repaint();
 
+        // the simulation runs before the panels, so they draw the world as it
+        // settled; what they change shows up as changed next frame
+        self.simulation(ctx);
+
         self.top_panel(ctx, _frame);
         self.demo_settings_panel(ctx, _frame);
         self.demo_panel(ctx, _frame);
@@ -88,30 +105,44 @@ impl eframe::App for Pathfinding {
 }
 
 impl Pathfinding {
+    /// Hands the simulation the settings and what was done on the grid last
+    /// frame, runs it, and leaves what it extracted for the demo panel.
+    fn simulation(&mut self, ctx: &egui::Context) {
+        let settings = self.demo_settings_panel.get_env_settings();
+        self.simulation.set_env_settings(settings);
+        if self.demo_settings_panel.generate {
+            self.simulation.generate();
+            self.demo_settings_panel.generate = false;
+        }
+        self.simulation.set_grid(self.demo_panel.grid());
+        self.simulation.push_input(self.demo_panel.take_input());
+
+        let dt = ctx.input(|i| i.stable_dt) * settings.sim_speed;
+        self.schedule.run(&mut self.simulation, &mut self.world, dt);
+        self.world.clear_trackers();
+        self.demo_panel.set_extract(self.simulation.take_extract());
+    }
     fn top_panel(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         self.top_panel
             .set_font_scale(self.app_settings_panel.get_font_scale());
-        self.top_panel.update(ctx, _frame);
+        self.top_panel.update(ctx, _frame, &mut self.world);
     }
     fn demo_settings_panel(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         self.demo_settings_panel.open = self.top_panel.is_demo_settings_open();
         self.demo_settings_panel
             .set_font_scale(self.app_settings_panel.get_font_scale());
-        self.demo_settings_panel.update(ctx, _frame);
+        self.demo_settings_panel
+            .update(ctx, _frame, &mut self.world);
     }
     fn demo_panel(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         self.demo_panel
             .set_env_settings(self.demo_settings_panel.get_env_settings());
-        if self.demo_settings_panel.generate {
-            self.demo_panel.generate();
-            self.demo_settings_panel.generate = false;
-        }
         self.demo_panel.is_waypoint = self.demo_settings_panel.is_waypoint;
-        self.demo_panel.update(ctx, _frame);
+        self.demo_panel.update(ctx, _frame, &mut self.world);
     }
     fn app_settings_panel(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         self.app_settings_panel.open = self.top_panel.is_app_settings_open();
-        self.app_settings_panel.update(ctx, _frame);
+        self.app_settings_panel.update(ctx, _frame, &mut self.world);
         if self.app_settings_panel.is_logger_open() {
             egui::Window::new("Log").title_bar(false).show(ctx, |ui| {
                 // draws the logger ui.
@@ -121,11 +152,13 @@ impl Pathfinding {
     }
     fn scene_hierarchy_panel(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         self.scene_hierarchy_panel.open = true;
-        self.scene_hierarchy_panel.update(ctx, _frame);
+        self.scene_hierarchy_panel
+            .update(ctx, _frame, &mut self.world);
     }
     fn entity_property_panel(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         self.entity_property_panel.open = true;
-        self.entity_property_panel.update(ctx, _frame);
+        self.entity_property_panel
+            .update(ctx, _frame, &mut self.world);
     }
 }
 
@@ -0,0 +1,674 @@
+use super::entity::EntityId;
+use super::pos2::Pos2;
+use std::collections::BTreeMap;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Status {
+    Success,
+    Failure,
+    Running,
+}
+
+/// What a behaviour tree can make its entity do. Leaves only ever go through
+/// this, whoever owns movement implements it.
+pub trait Actuator {
+    fn position(&self, id: EntityId) -> Option<Pos2>;
+    /// Id and position of the entity called `name`.
+    fn find(&self, name: &str) -> Option<(EntityId, Pos2)>;
+    /// Id, name and position of every entity tagged `tag`.
+    fn tagged(&self, tag: &str) -> Vec<(EntityId, String, Pos2)>;
+    fn is_free(&self, cell: Pos2) -> bool;
+    /// Length of the path the entity would walk between two cells.
+    fn path_length(&mut self, from: Pos2, to: Pos2) -> Option<f64>;
+    /// Heads for `target`: `Running` on the way, `Success` once there and
+    /// `Failure` if it can't get there.
+    fn move_to(&mut self, id: EntityId, target: Pos2) -> Status;
+    /// Lets the entity walk its patrol route, `Failure` if it has none.
+    fn patrol(&mut self, id: EntityId) -> Status;
+    /// Drops whatever movement the entity is busy with.
+    fn stop(&mut self, id: EntityId);
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Cell(Pos2),
+    /// Name of an entity.
+    Name(String),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Cell(p) => write!(f, "({}, {})", p.x, p.y),
+            Value::Name(name) => write!(f, "\"{}\"", name),
+        }
+    }
+}
+
+pub type Blackboard = BTreeMap<String, Value>;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ParallelPolicy {
+    /// Succeeds as soon as one child does.
+    Any,
+    /// Succeeds once every child has.
+    All,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum NodeKind {
+    /// Runs its children in order until one fails.
+    Sequence,
+    /// Picks the first child that doesn't fail, checked again every tick so
+    /// a higher priority child takes over as soon as it can.
+    Selector,
+    Parallel(ParallelPolicy),
+    Inverter,
+    /// Runs its child again every time it succeeds, `None` meaning forever.
+    Repeat(Option<u32>),
+    Succeeder,
+    /// Walks to the cell or entity stored under the blackboard key.
+    MoveTo(String),
+    Wait(f32),
+    Patrol,
+    /// Gets at least the distance away from the entity named on the blackboard.
+    FleeFrom(String, f32),
+    /// Whether the entity named on the blackboard is within the radius.
+    IsNear(String, f32),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub children: Vec<Node>,
+    status: Option<Status>,
+    cursor: usize,
+    elapsed: f32,
+    target: Option<Pos2>,
+}
+
+impl Node {
+    pub fn new(kind: NodeKind, children: Vec<Node>) -> Self {
+        Self {
+            kind,
+            children,
+            status: None,
+            cursor: 0,
+            elapsed: 0.,
+            target: None,
+        }
+    }
+
+    pub fn leaf(kind: NodeKind) -> Self {
+        Self::new(kind, Vec::new())
+    }
+
+    fn tick(
+        &mut self,
+        id: EntityId,
+        dt: f32,
+        blackboard: &Blackboard,
+        actuator: &mut dyn Actuator,
+    ) -> Status {
+        // a composite that finished last time starts over, its children kept
+        // their outcomes until now so the tree view shows how it ended
+        if !self.children.is_empty() && self.status.is_some_and(|s| s != Status::Running) {
+            for c in self.children.iter_mut() {
+                c.halt(id, actuator);
+            }
+            self.cursor = 0;
+        }
+        let status = match self.kind.clone() {
+            // decorators without their child have nothing to run
+            NodeKind::Inverter | NodeKind::Repeat(_) | NodeKind::Succeeder
+                if self.children.is_empty() =>
+            {
+                Status::Failure
+            }
+            NodeKind::Sequence => {
+                let mut status = Status::Success;
+                while self.cursor < self.children.len() {
+                    status = self.children[self.cursor].tick(id, dt, blackboard, actuator);
+                    if status != Status::Success {
+                        break;
+                    }
+                    self.cursor += 1;
+                }
+                status
+            }
+            NodeKind::Selector => {
+                let mut status = Status::Failure;
+                for i in 0..self.children.len() {
+                    status = self.children[i].tick(id, dt, blackboard, actuator);
+                    if status != Status::Failure {
+                        for lower in self.children[i + 1..].iter_mut() {
+                            lower.halt(id, actuator);
+                        }
+                        break;
+                    }
+                }
+                status
+            }
+            NodeKind::Parallel(policy) => {
+                let statuses: Vec<Status> = self
+                    .children
+                    .iter_mut()
+                    .map(|c| c.tick(id, dt, blackboard, actuator))
+                    .collect();
+                let count = |s: Status| statuses.iter().filter(|c| **c == s).count();
+                let n = statuses.len();
+                match policy {
+                    ParallelPolicy::Any if count(Status::Success) > 0 => Status::Success,
+                    ParallelPolicy::Any if count(Status::Failure) == n => Status::Failure,
+                    ParallelPolicy::All if count(Status::Failure) > 0 => Status::Failure,
+                    ParallelPolicy::All if count(Status::Success) == n => Status::Success,
+                    _ => Status::Running,
+                }
+            }
+            NodeKind::Inverter => match self.children[0].tick(id, dt, blackboard, actuator) {
+                Status::Success => Status::Failure,
+                Status::Failure => Status::Success,
+                Status::Running => Status::Running,
+            },
+            NodeKind::Repeat(count) => {
+                // the run that succeeded last tick goes again
+                if self.children[0].status == Some(Status::Success) {
+                    self.children[0].halt(id, actuator);
+                }
+                match self.children[0].tick(id, dt, blackboard, actuator) {
+                    Status::Success => {
+                        self.cursor += 1;
+                        if count.is_some_and(|c| self.cursor as u32 >= c) {
+                            Status::Success
+                        } else {
+                            Status::Running
+                        }
+                    }
+                    status => status,
+                }
+            }
+            NodeKind::Succeeder => match self.children[0].tick(id, dt, blackboard, actuator) {
+                Status::Running => Status::Running,
+                _ => Status::Success,
+            },
+            NodeKind::MoveTo(key) => match blackboard.get(&key) {
+                Some(Value::Cell(cell)) => actuator.move_to(id, *cell),
+                Some(Value::Name(name)) => match actuator.find(name) {
+                    Some((_, cell)) => actuator.move_to(id, cell),
+                    None => Status::Failure,
+                },
+                None => Status::Failure,
+            },
+            NodeKind::Wait(seconds) => {
+                self.elapsed += dt;
+                if self.elapsed >= seconds {
+                    Status::Success
+                } else {
+                    Status::Running
+                }
+            }
+            NodeKind::Patrol => actuator.patrol(id),
+            NodeKind::FleeFrom(key, distance) => {
+                self.flee(id, &key, distance, blackboard, actuator)
+            }
+            NodeKind::IsNear(key, radius) => match (
+                threat(id, &key, blackboard, actuator),
+                actuator.position(id),
+            ) {
+                (Some(threat), Some(me)) if cell_distance(me, threat) <= radius => Status::Success,
+                _ => Status::Failure,
+            },
+        };
+
+        // whatever is still running below a finished composite is cut short
+        if status != Status::Running {
+            for c in self.children.iter_mut() {
+                if c.status == Some(Status::Running) {
+                    c.halt(id, actuator);
+                }
+            }
+        }
+        self.status = Some(status);
+        status
+    }
+
+    fn flee(
+        &mut self,
+        id: EntityId,
+        key: &str,
+        distance: f32,
+        blackboard: &Blackboard,
+        actuator: &mut dyn Actuator,
+    ) -> Status {
+        let (Some(threat), Some(me)) = (threat(id, key, blackboard, actuator), actuator.position(id))
+        else {
+            return Status::Failure;
+        };
+        if cell_distance(me, threat) >= distance {
+            actuator.stop(id);
+            self.target = None;
+            return Status::Success;
+        }
+        // the threat may have moved closer to where we were running to
+        if !self
+            .target
+            .is_some_and(|t| cell_distance(t, threat) >= distance)
+        {
+            self.target = flee_target(me, threat, distance, actuator);
+        }
+        let Some(target) = self.target else {
+            return Status::Failure;
+        };
+        match actuator.move_to(id, target) {
+            Status::Failure => {
+                self.target = None;
+                Status::Failure
+            }
+            _ => Status::Running,
+        }
+    }
+
+    /// Stops anything still running below this node and forgets its progress.
+    fn halt(&mut self, id: EntityId, actuator: &mut dyn Actuator) {
+        if self.status == Some(Status::Running)
+            && matches!(
+                self.kind,
+                NodeKind::MoveTo(_) | NodeKind::Patrol | NodeKind::FleeFrom(..)
+            )
+        {
+            actuator.stop(id);
+        }
+        for c in self.children.iter_mut() {
+            c.halt(id, actuator);
+        }
+        self.status = None;
+        self.cursor = 0;
+        self.elapsed = 0.;
+        self.target = None;
+    }
+
+    fn label(&self) -> String {
+        match &self.kind {
+            NodeKind::Sequence => "Sequence".to_string(),
+            NodeKind::Selector => "Selector".to_string(),
+            NodeKind::Parallel(policy) => format!("Parallel ({:?})", policy),
+            NodeKind::Inverter => "Not".to_string(),
+            NodeKind::Repeat(Some(count)) => format!("Repeat {}/{}", self.cursor, count),
+            NodeKind::Repeat(None) => "Repeat".to_string(),
+            NodeKind::Succeeder => "Succeed".to_string(),
+            NodeKind::MoveTo(key) => format!("Move to {}", key),
+            NodeKind::Wait(seconds) => format!("Wait {:.1}/{:.1}s", self.elapsed, seconds),
+            NodeKind::Patrol => "Patrol".to_string(),
+            NodeKind::FleeFrom(key, distance) => format!("Flee {} from {}", distance, key),
+            NodeKind::IsNear(key, radius) => format!("{} within {}", key, radius),
+        }
+    }
+
+    fn ui(&self, ui: &mut egui::Ui) {
+        let (mark, col) = match self.status {
+            Some(Status::Running) => ("▶", egui::Color32::from_rgb(255, 200, 0)),
+            Some(Status::Success) => ("✔", egui::Color32::from_rgb(25, 200, 25)),
+            Some(Status::Failure) => ("✖", egui::Color32::from_rgb(230, 50, 50)),
+            None => ("•", egui::Color32::GRAY),
+        };
+        ui.colored_label(col, format!("{} {}", mark, self.label()));
+        if !self.children.is_empty() {
+            ui.indent(ui.next_auto_id(), |ui| {
+                for c in self.children.iter() {
+                    c.ui(ui);
+                }
+            });
+        }
+    }
+}
+
+fn cell_distance(a: Pos2, b: Pos2) -> f32 {
+    (((a.x - b.x).pow(2) + (a.y - b.y).pow(2)) as f32).sqrt()
+}
+
+/// Position of the entity named under `key`, never the entity itself.
+fn threat(
+    id: EntityId,
+    key: &str,
+    blackboard: &Blackboard,
+    actuator: &dyn Actuator,
+) -> Option<Pos2> {
+    match blackboard.get(key) {
+        Some(Value::Name(name)) => actuator
+            .find(name)
+            .filter(|(other, _)| *other != id)
+            .map(|(_, pos)| pos),
+        Some(Value::Cell(cell)) => Some(*cell),
+        None => None,
+    }
+}
+
+/// A free cell `distance` away from `threat`, as straight away from it as
+/// possible.
+fn flee_target(me: Pos2, threat: Pos2, distance: f32, actuator: &dyn Actuator) -> Option<Pos2> {
+    let (dx, dy) = ((me.x - threat.x) as f32, (me.y - threat.y) as f32);
+    let away = if dx == 0. && dy == 0. {
+        0.
+    } else {
+        dy.atan2(dx)
+    };
+    for turn in [0., 1., -1., 2., -2., 3., -3., 4., -4.] {
+        let angle = away + turn * std::f32::consts::FRAC_PI_8;
+        let cell = Pos2::new(
+            (threat.x as f32 + angle.cos() * distance * 1.2).round() as i64,
+            (threat.y as f32 + angle.sin() * distance * 1.2).round() as i64,
+        );
+        if actuator.is_free(cell) {
+            return Some(cell);
+        }
+    }
+    None
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BehaviourMode {
+    Idle,
+    Patrol,
+    /// Walks between its post and home, resting at both.
+    Sentry,
+    /// Runs from the threat and otherwise stays put.
+    Coward,
+    /// Patrols in shifts with breaks of at least 3 s at its post, and runs from
+    /// the threat when it comes close.
+    Guard,
+}
+
+impl BehaviourMode {
+    pub const ALL: [BehaviourMode; 5] = [
+        BehaviourMode::Idle,
+        BehaviourMode::Patrol,
+        BehaviourMode::Sentry,
+        BehaviourMode::Coward,
+        BehaviourMode::Guard,
+    ];
+
+    pub fn tree(&self) -> Node {
+        use NodeKind::*;
+        let flee = || {
+            Node::new(
+                Sequence,
+                vec![
+                    Node::leaf(IsNear("threat".to_string(), 6.)),
+                    Node::leaf(FleeFrom("threat".to_string(), 10.)),
+                ],
+            )
+        };
+        match self {
+            BehaviourMode::Idle => Node::leaf(Wait(1.)),
+            BehaviourMode::Patrol => Node::leaf(Patrol),
+            BehaviourMode::Sentry => Node::new(
+                Repeat(None),
+                vec![Node::new(
+                    Sequence,
+                    vec![
+                        Node::leaf(MoveTo("post".to_string())),
+                        Node::leaf(Wait(3.)),
+                        Node::new(Succeeder, vec![Node::leaf(MoveTo("home".to_string()))]),
+                        Node::leaf(Wait(3.)),
+                    ],
+                )],
+            ),
+            BehaviourMode::Coward => Node::new(
+                Selector,
+                vec![
+                    flee(),
+                    Node::new(
+                        Sequence,
+                        vec![
+                            Node::new(Inverter, vec![Node::leaf(IsNear("threat".to_string(), 6.))]),
+                            Node::leaf(Wait(0.5)),
+                        ],
+                    ),
+                ],
+            ),
+            BehaviourMode::Guard => Node::new(
+                Selector,
+                vec![
+                    flee(),
+                    Node::new(
+                        Sequence,
+                        vec![
+                            Node::new(
+                                Parallel(ParallelPolicy::Any),
+                                vec![Node::leaf(Patrol), Node::leaf(Wait(10.))],
+                            ),
+                            Node::new(
+                                Parallel(ParallelPolicy::All),
+                                vec![
+                                    Node::new(
+                                        Succeeder,
+                                        vec![Node::leaf(MoveTo("post".to_string()))],
+                                    ),
+                                    Node::leaf(Wait(3.)),
+                                ],
+                            ),
+                        ],
+                    ),
+                ],
+            ),
+        }
+    }
+}
+
+/// A behaviour tree with its blackboard. Trees start over once they finish.
+#[derive(Debug, PartialEq, Clone)]
+pub struct BehaviourTree {
+    pub root: Node,
+    pub blackboard: Blackboard,
+    /// Preset the tree was built from, to notice when it is changed.
+    pub mode: Option<BehaviourMode>,
+}
+
+impl Default for BehaviourTree {
+    fn default() -> Self {
+        Self {
+            root: BehaviourMode::Idle.tree(),
+            blackboard: Blackboard::new(),
+            mode: None,
+        }
+    }
+}
+
+impl BehaviourTree {
+    pub fn tick(&mut self, id: EntityId, dt: f32, actuator: &mut dyn Actuator) -> Status {
+        if self.root.status.is_some_and(|s| s != Status::Running) {
+            self.root.halt(id, actuator);
+        }
+        self.root.tick(id, dt, &self.blackboard, actuator)
+    }
+
+    /// Replaces the tree with the `mode` preset, stopping the old one first.
+    pub fn rebuild(&mut self, id: EntityId, mode: BehaviourMode, actuator: &mut dyn Actuator) {
+        self.root.halt(id, actuator);
+        self.root = mode.tree();
+        self.mode = Some(mode);
+    }
+
+    /// Live view of the tree, showing which nodes ran last tick and how they
+    /// did, followed by the blackboard.
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        self.root.ui(ui);
+        for (key, value) in self.blackboard.iter() {
+            ui.label(format!("{}: {}", key, value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands still at the origin; `move_to` fails, `patrol` never ends.
+    #[derive(Default)]
+    struct Idle {
+        stops: usize,
+    }
+
+    impl Actuator for Idle {
+        fn position(&self, _id: EntityId) -> Option<Pos2> {
+            Some(Pos2::new(0, 0))
+        }
+        fn find(&self, _name: &str) -> Option<(EntityId, Pos2)> {
+            None
+        }
+        fn tagged(&self, _tag: &str) -> Vec<(EntityId, String, Pos2)> {
+            Vec::new()
+        }
+        fn is_free(&self, _cell: Pos2) -> bool {
+            true
+        }
+        fn path_length(&mut self, _from: Pos2, _to: Pos2) -> Option<f64> {
+            None
+        }
+        fn move_to(&mut self, _id: EntityId, _target: Pos2) -> Status {
+            Status::Failure
+        }
+        fn patrol(&mut self, _id: EntityId) -> Status {
+            Status::Running
+        }
+        fn stop(&mut self, _id: EntityId) {
+            self.stops += 1;
+        }
+    }
+
+    fn statuses(node: &Node) -> Vec<Option<Status>> {
+        node.children.iter().map(|c| c.status).collect()
+    }
+
+    #[test]
+    fn finished_composites_keep_child_outcomes_until_entered_again() {
+        use NodeKind::*;
+        let mut tree = BehaviourTree {
+            root: Node::new(
+                Sequence,
+                vec![Node::leaf(Wait(0.)), Node::leaf(MoveTo("post".to_string()))],
+            ),
+            ..Default::default()
+        };
+        let mut actuator = Idle::default();
+        let id = EntityId::default();
+        assert_eq!(tree.tick(id, 0.1, &mut actuator), Status::Failure);
+        assert_eq!(
+            statuses(&tree.root),
+            vec![Some(Status::Success), Some(Status::Failure)]
+        );
+        // starts over from the first child
+        tree.root.children[0].kind = Wait(1.);
+        assert_eq!(tree.tick(id, 0.1, &mut actuator), Status::Running);
+        assert_eq!(statuses(&tree.root), vec![Some(Status::Running), None]);
+    }
+
+    #[test]
+    fn finishing_stops_children_still_running() {
+        use NodeKind::*;
+        let mut tree = BehaviourTree {
+            root: Node::new(
+                Parallel(ParallelPolicy::Any),
+                vec![Node::leaf(Patrol), Node::leaf(Wait(0.))],
+            ),
+            ..Default::default()
+        };
+        let mut actuator = Idle::default();
+        assert_eq!(
+            tree.tick(EntityId::default(), 0.1, &mut actuator),
+            Status::Success
+        );
+        assert_eq!(actuator.stops, 1);
+        assert_eq!(statuses(&tree.root), vec![None, Some(Status::Success)]);
+    }
+
+    #[test]
+    fn decorators_without_a_child_fail() {
+        let mut actuator = Idle::default();
+        for kind in [
+            NodeKind::Inverter,
+            NodeKind::Repeat(None),
+            NodeKind::Succeeder,
+        ] {
+            let mut tree = BehaviourTree {
+                root: Node::leaf(kind),
+                ..Default::default()
+            };
+            assert_eq!(
+                tree.tick(EntityId::default(), 0.1, &mut actuator),
+                Status::Failure
+            );
+        }
+    }
+
+    /// Stands at the origin with a wolf somewhere, walking wherever it is
+    /// told to.
+    struct Chased {
+        wolf: Pos2,
+        targets: Vec<Pos2>,
+        stops: usize,
+    }
+
+    impl Actuator for Chased {
+        fn position(&self, _id: EntityId) -> Option<Pos2> {
+            Some(Pos2::new(0, 0))
+        }
+        fn find(&self, name: &str) -> Option<(EntityId, Pos2)> {
+            (name == "wolf").then_some((EntityId::new(1, 0), self.wolf))
+        }
+        fn tagged(&self, _tag: &str) -> Vec<(EntityId, String, Pos2)> {
+            Vec::new()
+        }
+        fn is_free(&self, _cell: Pos2) -> bool {
+            true
+        }
+        fn path_length(&mut self, _from: Pos2, _to: Pos2) -> Option<f64> {
+            None
+        }
+        fn move_to(&mut self, _id: EntityId, target: Pos2) -> Status {
+            self.targets.push(target);
+            Status::Running
+        }
+        fn patrol(&mut self, _id: EntityId) -> Status {
+            Status::Running
+        }
+        fn stop(&mut self, _id: EntityId) {
+            self.stops += 1;
+        }
+    }
+
+    #[test]
+    fn cowards_run_from_a_close_threat_and_rest_otherwise() {
+        let mut tree = BehaviourTree::default();
+        tree.blackboard
+            .insert("threat".to_string(), Value::Name("wolf".to_string()));
+        let mut actuator = Chased {
+            wolf: Pos2::new(3, 0),
+            targets: Vec::new(),
+            stops: 0,
+        };
+        let id = EntityId::default();
+        tree.rebuild(id, BehaviourMode::Coward, &mut actuator);
+
+        assert_eq!(tree.tick(id, 0.1, &mut actuator), Status::Running);
+        let target = *actuator.targets.last().unwrap();
+        // away from the wolf, far enough
+        assert!(target.x < 0);
+        assert!(cell_distance(target, actuator.wolf) >= 10.);
+
+        // far enough once the wolf went off, the flight ends there
+        actuator.wolf = Pos2::new(30, 0);
+        let moves = actuator.targets.len();
+        assert_eq!(tree.tick(id, 0.1, &mut actuator), Status::Success);
+        assert_eq!(actuator.stops, 1);
+        assert_eq!(actuator.targets.len(), moves);
+        // and it rests instead
+        assert_eq!(tree.tick(id, 0.1, &mut actuator), Status::Running);
+        assert_eq!(
+            statuses(&tree.root),
+            vec![Some(Status::Failure), Some(Status::Running)]
+        );
+        assert_eq!(actuator.targets.len(), moves);
+        assert_eq!(tree.tick(id, 0.5, &mut actuator), Status::Success);
+    }
+}
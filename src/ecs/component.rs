@@ -1,97 +1,474 @@
 //#[derive(panel_macros::GenerateUI)]
+use super::behaviour::{BehaviourMode, BehaviourTree};
+use super::goap::{GoalMode, GoapAgent};
+use super::hierarchy::{Children, Parent};
 use super::pos2::Pos2;
+use super::prefab::PrefabInstance;
+use super::steering::Wander;
+use super::world::World;
+use crate::pathfinding::shape::{CircleParams, RectParams, ShapeParams};
 
-#[derive(Clone, Copy)]
-pub struct ComponentData<T> {
-    pub name: &'static str,
-    pub data: T,
+/// Draws a component's fields into the inspector and tells whether any was
+/// edited. `GenerateUI` derives it from the same code as `get_ui_drawer` for
+/// the structs marked `#[ui(inspect = Inspect)]`.
+pub trait Inspect {
+    fn inspect(&mut self, ui: &mut egui::Ui) -> bool;
 }
 
-impl<T: Default> Default for ComponentData<T> {
+/// Angle in radians, counter-clockwise from +x. Shown as degrees in the inspector.
+pub type Radians = f32;
+
+/// Where the entity is in the world.
+#[derive(
+    panel_macros::GenerateUI, Clone, Copy, PartialEq, Debug, serde::Deserialize, serde::Serialize,
+)]
+#[ui(inspect = Inspect)]
+pub struct Transform2 {
+    pub pos: Pos2,
+    #[ui(angle)]
+    pub heading: Radians,
+}
+
+impl Default for Transform2 {
     fn default() -> Self {
-        ComponentData {
-            name: "",
-            data: T::default(),
+        Self {
+            pos: Pos2::default(),
+            heading: 0.,
         }
     }
 }
 
-#[derive(Clone, Copy)]
-pub struct ComponentWrapper<T> {
-    pub component_data: ComponentData<T>,
+impl Transform2 {
+    /// Where a child with the given local transform is, below this one.
+    pub fn then(&self, local: &LocalTransform2) -> Transform2 {
+        Transform2 {
+            pos: Pos2::new(
+                self.pos.x + local.offset.x.round() as i64,
+                self.pos.y + local.offset.y.round() as i64,
+            ),
+            heading: self.heading + local.heading,
+        }
+    }
 }
 
-impl<T: Default> Default for ComponentWrapper<T> {
+/// Where the entity is against its parent's `Transform2`, for entities below
+/// one that has a transform. Offsets add up without turning the child
+/// around its parent, cells on the grid don't turn.
+#[derive(panel_macros::GenerateUI, Clone, Copy, Default)]
+#[ui(inspect = Inspect)]
+pub struct LocalTransform2 {
+    pub offset: egui::Vec2,
+    #[ui(angle)]
+    pub heading: Radians,
+    /// The world transform the entity was last put at from this one, to
+    /// tell whether it was moved in the world since.
+    pub(super) placed: Option<Transform2>,
+}
+
+impl LocalTransform2 {
+    /// The local transform that puts a child of `parent` at `world`.
+    pub fn between(parent: &Transform2, world: &Transform2) -> Self {
+        Self {
+            offset: egui::vec2(
+                (world.pos.x - parent.pos.x) as f32,
+                (world.pos.y - parent.pos.y) as f32,
+            ),
+            heading: world.heading - parent.heading,
+            placed: Some(*world),
+        }
+    }
+}
+#[derive(panel_macros::GenerateUI, Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[ui(inspect = Inspect)]
+pub struct Color {
+    pub col: egui::Color32,
+}
+
+impl Default for Color {
     fn default() -> Self {
-        ComponentWrapper {
-            component_data: ComponentData::<T>::default(),
+        Self {
+            col: egui::Color32::LIGHT_BLUE,
         }
     }
 }
 
-pub trait ComponentTrait<T>: Default {
-    fn get(&self) -> &T;
-    fn get_mut(&mut self) -> &mut T;
-    fn default() -> Self;
+#[derive(panel_macros::GenerateUI, Clone, serde::Deserialize, serde::Serialize)]
+#[ui(inspect = Inspect)]
+pub struct Mesh {
+    pub mesh: Vec<Pos2>,
 }
 
-impl<T> ComponentTrait<T> for ComponentWrapper<T>
-where
-    T: Clone + Default,
-{
-    fn get(&self) -> &T {
-        &self.component_data.data
+impl Default for Mesh {
+    fn default() -> Self {
+        Self { mesh: Vec::new() }
     }
+}
+
+/// Linear velocity in grid cells per second.
+#[derive(
+    panel_macros::GenerateUI, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize,
+)]
+#[ui(inspect = Inspect)]
+pub struct Velocity {
+    pub linear: egui::Vec2,
+}
 
-    fn get_mut(&mut self) -> &mut T {
-        &mut self.component_data.data
+impl Default for Velocity {
+    fn default() -> Self {
+        Self {
+            linear: egui::Vec2::ZERO,
+        }
     }
+}
+
+#[derive(panel_macros::GenerateUI, Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[ui(inspect = Inspect)]
+pub struct Kinematics {
+    pub max_speed: f32,
+    pub max_force: f32,
+}
 
+impl Default for Kinematics {
     fn default() -> Self {
-        ComponentWrapper::<T> {
-            component_data: ComponentData::<T>::default(),
+        Self {
+            max_speed: 8.,
+            max_force: 20.,
         }
     }
 }
 
-#[derive(panel_macros::GenerateUI, Clone, Copy)]
-pub struct Transform2 {
-    pub pos: Pos2,
+/// Weights (0 to 100) of the steering behaviours blended each tick. `target`
+/// is what seek, flee and arrive steer relative to, `quarry` names the entity
+/// to pursue or evade, the nearest one if several have that name.
+#[derive(panel_macros::GenerateUI, Clone, serde::Deserialize, serde::Serialize)]
+#[ui(inspect = Inspect)]
+pub struct Steering {
+    pub target: Pos2,
+    #[ui(clearable)]
+    pub quarry: String,
+    pub seek: f32,
+    pub flee: f32,
+    pub arrive: f32,
+    pub wander: f32,
+    pub pursue: f32,
+    pub evade: f32,
+    pub separation: f32,
+    pub cohesion: f32,
+    pub alignment: f32,
+    pub neighbor_radius: f32,
 }
 
-impl Default for Transform2 {
+impl Default for Steering {
     fn default() -> Self {
         Self {
-            pos: Pos2::default(),
+            target: Pos2::new(50, 50),
+            quarry: "".to_string(),
+            seek: 0.,
+            flee: 0.,
+            arrive: 0.,
+            wander: 0.,
+            pursue: 0.,
+            evade: 0.,
+            separation: 0.,
+            cohesion: 0.,
+            alignment: 0.,
+            neighbor_radius: 5.,
         }
     }
 }
-#[derive(panel_macros::GenerateUI, Clone, Copy)]
-pub struct Color {
-    pub col: egui::Color32,
+
+/// Where the entity stands in a custom formation, relative to the leader:
+/// +x is ahead of it and +y to its left, in grid cells.
+#[derive(panel_macros::GenerateUI, Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[ui(inspect = Inspect)]
+pub struct FormationSlot {
+    pub offset: egui::Vec2,
 }
 
-impl Default for Color {
+impl Default for FormationSlot {
     fn default() -> Self {
         Self {
-            col: egui::Color32::LIGHT_BLUE,
+            offset: egui::vec2(-2., 0.),
+        }
+    }
+}
+
+/// Ordered cells of a route. Edited as a list in the inspector.
+pub type Waypoints = Vec<Pos2>;
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum PatrolMode {
+    /// Back to the first waypoint after the last one.
+    Loop,
+    /// Back and forth, turning around at either end.
+    PingPong,
+    /// Stops at the last waypoint.
+    Once,
+}
+
+impl PatrolMode {
+    pub const ALL: [PatrolMode; 3] = [PatrolMode::Loop, PatrolMode::PingPong, PatrolMode::Once];
+}
+
+/// A route the entity keeps walking whenever it has nothing else to do.
+/// `next` is the waypoint it is heading for and `returning` whether a
+/// ping-pong patrol is on its way back.
+#[derive(panel_macros::GenerateUI, Clone, serde::Deserialize, serde::Serialize)]
+#[ui(inspect = Inspect)]
+pub struct Patrol {
+    #[ui(points, range = 0..=100)]
+    pub waypoints: Waypoints,
+    #[ui(choices)]
+    pub mode: PatrolMode,
+    pub next: usize,
+    pub returning: bool,
+}
+
+impl Default for Patrol {
+    fn default() -> Self {
+        Self {
+            waypoints: Vec::new(),
+            mode: PatrolMode::Loop,
+            next: 0,
+            returning: false,
         }
     }
 }
 
+/// Lets the entity act on its own through a behaviour tree built from
+/// `mode`. `post` and `threat`, the name of an entity to keep away from, are
+/// put on the tree's blackboard.
 #[derive(panel_macros::GenerateUI, Clone)]
-pub struct Mesh {
-    pub mesh: Vec<Pos2>,
+#[ui(inspect = Inspect)]
+pub struct Behaviour {
+    #[ui(choices)]
+    pub mode: BehaviourMode,
+    pub post: Pos2,
+    pub threat: String,
+    pub tree: BehaviourTree,
 }
 
-impl Default for Mesh {
+impl Default for Behaviour {
     fn default() -> Self {
-        Self { mesh: Vec::new() }
+        Self {
+            mode: BehaviourMode::Idle,
+            post: Pos2::new(50, 50),
+            threat: "".to_string(),
+            tree: BehaviourTree::default(),
+        }
+    }
+}
+
+/// Lets the entity plan its own actions towards the goal of `goal`. The
+/// tags say which entities count as resources, stockpiles and tools.
+#[derive(panel_macros::GenerateUI, Clone)]
+#[ui(inspect = Inspect)]
+pub struct Goap {
+    #[ui(choices)]
+    pub goal: GoalMode,
+    pub resource: String,
+    pub stockpile: String,
+    pub tool: String,
+    pub agent: GoapAgent,
+}
+
+impl Default for Goap {
+    fn default() -> Self {
+        Self {
+            goal: GoalMode::Idle,
+            resource: "resource".to_string(),
+            stockpile: "stockpile".to_string(),
+            tool: "tool".to_string(),
+            agent: GoapAgent::default(),
+        }
+    }
+}
+
+/// What the planners weigh for this entity on top of distance: cells under
+/// the influence of entities tagged `avoid` cost up to `avoid_weight` / 10
+/// times more, cells near ones tagged `prefer` up to `prefer_weight` / 10
+/// times less.
+#[derive(panel_macros::GenerateUI, Clone, serde::Deserialize, serde::Serialize)]
+#[ui(inspect = Inspect)]
+pub struct Tactics {
+    pub avoid: String,
+    pub avoid_weight: f32,
+    pub prefer: String,
+    pub prefer_weight: f32,
+}
+
+impl Default for Tactics {
+    fn default() -> Self {
+        Self {
+            avoid: "enemy".to_string(),
+            avoid_weight: 30.,
+            prefer: "cover".to_string(),
+            prefer_weight: 5.,
+        }
+    }
+}
+
+/// Limits what the entity knows of the map to what it has seen within
+/// `radius` cells. It plans as if unknown cells were free.
+#[derive(panel_macros::GenerateUI, Clone, serde::Deserialize, serde::Serialize)]
+#[ui(inspect = Inspect)]
+pub struct Sensor {
+    pub radius: f32,
+}
+
+impl Default for Sensor {
+    fn default() -> Self {
+        Self { radius: 10. }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum ShapeMode {
+    Rect,
+    Circle,
+    Polygon,
+}
+
+impl ShapeMode {
+    pub const ALL: [ShapeMode; 3] = [ShapeMode::Rect, ShapeMode::Circle, ShapeMode::Polygon];
+}
+
+/// Corners of a polygon against the entity's position. Edited as a list in
+/// the inspector.
+pub type Vertices = Vec<egui::Vec2>;
+
+/// Makes the entity an obstacle that blocks the cells its shape covers. A
+/// rect reaches `size` cells right and up from the entity's position, a
+/// circle has a radius of `size.x` cells around it and a polygon joins its
+/// `vertices`.
+#[derive(
+    panel_macros::GenerateUI, Debug, PartialEq, Clone, serde::Deserialize, serde::Serialize,
+)]
+#[ui(inspect = Inspect)]
+pub struct Collider {
+    #[ui(choices)]
+    pub shape: ShapeMode,
+    pub size: egui::Vec2,
+    #[ui(points)]
+    pub vertices: Vertices,
+}
+
+impl Default for Collider {
+    fn default() -> Self {
+        Self {
+            shape: ShapeMode::Rect,
+            size: egui::vec2(4., 4.),
+            vertices: vec![egui::vec2(0., 0.), egui::vec2(4., 0.), egui::vec2(2., 4.)],
+        }
+    }
+}
+
+impl Collider {
+    /// The shape with the entity at `pos`.
+    pub fn shape_at(&self, pos: Pos2) -> ShapeParams {
+        let (x, y) = (pos.x as f64, pos.y as f64);
+        match self.shape {
+            ShapeMode::Rect => ShapeParams::Rectangle(RectParams {
+                center_x: x,
+                center_y: y,
+                width: self.size.x.max(0.) as f64,
+                height: self.size.y.max(0.) as f64,
+            }),
+            ShapeMode::Circle => ShapeParams::Circle(CircleParams {
+                center_x: x,
+                center_y: y,
+                radius_x: self.size.x.max(0.) as f64,
+                radius_y: self.size.x.max(0.) as f64,
+            }),
+            ShapeMode::Polygon => ShapeParams::Polygon(
+                self.vertices
+                    .iter()
+                    .map(|v| [x + v.x as f64, y + v.y as f64])
+                    .collect(),
+            ),
+        }
     }
 }
 
-pub enum Component {
-    Transform2(ComponentWrapper<Transform2>),
-    Color(ComponentWrapper<Color>),
-    Mesh(ComponentWrapper<Mesh>),
+/// Registers every component type, in the order the inspector lists them,
+/// with the ones it shows, the ones it offers to add and the ones prefabs
+/// can hold.
+pub fn register(world: &mut World) {
+    world
+        .register::<Transform2>("Transform")
+        .inspect()
+        .placement();
+    world
+        .register::<LocalTransform2>("Local Transform")
+        .inspect();
+    world.register::<Parent>("Parent");
+    world.register::<Children>("Children");
+    world.register::<PrefabInstance>("Prefab");
+    world.register::<Wander>("Wander");
+    world.register::<Color>("Color").inspect().prefab();
+    world.register::<Mesh>("Mesh").inspect().placement();
+    world.register::<Velocity>("Velocity").inspect().prefab();
+    world
+        .register::<Kinematics>("Kinematics")
+        .inspect()
+        .prefab();
+    world
+        .register::<Steering>("Steering")
+        .inspect()
+        .prefab()
+        .addable(|world, id| {
+            if !world.has::<Velocity>(id) {
+                world.add(id, Velocity::default());
+            }
+            if !world.has::<Kinematics>(id) {
+                world.add(id, Kinematics::default());
+            }
+        });
+    world
+        .register::<FormationSlot>("Formation Slot")
+        .inspect()
+        .prefab()
+        .addable(|_, _| {});
+    world
+        .register::<Patrol>("Patrol")
+        .inspect()
+        .prefab()
+        .addable(|world, id| {
+            // starts out from where the entity stands
+            let Some(pos) = world.get::<Transform2>(id).map(|t| t.pos) else {
+                return;
+            };
+            if let Some(patrol) = world.get_mut::<Patrol>(id) {
+                patrol.waypoints.push(pos);
+            }
+        });
+    world
+        .register::<Behaviour>("Behaviour")
+        .inspect()
+        .with_details(|behaviour, ui| {
+            ui.collapsing("Tree", |ui| behaviour.tree.ui(ui));
+        })
+        .addable(|_, _| {});
+    world
+        .register::<Goap>("Goal")
+        .inspect()
+        .with_details(|goap, ui| {
+            ui.collapsing("Plan", |ui| goap.agent.ui(ui));
+        })
+        .addable(|_, _| {});
+    world
+        .register::<Tactics>("Tactics")
+        .inspect()
+        .prefab()
+        .addable(|_, _| {});
+    world
+        .register::<Sensor>("Sensor")
+        .inspect()
+        .prefab()
+        .addable(|_, _| {});
+    world
+        .register::<Collider>("Collider")
+        .inspect()
+        .prefab()
+        .addable(|_, _| {});
 }
@@ -0,0 +1,171 @@
+use super::entity::EntityId;
+use super::world::World;
+use std::any::{Any, TypeId};
+use std::marker::PhantomData;
+
+/// An entity was spawned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EntitySpawned(pub EntityId);
+
+/// An entity was despawned, its components are gone already.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EntityDespawned(pub EntityId);
+
+/// A component was added or borrowed mutably during the last frame, sent
+/// once per component when the frame ends.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ComponentChanged {
+    pub id: EntityId,
+    pub component: TypeId,
+}
+
+impl ComponentChanged {
+    pub fn is<T: 'static>(&self) -> bool {
+        self.component == TypeId::of::<T>()
+    }
+}
+
+/// An entity walked its path to the end.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathCompleted(pub EntityId);
+
+/// No path was found for an entity, or its goal turned out to be blocked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathFailed(pub EntityId);
+
+/// Entities were selected or unselected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SelectionChanged;
+
+/// Events of one type sent over the last two frames. Each event is kept
+/// until the second `World::clear_trackers` after it was sent, so readers
+/// that run before and after the end of a frame all get to see it.
+pub struct Events<E> {
+    previous: Vec<(usize, E)>,
+    current: Vec<(usize, E)>,
+    /// How many events were ever sent, which numbers the next one.
+    count: usize,
+}
+
+impl<E> Default for Events<E> {
+    fn default() -> Self {
+        Self {
+            previous: Vec::new(),
+            current: Vec::new(),
+            count: 0,
+        }
+    }
+}
+
+impl<E> Events<E> {
+    pub fn send(&mut self, event: E) {
+        self.current.push((self.count, event));
+        self.count += 1;
+    }
+}
+
+/// What the world needs of an event queue without knowing its event type.
+pub(super) trait Queue {
+    /// Drops the events sent before the last update.
+    fn update(&mut self);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<E: 'static> Queue for Events<E> {
+    fn update(&mut self) {
+        self.previous = std::mem::take(&mut self.current);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Reads the events of one type a system or panel hasn't seen yet. Events
+/// it didn't get around to reading for two frames are lost to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventReader<E> {
+    next: usize,
+    marker: PhantomData<E>,
+}
+
+impl<E> Default for EventReader<E> {
+    fn default() -> Self {
+        Self {
+            next: 0,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<E: 'static> EventReader<E> {
+    pub fn read<'a>(&mut self, world: &'a World) -> impl Iterator<Item = &'a E> + 'a {
+        let next = self.next;
+        let events = world.events::<E>();
+        self.next = events.map_or(next, |events| events.count);
+        events
+            .into_iter()
+            .flat_map(|events| events.previous.iter().chain(events.current.iter()))
+            .filter(move |(i, _)| *i >= next)
+            .map(|(_, event)| event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Ping(u32);
+
+    fn read(reader: &mut EventReader<Ping>, world: &World) -> Vec<u32> {
+        reader.read(world).map(|ping| ping.0).collect()
+    }
+
+    #[test]
+    fn events_last_until_the_second_frame_end_after_they_were_sent() {
+        let mut world = World::default();
+        let mut early = EventReader::default();
+        let mut late = EventReader::default();
+        let mut too_late = EventReader::default();
+        world.send(Ping(1));
+        assert_eq!(read(&mut early, &world), vec![1]);
+
+        world.clear_trackers();
+        world.send(Ping(2));
+        assert_eq!(read(&mut late, &world), vec![1, 2]);
+
+        world.clear_trackers();
+        assert_eq!(read(&mut too_late, &world), vec![2]);
+        world.clear_trackers();
+        assert!(read(&mut too_late, &world).is_empty());
+    }
+
+    #[test]
+    fn readers_see_each_event_once() {
+        let mut world = World::default();
+        let mut reader = EventReader::default();
+        world.send(Ping(1));
+        assert_eq!(read(&mut reader, &world), vec![1]);
+        assert!(read(&mut reader, &world).is_empty());
+        world.clear_trackers();
+        world.send(Ping(2));
+        assert_eq!(read(&mut reader, &world), vec![2]);
+    }
+
+    #[test]
+    fn readers_that_fall_behind_skip_what_was_dropped() {
+        let mut world = World::default();
+        let mut reader = EventReader::default();
+        world.send(Ping(1));
+        world.clear_trackers();
+        world.clear_trackers();
+        world.send(Ping(2));
+        assert_eq!(read(&mut reader, &world), vec![2]);
+    }
+}
@@ -0,0 +1,211 @@
+use super::behaviour::{Actuator, Status};
+use super::entity::EntityId;
+use crate::pathfinding::goap::{self, facts, Action, Facts, Plan};
+
+/// Seconds to wait before planning again after no plan was found.
+const RETRY_DELAY: f32 = 1.;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum GoalMode {
+    Idle,
+    /// Brings resources to the nearest stockpile over and over, fetching a
+    /// tool first when that pays off.
+    Gather,
+}
+
+/// Tags of the entities a goal plans with.
+pub struct Tags<'a> {
+    pub resource: &'a str,
+    pub stockpile: &'a str,
+    pub tool: &'a str,
+}
+
+impl GoalMode {
+    pub const ALL: [GoalMode; 2] = [GoalMode::Idle, GoalMode::Gather];
+
+    pub fn goal(&self) -> Facts {
+        match self {
+            GoalMode::Idle => Facts::new(),
+            GoalMode::Gather => facts(&[("stored", true)]),
+        }
+    }
+
+    /// One action per entity it can be carried out at, so the planner picks
+    /// which one by the walk there.
+    pub fn actions(&self, id: EntityId, tags: &Tags<'_>, actuator: &dyn Actuator) -> Vec<Action> {
+        let others = |tag: &str| {
+            let mut found = actuator.tagged(tag);
+            found.retain(|(other, _, _)| *other != id);
+            found
+        };
+        let mut actions = Vec::new();
+        if *self == GoalMode::Idle {
+            return actions;
+        }
+        for (_, name, pos) in others(tags.tool) {
+            actions.push(
+                Action::new(
+                    &format!("Pick up {}", name),
+                    &[("has_tool", false)],
+                    &[("has_tool", true)],
+                )
+                .at(pos)
+                .costing(1., 0.5),
+            );
+        }
+        for (_, name, pos) in others(tags.resource) {
+            actions.push(
+                Action::new(
+                    &format!("Harvest {}", name),
+                    &[("has_tool", true), ("has_resource", false)],
+                    &[("has_resource", true)],
+                )
+                .at(pos)
+                .costing(1., 1.),
+            );
+            actions.push(
+                Action::new(
+                    &format!("Harvest {} by hand", name),
+                    &[("has_resource", false)],
+                    &[("has_resource", true)],
+                )
+                .at(pos)
+                .costing(20., 4.),
+            );
+        }
+        for (_, name, pos) in others(tags.stockpile) {
+            actions.push(
+                Action::new(
+                    &format!("Deliver to {}", name),
+                    &[("has_resource", true)],
+                    &[("has_resource", false), ("stored", true)],
+                )
+                .at(pos)
+                .costing(1., 0.5),
+            );
+        }
+        actions
+    }
+}
+
+/// Plans towards the goal of its mode and carries the plan out step by step,
+/// planning again when a step can't be done or the plan is through.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct GoapAgent {
+    pub facts: Facts,
+    pub plan: Option<Plan>,
+    step: usize,
+    elapsed: f32,
+    /// Goal the agent is working on, to notice when it is changed.
+    pub mode: Option<GoalMode>,
+}
+
+impl GoapAgent {
+    pub fn tick(
+        &mut self,
+        id: EntityId,
+        dt: f32,
+        mode: GoalMode,
+        tags: &Tags<'_>,
+        actuator: &mut dyn Actuator,
+    ) -> Status {
+        if self.mode != Some(mode) {
+            log::info!("{} now has the goal {:?}", id, mode);
+            self.abort(id, actuator);
+            self.mode = Some(mode);
+            self.facts.clear();
+        }
+        let goal = mode.goal();
+        if goal.is_empty() {
+            return Status::Success;
+        }
+
+        let Some(plan) = self.plan.as_ref() else {
+            if self.elapsed > 0. {
+                self.elapsed -= dt;
+                return Status::Failure;
+            }
+            // repeating goals start over once reached
+            if goap::satisfies(&self.facts, &goal) {
+                for fact in goal.keys() {
+                    self.facts.remove(fact);
+                }
+            }
+            let Some(start) = actuator.position(id) else {
+                return Status::Failure;
+            };
+            let actions = mode.actions(id, tags, actuator);
+            self.plan = goap::plan(start, &self.facts, &goal, &actions, |a, b| {
+                actuator.path_length(a, b)
+            })
+            .filter(|plan| !plan.steps.is_empty());
+            self.step = 0;
+            if self.plan.is_none() {
+                log::info!("{} found no plan for {:?}", id, mode);
+                self.elapsed = RETRY_DELAY;
+                return Status::Failure;
+            }
+            return Status::Running;
+        };
+
+        let (action, steps) = (plan.steps[self.step].clone(), plan.steps.len());
+        if !action.is_applicable(&self.facts) {
+            self.abort(id, actuator);
+            return Status::Running;
+        }
+        if let Some(target) = action.target {
+            match actuator.move_to(id, target) {
+                Status::Running => return Status::Running,
+                Status::Failure => {
+                    self.abort(id, actuator);
+                    self.elapsed = RETRY_DELAY;
+                    return Status::Failure;
+                }
+                Status::Success => {}
+            }
+        }
+        self.elapsed += dt;
+        if self.elapsed < action.duration {
+            return Status::Running;
+        }
+        action.apply(&mut self.facts);
+        self.elapsed = 0.;
+        self.step += 1;
+        if self.step == steps {
+            self.plan = None;
+            return Status::Success;
+        }
+        Status::Running
+    }
+
+    /// Drops the plan and whatever movement it started.
+    pub fn abort(&mut self, id: EntityId, actuator: &mut dyn Actuator) {
+        if self.plan.take().is_some() {
+            actuator.stop(id);
+        }
+        self.step = 0;
+        self.elapsed = 0.;
+    }
+
+    /// The facts the agent holds and its plan, the step it is on marked.
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        for (fact, value) in self.facts.iter() {
+            ui.label(format!("{}: {}", fact, value));
+        }
+        let Some(plan) = self.plan.as_ref() else {
+            ui.colored_label(egui::Color32::GRAY, "No plan");
+            return;
+        };
+        ui.label(format!("Plan (cost {:.1})", plan.cost));
+        for (i, action) in plan.steps.iter().enumerate() {
+            let (mark, col) = if i < self.step {
+                ("✔", egui::Color32::from_rgb(25, 200, 25))
+            } else if i == self.step {
+                ("▶", egui::Color32::from_rgb(255, 200, 0))
+            } else {
+                ("•", egui::Color32::GRAY)
+            };
+            ui.colored_label(col, format!("{} {}", mark, action.name));
+        }
+    }
+}
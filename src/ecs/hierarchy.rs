@@ -0,0 +1,189 @@
+use super::component::{LocalTransform2, Transform2};
+use super::entity::EntityId;
+use super::world::World;
+
+/// The entity this one hangs below. Every entity but the scene root has one.
+#[derive(Clone, Copy)]
+pub struct Parent(pub EntityId);
+
+/// The entities hanging below this one, in the order they were added.
+#[derive(Clone, Default)]
+pub struct Children(pub Vec<EntityId>);
+
+impl World {
+    pub fn parent(&self, id: EntityId) -> Option<EntityId> {
+        self.get::<Parent>(id).map(|parent| parent.0)
+    }
+
+    pub fn children(&self, id: EntityId) -> Vec<EntityId> {
+        self.get::<Children>(id)
+            .map_or(Vec::new(), |children| children.0.clone())
+    }
+
+    /// Whether `id` hangs somewhere below `ancestor`.
+    pub fn is_descendant(&self, id: EntityId, ancestor: EntityId) -> bool {
+        let mut current = self.parent(id);
+        while let Some(parent) = current {
+            if parent == ancestor {
+                return true;
+            }
+            current = self.parent(parent);
+        }
+        false
+    }
+
+    /// Hangs `id` below `parent` as its last child.
+    pub(super) fn attach(&mut self, id: EntityId, parent: EntityId) {
+        self.add(id, Parent(parent));
+        match self.get_mut::<Children>(parent) {
+            Some(children) => children.0.push(id),
+            None => self.add(parent, Children(vec![id])),
+        }
+    }
+
+    /// Takes `id` out of its parent's children.
+    pub(super) fn detach(&mut self, id: EntityId) {
+        let Some(parent) = self.remove::<Parent>(id) else {
+            return;
+        };
+        if let Some(children) = self.get_mut::<Children>(parent.0) {
+            children.0.retain(|child| *child != id);
+        }
+    }
+
+    /// Moves the entity below `parent`, keeping where it is in the world.
+    /// Returns false and does nothing for the root, stale handles, or a
+    /// `parent` that is the entity itself or below it.
+    pub fn set_parent(&mut self, id: EntityId, parent: EntityId) -> bool {
+        if id == self.root()
+            || !self.contains(id)
+            || !self.contains(parent)
+            || id == parent
+            || self.is_descendant(parent, id)
+        {
+            return false;
+        }
+        if self.parent(id) == Some(parent) {
+            return true;
+        }
+        self.detach(id);
+        self.attach(id, parent);
+        self.place(id, parent);
+        true
+    }
+
+    /// Works out the local transform that keeps the entity where it is in
+    /// the world, below its parent. Entities below one without a transform
+    /// have none.
+    fn place(&mut self, id: EntityId, parent: EntityId) {
+        let world = self.get::<Transform2>(id).copied();
+        let parent = self.get::<Transform2>(parent).copied();
+        match (world, parent) {
+            (Some(world), Some(parent)) => self.add(id, LocalTransform2::between(&parent, &world)),
+            _ => {
+                self.remove::<LocalTransform2>(id);
+            }
+        }
+    }
+
+    /// Carries transforms down the hierarchy, parents before their children.
+    /// An entity that was moved in the world since it was last placed keeps
+    /// its world transform and its local one follows, otherwise it is put
+    /// where its local transform says below its parent. So moving a parent
+    /// takes its children along, and moving a child moves it against its
+    /// parent.
+    pub fn propagate_transforms(&mut self) {
+        let mut pending = self.children(self.root());
+        while let Some(id) = pending.pop() {
+            pending.extend(self.children(id));
+            let Some(parent) = self.parent(id) else {
+                continue;
+            };
+            let world = self.get::<Transform2>(id).copied();
+            let parent_world = self.get::<Transform2>(parent).copied();
+            let local = self.get::<LocalTransform2>(id).copied();
+            match (world, parent_world, local) {
+                (Some(world), Some(parent_world), Some(local)) if local.placed == Some(world) => {
+                    let placed = parent_world.then(&local);
+                    if placed != world {
+                        if let Some(transform) = self.get_mut::<Transform2>(id) {
+                            *transform = placed;
+                        }
+                        if let Some(local) = self.get_mut::<LocalTransform2>(id) {
+                            local.placed = Some(placed);
+                        }
+                    }
+                }
+                _ => self.place(id, parent),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::entity::Entity;
+    use crate::ecs::pos2::Pos2;
+
+    fn spawn_at(world: &mut World, x: i64, y: i64) -> EntityId {
+        let id = world.spawn(Entity::default());
+        world.add(
+            id,
+            Transform2 {
+                pos: Pos2::new(x, y),
+                heading: 0.,
+            },
+        );
+        id
+    }
+
+    fn pos(world: &World, id: EntityId) -> Pos2 {
+        world.get::<Transform2>(id).unwrap().pos
+    }
+
+    #[test]
+    fn reparenting_keeps_the_world_position() {
+        let mut world = World::default();
+        let parent = spawn_at(&mut world, 10, 10);
+        let child = spawn_at(&mut world, 13, 8);
+        assert!(world.set_parent(child, parent));
+        assert_eq!(world.children(parent), vec![child]);
+        assert!(!world.children(world.root()).contains(&child));
+        world.propagate_transforms();
+        assert_eq!(pos(&world, child), Pos2::new(13, 8));
+        let local = world.get::<LocalTransform2>(child).unwrap();
+        assert_eq!(local.offset, egui::vec2(3., -2.));
+
+        // no cycles, and the root stays on top
+        assert!(!world.set_parent(parent, child));
+        assert!(!world.set_parent(child, child));
+        assert!(!world.set_parent(world.root(), child));
+        assert_eq!(world.parent(parent), Some(world.root()));
+    }
+
+    #[test]
+    fn children_follow_their_parent_and_can_move_on_their_own() {
+        let mut world = World::default();
+        let parent = spawn_at(&mut world, 10, 10);
+        let child = spawn_at(&mut world, 12, 10);
+        let grandchild = spawn_at(&mut world, 12, 11);
+        world.set_parent(child, parent);
+        world.set_parent(grandchild, child);
+        world.propagate_transforms();
+
+        world.get_mut::<Transform2>(parent).unwrap().pos = Pos2::new(20, 0);
+        world.propagate_transforms();
+        assert_eq!(pos(&world, child), Pos2::new(22, 0));
+        assert_eq!(pos(&world, grandchild), Pos2::new(22, 1));
+
+        // moving the child keeps it there and only takes its own children along
+        world.get_mut::<Transform2>(child).unwrap().pos = Pos2::new(25, 5);
+        world.propagate_transforms();
+        assert_eq!(pos(&world, parent), Pos2::new(20, 0));
+        assert_eq!(pos(&world, child), Pos2::new(25, 5));
+        assert_eq!(pos(&world, grandchild), Pos2::new(25, 6));
+        let local = world.get::<LocalTransform2>(child).unwrap();
+        assert_eq!(local.offset, egui::vec2(5., 5.));
+    }
+}
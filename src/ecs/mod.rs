@@ -1,3 +1,15 @@
+pub mod behaviour;
 pub mod component;
 pub mod entity;
+pub mod event;
+pub mod goap;
+pub mod hierarchy;
+pub mod patrol;
 pub mod pos2;
+pub mod prefab;
+pub mod query;
+pub mod schedule;
+pub mod spatial;
+pub mod steering;
+pub mod storage;
+pub mod world;
@@ -0,0 +1,97 @@
+use super::component::{Patrol, PatrolMode};
+use super::pos2::Pos2;
+
+/// The waypoint the patrol is heading for, `None` once a one-way patrol is
+/// done or while it has no waypoints.
+pub fn current_waypoint(patrol: &Patrol) -> Option<Pos2> {
+    patrol.waypoints.get(patrol.next).copied()
+}
+
+/// Moves on to the waypoint after the current one.
+pub fn advance(patrol: &mut Patrol) {
+    let n = patrol.waypoints.len();
+    match patrol.mode {
+        PatrolMode::Loop => patrol.next = (patrol.next + 1) % n.max(1),
+        PatrolMode::Once => patrol.next += 1,
+        PatrolMode::PingPong if n < 2 => patrol.next = 0,
+        PatrolMode::PingPong if patrol.returning => {
+            if patrol.next == 0 {
+                patrol.returning = false;
+                patrol.next = 1;
+            } else {
+                patrol.next -= 1;
+            }
+        }
+        PatrolMode::PingPong => {
+            if patrol.next + 1 >= n {
+                patrol.returning = true;
+                patrol.next = n - 2;
+            } else {
+                patrol.next += 1;
+            }
+        }
+    }
+}
+
+/// Puts a repeating patrol back on its route after waypoints were removed
+/// or the mode was changed in the inspector.
+pub fn restart_if_off_route(patrol: &mut Patrol) {
+    if patrol.mode != PatrolMode::Once && patrol.next >= patrol.waypoints.len() {
+        patrol.next = 0;
+        patrol.returning = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The next `n` waypoints visited by a patrol over (0, 0), (1, 0), (2, 0).
+    fn tour(mode: PatrolMode, n: usize) -> Vec<Option<i64>> {
+        let mut patrol = Patrol {
+            waypoints: (0..3).map(|x| Pos2::new(x, 0)).collect(),
+            mode,
+            ..Default::default()
+        };
+        (0..n)
+            .map(|_| {
+                let x = current_waypoint(&patrol).map(|p| p.x);
+                advance(&mut patrol);
+                x
+            })
+            .collect()
+    }
+
+    #[test]
+    fn modes_visit_the_waypoints_in_their_order() {
+        let some = |xs: &[i64]| xs.iter().map(|x| Some(*x)).collect::<Vec<_>>();
+        assert_eq!(tour(PatrolMode::Loop, 7), some(&[0, 1, 2, 0, 1, 2, 0]));
+        assert_eq!(tour(PatrolMode::PingPong, 7), some(&[0, 1, 2, 1, 0, 1, 2]));
+        assert_eq!(
+            tour(PatrolMode::Once, 5),
+            vec![Some(0), Some(1), Some(2), None, None]
+        );
+    }
+
+    #[test]
+    fn repeating_patrols_restart_when_waypoints_go() {
+        let mut patrol = Patrol {
+            waypoints: (0..3).map(|x| Pos2::new(x, 0)).collect(),
+            mode: PatrolMode::PingPong,
+            next: 2,
+            returning: true,
+        };
+        patrol.waypoints.truncate(1);
+        restart_if_off_route(&mut patrol);
+        assert_eq!((patrol.next, patrol.returning), (0, false));
+
+        // a finished one-way patrol stays finished
+        let mut once = Patrol {
+            mode: PatrolMode::Once,
+            next: 3,
+            ..patrol.clone()
+        };
+        restart_if_off_route(&mut once);
+        assert_eq!(current_waypoint(&once), None);
+    }
+}
@@ -0,0 +1,279 @@
+use super::entity::{Entity, EntityId};
+use super::world::World;
+use std::collections::BTreeMap;
+
+/// Where the prefab data files are, one prefab per `.json` file.
+pub const PREFAB_DIR: &str = "assets/prefabs";
+
+/// The prefabs shipped with the app, so they are there on the web too.
+const BUILTIN: [&str; 4] = [
+    include_str!("../../assets/prefabs/entity.json"),
+    include_str!("../../assets/prefabs/agent.json"),
+    include_str!("../../assets/prefabs/wanderer.json"),
+    include_str!("../../assets/prefabs/obstacle.json"),
+];
+
+/// A named template entities are spawned from: the name and tag they start
+/// out with and the values of their components, by the name the components
+/// are registered under.
+#[derive(Clone, PartialEq, Debug, serde::Deserialize, serde::Serialize)]
+pub struct Prefab {
+    pub name: String,
+    #[serde(default)]
+    pub tag: String,
+    pub components: BTreeMap<String, serde_json::Value>,
+}
+
+/// Marks an entity as spawned from the prefab named `prefab`. Its components
+/// follow the prefab's as long as they aren't overridden, that is as long as
+/// they have the value the prefab gives them.
+#[derive(Clone, Debug)]
+pub struct PrefabInstance {
+    pub prefab: String,
+}
+
+pub(super) fn load_builtin(world: &mut World) {
+    for data in BUILTIN {
+        match serde_json::from_str::<Prefab>(data) {
+            Ok(prefab) => world.set_prefab(prefab),
+            Err(err) => log::warn!("builtin prefab: {}", err),
+        }
+    }
+}
+
+impl World {
+    pub fn prefab(&self, name: &str) -> Option<&Prefab> {
+        self.prefabs().iter().find(|prefab| prefab.name == name)
+    }
+
+    /// Adds the prefab, or replaces the one of the same name and updates the
+    /// components of its instances that weren't overridden, leaving them
+    /// where they are. Components prefabs can't hold are dropped with a
+    /// warning.
+    pub fn set_prefab(&mut self, mut prefab: Prefab) {
+        let components = std::mem::take(&mut prefab.components);
+        for (name, value) in components {
+            match self.normalize_component(&name, &value) {
+                Some(value) => {
+                    prefab.components.insert(name, value);
+                }
+                None => log::warn!("prefab {}: can't hold {} {}", prefab.name, name, value),
+            }
+        }
+
+        let old = self.prefab(&prefab.name).cloned();
+        match self
+            .prefabs_mut()
+            .iter_mut()
+            .find(|other| other.name == prefab.name)
+        {
+            Some(other) => *other = prefab.clone(),
+            None => self.prefabs_mut().push(prefab.clone()),
+        }
+        let Some(old) = old else {
+            return;
+        };
+        for id in self.instances(&prefab.name) {
+            for name in self.shared_components() {
+                if self.save_component(name, id).as_ref() != old.components.get(name) {
+                    continue;
+                }
+                match prefab.components.get(name) {
+                    Some(value) => {
+                        self.load_component(name, id, value);
+                    }
+                    None => self.remove_component(name, id),
+                }
+            }
+        }
+    }
+
+    /// Spawns an entity from the prefab below `parent`, none if there is no
+    /// prefab of that name.
+    pub fn instantiate(&mut self, prefab: &str, parent: EntityId) -> Option<EntityId> {
+        let prefab = self.prefab(prefab)?.clone();
+        let mut entity = Entity::default();
+        entity.data.name = prefab.name.clone();
+        entity.data.tag = prefab.tag.clone();
+        let id = self.spawn_child(parent, entity);
+        for (name, value) in prefab.components.iter() {
+            self.load_component(name, id, value);
+        }
+        self.add(
+            id,
+            PrefabInstance {
+                prefab: prefab.name,
+            },
+        );
+        Some(id)
+    }
+
+    pub fn instances(&self, prefab: &str) -> Vec<EntityId> {
+        self.query::<(EntityId, &PrefabInstance)>()
+            .filter(|(_, instance)| instance.prefab == prefab)
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// The components of a prefab instance that differ from its prefab,
+    /// including ones it added or removed. Where it is doesn't count.
+    pub fn overrides(&self, id: EntityId) -> Vec<&'static str> {
+        let Some(prefab) = self
+            .get::<PrefabInstance>(id)
+            .and_then(|instance| self.prefab(&instance.prefab))
+        else {
+            return Vec::new();
+        };
+        self.shared_components()
+            .into_iter()
+            .filter(|name| self.save_component(name, id).as_ref() != prefab.components.get(*name))
+            .collect()
+    }
+
+    /// Gives the instance's component back the value its prefab has, or
+    /// removes it if the prefab has none.
+    pub fn revert(&mut self, id: EntityId, component: &str) {
+        let Some(prefab) = self
+            .get::<PrefabInstance>(id)
+            .and_then(|instance| self.prefab(&instance.prefab))
+        else {
+            return;
+        };
+        match prefab.components.get(component).cloned() {
+            Some(value) => {
+                self.load_component(component, id, &value);
+            }
+            None => self.remove_component(component, id),
+        }
+    }
+
+    /// Makes the instance's overrides part of its prefab, which passes them
+    /// on to the other instances that didn't override them.
+    pub fn apply_overrides(&mut self, id: EntityId) {
+        let Some(mut prefab) = self
+            .get::<PrefabInstance>(id)
+            .and_then(|instance| self.prefab(&instance.prefab))
+            .cloned()
+        else {
+            return;
+        };
+        for name in self.overrides(id) {
+            match self.save_component(name, id) {
+                Some(value) => prefab.components.insert(name.to_string(), value),
+                None => prefab.components.remove(name),
+            };
+        }
+        self.set_prefab(prefab);
+    }
+
+    /// Reads the prefab data files again, updating the prefabs and their
+    /// instances.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn reload_prefabs(&mut self) {
+        let entries = match std::fs::read_dir(PREFAB_DIR) {
+            Ok(entries) => entries,
+            Err(err) => {
+                log::warn!("{}: {}", PREFAB_DIR, err);
+                return;
+            }
+        };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if path.extension() != Some(std::ffi::OsStr::new("json")) {
+                continue;
+            }
+            let prefab = std::fs::read_to_string(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|data| {
+                    serde_json::from_str::<Prefab>(&data).map_err(|err| err.to_string())
+                });
+            match prefab {
+                Ok(prefab) => self.set_prefab(prefab),
+                Err(err) => log::warn!("{}: {}", path.display(), err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::component::{Kinematics, Transform2};
+    use crate::ecs::pos2::Pos2;
+
+    fn speed(world: &World, id: EntityId) -> Option<f32> {
+        world.get::<Kinematics>(id).map(|k| k.max_speed)
+    }
+
+    fn set_speed(world: &mut World, id: EntityId, max_speed: f32) {
+        world.get_mut::<Kinematics>(id).unwrap().max_speed = max_speed;
+    }
+
+    /// Two instances of the builtin agent prefab.
+    fn agents() -> (World, EntityId, EntityId) {
+        let mut world = World::default();
+        let root = world.root();
+        let a = world.instantiate("Agent", root).unwrap();
+        let b = world.instantiate("Agent", root).unwrap();
+        (world, a, b)
+    }
+
+    #[test]
+    fn instances_start_out_as_their_prefab() {
+        let (mut world, a, b) = agents();
+        assert_eq!(world.instances("Agent"), vec![a, b]);
+        assert_eq!(world.entity(a).unwrap().data.tag, "agent");
+        assert_eq!(speed(&world, a), Some(8.));
+        assert!(world.overrides(a).is_empty());
+        let root = world.root();
+        assert!(world.instantiate("Nothing", root).is_none());
+    }
+
+    #[test]
+    fn changed_and_removed_components_are_overrides_placement_is_not() {
+        let (mut world, a, b) = agents();
+        set_speed(&mut world, a, 3.);
+        world.set(
+            a,
+            Transform2 {
+                pos: Pos2::new(1, 2),
+                heading: 0.,
+            },
+        );
+        assert_eq!(world.overrides(a), vec!["Kinematics"]);
+        assert!(world.overrides(b).is_empty());
+        world.remove::<Kinematics>(b);
+        assert_eq!(world.overrides(b), vec!["Kinematics"]);
+    }
+
+    #[test]
+    fn reverting_gives_back_the_prefab_value() {
+        let (mut world, a, b) = agents();
+        set_speed(&mut world, a, 3.);
+        world.revert(a, "Kinematics");
+        assert_eq!(speed(&world, a), Some(8.));
+        world.remove::<Kinematics>(b);
+        world.revert(b, "Kinematics");
+        assert_eq!(speed(&world, b), Some(8.));
+        assert!(world.overrides(a).is_empty());
+        assert!(world.overrides(b).is_empty());
+    }
+
+    #[test]
+    fn applying_passes_overrides_on_to_instances_that_kept_the_prefab_value() {
+        let (mut world, a, b) = agents();
+        let root = world.root();
+        let c = world.instantiate("Agent", root).unwrap();
+        set_speed(&mut world, c, 5.);
+        set_speed(&mut world, a, 3.);
+        world.apply_overrides(a);
+        assert_eq!(
+            world.prefab("Agent").unwrap().components["Kinematics"]["max_speed"],
+            3.
+        );
+        assert!(world.overrides(a).is_empty());
+        assert_eq!(speed(&world, b), Some(3.));
+        // c overrode it itself, so it keeps its own
+        assert_eq!(speed(&world, c), Some(5.));
+        assert_eq!(world.overrides(c), vec!["Kinematics"]);
+    }
+}
@@ -0,0 +1,387 @@
+use super::entity::EntityId;
+use super::storage::{self, Column};
+use super::world::World;
+use std::any::TypeId;
+use std::marker::PhantomData;
+
+/// What a query fetches of every entity it matches: `EntityId`, `&T`,
+/// `&mut T`, `Option`s of those or tuples of all of these. A component type
+/// may only appear once per query.
+pub trait Query {
+    type Item<'a>;
+    /// Columns the query borrows mutably, taken out of the world while it
+    /// runs.
+    type State;
+
+    /// Narrows `shortest` down to the smallest column the query requires,
+    /// which is the one its matches are looked for in.
+    fn shortest(world: &World, shortest: &mut Option<(usize, TypeId)>);
+    fn take(world: &mut World) -> Self::State;
+    fn restore(world: &mut World, state: Self::State);
+    fn fetch<'a>(
+        world: &'a World,
+        state: &'a mut Self::State,
+        id: EntityId,
+    ) -> Option<Self::Item<'a>>;
+}
+
+/// Queries that only read, which can be iterated while others read too.
+pub trait ReadOnlyQuery: Query {
+    fn get(world: &World, id: EntityId) -> Option<Self::Item<'_>>;
+}
+
+/// Narrows down which entities a query matches, without fetching anything:
+/// `With`, `Without`, `Changed` or tuples of them.
+pub trait Filter {
+    fn shortest(_world: &World, _shortest: &mut Option<(usize, TypeId)>) {}
+    fn matches(world: &World, id: EntityId) -> bool;
+}
+
+fn narrow<T: 'static>(world: &World, shortest: &mut Option<(usize, TypeId)>) {
+    // a column that was never added to matches nothing
+    let len = world.column::<T>().map_or(0, |column| column.len());
+    if shortest.filter(|(shortest, _)| *shortest <= len).is_none() {
+        *shortest = Some((len, TypeId::of::<T>()));
+    }
+}
+
+impl Query for EntityId {
+    type Item<'a> = EntityId;
+    type State = ();
+
+    fn shortest(_world: &World, _shortest: &mut Option<(usize, TypeId)>) {}
+
+    fn take(_world: &mut World) -> Self::State {}
+
+    fn restore(_world: &mut World, _state: Self::State) {}
+
+    fn fetch<'a>(world: &'a World, _state: &'a mut (), id: EntityId) -> Option<EntityId> {
+        Self::get(world, id)
+    }
+}
+
+impl ReadOnlyQuery for EntityId {
+    fn get(_world: &World, id: EntityId) -> Option<EntityId> {
+        Some(id)
+    }
+}
+
+impl<T: 'static> Query for &T {
+    type Item<'a> = &'a T;
+    type State = ();
+
+    fn shortest(world: &World, shortest: &mut Option<(usize, TypeId)>) {
+        narrow::<T>(world, shortest);
+    }
+
+    fn take(_world: &mut World) -> Self::State {}
+
+    fn restore(_world: &mut World, _state: Self::State) {}
+
+    fn fetch<'a>(world: &'a World, _state: &'a mut (), id: EntityId) -> Option<&'a T> {
+        Self::get(world, id)
+    }
+}
+
+impl<T: 'static> ReadOnlyQuery for &T {
+    fn get(world: &World, id: EntityId) -> Option<&T> {
+        world.get::<T>(id)
+    }
+}
+
+impl<T: 'static> Query for &mut T {
+    type Item<'a> = &'a mut T;
+    /// The column and the tick to stamp what is borrowed with.
+    type State = (Box<dyn Column>, u32);
+
+    fn shortest(world: &World, shortest: &mut Option<(usize, TypeId)>) {
+        narrow::<T>(world, shortest);
+    }
+
+    fn take(world: &mut World) -> Self::State {
+        (world.take_column::<T>(), world.change_tick())
+    }
+
+    fn restore(world: &mut World, state: Self::State) {
+        world.restore_column::<T>(state.0);
+    }
+
+    fn fetch<'a>(_world: &'a World, state: &'a mut Self::State, id: EntityId) -> Option<&'a mut T> {
+        storage::downcast_mut::<T>(state.0.as_mut()).get_mut(id, state.1)
+    }
+}
+
+impl<T: 'static> Query for Option<&T> {
+    type Item<'a> = Option<&'a T>;
+    type State = ();
+
+    fn shortest(_world: &World, _shortest: &mut Option<(usize, TypeId)>) {}
+
+    fn take(_world: &mut World) -> Self::State {}
+
+    fn restore(_world: &mut World, _state: Self::State) {}
+
+    fn fetch<'a>(world: &'a World, _state: &'a mut (), id: EntityId) -> Option<Option<&'a T>> {
+        Self::get(world, id)
+    }
+}
+
+impl<T: 'static> ReadOnlyQuery for Option<&T> {
+    fn get(world: &World, id: EntityId) -> Option<Option<&T>> {
+        Some(world.get::<T>(id))
+    }
+}
+
+impl<T: 'static> Query for Option<&mut T> {
+    type Item<'a> = Option<&'a mut T>;
+    type State = (Box<dyn Column>, u32);
+
+    fn shortest(_world: &World, _shortest: &mut Option<(usize, TypeId)>) {}
+
+    fn take(world: &mut World) -> Self::State {
+        (world.take_column::<T>(), world.change_tick())
+    }
+
+    fn restore(world: &mut World, state: Self::State) {
+        world.restore_column::<T>(state.0);
+    }
+
+    fn fetch<'a>(
+        _world: &'a World,
+        state: &'a mut Self::State,
+        id: EntityId,
+    ) -> Option<Option<&'a mut T>> {
+        Some(storage::downcast_mut::<T>(state.0.as_mut()).get_mut(id, state.1))
+    }
+}
+
+macro_rules! tuple_query {
+    ($($q:ident),*) => {
+        impl<$($q: Query),*> Query for ($($q,)*) {
+            type Item<'a> = ($($q::Item<'a>,)*);
+            type State = ($($q::State,)*);
+
+            fn shortest(world: &World, shortest: &mut Option<(usize, TypeId)>) {
+                $($q::shortest(world, shortest);)*
+            }
+
+            fn take(world: &mut World) -> Self::State {
+                ($($q::take(world),)*)
+            }
+
+            #[allow(non_snake_case)]
+            fn restore(world: &mut World, state: Self::State) {
+                let ($($q,)*) = state;
+                $($q::restore(world, $q);)*
+            }
+
+            #[allow(non_snake_case)]
+            fn fetch<'a>(
+                world: &'a World,
+                state: &'a mut Self::State,
+                id: EntityId,
+            ) -> Option<Self::Item<'a>> {
+                let ($($q,)*) = state;
+                Some(($($q::fetch(world, $q, id)?,)*))
+            }
+        }
+
+        impl<$($q: ReadOnlyQuery),*> ReadOnlyQuery for ($($q,)*) {
+            fn get(world: &World, id: EntityId) -> Option<Self::Item<'_>> {
+                Some(($($q::get(world, id)?,)*))
+            }
+        }
+
+        impl<$($q: Filter),*> Filter for ($($q,)*) {
+            fn shortest(world: &World, shortest: &mut Option<(usize, TypeId)>) {
+                $($q::shortest(world, shortest);)*
+            }
+
+            fn matches(world: &World, id: EntityId) -> bool {
+                $($q::matches(world, id))&&*
+            }
+        }
+    };
+}
+
+tuple_query!(A);
+tuple_query!(A, B);
+tuple_query!(A, B, C);
+tuple_query!(A, B, C, D);
+tuple_query!(A, B, C, D, E);
+
+impl Filter for () {
+    fn matches(_world: &World, _id: EntityId) -> bool {
+        true
+    }
+}
+
+/// Only entities that have a `T`.
+pub struct With<T>(PhantomData<T>);
+
+impl<T: 'static> Filter for With<T> {
+    fn shortest(world: &World, shortest: &mut Option<(usize, TypeId)>) {
+        narrow::<T>(world, shortest);
+    }
+
+    fn matches(world: &World, id: EntityId) -> bool {
+        world.has::<T>(id)
+    }
+}
+
+/// Only entities that don't have a `T`.
+pub struct Without<T>(PhantomData<T>);
+
+impl<T: 'static> Filter for Without<T> {
+    fn matches(world: &World, id: EntityId) -> bool {
+        !world.has::<T>(id)
+    }
+}
+
+/// Only entities whose `T` was added or borrowed mutably since the last
+/// `World::clear_trackers`.
+pub struct Changed<T>(PhantomData<T>);
+
+impl<T: 'static> Filter for Changed<T> {
+    fn shortest(world: &World, shortest: &mut Option<(usize, TypeId)>) {
+        narrow::<T>(world, shortest);
+    }
+
+    fn matches(world: &World, id: EntityId) -> bool {
+        world.is_changed::<T>(id)
+    }
+}
+
+impl World {
+    /// Entities the query could match, filtered, in the order of the
+    /// smallest column the query requires.
+    fn candidates<Q: Query, F: Filter>(&self) -> Vec<EntityId> {
+        let mut shortest = None;
+        Q::shortest(self, &mut shortest);
+        F::shortest(self, &mut shortest);
+        let ids = match shortest {
+            Some((_, type_id)) => self.column_ids(type_id),
+            None => self.ids(),
+        };
+        ids.into_iter().filter(|id| F::matches(self, *id)).collect()
+    }
+
+    /// Every match of a query that only reads, e.g.
+    /// `world.query::<(EntityId, &Transform2, Option<&Color>)>()`.
+    pub fn query<Q: ReadOnlyQuery>(&self) -> impl Iterator<Item = Q::Item<'_>> + '_ {
+        self.query_filtered::<Q, ()>()
+    }
+
+    pub fn query_filtered<Q: ReadOnlyQuery, F: Filter>(
+        &self,
+    ) -> impl Iterator<Item = Q::Item<'_>> + '_ {
+        self.candidates::<Q, F>()
+            .into_iter()
+            .filter_map(move |id| Q::get(self, id))
+    }
+
+    /// Runs `f` on every match of a query that may borrow several components
+    /// mutably at once, e.g. `(&Kinematics, &mut Velocity, &mut Transform2)`.
+    /// Every component borrowed mutably counts as changed, so systems that
+    /// only sometimes change them should go through `World::set` or
+    /// `World::get_mut_untracked` instead. Filters see the components as
+    /// they were before.
+    pub fn query_filtered_mut<Q: Query, F: Filter>(&mut self, mut f: impl FnMut(Q::Item<'_>)) {
+        let ids = self.candidates::<Q, F>();
+        let mut state = Q::take(self);
+        for id in ids {
+            if let Some(item) = Q::fetch(self, &mut state, id) {
+                f(item);
+            }
+        }
+        Q::restore(self, state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::component::{Color, Kinematics, Velocity};
+    use crate::ecs::entity::Entity;
+
+    /// A world with one entity that has a `Velocity`, one that has a
+    /// `Velocity` and `Kinematics` and one that has neither, with the frame
+    /// they were spawned in over.
+    fn world() -> (World, [EntityId; 3]) {
+        let mut world = World::default();
+        let moving = world.spawn(Entity::default());
+        world.add(moving, Velocity::default());
+        let driven = world.spawn(Entity::default());
+        world.add(driven, Velocity::default());
+        world.add(driven, Kinematics::default());
+        let idle = world.spawn(Entity::default());
+        world.clear_trackers();
+        (world, [moving, driven, idle])
+    }
+
+    #[test]
+    fn filters_narrow_down_the_matches() {
+        let (world, [moving, driven, _]) = world();
+        let mut all: Vec<EntityId> = world
+            .query::<(EntityId, &Velocity)>()
+            .map(|(id, _)| id)
+            .collect();
+        all.sort();
+        assert_eq!(all, vec![moving, driven]);
+        let with: Vec<EntityId> = world
+            .query_filtered::<EntityId, (With<Velocity>, With<Kinematics>)>()
+            .collect();
+        assert_eq!(with, vec![driven]);
+        let without: Vec<EntityId> = world
+            .query_filtered::<EntityId, (With<Velocity>, Without<Kinematics>)>()
+            .collect();
+        assert_eq!(without, vec![moving]);
+        // a column nothing was added to matches nothing
+        assert_eq!(world.query::<&Color>().count(), 0);
+    }
+
+    #[test]
+    fn changed_matches_what_was_borrowed_mutably_this_frame() {
+        let (mut world, [moving, driven, _]) = world();
+        assert_eq!(
+            world
+                .query_filtered::<EntityId, Changed<Velocity>>()
+                .count(),
+            0
+        );
+
+        world.query_filtered_mut::<&mut Velocity, With<Kinematics>>(|v| {
+            v.linear.x = 1.;
+        });
+        let changed: Vec<EntityId> = world
+            .query_filtered::<EntityId, Changed<Velocity>>()
+            .collect();
+        assert_eq!(changed, vec![driven]);
+        assert_eq!(world.get::<Velocity>(driven).unwrap().linear.x, 1.);
+        assert!(!world.is_changed::<Velocity>(moving));
+
+        world.clear_trackers();
+        assert_eq!(
+            world
+                .query_filtered::<EntityId, Changed<Velocity>>()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn several_columns_can_be_borrowed_mutably_at_once() {
+        let (mut world, [_, driven, _]) = world();
+        world.query_filtered_mut::<(&mut Velocity, &mut Kinematics), ()>(|(v, k)| {
+            v.linear.x = k.max_speed;
+        });
+        assert_eq!(world.get::<Velocity>(driven).unwrap().linear.x, 8.);
+    }
+
+    #[test]
+    #[should_panic(expected = "borrowed mutably twice")]
+    fn borrowing_a_column_mutably_twice_is_rejected() {
+        let (mut world, _) = world();
+        world.query_filtered_mut::<(&mut Velocity, Option<&mut Velocity>), ()>(|_| {});
+    }
+}
@@ -0,0 +1,275 @@
+use super::world::World;
+
+/// Where in the frame a system runs. Stages run in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// Applies what the user did since the last frame.
+    Input,
+    /// Polls and starts path searches and decides where entities go next.
+    Planning,
+    /// Moves entities along their paths.
+    Movement,
+    /// Carries transforms over to whatever depends on them.
+    TransformPropagation,
+    /// Copies out what the panels draw, after the world settled.
+    RenderExtract,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [
+        Stage::Input,
+        Stage::Planning,
+        Stage::Movement,
+        Stage::TransformPropagation,
+        Stage::RenderExtract,
+    ];
+}
+
+/// Fixed steps run at most this often per frame, a slower frame drops the
+/// rest rather than falling further behind.
+const MAX_FIXED_STEPS: u32 = 16;
+
+type Run<C> = Box<dyn FnMut(&mut C, &mut World, f32)>;
+
+struct System<C> {
+    name: &'static str,
+    stage: Stage,
+    run: Run<C>,
+    fixed: bool,
+    after: Vec<&'static str>,
+    before: Vec<&'static str>,
+}
+
+/// Runs systems over a context `C` and the world once per frame, stage by
+/// stage. Within a stage, systems run in the order they were added unless
+/// `before` and `after` say otherwise. Fixed systems run once per elapsed
+/// `fixed_step` instead, in their place in that order, so they advance the
+/// same however fast frames come.
+pub struct Schedule<C> {
+    systems: Vec<System<C>>,
+    /// Indices into `systems` in the order they run, worked out again on the
+    /// next run whenever systems were added.
+    order: Option<Vec<usize>>,
+    fixed_step: f32,
+    accumulator: f32,
+}
+
+impl<C> Default for Schedule<C> {
+    fn default() -> Self {
+        Self {
+            systems: Vec::new(),
+            order: None,
+            fixed_step: 1. / 60.,
+            accumulator: 0.,
+        }
+    }
+}
+
+impl<C> Schedule<C> {
+    pub fn with_fixed_step(mut self, fixed_step: f32) -> Self {
+        self.fixed_step = fixed_step;
+        self
+    }
+
+    /// Adds a system that runs with the time passed since the last frame, in
+    /// seconds.
+    pub fn add_system(
+        &mut self,
+        name: &'static str,
+        stage: Stage,
+        run: impl FnMut(&mut C, &mut World, f32) + 'static,
+    ) -> SystemConfig<'_, C> {
+        self.order = None;
+        self.systems.push(System {
+            name,
+            stage,
+            run: Box::new(run),
+            fixed: false,
+            after: Vec::new(),
+            before: Vec::new(),
+        });
+        SystemConfig {
+            system: self.systems.last_mut().unwrap(),
+        }
+    }
+
+    /// Sorts the systems of every stage so each one comes after the ones it
+    /// has to, keeping the order they were added in otherwise.
+    fn sort(&self) -> Vec<usize> {
+        for system in self.systems.iter() {
+            for name in system.after.iter().chain(system.before.iter()) {
+                if !self.systems.iter().any(|s| s.name == *name) {
+                    log::warn!(
+                        "{} is ordered against {}, which isn't scheduled",
+                        system.name,
+                        name
+                    );
+                }
+            }
+        }
+
+        let mut order = Vec::new();
+        for stage in Stage::ALL {
+            let members: Vec<usize> = (0..self.systems.len())
+                .filter(|i| self.systems[*i].stage == stage)
+                .collect();
+            let runs_before = |a: usize, b: usize| {
+                let (a, b) = (&self.systems[a], &self.systems[b]);
+                b.after.contains(&a.name) || a.before.contains(&b.name)
+            };
+            let mut pending = members.clone();
+            while !pending.is_empty() {
+                let Some(next) = pending
+                    .iter()
+                    .position(|b| !pending.iter().any(|a| a != b && runs_before(*a, *b)))
+                else {
+                    let names: Vec<&str> = pending.iter().map(|i| self.systems[*i].name).collect();
+                    panic!("{:?} systems are ordered in a cycle: {:?}", stage, names);
+                };
+                order.push(pending.remove(next));
+            }
+        }
+        order
+    }
+
+    /// Runs every system once, fixed ones once per fixed step that fit into
+    /// the time passed.
+    pub fn run(&mut self, context: &mut C, world: &mut World, dt: f32) {
+        let order = match self.order.take() {
+            Some(order) => order,
+            None => self.sort(),
+        };
+
+        self.accumulator += dt.max(0.);
+        let mut steps = 0;
+        while self.accumulator >= self.fixed_step && steps < MAX_FIXED_STEPS {
+            self.accumulator -= self.fixed_step;
+            steps += 1;
+        }
+        // too far behind to catch up, so drop the backlog rather than spiral
+        if self.accumulator >= self.fixed_step {
+            self.accumulator = 0.;
+        }
+
+        for i in order.iter() {
+            let system = &mut self.systems[*i];
+            if system.fixed {
+                for _ in 0..steps {
+                    (system.run)(context, world, self.fixed_step);
+                }
+            } else {
+                (system.run)(context, world, dt);
+            }
+        }
+        self.order = Some(order);
+    }
+}
+
+/// Returned by `Schedule::add_system` to constrain when the system runs.
+pub struct SystemConfig<'a, C> {
+    system: &'a mut System<C>,
+}
+
+impl<C> SystemConfig<'_, C> {
+    /// Runs after the named system of the same stage.
+    pub fn after(self, name: &'static str) -> Self {
+        self.system.after.push(name);
+        self
+    }
+
+    /// Runs before the named system of the same stage.
+    pub fn before(self, name: &'static str) -> Self {
+        self.system.before.push(name);
+        self
+    }
+
+    /// Runs once per fixed step, with the step as its time.
+    pub fn fixed(self) -> Self {
+        self.system.fixed = true;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Adds a system that logs its name to the context when it runs.
+    fn log<'a>(
+        schedule: &'a mut Schedule<Vec<&'static str>>,
+        name: &'static str,
+        stage: Stage,
+    ) -> SystemConfig<'a, Vec<&'static str>> {
+        schedule.add_system(name, stage, move |ran, _, _| ran.push(name))
+    }
+
+    fn run(schedule: &mut Schedule<Vec<&'static str>>, dt: f32) -> Vec<&'static str> {
+        let mut ran = Vec::new();
+        schedule.run(&mut ran, &mut World::default(), dt);
+        ran
+    }
+
+    #[test]
+    fn stages_run_in_order_whatever_order_systems_were_added_in() {
+        let mut schedule = Schedule::default();
+        log(&mut schedule, "extract", Stage::RenderExtract);
+        log(&mut schedule, "move", Stage::Movement);
+        log(&mut schedule, "input", Stage::Input);
+        log(&mut schedule, "plan", Stage::Planning);
+        log(&mut schedule, "propagate", Stage::TransformPropagation);
+        assert_eq!(
+            run(&mut schedule, 0.),
+            vec!["input", "plan", "move", "propagate", "extract"]
+        );
+    }
+
+    #[test]
+    fn before_and_after_reorder_systems_of_a_stage() {
+        let mut schedule = Schedule::default();
+        log(&mut schedule, "c", Stage::Planning).after("b");
+        log(&mut schedule, "a", Stage::Planning);
+        log(&mut schedule, "b", Stage::Planning);
+        log(&mut schedule, "first", Stage::Planning).before("a");
+        // whichever was added first of the ones free to run goes next
+        assert_eq!(run(&mut schedule, 0.), vec!["b", "c", "first", "a"]);
+        // the order is kept, and worked out again once systems are added
+        assert_eq!(run(&mut schedule, 0.), vec!["b", "c", "first", "a"]);
+        log(&mut schedule, "last", Stage::Planning).before("b");
+        assert_eq!(run(&mut schedule, 0.), vec!["first", "a", "last", "b", "c"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cycle")]
+    fn ordering_cycles_are_rejected() {
+        let mut schedule = Schedule::default();
+        log(&mut schedule, "a", Stage::Movement).after("b");
+        log(&mut schedule, "b", Stage::Movement).after("a");
+        run(&mut schedule, 0.);
+    }
+
+    #[test]
+    fn fixed_systems_run_once_per_elapsed_step() {
+        let mut schedule = Schedule::default().with_fixed_step(0.25);
+        log(&mut schedule, "frame", Stage::Movement);
+        log(&mut schedule, "step", Stage::Movement).fixed();
+        assert_eq!(run(&mut schedule, 0.625), vec!["frame", "step", "step"]);
+        // the eighth left over adds up with the next frame's
+        assert_eq!(run(&mut schedule, 0.125), vec!["frame", "step"]);
+        assert_eq!(run(&mut schedule, 0.125), vec!["frame"]);
+        // a long stall only catches up so far and drops the rest
+        assert_eq!(run(&mut schedule, 100.).len(), 1 + MAX_FIXED_STEPS as usize);
+        assert_eq!(run(&mut schedule, 0.), vec!["frame"]);
+    }
+
+    #[test]
+    fn fixed_systems_get_the_step_as_their_time() {
+        let mut schedule: Schedule<Vec<f32>> = Schedule::default().with_fixed_step(0.5);
+        schedule.add_system("frame", Stage::Movement, |times, _, dt| times.push(dt));
+        schedule
+            .add_system("step", Stage::Movement, |times, _, dt| times.push(dt))
+            .fixed();
+        let mut times = Vec::new();
+        schedule.run(&mut times, &mut World::default(), 1.25);
+        assert_eq!(times, vec![1.25, 0.5, 0.5]);
+    }
+}
@@ -0,0 +1,276 @@
+use super::entity::EntityId;
+use super::pos2::Pos2;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Side of the square buckets, in grid cells.
+const BUCKET_SIZE: f64 = 8.;
+
+/// Axis-aligned box in grid coordinates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: [f64; 2],
+    pub max: [f64; 2],
+}
+
+impl Aabb {
+    /// The box spanned by two opposite corners, in any order.
+    pub fn new(a: [f64; 2], b: [f64; 2]) -> Self {
+        Self {
+            min: [a[0].min(b[0]), a[1].min(b[1])],
+            max: [a[0].max(b[0]), a[1].max(b[1])],
+        }
+    }
+
+    /// The box covering the cell.
+    pub fn cell(pos: Pos2) -> Self {
+        let (x, y) = (pos.x as f64, pos.y as f64);
+        Self {
+            min: [x, y],
+            max: [x + 1., y + 1.],
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self {
+            min: [self.min[0].min(other.min[0]), self.min[1].min(other.min[1])],
+            max: [self.max[0].max(other.max[0]), self.max[1].max(other.max[1])],
+        }
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min[0] <= other.max[0]
+            && other.min[0] <= self.max[0]
+            && self.min[1] <= other.max[1]
+            && other.min[1] <= self.max[1]
+    }
+
+    fn buckets(&self) -> impl Iterator<Item = (i64, i64)> {
+        let (min, max) = (bucket(self.min), bucket(self.max));
+        (min.0..=max.0).flat_map(move |x| (min.1..=max.1).map(move |y| (x, y)))
+    }
+}
+
+fn bucket(p: [f64; 2]) -> (i64, i64) {
+    (
+        (p[0] / BUCKET_SIZE).floor() as i64,
+        (p[1] / BUCKET_SIZE).floor() as i64,
+    )
+}
+
+fn distance(a: [f64; 2], b: [f64; 2]) -> f64 {
+    (a[0] - b[0]).hypot(a[1] - b[1])
+}
+
+struct Entry {
+    center: [f64; 2],
+    bounds: Aabb,
+}
+
+/// Buckets entities by where they are, so finding the ones at a point, in a
+/// box or near a position looks at a few buckets instead of every entity.
+/// Each entity has a center, the middle of the cell it stands on, that
+/// radius and nearest queries measure from, and bounds that cover its mesh
+/// and collider, which point and box queries test against. It sits in every
+/// bucket its bounds overlap.
+///
+/// The `World` keeps one up to date from transform, mesh and collider
+/// changes, see `World::spatial`.
+#[derive(Default)]
+pub struct SpatialHash {
+    buckets: HashMap<(i64, i64), Vec<EntityId>>,
+    entries: HashMap<EntityId, Entry>,
+}
+
+impl SpatialHash {
+    /// Puts the entity at `center` with the given bounds, which are widened
+    /// to cover the center.
+    pub fn insert(&mut self, id: EntityId, center: [f64; 2], bounds: Aabb) {
+        self.remove(id);
+        let bounds = bounds.union(&Aabb::new(center, center));
+        for key in bounds.buckets() {
+            self.buckets.entry(key).or_default().push(id);
+        }
+        self.entries.insert(id, Entry { center, bounds });
+    }
+
+    pub fn remove(&mut self, id: EntityId) {
+        let Some(entry) = self.entries.remove(&id) else {
+            return;
+        };
+        for key in entry.bounds.buckets() {
+            if let Some(ids) = self.buckets.get_mut(&key) {
+                ids.retain(|other| *other != id);
+                if ids.is_empty() {
+                    self.buckets.remove(&key);
+                }
+            }
+        }
+    }
+
+    pub fn ids(&self) -> Vec<EntityId> {
+        self.entries.keys().copied().collect()
+    }
+
+    /// Entities whose bounds overlap the box, in id order.
+    fn overlapping(&self, area: &Aabb) -> BTreeSet<EntityId> {
+        area.buckets()
+            .filter_map(|key| self.buckets.get(&key))
+            .flatten()
+            .filter(|id| self.entries[*id].bounds.intersects(area))
+            .copied()
+            .collect()
+    }
+
+    /// Entities whose bounds cover the point.
+    pub fn at_point(&self, p: [f64; 2]) -> Vec<EntityId> {
+        self.overlapping(&Aabb::new(p, p)).into_iter().collect()
+    }
+
+    /// Entities whose bounds overlap the box.
+    pub fn in_rect(&self, area: Aabb) -> Vec<EntityId> {
+        self.overlapping(&area).into_iter().collect()
+    }
+
+    /// Entities whose center is at most `radius` away from `center`.
+    pub fn in_radius(&self, center: [f64; 2], radius: f64) -> Vec<EntityId> {
+        let area = Aabb::new(
+            [center[0] - radius, center[1] - radius],
+            [center[0] + radius, center[1] + radius],
+        );
+        self.overlapping(&area)
+            .into_iter()
+            .filter(|id| distance(self.entries[id].center, center) <= radius)
+            .collect()
+    }
+
+    /// Up to `k` entities `filter` accepts whose center is at most
+    /// `max_distance` away from `center`, the nearest first. Searches rings
+    /// of buckets outwards until no bucket further out can hold anything
+    /// nearer.
+    pub fn nearest(
+        &self,
+        center: [f64; 2],
+        k: usize,
+        max_distance: f64,
+        filter: impl Fn(EntityId) -> bool,
+    ) -> Vec<EntityId> {
+        let origin = bucket(center);
+        // how far `center` is from the nearest edge of its own bucket
+        let edge = center
+            .iter()
+            .map(|c| {
+                let offset = c.rem_euclid(BUCKET_SIZE);
+                offset.min(BUCKET_SIZE - offset)
+            })
+            .fold(f64::INFINITY, f64::min);
+        let mut seen = HashSet::new();
+        let mut found: Vec<(f64, EntityId)> = Vec::new();
+        let mut ring = 0i64;
+        while k > 0 && seen.len() < self.entries.len() {
+            // whatever is outside the square of rings searched so far is at
+            // least as far as that square's nearest edge
+            let reach = if ring == 0 {
+                0.
+            } else {
+                (ring - 1) as f64 * BUCKET_SIZE + edge
+            };
+            if reach > max_distance || (found.len() >= k && found[k - 1].0 <= reach) {
+                break;
+            }
+            for x in origin.0 - ring..=origin.0 + ring {
+                for y in origin.1 - ring..=origin.1 + ring {
+                    if (x - origin.0).abs() != ring && (y - origin.1).abs() != ring {
+                        continue;
+                    }
+                    for id in self.buckets.get(&(x, y)).into_iter().flatten() {
+                        if !seen.insert(*id) || !filter(*id) {
+                            continue;
+                        }
+                        let d = distance(self.entries[id].center, center);
+                        if d <= max_distance {
+                            found.push((d, *id));
+                        }
+                    }
+                }
+            }
+            found.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+            ring += 1;
+        }
+        found.truncate(k);
+        found.into_iter().map(|(_, id)| id).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(index: u32) -> EntityId {
+        EntityId::new(index, 0)
+    }
+
+    fn cell_center(x: f64, y: f64) -> [f64; 2] {
+        [x + 0.5, y + 0.5]
+    }
+
+    /// Entities on a single cell each, at the given cells.
+    fn with_cells(cells: &[(f64, f64)]) -> SpatialHash {
+        let mut hash = SpatialHash::default();
+        for (i, (x, y)) in cells.iter().enumerate() {
+            let center = cell_center(*x, *y);
+            hash.insert(
+                id(i as u32),
+                center,
+                Aabb::cell(Pos2::new(*x as i64, *y as i64)),
+            );
+        }
+        hash
+    }
+
+    #[test]
+    fn nearest_looks_across_bucket_borders() {
+        // 7 and 8 sit either side of the border between the first two buckets
+        let hash = with_cells(&[(7., 3.), (8., 3.), (20., 3.)]);
+        let center = cell_center(7., 3.);
+        let found = hash.nearest(center, 8, 7.41, |other| other != id(0));
+        assert_eq!(found, vec![id(1)]);
+        // past the border on the other axis and in the diagonal bucket too
+        let corners = with_cells(&[(7., 7.), (7., 8.), (8., 7.), (8., 8.), (12., 12.)]);
+        let found = corners.nearest(cell_center(7., 7.), 8, 2., |_| true);
+        assert_eq!(found, vec![id(0), id(1), id(2), id(3)]);
+    }
+
+    #[test]
+    fn nearest_orders_by_distance_and_stops_at_k() {
+        let hash = with_cells(&[(30., 30.), (0., 0.), (9., 0.), (17., 0.)]);
+        let found = hash.nearest(cell_center(15., 0.), 2, f64::INFINITY, |_| true);
+        assert_eq!(found, vec![id(3), id(2)]);
+        let found = hash.nearest(cell_center(15., 0.), 10, f64::INFINITY, |_| true);
+        assert_eq!(found, vec![id(3), id(2), id(1), id(0)]);
+        assert!(hash
+            .nearest(cell_center(15., 0.), 10, 1., |_| true)
+            .is_empty());
+    }
+
+    #[test]
+    fn in_radius_measures_from_centers_across_buckets() {
+        let hash = with_cells(&[(7., 7.), (8., 8.), (10., 7.), (3., 3.)]);
+        let mut found = hash.in_radius(cell_center(7., 7.), 3.);
+        found.sort();
+        assert_eq!(found, vec![id(0), id(1), id(2)]);
+    }
+
+    #[test]
+    fn in_rect_finds_bounds_that_straddle_buckets() {
+        let mut hash = with_cells(&[(2., 2.), (15., 15.)]);
+        // a wide entity centred in the first bucket reaching into the third
+        hash.insert(id(2), cell_center(6., 2.), Aabb::new([4., 1.], [20., 4.]));
+        assert_eq!(hash.in_rect(Aabb::new([17., 0.], [18., 5.])), vec![id(2)]);
+        assert_eq!(
+            hash.in_rect(Aabb::new([0., 0.], [16., 16.])),
+            vec![id(0), id(1), id(2)]
+        );
+        hash.remove(id(2));
+        assert!(hash.in_rect(Aabb::new([17., 0.], [18., 5.])).is_empty());
+    }
+}
@@ -0,0 +1,324 @@
+use super::component::{Collider, Kinematics, Steering, Transform2, Velocity};
+use super::entity::EntityId;
+use super::query::{With, Without};
+use super::world::World;
+use egui::{Pos2, Vec2};
+use rand::Rng;
+use std::collections::HashMap;
+
+/// Distance at which arrive starts slowing down.
+const SLOWING_RADIUS: f32 = 3.;
+/// Wander circle: how far ahead it sits, its radius and how much the angle
+/// on it may jitter per second.
+const WANDER_DISTANCE: f32 = 2.;
+const WANDER_RADIUS: f32 = 1.;
+const WANDER_JITTER: f64 = 6.;
+/// How much further than its `neighbor_radius` an entity looks for
+/// neighbours in the spatial index, which only knows the cells entities
+/// stood on when it was last updated.
+const NEIGHBOR_MARGIN: f32 = 2.;
+
+/// Where on its wander circle the entity is heading, kept between ticks.
+/// Internal to the steering system, so it isn't inspected or saved.
+#[derive(Clone, Copy, Default)]
+pub struct Wander {
+    pub angle: f64,
+}
+
+#[derive(Clone, Copy)]
+struct Body {
+    id: EntityId,
+    pos: Pos2,
+    vel: Vec2,
+}
+
+fn seek(body: &Body, target: Pos2, max_speed: f32) -> Vec2 {
+    (target - body.pos).normalized() * max_speed - body.vel
+}
+
+fn flee(body: &Body, target: Pos2, max_speed: f32) -> Vec2 {
+    (body.pos - target).normalized() * max_speed - body.vel
+}
+
+fn arrive(body: &Body, target: Pos2, max_speed: f32) -> Vec2 {
+    let to = target - body.pos;
+    let speed = max_speed * (to.length() / SLOWING_RADIUS).min(1.);
+    to.normalized() * speed - body.vel
+}
+
+/// Where `quarry` will be by the time we could get there at full speed.
+fn predict(body: &Body, quarry: &Body, max_speed: f32) -> Pos2 {
+    let t = (quarry.pos - body.pos).length() / max_speed.max(f32::EPSILON);
+    quarry.pos + quarry.vel * t
+}
+
+fn wander(body: &Body, angle: &mut f64, dt: f32, max_speed: f32) -> Vec2 {
+    *angle += rand::thread_rng().gen_range(-1.0..1.0) * WANDER_JITTER * dt as f64;
+    let heading = if body.vel.length_sq() > 0. {
+        body.vel.normalized()
+    } else {
+        Vec2::X
+    };
+    let offset = Vec2::angled(heading.angle() + *angle as f32) * WANDER_RADIUS;
+    seek(
+        body,
+        body.pos + heading * WANDER_DISTANCE + offset,
+        max_speed,
+    )
+}
+
+fn flock(body: &Body, neighbors: &[Body], max_speed: f32) -> (Vec2, Vec2, Vec2) {
+    if neighbors.is_empty() {
+        return (Vec2::ZERO, Vec2::ZERO, Vec2::ZERO);
+    }
+    let mut away = Vec2::ZERO;
+    let mut center = Vec2::ZERO;
+    let mut heading = Vec2::ZERO;
+    for n in neighbors.iter() {
+        let offset = body.pos - n.pos;
+        // closer neighbours push harder
+        away += offset.normalized() / offset.length().max(0.1);
+        center += n.pos.to_vec2();
+        heading += n.vel;
+    }
+    let count = neighbors.len() as f32;
+    let separation = if away.length_sq() > 0. {
+        away.normalized() * max_speed - body.vel
+    } else {
+        Vec2::ZERO
+    };
+    let cohesion = seek(body, (center / count).to_pos2(), max_speed);
+    let alignment = heading / count - body.vel;
+    (separation, cohesion, alignment)
+}
+
+fn blend(
+    body: &Body,
+    steering: &Steering,
+    wander_angle: &mut f64,
+    kinematics: &Kinematics,
+    neighbors: &[Body],
+    quarry: Option<&Body>,
+    dt: f32,
+) -> Vec2 {
+    let max_speed = kinematics.max_speed;
+    let target = Pos2::new(
+        steering.target.x as f32 + 0.5,
+        steering.target.y as f32 + 0.5,
+    );
+    let (separation, cohesion, alignment) = flock(body, neighbors, max_speed);
+
+    let mut force = Vec2::ZERO;
+    let mut add = |weight: f32, f: Vec2| {
+        if weight > 0. && f.is_finite() {
+            force += f * (weight / 100.);
+        }
+    };
+    add(steering.seek, seek(body, target, max_speed));
+    add(steering.flee, flee(body, target, max_speed));
+    add(steering.arrive, arrive(body, target, max_speed));
+    if steering.wander > 0. {
+        add(steering.wander, wander(body, wander_angle, dt, max_speed));
+    }
+    if let Some(quarry) = quarry {
+        let ahead = predict(body, quarry, max_speed);
+        add(steering.pursue, seek(body, ahead, max_speed));
+        add(steering.evade, flee(body, ahead, max_speed));
+    }
+    add(steering.separation, separation);
+    add(steering.cohesion, cohesion);
+    add(steering.alignment, alignment);
+
+    if force.length() > kinematics.max_force {
+        force = force.normalized() * kinematics.max_force;
+    }
+    force
+}
+
+/// Blends the weighted behaviours of every entity with a `Steering`,
+/// `Velocity` and `Kinematics` component and integrates the result into its
+/// `Velocity`. `positions` holds continuous positions where the caller has
+/// them, everything else is assumed to sit at the center of its cell.
+pub fn apply_steering(world: &mut World, dt: f32, positions: &HashMap<EntityId, Pos2>) {
+    let mut bodies = Vec::new();
+    let mut names = HashMap::new();
+    // colliders are obstacles, not bodies to flock with or chase
+    for (id, transform, velocity) in
+        world.query_filtered::<(EntityId, &Transform2, Option<&Velocity>), Without<Collider>>()
+    {
+        let pos = positions.get(&id).copied().unwrap_or(Pos2::new(
+            transform.pos.x as f32 + 0.5,
+            transform.pos.y as f32 + 0.5,
+        ));
+        bodies.push(Body {
+            id,
+            pos,
+            vel: velocity.map_or(Vec2::ZERO, |v| v.linear),
+        });
+        if let Some(e) = world.entity(id) {
+            names
+                .entry(e.data.name.clone())
+                .or_insert_with(Vec::new)
+                .push(bodies.len() - 1);
+        }
+    }
+    let index: HashMap<EntityId, usize> = bodies
+        .iter()
+        .enumerate()
+        .map(|(i, body)| (body.id, i))
+        .collect();
+    let nearby: HashMap<EntityId, Vec<usize>> = world
+        .query::<(EntityId, &Steering)>()
+        .filter_map(|(id, steering)| {
+            let body = &bodies[*index.get(&id)?];
+            let radius = steering.neighbor_radius + NEIGHBOR_MARGIN;
+            let candidates = world
+                .spatial()
+                .in_radius([body.pos.x as f64, body.pos.y as f64], radius as f64)
+                .into_iter()
+                .filter_map(|other| index.get(&other).copied())
+                .collect();
+            Some((id, candidates))
+        })
+        .collect();
+
+    let ids: Vec<EntityId> = world
+        .query_filtered::<EntityId, (With<Steering>, With<Kinematics>)>()
+        .collect();
+    for id in ids {
+        let Some(body) = index.get(&id).map(|i| &bodies[*i]) else {
+            continue;
+        };
+        let Some(kinematics) = world.get::<Kinematics>(id).copied() else {
+            continue;
+        };
+        let Some(steering) = world.get::<Steering>(id) else {
+            continue;
+        };
+        let wanders = steering.wander > 0.;
+        let mut angle = world.get::<Wander>(id).map_or(0., |w| w.angle);
+        let neighbors: Vec<Body> = nearby
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .map(|i| bodies[*i])
+            .filter(|b| b.id != id && (b.pos - body.pos).length() < steering.neighbor_radius)
+            .collect();
+        // the nearest of the entities with the quarry's name
+        let quarry = names
+            .get(&steering.quarry)
+            .into_iter()
+            .flatten()
+            .map(|i| &bodies[*i])
+            .filter(|b| b.id != id)
+            .min_by(|a, b| {
+                (a.pos - body.pos)
+                    .length_sq()
+                    .total_cmp(&(b.pos - body.pos).length_sq())
+            });
+        let force = blend(
+            body,
+            steering,
+            &mut angle,
+            &kinematics,
+            &neighbors,
+            quarry,
+            dt,
+        );
+        let mut linear = body.vel + force * dt;
+        if linear.length() > kinematics.max_speed {
+            linear = linear.normalized() * kinematics.max_speed;
+        }
+        world.set(id, Velocity { linear });
+        // nothing watches the wander angle, so it isn't counted as a change
+        match world.get_mut_untracked::<Wander>(id) {
+            Some(wander) => wander.angle = angle,
+            None if wanders => world.add(id, Wander { angle }),
+            None => {}
+        }
+    }
+}
+
+/// Whether any behaviour of `steering` is switched on.
+pub fn is_steering(steering: &Steering) -> bool {
+    [
+        steering.seek,
+        steering.flee,
+        steering.arrive,
+        steering.wander,
+        steering.pursue,
+        steering.evade,
+        steering.separation,
+        steering.cohesion,
+        steering.alignment,
+    ]
+    .iter()
+    .any(|w| *w > 0.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::entity::Entity;
+    use crate::ecs::pos2::Pos2 as Cell;
+
+    fn spawn(world: &mut World, name: &str, cell: (i64, i64)) -> EntityId {
+        let mut entity = Entity::default();
+        entity.data.name = name.to_string();
+        let id = world.spawn(entity);
+        world.add(
+            id,
+            Transform2 {
+                pos: Cell::new(cell.0, cell.1),
+                heading: 0.,
+            },
+        );
+        id
+    }
+
+    #[test]
+    fn arrive_slows_down_near_the_target() {
+        let body = |x: f32| Body {
+            id: EntityId::default(),
+            pos: Pos2::new(x, 0.),
+            vel: Vec2::ZERO,
+        };
+        let target = Pos2::new(10., 0.);
+        let far = arrive(&body(0.), target, 8.);
+        let near = arrive(&body(9.), target, 8.);
+        assert!((far.length() - 8.).abs() < 1e-5);
+        assert!(near.length() < far.length());
+        assert!(near.x > 0. && near.y == 0.);
+        // flee heads the other way at full speed
+        let away = flee(&body(0.), target, 8.);
+        assert!((away + far).length() < 1e-5);
+    }
+
+    #[test]
+    fn pursuers_chase_the_nearest_quarry_by_name() {
+        let mut world = World::default();
+        let hunter = spawn(&mut world, "hunter", (10, 10));
+        world.add(
+            hunter,
+            Steering {
+                quarry: "prey".to_string(),
+                pursue: 100.,
+                ..Default::default()
+            },
+        );
+        world.add(hunter, Kinematics::default());
+        world.add(hunter, Velocity::default());
+        spawn(&mut world, "prey", (20, 10));
+        spawn(&mut world, "prey", (10, 13));
+
+        apply_steering(&mut world, 0.1, &HashMap::new());
+        let v = world.get::<Velocity>(hunter).unwrap().linear;
+        assert!(v.y > 0. && v.x.abs() < 1e-5, "{:?}", v);
+
+        // nobody by that name, nothing to pursue
+        world.get_mut::<Steering>(hunter).unwrap().quarry = "".to_string();
+        world.set(hunter, Velocity::default());
+        apply_steering(&mut world, 0.1, &HashMap::new());
+        assert_eq!(world.get::<Velocity>(hunter).unwrap().linear, Vec2::ZERO);
+    }
+}
@@ -0,0 +1,412 @@
+use super::component::Inspect;
+use super::entity::EntityId;
+use super::world::World;
+use std::any::Any;
+use std::marker::PhantomData;
+
+/// Components of one type, packed densely. `sparse` maps an entity's slot
+/// index to where its component sits in `values`, so lookups are constant
+/// time and iteration doesn't visit entities without the component. `ticks`
+/// holds the world's change tick of when each one was last added or borrowed
+/// mutably.
+pub struct SparseSet<T> {
+    sparse: Vec<Option<usize>>,
+    ids: Vec<EntityId>,
+    values: Vec<T>,
+    ticks: Vec<u32>,
+}
+
+impl<T> Default for SparseSet<T> {
+    fn default() -> Self {
+        Self {
+            sparse: Vec::new(),
+            ids: Vec::new(),
+            values: Vec::new(),
+            ticks: Vec::new(),
+        }
+    }
+}
+
+impl<T> SparseSet<T> {
+    fn dense(&self, id: EntityId) -> Option<usize> {
+        let i = (*self.sparse.get(id.index() as usize)?)?;
+        (self.ids[i] == id).then_some(i)
+    }
+
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.dense(id).is_some()
+    }
+
+    pub fn get(&self, id: EntityId) -> Option<&T> {
+        self.dense(id).map(|i| &self.values[i])
+    }
+
+    /// Borrows the component mutably, counting it as changed at `tick`.
+    pub fn get_mut(&mut self, id: EntityId, tick: u32) -> Option<&mut T> {
+        let i = self.dense(id)?;
+        self.ticks[i] = tick;
+        Some(&mut self.values[i])
+    }
+
+    /// Borrows the component mutably without counting it as changed, for
+    /// callers that `mark_changed` once they know they changed it.
+    pub fn get_mut_untracked(&mut self, id: EntityId) -> Option<&mut T> {
+        let i = self.dense(id)?;
+        Some(&mut self.values[i])
+    }
+
+    pub fn mark_changed(&mut self, id: EntityId, tick: u32) {
+        if let Some(i) = self.dense(id) {
+            self.ticks[i] = tick;
+        }
+    }
+
+    /// The tick the component was last added or borrowed mutably at.
+    pub fn changed(&self, id: EntityId) -> Option<u32> {
+        self.dense(id).map(|i| self.ticks[i])
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Adds the component, replacing the one the entity already had.
+    pub fn insert(&mut self, id: EntityId, value: T, tick: u32) -> Option<T> {
+        let index = id.index() as usize;
+        if self.sparse.len() <= index {
+            self.sparse.resize(index + 1, None);
+        }
+        match self.sparse[index] {
+            Some(i) if self.ids[i] == id => {
+                self.ticks[i] = tick;
+                Some(std::mem::replace(&mut self.values[i], value))
+            }
+            // left behind by an earlier occupant of the slot
+            Some(i) => {
+                self.ids[i] = id;
+                self.values[i] = value;
+                self.ticks[i] = tick;
+                None
+            }
+            None => {
+                self.sparse[index] = Some(self.ids.len());
+                self.ids.push(id);
+                self.values.push(value);
+                self.ticks.push(tick);
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: EntityId) -> Option<T> {
+        let i = self.dense(id)?;
+        self.sparse[id.index() as usize] = None;
+        self.ids.swap_remove(i);
+        let value = self.values.swap_remove(i);
+        self.ticks.swap_remove(i);
+        if let Some(moved) = self.ids.get(i) {
+            self.sparse[moved.index() as usize] = Some(i);
+        }
+        Some(value)
+    }
+}
+
+/// What the world needs of a column without knowing its component type.
+pub trait Column {
+    /// Entities that have the component, in storage order.
+    fn ids(&self) -> &[EntityId];
+    fn contains(&self, id: EntityId) -> bool;
+    /// Entities whose component was added or borrowed mutably after `tick`.
+    fn changed_since(&self, tick: u32) -> Vec<EntityId>;
+    fn remove(&mut self, id: EntityId);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: 'static> Column for SparseSet<T> {
+    fn ids(&self) -> &[EntityId] {
+        &self.ids
+    }
+
+    fn contains(&self, id: EntityId) -> bool {
+        SparseSet::contains(self, id)
+    }
+
+    fn changed_since(&self, tick: u32) -> Vec<EntityId> {
+        self.ids
+            .iter()
+            .zip(self.ticks.iter())
+            .filter(|(_, changed)| **changed > tick)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    fn remove(&mut self, id: EntityId) {
+        SparseSet::remove(self, id);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+pub(super) fn downcast<T: 'static>(column: &dyn Column) -> &SparseSet<T> {
+    column
+        .as_any()
+        .downcast_ref()
+        .expect("column registered under another type")
+}
+
+pub(super) fn downcast_mut<T: 'static>(column: &mut dyn Column) -> &mut SparseSet<T> {
+    column
+        .as_any_mut()
+        .downcast_mut()
+        .expect("column registered under another type")
+}
+
+type Inspector = fn(&mut dyn Column, EntityId, u32, &mut egui::Ui);
+type Details = Box<dyn Fn(&dyn Column, EntityId, &mut egui::Ui)>;
+
+/// How a component is written to and read from the data prefabs keep.
+#[derive(Clone, Copy)]
+pub(super) struct Data {
+    pub(super) save: fn(&dyn Column, EntityId) -> Option<serde_json::Value>,
+    /// Sets the entity's component from the data, false if it doesn't fit.
+    pub(super) load: fn(&mut dyn Column, EntityId, &serde_json::Value, u32) -> bool,
+    /// The data as the component would save it, so values read from files
+    /// compare equal to saved ones.
+    pub(super) normalize: fn(&serde_json::Value) -> Option<serde_json::Value>,
+    /// Whether the component says where an instance is rather than what it
+    /// is, see `Registration::placement`.
+    pub(super) placement: bool,
+}
+
+/// A registered component type: its column and how the inspector shows it.
+pub struct ComponentInfo {
+    pub name: &'static str,
+    pub(super) column: Box<dyn Column>,
+    /// Whether a query borrowed the column mutably and has yet to give it
+    /// back.
+    pub(super) taken: bool,
+    pub(super) inspect: Option<Inspector>,
+    pub(super) details: Option<Details>,
+    pub(super) add: Option<fn(&mut dyn Column, EntityId, u32)>,
+    pub(super) on_add: fn(&mut World, EntityId),
+    pub(super) data: Option<Data>,
+}
+
+impl ComponentInfo {
+    pub(super) fn new<T: 'static>(name: &'static str) -> Self {
+        Self {
+            name,
+            column: Box::<SparseSet<T>>::default(),
+            taken: false,
+            inspect: None,
+            details: None,
+            add: None,
+            on_add: |_, _| {},
+            data: None,
+        }
+    }
+}
+
+fn inspect<T: Inspect + 'static>(
+    column: &mut dyn Column,
+    id: EntityId,
+    tick: u32,
+    ui: &mut egui::Ui,
+) {
+    let column = downcast_mut::<T>(column);
+    let changed = column
+        .get_mut_untracked(id)
+        .is_some_and(|value| value.inspect(ui));
+    if changed {
+        column.mark_changed(id, tick);
+    }
+}
+
+fn add_default<T: Default + 'static>(column: &mut dyn Column, id: EntityId, tick: u32) {
+    downcast_mut::<T>(column).insert(id, T::default(), tick);
+}
+
+fn save<T: serde::Serialize + 'static>(
+    column: &dyn Column,
+    id: EntityId,
+) -> Option<serde_json::Value> {
+    serde_json::to_value(downcast::<T>(column).get(id)?).ok()
+}
+
+fn load<T: serde::de::DeserializeOwned + 'static>(
+    column: &mut dyn Column,
+    id: EntityId,
+    value: &serde_json::Value,
+    tick: u32,
+) -> bool {
+    match T::deserialize(value) {
+        Ok(value) => {
+            downcast_mut::<T>(column).insert(id, value, tick);
+            true
+        }
+        Err(err) => {
+            log::warn!("{}: {}", std::any::type_name::<T>(), err);
+            false
+        }
+    }
+}
+
+fn normalize<T: serde::Serialize + serde::de::DeserializeOwned>(
+    value: &serde_json::Value,
+) -> Option<serde_json::Value> {
+    serde_json::to_value(T::deserialize(value).ok()?).ok()
+}
+
+/// Returned by `World::register` to say how the inspector treats the type.
+pub struct Registration<'a, T> {
+    pub(super) info: &'a mut ComponentInfo,
+    pub(super) marker: PhantomData<T>,
+}
+
+impl<'a, T: 'static> Registration<'a, T> {
+    /// Shows the component in the inspector with the fields `GenerateUI`
+    /// derived.
+    pub fn inspect(self) -> Self
+    where
+        T: Inspect,
+    {
+        self.info.inspect = Some(inspect::<T>);
+        self
+    }
+
+    /// Shows more below the fields, for state that isn't edited by hand.
+    pub fn with_details(self, details: fn(&T, &mut egui::Ui)) -> Self {
+        self.info.details = Some(Box::new(move |column, id, ui| {
+            if let Some(value) = downcast::<T>(column).get(id) {
+                details(value, ui);
+            }
+        }));
+        self
+    }
+
+    /// Offers an "Add" button in the inspector for entities without the
+    /// component. `on_add` runs after the default was added, to fill it in
+    /// from the entity or add what it depends on.
+    pub fn addable(self, on_add: fn(&mut World, EntityId)) -> Self
+    where
+        T: Default,
+    {
+        self.info.add = Some(add_default::<T>);
+        self.info.on_add = on_add;
+        self
+    }
+
+    /// Lets prefabs hold the component, under the name it was registered
+    /// with.
+    pub fn prefab(self) -> Self
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        self.info.data = Some(Data {
+            save: save::<T>,
+            load: load::<T>,
+            normalize: normalize::<T>,
+            placement: false,
+        });
+        self
+    }
+
+    /// Lets prefabs hold the component as where their instances start out.
+    /// Instances go their own ways from there, so it is neither an override
+    /// nor updated when the prefab changes.
+    pub fn placement(self) -> Self
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let registration = self.prefab();
+        if let Some(data) = registration.info.data.as_mut() {
+            data.placement = true;
+        }
+        registration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::component::{Color, Velocity};
+    use crate::ecs::entity::Entity;
+
+    #[test]
+    fn stale_handles_miss_what_reuses_their_slot() {
+        let mut set = SparseSet::default();
+        let old = EntityId::new(3, 0);
+        let new = EntityId::new(3, 1);
+        set.insert(old, 'a', 1);
+        assert_eq!(set.get(new), None);
+        // the new occupant takes over what the old one left behind
+        assert_eq!(set.insert(new, 'b', 2), None);
+        assert_eq!(set.get(old), None);
+        assert_eq!(set.get(new), Some(&'b'));
+        assert_eq!(set.remove(old), None);
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn removing_keeps_the_moved_component_reachable() {
+        let mut set = SparseSet::default();
+        let ids: Vec<EntityId> = (0..3).map(|i| EntityId::new(i, 0)).collect();
+        for (i, id) in ids.iter().enumerate() {
+            set.insert(*id, i, 1);
+        }
+        assert_eq!(set.remove(ids[0]), Some(0));
+        assert_eq!(set.get(ids[1]), Some(&1));
+        assert_eq!(set.get(ids[2]), Some(&2));
+        assert!(!set.contains(ids[0]));
+    }
+
+    #[test]
+    fn despawned_slots_are_reused_with_the_next_generation() {
+        let mut world = World::default();
+        let old = world.spawn(Entity::default());
+        world.add(old, Color::default());
+        world.add(old, Velocity::default());
+        assert_eq!(world.despawn(old), vec![old]);
+        assert!(!world.contains(old));
+
+        let new = world.spawn(Entity::default());
+        assert_eq!(new.index(), old.index());
+        assert_eq!(new.generation(), old.generation() + 1);
+        assert!(world.contains(new));
+        assert!(!world.contains(old));
+        // nothing of the old occupant carries over, nor can its handle reach
+        // the new one
+        assert!(!world.has::<Color>(new));
+        world.add(old, Velocity::default());
+        assert!(!world.has::<Velocity>(new));
+        assert!(world.entity(old).is_none());
+        assert!(world.despawn(old).is_empty());
+        assert!(world.contains(new));
+    }
+
+    #[test]
+    fn components_count_as_changed_until_the_frame_ends() {
+        let mut world = World::default();
+        let id = world.spawn(Entity::default());
+        world.add(id, Velocity::default());
+        assert!(world.is_changed::<Velocity>(id));
+        world.clear_trackers();
+        assert!(!world.is_changed::<Velocity>(id));
+
+        // setting the same value isn't a change, borrowing mutably is
+        world.set(id, Velocity::default());
+        assert!(!world.is_changed::<Velocity>(id));
+        world.get_mut::<Velocity>(id);
+        assert!(world.is_changed::<Velocity>(id));
+        world.clear_trackers();
+        world.get_mut_untracked::<Velocity>(id);
+        assert!(!world.is_changed::<Velocity>(id));
+    }
+}
@@ -0,0 +1,656 @@
+use super::component::{self, Collider, Mesh, Transform2};
+use super::entity::{Entity, EntityData, EntityId};
+use super::event::{
+    ComponentChanged, EntityDespawned, EntitySpawned, Events, Queue, SelectionChanged,
+};
+use super::pos2;
+use super::prefab::{self, Prefab};
+use super::query::{Changed, With};
+use super::spatial::{Aabb, SpatialHash};
+use super::storage::{self, Column, ComponentInfo, Data, Registration, SparseSet};
+use std::any::TypeId;
+use std::collections::{BTreeSet, HashMap};
+use std::marker::PhantomData;
+
+/// One place an entity can live in. The generation counts how many entities
+/// were despawned from it, so stale handles to earlier occupants don't match.
+#[derive(Default)]
+struct Slot {
+    generation: u32,
+    entity: Option<Entity>,
+}
+
+/// Owns every entity of the scene, their components and which ones are
+/// selected. The scene root is spawned first and is never despawned, every
+/// other entity hangs below it through `Parent` and `Children` components.
+///
+/// Each component type is stored in a column of its own, which it gets when
+/// it is registered up front, see `component::register`. Components of types
+/// that were never registered are dropped with a warning.
+///
+/// Components remember the change tick they were last added or borrowed
+/// mutably at. The tick advances with `clear_trackers` once per frame, until
+/// then the component counts as changed for `Changed` filters, and a
+/// `ComponentChanged` event is sent for it when the tick advances.
+///
+/// Events are sent through the world and read with an `EventReader`.
+///
+/// The prefabs entities can be spawned from live here too, see `prefab`,
+/// and a spatial index of where entities are, see `spatial`.
+pub struct World {
+    slots: Vec<Slot>,
+    /// Indices of empty slots, reused before new ones are added.
+    free: Vec<u32>,
+    selected: BTreeSet<EntityId>,
+    root: EntityId,
+    components: HashMap<TypeId, ComponentInfo>,
+    /// Component types in the order they were registered, which is the order
+    /// the inspector lists them in.
+    order: Vec<TypeId>,
+    change_tick: u32,
+    last_change_tick: u32,
+    events: HashMap<TypeId, Box<dyn Queue>>,
+    prefabs: Vec<Prefab>,
+    spatial: SpatialHash,
+}
+
+impl Default for World {
+    fn default() -> Self {
+        let mut world = Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            selected: BTreeSet::new(),
+            root: EntityId::default(),
+            components: HashMap::new(),
+            order: Vec::new(),
+            change_tick: 1,
+            last_change_tick: 0,
+            events: HashMap::new(),
+            prefabs: Vec::new(),
+            spatial: SpatialHash::default(),
+        };
+        component::register(&mut world);
+        prefab::load_builtin(&mut world);
+        let mut scene = Entity::default();
+        scene.data.name = "Scene".to_string();
+        world.root = world.insert(scene, None);
+        world
+    }
+}
+
+impl World {
+    pub fn root(&self) -> EntityId {
+        self.root
+    }
+
+    /// Spawns the entity below the scene root.
+    pub fn spawn(&mut self, entity: Entity) -> EntityId {
+        self.insert(entity, Some(self.root))
+    }
+
+    /// Spawns the entity below `parent`, or below the root when `parent` is
+    /// stale.
+    pub fn spawn_child(&mut self, parent: EntityId, entity: Entity) -> EntityId {
+        if !self.contains(parent) {
+            return self.spawn(entity);
+        }
+        self.insert(entity, Some(parent))
+    }
+
+    fn insert(&mut self, mut entity: Entity, parent: Option<EntityId>) -> EntityId {
+        let index = self.free.pop().unwrap_or_else(|| {
+            self.slots.push(Slot::default());
+            (self.slots.len() - 1) as u32
+        });
+        let slot = &mut self.slots[index as usize];
+        let id = EntityId::new(index, slot.generation);
+        entity.set_id(id);
+        if entity.data.name == EntityData::default().name {
+            entity.data.name = format!("Entity {}", id);
+        }
+        slot.entity = Some(entity);
+        if let Some(parent) = parent {
+            self.attach(id, parent);
+        }
+        self.send(EntitySpawned(id));
+        id
+    }
+
+    fn slot(&self, id: EntityId) -> Option<&Slot> {
+        self.slots
+            .get(id.index() as usize)
+            .filter(|slot| slot.generation == id.generation() && slot.entity.is_some())
+    }
+
+    /// Despawns the entity together with everything below it and drops them
+    /// from the selection. Returns the despawned ids, none for the root or a
+    /// stale handle.
+    pub fn despawn(&mut self, id: EntityId) -> Vec<EntityId> {
+        if id == self.root || !self.contains(id) {
+            return Vec::new();
+        }
+        self.detach(id);
+        let mut despawned = vec![id];
+        let mut i = 0;
+        while i < despawned.len() {
+            despawned.extend(self.children(despawned[i]));
+            i += 1;
+        }
+        let was_selected = despawned.iter().any(|id| self.selected.contains(id));
+        for id in despawned.iter() {
+            self.selected.remove(id);
+            self.spatial.remove(*id);
+            for info in self.components.values_mut() {
+                info.column.remove(*id);
+            }
+            let slot = &mut self.slots[id.index() as usize];
+            slot.entity = None;
+            slot.generation += 1;
+            self.free.push(id.index());
+            self.send(EntityDespawned(*id));
+        }
+        if was_selected {
+            self.send(SelectionChanged);
+        }
+        despawned
+    }
+
+    /// Whether the handle still refers to a live entity.
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.slot(id).is_some()
+    }
+
+    pub fn entity(&self, id: EntityId) -> Option<&Entity> {
+        self.slot(id)?.entity.as_ref()
+    }
+
+    pub fn entity_mut(&mut self, id: EntityId) -> Option<&mut Entity> {
+        if !self.contains(id) {
+            return None;
+        }
+        self.slots[id.index() as usize].entity.as_mut()
+    }
+
+    pub fn ids(&self) -> Vec<EntityId> {
+        self.iter().map(|(id, _)| id).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (EntityId, &Entity)> {
+        self.slots
+            .iter()
+            .filter_map(|slot| slot.entity.as_ref())
+            .map(|e| (e.get_id(), e))
+    }
+
+    pub fn selected(&self) -> Vec<EntityId> {
+        self.selected.iter().copied().collect()
+    }
+
+    pub fn is_selected(&self, id: EntityId) -> bool {
+        self.selected.contains(&id)
+    }
+
+    /// Selects or unselects the entity, stale handles are ignored.
+    pub fn set_selected(&mut self, id: EntityId, state: bool) {
+        let changed = if !state {
+            self.selected.remove(&id)
+        } else {
+            self.contains(id) && self.selected.insert(id)
+        };
+        if changed {
+            self.send(SelectionChanged);
+        }
+    }
+
+    pub fn select(&mut self, id: EntityId) {
+        self.set_selected(id, true);
+    }
+
+    pub fn unselect_all(&mut self) {
+        if !self.selected.is_empty() {
+            self.selected.clear();
+            self.send(SelectionChanged);
+        }
+    }
+
+    pub fn send<E: 'static>(&mut self, event: E) {
+        let queue = self
+            .events
+            .entry(TypeId::of::<E>())
+            .or_insert_with(|| Box::<Events<E>>::default());
+        queue
+            .as_any_mut()
+            .downcast_mut::<Events<E>>()
+            .expect("queue registered under another type")
+            .send(event);
+    }
+
+    /// The queue of `E`, none if no `E` was ever sent.
+    pub(super) fn events<E: 'static>(&self) -> Option<&Events<E>> {
+        self.events.get(&TypeId::of::<E>())?.as_any().downcast_ref()
+    }
+
+    /// Registers `T` under the name the inspector shows, returning what
+    /// says how it is shown. Registering a type again keeps its components.
+    pub fn register<T: 'static>(&mut self, name: &'static str) -> Registration<'_, T> {
+        let type_id = TypeId::of::<T>();
+        if !self.components.contains_key(&type_id) {
+            self.order.push(type_id);
+        }
+        let info = self
+            .components
+            .entry(type_id)
+            .or_insert_with(|| ComponentInfo::new::<T>(name));
+        info.name = name;
+        Registration {
+            info,
+            marker: PhantomData,
+        }
+    }
+
+    pub(super) fn column<T: 'static>(&self) -> Option<&SparseSet<T>> {
+        let Some(info) = self.components.get(&TypeId::of::<T>()) else {
+            unregistered::<T>();
+            return None;
+        };
+        Some(storage::downcast(info.column.as_ref()))
+    }
+
+    fn column_mut<T: 'static>(&mut self) -> Option<&mut SparseSet<T>> {
+        let Some(info) = self.components.get_mut(&TypeId::of::<T>()) else {
+            unregistered::<T>();
+            return None;
+        };
+        Some(storage::downcast_mut(info.column.as_mut()))
+    }
+
+    /// Takes the column of `T` out while a query borrows it mutably, leaving
+    /// an empty one behind. A type that isn't registered gets an empty
+    /// column that is dropped again on `restore_column`.
+    pub(super) fn take_column<T: 'static>(&mut self) -> Box<dyn Column> {
+        let Some(info) = self.components.get_mut(&TypeId::of::<T>()) else {
+            unregistered::<T>();
+            return Box::<SparseSet<T>>::default();
+        };
+        assert!(
+            !info.taken,
+            "{} is borrowed mutably twice in one query",
+            info.name
+        );
+        info.taken = true;
+        std::mem::replace(&mut info.column, Box::<SparseSet<T>>::default())
+    }
+
+    pub(super) fn restore_column<T: 'static>(&mut self, column: Box<dyn Column>) {
+        if let Some(info) = self.components.get_mut(&TypeId::of::<T>()) {
+            info.column = column;
+            info.taken = false;
+        }
+    }
+
+    /// Entities with a component of the given type, none if it isn't
+    /// registered.
+    pub(super) fn column_ids(&self, type_id: TypeId) -> Vec<EntityId> {
+        self.components
+            .get(&type_id)
+            .map_or(Vec::new(), |info| info.column.ids().to_vec())
+    }
+
+    /// The tick components borrowed mutably now are stamped with.
+    pub(super) fn change_tick(&self) -> u32 {
+        self.change_tick
+    }
+
+    /// Whether the entity's `T` was added or borrowed mutably since the last
+    /// `clear_trackers`.
+    pub fn is_changed<T: 'static>(&self, id: EntityId) -> bool {
+        self.column::<T>()
+            .and_then(|column| column.changed(id))
+            .is_some_and(|tick| tick > self.last_change_tick)
+    }
+
+    /// Ends the frame: sends `ComponentChanged` for every component changed
+    /// since the last time, after which they no longer count as changed, and
+    /// drops the events sent before the last time.
+    pub fn clear_trackers(&mut self) {
+        let mut changed = Vec::new();
+        for type_id in self.order.iter() {
+            for id in self.components[type_id]
+                .column
+                .changed_since(self.last_change_tick)
+            {
+                changed.push(ComponentChanged {
+                    id,
+                    component: *type_id,
+                });
+            }
+        }
+        for event in changed {
+            self.send(event);
+        }
+        for queue in self.events.values_mut() {
+            queue.update();
+        }
+        self.last_change_tick = self.change_tick;
+        self.change_tick += 1;
+    }
+
+    /// Adds the component to the entity, replacing one of the same type.
+    /// Stale handles are ignored.
+    pub fn add<T: 'static>(&mut self, id: EntityId, component: T) {
+        if self.contains(id) {
+            let tick = self.change_tick;
+            if let Some(column) = self.column_mut::<T>() {
+                column.insert(id, component, tick);
+            }
+        }
+    }
+
+    pub fn remove<T: 'static>(&mut self, id: EntityId) -> Option<T> {
+        self.column_mut::<T>()?.remove(id)
+    }
+
+    pub fn has<T: 'static>(&self, id: EntityId) -> bool {
+        self.column::<T>().is_some_and(|column| column.contains(id))
+    }
+
+    pub fn get<T: 'static>(&self, id: EntityId) -> Option<&T> {
+        self.column::<T>()?.get(id)
+    }
+
+    pub fn get_mut<T: 'static>(&mut self, id: EntityId) -> Option<&mut T> {
+        let tick = self.change_tick;
+        self.column_mut::<T>()?.get_mut(id, tick)
+    }
+
+    /// Borrows the component mutably without counting it as changed, for
+    /// state nothing watches or for callers that `mark_changed` once they
+    /// know they changed it.
+    pub fn get_mut_untracked<T: 'static>(&mut self, id: EntityId) -> Option<&mut T> {
+        self.column_mut::<T>()?.get_mut_untracked(id)
+    }
+
+    pub fn mark_changed<T: 'static>(&mut self, id: EntityId) {
+        let tick = self.change_tick;
+        if let Some(column) = self.column_mut::<T>() {
+            column.mark_changed(id, tick);
+        }
+    }
+
+    /// Gives the entity's component the value, counting it as changed only
+    /// if it differs from the one it had, so systems can write their results
+    /// back every frame without `Changed` seeing it every frame. Does nothing
+    /// if the entity doesn't have the component.
+    pub fn set<T: PartialEq + 'static>(&mut self, id: EntityId, value: T) {
+        let Some(current) = self.get_mut_untracked::<T>(id) else {
+            return;
+        };
+        if *current != value {
+            *current = value;
+            self.mark_changed::<T>(id);
+        }
+    }
+
+    /// The registered components the entity has that the inspector shows,
+    /// as type and name.
+    pub fn inspectable(&self, id: EntityId) -> Vec<(TypeId, &'static str)> {
+        self.order
+            .iter()
+            .map(|type_id| (*type_id, &self.components[type_id]))
+            .filter(|(_, info)| info.inspect.is_some() && info.column.contains(id))
+            .map(|(type_id, info)| (type_id, info.name))
+            .collect()
+    }
+
+    /// Draws the entity's component of the given type into the inspector.
+    pub fn inspect(&mut self, type_id: TypeId, id: EntityId, ui: &mut egui::Ui) {
+        let Some(info) = self.components.get_mut(&type_id) else {
+            return;
+        };
+        if let Some(inspect) = info.inspect {
+            inspect(info.column.as_mut(), id, self.change_tick, ui);
+        }
+        if let Some(details) = &info.details {
+            details(info.column.as_ref(), id, ui);
+        }
+    }
+
+    /// The registered components the inspector offers to add to the entity.
+    pub fn addable(&self, id: EntityId) -> Vec<(TypeId, &'static str)> {
+        self.order
+            .iter()
+            .map(|type_id| (*type_id, &self.components[type_id]))
+            .filter(|(_, info)| info.add.is_some() && !info.column.contains(id))
+            .map(|(type_id, info)| (type_id, info.name))
+            .collect()
+    }
+
+    /// Adds the default of a registered component and runs its add hook.
+    pub fn add_default(&mut self, type_id: TypeId, id: EntityId) {
+        if !self.contains(id) {
+            return;
+        }
+        let Some(info) = self.components.get_mut(&type_id) else {
+            return;
+        };
+        let Some(add) = info.add else {
+            return;
+        };
+        add(info.column.as_mut(), id, self.change_tick);
+        let on_add = info.on_add;
+        on_add(self, id);
+    }
+
+    pub fn prefabs(&self) -> &[Prefab] {
+        &self.prefabs
+    }
+
+    pub(super) fn prefabs_mut(&mut self) -> &mut Vec<Prefab> {
+        &mut self.prefabs
+    }
+
+    /// Names of the components prefab instances share with their prefab,
+    /// in the order they were registered: the ones prefabs can hold that
+    /// aren't placement.
+    pub(super) fn shared_components(&self) -> Vec<&'static str> {
+        self.order
+            .iter()
+            .map(|type_id| &self.components[type_id])
+            .filter(|info| info.data.is_some_and(|data| !data.placement))
+            .map(|info| info.name)
+            .collect()
+    }
+
+    fn data_component(&self, name: &str) -> Option<(&ComponentInfo, Data)> {
+        self.components
+            .values()
+            .filter(|info| info.name == name)
+            .find_map(|info| info.data.map(|data| (info, data)))
+    }
+
+    fn data_component_mut(&mut self, name: &str) -> Option<(&mut ComponentInfo, Data)> {
+        self.components
+            .values_mut()
+            .filter(|info| info.name == name)
+            .find_map(|info| info.data.map(|data| (info, data)))
+    }
+
+    /// The entity's component registered as `name` as prefab data, none if
+    /// it has none or prefabs can't hold it.
+    pub(super) fn save_component(&self, name: &str, id: EntityId) -> Option<serde_json::Value> {
+        let (info, data) = self.data_component(name)?;
+        (data.save)(info.column.as_ref(), id)
+    }
+
+    /// Sets the entity's component registered as `name` from prefab data.
+    /// Returns false if prefabs can't hold it or the data doesn't fit.
+    pub(super) fn load_component(
+        &mut self,
+        name: &str,
+        id: EntityId,
+        value: &serde_json::Value,
+    ) -> bool {
+        if !self.contains(id) {
+            return false;
+        }
+        let tick = self.change_tick;
+        self.data_component_mut(name)
+            .is_some_and(|(info, data)| (data.load)(info.column.as_mut(), id, value, tick))
+    }
+
+    pub(super) fn remove_component(&mut self, name: &str, id: EntityId) {
+        if let Some((info, _)) = self.data_component_mut(name) {
+            info.column.remove(id);
+        }
+    }
+
+    /// The prefab data as the component registered as `name` would save
+    /// it, none if prefabs can't hold it or the data doesn't fit.
+    pub(super) fn normalize_component(
+        &self,
+        name: &str,
+        value: &serde_json::Value,
+    ) -> Option<serde_json::Value> {
+        let (_, data) = self.data_component(name)?;
+        (data.normalize)(value)
+    }
+
+    /// Where entities with a `Transform2` were at the last
+    /// `propagate_entity_changes`.
+    pub fn spatial(&self) -> &SpatialHash {
+        &self.spatial
+    }
+
+    /// Entities whose mesh covers the cell at the point, or whose collider
+    /// covers the point.
+    pub fn entities_at(&self, x: f64, y: f64) -> Vec<EntityId> {
+        let pos = pos2::Pos2 {
+            x: x as i64,
+            y: y as i64,
+        };
+        self.spatial
+            .at_point([x, y])
+            .into_iter()
+            .filter(|id| {
+                let on_mesh = self
+                    .get::<Mesh>(*id)
+                    .is_some_and(|mesh| mesh.mesh.contains(&pos));
+                let in_collider = match (self.get::<Transform2>(*id), self.get::<Collider>(*id)) {
+                    (Some(transform), Some(collider)) => {
+                        collider.shape_at(transform.pos).contains([x, y])
+                    }
+                    _ => false,
+                };
+                on_mesh || in_collider
+            })
+            .collect()
+    }
+
+    /// Entities with a mesh cell or collider in the box.
+    pub fn entities_in(&self, area: Aabb) -> Vec<EntityId> {
+        self.spatial
+            .in_rect(area)
+            .into_iter()
+            .filter(|id| {
+                let on_mesh = self.get::<Mesh>(*id).is_some_and(|mesh| {
+                    mesh.mesh
+                        .iter()
+                        .any(|cell| Aabb::cell(*cell).intersects(&area))
+                });
+                on_mesh || self.has::<Collider>(*id)
+            })
+            .collect()
+    }
+
+    /// Carries transforms down the hierarchy, then moves the mesh of every
+    /// entity whose transform changed onto its position and updates the
+    /// spatial index.
+    pub fn propagate_entity_changes(&mut self) {
+        self.propagate_transforms();
+        self.query_filtered_mut::<(&Transform2, &mut Mesh), Changed<Transform2>>(
+            |(transform, mesh)| {
+                for p in mesh.mesh.iter_mut() {
+                    *p = transform.pos;
+                }
+            },
+        );
+        self.update_spatial();
+    }
+
+    /// Re-indexes the entities whose transform, mesh or collider changed and
+    /// drops the ones that lost their transform.
+    fn update_spatial(&mut self) {
+        let mut dirty: Vec<EntityId> = self
+            .spatial
+            .ids()
+            .into_iter()
+            .filter(|id| !self.has::<Transform2>(*id))
+            .collect();
+        dirty.extend(self.query_filtered::<EntityId, Changed<Transform2>>());
+        dirty.extend(self.query_filtered::<EntityId, (With<Transform2>, Changed<Mesh>)>());
+        dirty.extend(self.query_filtered::<EntityId, (With<Transform2>, Changed<Collider>)>());
+        for id in dirty {
+            let Some(transform) = self.get::<Transform2>(id) else {
+                self.spatial.remove(id);
+                continue;
+            };
+            let mut bounds = Aabb::cell(transform.pos);
+            if let Some(mesh) = self.get::<Mesh>(id) {
+                for cell in mesh.mesh.iter() {
+                    bounds = bounds.union(&Aabb::cell(*cell));
+                }
+            }
+            if let Some(collider) = self.get::<Collider>(id) {
+                for p in collider.shape_at(transform.pos).outline() {
+                    bounds = bounds.union(&Aabb::new(p, p));
+                }
+            }
+            let center = [transform.pos.x as f64 + 0.5, transform.pos.y as f64 + 0.5];
+            self.spatial.insert(id, center, bounds);
+        }
+    }
+}
+
+/// Warns about a component type that was never registered, so a type left
+/// out of `component::register` doesn't go unnoticed.
+fn unregistered<T>() {
+    log::warn!(
+        "{} is used as a component but was never registered",
+        std::any::type_name::<T>()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::event::EventReader;
+
+    #[test]
+    fn despawning_takes_everything_below_along() {
+        let mut world = World::default();
+        let mut despawned = EventReader::<EntityDespawned>::default();
+        let parent = world.spawn(Entity::default());
+        let child = world.spawn_child(parent, Entity::default());
+        let grandchild = world.spawn_child(child, Entity::default());
+        let sibling = world.spawn(Entity::default());
+        world.set_selected(grandchild, true);
+
+        assert_eq!(world.despawn(parent), vec![parent, child, grandchild]);
+        assert!(world
+            .ids()
+            .iter()
+            .all(|id| *id == world.root() || *id == sibling));
+        assert_eq!(world.children(world.root()), vec![sibling]);
+        assert!(world.selected().is_empty());
+        let events: Vec<EntityId> = despawned.read(&world).map(|event| event.0).collect();
+        assert_eq!(events, vec![parent, child, grandchild]);
+
+        // the root and handles that already went stale are left alone
+        assert!(world.despawn(world.root()).is_empty());
+        assert!(world.despawn(child).is_empty());
+        assert!(world.contains(sibling));
+        // nor can a stale parent take new children
+        let orphan = world.spawn_child(child, Entity::default());
+        assert_eq!(world.parent(orphan), Some(world.root()));
+    }
+}
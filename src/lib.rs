@@ -4,4 +4,5 @@ mod app;
 mod panel;
 mod pathfinding;
 pub use app::Pathfinding;
-mod ecs;
\ No newline at end of file
+mod ecs;
+mod simulation;
\ No newline at end of file
@@ -1,5 +1,6 @@
 use super::fixed_demo_label;
 use super::Panel;
+use crate::ecs::world::World;
 
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)]
@@ -46,7 +47,7 @@ impl Default for AppSettingsPanel {
 
 impl Panel for AppSettingsPanel {
     #[allow(unused_variables)]
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame, world: &mut World) {
         let mut open = self.open;
 
         let dimensions = self.app_settings.app_settings_panel_dimensions;
@@ -1,120 +1,15 @@
-use super::scene_hierarchy_panel::get_selected;
 use super::Panel;
-use crate::ecs::entity::get_entity_from_id;
-use crate::ecs::{component::*, entity::ENTITY_MANAGER};
-
 use crate::ecs::pos2::{self, Pos2};
-use crate::pathfinding::NavMesh;
-use poll_promise::Promise;
-use rand::Rng;
-use std::collections::HashMap;
+use crate::ecs::spatial::Aabb;
+use crate::ecs::world::World;
+use crate::pathfinding::shape::ShapeParams;
+use crate::simulation::settings::{EnvironmentSettings, Stage};
+use crate::simulation::{BoxSelect, Click, Extract, Input};
 use std::collections::HashSet;
 use std::f64::consts::TAU;
 
-struct PathPromise(Option<Promise<Option<Vec<Pos2>>>>);
-impl std::fmt::Debug for PathPromise {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        // Your custom logic here
-        write!(f, "DebuggablePromise(...)")
-    }
-}
-impl PartialEq for PathPromise {
-    fn eq(&self, _other: &Self) -> bool {
-        false
-    }
-}
-impl Clone for PathPromise {
-    fn clone(&self) -> Self {
-        Self(Option::None)
-    }
-}
-#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
-pub enum Stage {
-    AStar,
-    Office,
-    Generated,
-}
-
-#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
-pub enum Generated {
-    N(usize),
-}
-
-#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
-pub enum Obstacle {
-    Rectangular,
-    Circular,
-}
-
-#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
-#[serde(default)]
-pub struct AStarStageSettings {
-    pub red_notch_size: f32,
-    pub blue_notch_size: f32,
-}
-
-impl Default for AStarStageSettings {
-    fn default() -> Self {
-        Self {
-            red_notch_size: 3.,
-            blue_notch_size: 2.,
-        }
-    }
-}
-
-#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
-#[serde(default)]
-pub struct EnvironmentSettings {
-    pub stage: Stage,
-    pub n: Generated,
-    pub obstacle: Obstacle,
-
-    pub a_star_stage_settings: AStarStageSettings,
-
-    pub rect_side_min: f32,
-    pub rect_side_max: f32,
-    pub circle_radius_min: f32,
-    pub circle_radius_max: f32,
-}
-
-impl Default for EnvironmentSettings {
-    fn default() -> Self {
-        Self {
-            stage: Stage::Generated,
-            n: Generated::N(20 as usize),
-            obstacle: Obstacle::Rectangular,
-            a_star_stage_settings: AStarStageSettings::default(),
-            rect_side_min: 4.,
-            rect_side_max: 5.,
-            circle_radius_min: 2.,
-            circle_radius_max: 3.,
-        }
-    }
-}
-
-#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
-pub struct CircleParams {
-    center_x: f64,
-    center_y: f64,
-    radius_x: f64,
-    radius_y: f64,
-}
-
-#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
-pub struct RectParams {
-    center_x: f64,
-    center_y: f64,
-    width: f64,
-    height: f64,
-}
-
-#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
-pub enum ShapeParams {
-    Circle(CircleParams),
-    Rectangle(RectParams),
-}
-
-//#[derive(Debug, PartialEq, Clone, serde::Deserialize, serde::Serialize)]
+/// Draws the grid and what the simulation extracted after it ran, and
+/// gathers the clicks on it for the simulation's input stage.
 #[derive(Debug, PartialEq, Clone)]
 pub struct DemoPanel {
     pub open: bool,
@@ -124,32 +19,18 @@ pub struct DemoPanel {
     cursor_x: f64,
     cursor_y: f64,
 
-    base_points: Vec<[f64; 2]>,
-    waypoint_points: Vec<[f64; 2]>,
-    path_points: Vec<[f64; 2]>,
-    search_points: Vec<[f64; 2]>,
-    selected_points: Vec<[f64; 2]>,
     hovered_points: Vec<[f64; 2]>,
-    generate: bool,
-    obstacles: Vec<ShapeParams>,
-    space_lut: HashMap<(i64, i64), bool>,
     stretch: bool,
     first_frame: bool,
 
-    navmesh: NavMesh,
-
-    start: Pos2,
-    end: Pos2,
-
-    path: Vec<Pos2>,
-
-    path_map: HashMap<usize, PathPromise>,
-    current_paths: HashMap<usize, Vec<Pos2>>,
-
     pub is_waypoint: bool,
-    timer: f32,
 
     queued_points: Vec<Pos2>,
+    clicks: Vec<Click>,
+    /// Where the box being dragged out started.
+    box_start: Option<[f64; 2]>,
+    box_selects: Vec<BoxSelect>,
+    extract: Extract,
 }
 
 impl Default for DemoPanel {
@@ -166,49 +47,34 @@ impl Default for DemoPanel {
             cursor_x: f64::MAX,
             cursor_y: f64::MAX,
 
-            base_points: Vec::new(),
-            waypoint_points: Vec::new(),
-            path_points: Vec::new(),
-            search_points: Vec::new(),
-            selected_points: Vec::new(),
             hovered_points: Vec::new(),
-            generate: false,
-            obstacles: Vec::new(),
-            space_lut: HashMap::default(),
             first_frame: true,
             stretch: false,
-
-            navmesh: NavMesh::default(),
-            start: Pos2::default(),
-            end: Pos2::default(),
-            path: Vec::default(),
-            path_map: HashMap::default(),
-            current_paths: HashMap::default(),
             is_waypoint: true,
-            timer: 0.5,
             queued_points: Vec::default(),
+            clicks: Vec::new(),
+            box_start: None,
+            box_selects: Vec::new(),
+            extract: Extract::default(),
         }
     }
 }
 
 impl Panel for DemoPanel {
     #[allow(unused_variables)]
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame, world: &mut World) {
         egui::CentralPanel::default().show(ctx, |ui| {
             let painter = egui::Painter::new(
                 ui.ctx().clone(),
                 ui.layer_id(),
                 ui.available_rect_before_wrap(),
             );
-            self.navmesh
-                .set_grid_boundaries(Pos2::from_min(&self.grid), Pos2::from_max(&self.grid));
             self.paint_grid(ui, &painter);
             // Make sure we allocate what we used (everything)
             ui.expand_to_include_rect(painter.clip_rect());
 
             ui.style_mut().spacing.item_spacing.x = 0.;
         });
-        self.generate = false;
     }
     #[allow(unused)]
     fn ui(&mut self, ui: &mut egui::Ui, frame: &mut eframe::Frame) {}
@@ -216,11 +82,27 @@ impl Panel for DemoPanel {
 
 impl DemoPanel {
     pub fn set_env_settings(&mut self, new_settings: EnvironmentSettings) {
-        if (self.env_settings.stage != Stage::AStar) && (new_settings.stage == Stage::AStar) {
-            self.swap_to_a_star_stage();
-        }
         self.env_settings = new_settings;
     }
+
+    /// The bounds of the grid shown.
+    pub fn grid(&self) -> egui::Rect {
+        self.grid
+    }
+
+    /// What the user did on the grid since the last call.
+    pub fn take_input(&mut self) -> Input {
+        Input {
+            clicks: std::mem::take(&mut self.clicks),
+            box_selects: std::mem::take(&mut self.box_selects),
+            waypoints: std::mem::take(&mut self.queued_points),
+        }
+    }
+
+    /// Takes over what the simulation extracted, drawn until the next one.
+    pub fn set_extract(&mut self, extract: Extract) {
+        self.extract = extract;
+    }
 }
 
 impl DemoPanel {
@@ -234,6 +116,8 @@ impl DemoPanel {
 
         let _cursor = egui::CursorIcon::Default;
 
+        // shift drags out a selection box instead of panning
+        let shift = ui.input(|i| i.modifiers.shift);
         let _r = ui.scope(|ui| {
             let plot = egui_plot::Plot::new("navmesh")
                 .legend(egui_plot::Legend::default().position(egui_plot::Corner::RightBottom))
@@ -241,6 +125,7 @@ impl DemoPanel {
                 .show_y(false)
                 .y_axis_width(2)
                 .allow_zoom(false)
+                .allow_drag(!shift)
                 .allow_boxed_zoom(true)
                 .auto_bounds_x()
                 .auto_bounds_y()
@@ -268,33 +153,29 @@ impl DemoPanel {
                     }
                 }
 
-                self.timer -= plot_ui.ctx().input(|r| r.stable_dt);
-                if self.timer <= 0.0 {
-                    //advance entts
-                    self.move_entities();
-                    self.timer = 0.05;
-                }
                 if self.is_waypoint {
                     plot_ui.points(path_markers);
+                    self.draw_time_annotations(plot_ui);
+                    self.draw_formation_slots(plot_ui);
                 } else {
                     // move entts
                 }
 
-                if self.generate {
-                    self.generate_obstacles();
-                }
-
                 self.draw_stage(plot_ui);
+                self.draw_obstacles(plot_ui);
 
+                self.draw_planner_overlay(plot_ui);
+                self.draw_influence(plot_ui);
+                self.draw_sampling_tree(plot_ui);
+
+                self.draw_known_map(plot_ui);
+                self.draw_patrols(plot_ui);
                 self.draw_entities(plot_ui);
+                self.draw_selection_box(plot_ui);
 
                 plot_ui.points(hovered_markers);
 
                 self.update_marker_size(plot_ui);
-
-                unsafe {
-                    crate::ecs::entity::propagate_entity_changes();
-                }
             });
         });
 
@@ -302,58 +183,7 @@ impl DemoPanel {
             self.first_frame = false;
         }
     }
-}
-
-fn fill_lut_with_rectangle(
-    lut: &mut HashMap<(i64, i64), bool>,
-    x: f64,
-    y: f64,
-    width: f64,
-    height: f64,
-) {
-    let min_ix = x.floor() as i64;
-    let max_ix = (x + width).floor() as i64;
-    let min_iy = y.floor() as i64;
-    let max_iy = (y + height).floor() as i64;
-
-    for ix in min_ix..=max_ix {
-        for iy in min_iy..=max_iy {
-            lut.insert((ix, iy), true);
-        }
-    }
-}
-
-fn fill_lut_with_circle(lut: &mut HashMap<(i64, i64), bool>, cx: f64, cy: f64, r: f64) {
-    let min_x = (cx - r).floor() as i64;
-    let max_x = (cx + r).ceil() as i64;
-    let min_y = (cy - r).floor() as i64;
-    let max_y = (cy + r).ceil() as i64;
-
-    for x in min_x..=max_x {
-        for y in min_y..=max_y {
-            if is_inside_circle(x, y, cx, cy, r) {
-                lut.insert((x, y), true);
-            }
-        }
-    }
-}
 
-fn is_inside_circle(x: i64, y: i64, cx: f64, cy: f64, r: f64) -> bool {
-    let corners = [
-        (x as f64, y as f64),
-        (x as f64 + 1.0, y as f64),
-        (x as f64, y as f64 + 1.0),
-        (x as f64 + 1.0, y as f64 + 1.0),
-    ];
-
-    corners.iter().any(|&(px, py)| {
-        let dx = px - cx;
-        let dy = py - cy;
-        dx * dx + dy * dy <= r * r
-    })
-}
-
-impl DemoPanel {
     fn create_rectangle(&self, x: f64, y: f64, width: f64, height: f64) -> Vec<egui_plot::Line> {
         let top_left = [x, y];
         let top_right = [x + width, y];
@@ -381,6 +211,17 @@ impl DemoPanel {
         ))
     }
 
+    /// Arrow-like triangle pointing along `heading`, about one cell long.
+    fn create_oriented_agent(&self, cx: f64, cy: f64, heading: f64) -> egui_plot::Polygon {
+        let (dx, dy) = (heading.cos(), heading.sin());
+        egui_plot::Polygon::new(egui_plot::PlotPoints::new(vec![
+            [cx + 0.8 * dx, cy + 0.8 * dy],
+            [cx - 0.5 * dx - 0.5 * dy, cy - 0.5 * dy + 0.5 * dx],
+            [cx - 0.2 * dx, cy - 0.2 * dy],
+            [cx - 0.5 * dx + 0.5 * dy, cy - 0.5 * dy - 0.5 * dx],
+        ]))
+    }
+
     fn create_ellipse(&self, cx: f64, cy: f64, rx: f64, ry: f64) -> egui_plot::Polygon {
         egui_plot::Polygon::new(egui_plot::PlotPoints::from_parametric_callback(
             |t| (rx * t.sin() + cx, ry * t.cos() + cy),
@@ -389,70 +230,6 @@ impl DemoPanel {
         ))
     }
 
-    fn generate_obstacles(&mut self) {
-        self.obstacles.clear();
-        self.space_lut.clear();
-
-        match self.env_settings.n {
-            Generated::N(n) => {
-                for _ in 0..n {
-                    match self.env_settings.obstacle {
-                        Obstacle::Circular => {
-                            let center_x = rand::thread_rng()
-                                .gen_range((self.grid.min.x as i32)..(self.grid.max.x as i32))
-                                as f64;
-                            let center_y = rand::thread_rng().gen_range(0..100) as f64;
-                            let radius_x = rand::thread_rng().gen_range(
-                                self.env_settings.circle_radius_min
-                                    ..self.env_settings.circle_radius_max,
-                            ) as f64;
-                            let radius_y = radius_x;
-
-                            let circle_params = CircleParams {
-                                center_x: center_x as f64,
-                                center_y: center_y as f64,
-                                radius_x: radius_x as f64,
-                                radius_y: radius_y as f64,
-                            };
-                            fill_lut_with_circle(&mut self.space_lut, center_x, center_y, radius_x);
-                            self.obstacles.push(ShapeParams::Circle(circle_params));
-                        }
-                        Obstacle::Rectangular => {
-                            let center_x = rand::thread_rng()
-                                .gen_range((self.grid.min.x as i32)..(self.grid.max.x as i32))
-                                as f64;
-                            let center_y = rand::thread_rng().gen_range(0..100) as f64;
-                            let width = rand::thread_rng().gen_range(
-                                self.env_settings.rect_side_min..self.env_settings.rect_side_max,
-                            ) as f64;
-                            let height = rand::thread_rng().gen_range(
-                                self.env_settings.rect_side_min..self.env_settings.rect_side_max,
-                            ) as f64;
-
-                            let rect_params = RectParams {
-                                center_x: center_x,
-                                center_y: center_y,
-                                width: width as f64,
-                                height: height as f64,
-                            };
-                            fill_lut_with_rectangle(
-                                &mut self.space_lut,
-                                center_x,
-                                center_y,
-                                width,
-                                height,
-                            );
-                            self.obstacles.push(ShapeParams::Rectangle(rect_params));
-                        }
-                    }
-                }
-            }
-        }
-        self.navmesh.set_space_lut(self.space_lut.clone());
-    }
-}
-
-impl DemoPanel {
     fn draw_grid_boundaries(&self, plot_ui: &mut egui_plot::PlotUi) {
         let top_left = [self.grid.left() as f64, self.grid.top() as f64];
         let top_right = [self.grid.right() as f64, self.grid.top() as f64];
@@ -474,9 +251,7 @@ impl DemoPanel {
     }
 
     fn draw_stage(&mut self, plot_ui: &mut egui_plot::PlotUi) {
-        if self.env_settings.stage == Stage::Generated {
-            self.generated_stage(plot_ui);
-        } else if self.env_settings.stage == Stage::AStar {
+        if self.env_settings.stage == Stage::AStar {
             self.a_star_stage(plot_ui);
         }
     }
@@ -523,84 +298,207 @@ impl DemoPanel {
         );
     }
 
-    fn generated_stage(&mut self, plot_ui: &mut egui_plot::PlotUi) {
+    /// Every collider as it was last rasterised, the selected ones outlined.
+    fn draw_obstacles(&self, plot_ui: &mut egui_plot::PlotUi) {
         let force_col = egui::Color32::from_rgba_unmultiplied(255, 0, 165, 10);
-        for obs in self.obstacles.iter() {
-            match obs {
+        let selected_col = egui::Color32::from_rgba_unmultiplied(255, 0, 165, 200);
+        for obstacle in self.extract.obstacles.iter() {
+            let col = if obstacle.selected {
+                selected_col
+            } else {
+                force_col
+            };
+            let shape = &obstacle.shape;
+            match shape {
                 ShapeParams::Circle(cp) => {
                     let circle = self
                         .create_circle(cp.center_x, cp.center_y, cp.radius_y)
-                        .fill_color(force_col);
+                        .fill_color(force_col)
+                        .stroke(egui::Stroke::new(1., col));
                     plot_ui.polygon(circle);
                 }
                 ShapeParams::Rectangle(rp) => {
                     let rect = self.create_rectangle(rp.center_x, rp.center_y, rp.width, rp.height);
                     for line in rect {
-                        plot_ui.line(line.color(force_col));
+                        plot_ui.line(line.color(col));
                     }
                 }
+                ShapeParams::Polygon(_) => {
+                    let polygon =
+                        egui_plot::Polygon::new(egui_plot::PlotPoints::new(shape.outline()))
+                            .fill_color(force_col)
+                            .stroke(egui::Stroke::new(1., col));
+                    plot_ui.polygon(polygon);
+                }
             }
         }
     }
 
-    fn move_entities(&mut self) {
-        unsafe {
-            for (id, path) in self.current_paths.iter_mut() {
-                let mut entt_opt = ENTITY_MANAGER.get_mut(id);
-                if let Some(entt) = entt_opt {
-                    for c in entt.components.iter_mut() {
-                        if let Component::Transform2(tc) = c {
-                            if let Some(pos) = path.first() {
-                                tc.get_mut().pos = *pos;
-                                path.remove(0);
-                            }
-                        }
-                    }
+    /// What the selected entity with a sensor knows: unknown cells are fogged,
+    /// the ones it sees right now lit and obstacles it remembers marked.
+    fn draw_known_map(&self, plot_ui: &mut egui_plot::PlotUi) {
+        let Some(known) = &self.extract.known_cells else {
+            return;
+        };
+        let fog_col = egui::Color32::from_rgba_unmultiplied(40, 40, 40, 110);
+        let sight_col = egui::Color32::from_rgba_unmultiplied(255, 230, 120, 35);
+        let memory_col = egui::Color32::from_rgba_unmultiplied(150, 90, 40, 120);
+        for (points, col) in [
+            (&known.unknown, fog_col),
+            (&known.visible, sight_col),
+            (&known.remembered, memory_col),
+        ] {
+            plot_ui.points(
+                egui_plot::Points::new(points.clone())
+                    .filled(true)
+                    .radius(self.marker_size)
+                    .color(col)
+                    .shape(egui_plot::MarkerShape::Square),
+            );
+        }
+    }
+
+    /// Heatmap of the cost layer of the selected entity when it has tactics,
+    /// red where cells cost more and green where less. Otherwise the
+    /// strongest influence of any tag per cell.
+    fn draw_influence(&self, plot_ui: &mut egui_plot::PlotUi) {
+        const LEVELS: usize = 8;
+        let mut levels: Vec<[Vec<[f64; 2]>; 2]> = vec![[Vec::new(), Vec::new()]; LEVELS];
+        for heat in self.extract.heat.iter() {
+            let (x, y) = heat.cell;
+            let level = ((heat.strength * LEVELS as f64) as usize).min(LEVELS - 1);
+            levels[level][heat.preferred as usize].push([x as f64 + 0.5, y as f64 + 0.5]);
+        }
+        for (level, [hot, cool]) in levels.into_iter().enumerate() {
+            let alpha = (20 + 20 * level) as u8;
+            let hot_col = egui::Color32::from_rgba_unmultiplied(255, 60, 0, alpha);
+            let cool_col = egui::Color32::from_rgba_unmultiplied(0, 200, 80, alpha);
+            for (points, col) in [(hot, hot_col), (cool, cool_col)] {
+                if points.is_empty() {
+                    continue;
                 }
+                plot_ui.points(
+                    egui_plot::Points::new(points)
+                        .filled(true)
+                        .radius(self.marker_size)
+                        .color(col)
+                        .shape(egui_plot::MarkerShape::Square),
+                );
             }
         }
     }
 
-    fn draw_entities(&mut self, plot_ui: &mut egui_plot::PlotUi) {
-        unsafe {
-            let entities = ENTITY_MANAGER.iter();
-            let selected = get_selected();
-            for (id, e) in entities {
-                if *id != 0_usize {
-                    let mut pos = pos2::Pos2::default();
-                    let mut points_to_draw: Vec<[f64; 2]> = Vec::new();
-                    let mut col = egui::Color32::default();
-                    for c in &e.components {
-                        match c {
-                            Component::Transform2(tc) => {
-                                pos = tc.get().pos;
-                                points_to_draw.push([
-                                    tc.get().pos.x as f64 + 0.5f64,
-                                    tc.get().pos.y as f64 + 0.5f64,
-                                ]);
-                            }
-                            Component::Color(cc) => {
-                                col = cc.get().col;
-                            }
-                            Component::Mesh(_mc) => {}
-                        }
-                    }
-                    let markers = egui_plot::Points::new(points_to_draw)
-                        .filled(true)
-                        .radius(self.marker_size * 1.2)
-                        .highlight(true)
-                        .color(col) // Red color
-                        .shape(egui_plot::MarkerShape::Square);
-
-                    plot_ui.points(markers);
-
-                    if selected.contains(id) {
-                        plot_ui.polygon(
-                            self.create_circle(pos.x as f64 + 0.5f64, pos.y as f64 + 0.5f64, 3f64)
-                                .name(e.data.name.clone()),
-                        );
-                    }
+    /// The planner's navigation structure when shown, the sampling tree
+    /// aside as it grows.
+    fn draw_planner_overlay(&self, plot_ui: &mut egui_plot::PlotUi) {
+        let wire_col = egui::Color32::from_rgba_unmultiplied(0, 165, 255, 60);
+        for points in self.extract.overlay.iter() {
+            plot_ui.line(
+                egui_plot::Line::new(egui_plot::PlotPoints::new(points.clone()))
+                    .width(1.)
+                    .color(wire_col),
+            );
+        }
+    }
+
+    /// Labels SIPP paths with the tick each cell is reached, on the clock
+    /// all of them share. Waits are shown as a tick range on the cell the
+    /// agent waits on.
+    fn draw_time_annotations(&self, plot_ui: &mut egui_plot::PlotUi) {
+        for (start, path) in self.extract.timed_paths.iter() {
+            let mut t = 0;
+            while t < path.len() {
+                let mut until = t;
+                while until + 1 < path.len() && path[until + 1] == path[t] {
+                    until += 1;
                 }
+                let (from, to) = (start + t as u64, start + until as u64);
+                let label = if until > t {
+                    format!("{}-{}", from, to)
+                } else if from % 5 == 0 || t + 1 == path.len() {
+                    format!("{}", from)
+                } else {
+                    String::new()
+                };
+                if !label.is_empty() {
+                    plot_ui.text(
+                        egui_plot::Text::new(
+                            egui_plot::PlotPoint::new(
+                                path[t].x as f64 + 0.5,
+                                path[t].y as f64 + 1.2,
+                            ),
+                            label,
+                        )
+                        .color(egui::Color32::from_rgb(25, 200, 25)),
+                    );
+                }
+                t = until + 1;
+            }
+        }
+    }
+
+    fn draw_sampling_tree(&self, plot_ui: &mut egui_plot::PlotUi) {
+        let tree_cols = [
+            egui::Color32::from_rgba_unmultiplied(255, 140, 0, 90),
+            egui::Color32::from_rgba_unmultiplied(160, 80, 255, 90),
+        ];
+        for (a, b, tree) in self.extract.tree_edges.iter() {
+            plot_ui.line(
+                egui_plot::Line::new(egui_plot::PlotPoints::new(vec![*a, *b]))
+                    .width(1.)
+                    .color(tree_cols[tree % tree_cols.len()]),
+            );
+        }
+    }
+
+    /// Every patrol route as a line through its waypoints, numbered in order.
+    fn draw_patrols(&self, plot_ui: &mut egui_plot::PlotUi) {
+        for route in self.extract.patrols.iter() {
+            let col = route.col;
+            let mut points = route.points.clone();
+            plot_ui.points(
+                egui_plot::Points::new(points.clone())
+                    .filled(true)
+                    .radius(self.marker_size * 0.8)
+                    .color(col)
+                    .shape(egui_plot::MarkerShape::Diamond),
+            );
+            for (i, [x, y]) in points.iter().enumerate() {
+                plot_ui.text(
+                    egui_plot::Text::new(
+                        egui_plot::PlotPoint::new(*x, y + 1.2),
+                        format!("{}", i + 1),
+                    )
+                    .color(col),
+                );
+            }
+            if route.looped {
+                points.push(points[0]);
+            }
+            plot_ui.line(
+                egui_plot::Line::new(egui_plot::PlotPoints::new(points))
+                    .width(1.)
+                    .color(col),
+            );
+        }
+    }
+
+    fn draw_entities(&self, plot_ui: &mut egui_plot::PlotUi) {
+        for sprite in self.extract.sprites.iter() {
+            let [cx, cy] = sprite.center;
+            plot_ui.polygon(
+                self.create_oriented_agent(cx, cy, sprite.heading)
+                    .fill_color(sprite.col)
+                    .stroke(egui::Stroke::new(1., sprite.col))
+                    .highlight(true),
+            );
+
+            if sprite.selected {
+                let pos = sprite.cell;
+                plot_ui.polygon(
+                    self.create_circle(pos.x as f64 + 0.5f64, pos.y as f64 + 0.5f64, 3f64)
+                        .name(sprite.name.clone()),
+                );
             }
         }
     }
@@ -608,11 +506,6 @@ impl DemoPanel {
 
 impl DemoPanel {
     fn update_markers(&mut self, ui: &egui::Ui) -> Vec<egui_plot::Points> {
-        self.base_points.clear();
-        self.waypoint_points.clear();
-        self.path_points.clear();
-        self.search_points.clear();
-        self.selected_points.clear();
         self.hovered_points.clear();
 
         let startx = self.grid.min.x as i32;
@@ -620,20 +513,13 @@ impl DemoPanel {
         let endx = startx + self.grid.width() as i32;
         let endy = starty + self.grid.height() as i32;
 
-        for ((x, y), covered) in &self.space_lut {
-            if *covered {
-                self.base_points
-                    .push([(*x as f64).floor() + 0.5f64, (*y as f64).floor() + 0.5f64]);
-            }
-        }
-
         let mut unique_positions = HashSet::new();
+        let mut path_points = Vec::new();
 
-        for path in self.current_paths.values() {
+        for path in self.extract.paths.iter() {
             for pos in path {
                 if unique_positions.insert(*pos) {
-                    self.path_points
-                        .push([pos.x as f64 + 0.5, pos.y as f64 + 0.5]);
+                    path_points.push([pos.x as f64 + 0.5, pos.y as f64 + 0.5]);
                 }
             }
         }
@@ -666,14 +552,14 @@ impl DemoPanel {
             .color(egui::Color32::from_rgba_unmultiplied(255, 0, 0, 255)) // Red color
             .shape(egui_plot::MarkerShape::Square);
 
-        let base_markers = egui_plot::Points::new(self.base_points.clone())
+        let base_markers = egui_plot::Points::new(self.extract.blocked.clone())
             .filled(true)
             .radius(self.marker_size)
             .highlight(true)
             .color(base_color)
             .shape(egui_plot::MarkerShape::Square);
 
-        let path_markers = egui_plot::Points::new(self.path_points.clone())
+        let path_markers = egui_plot::Points::new(path_points)
             .filled(true)
             .radius(self.marker_size)
             .highlight(true)
@@ -689,8 +575,6 @@ impl DemoPanel {
     }
 
     fn update_cursor_pos(&mut self, plot_ui: &egui_plot::PlotUi) -> (f64, f64) {
-        static mut PATH_PROMISE: Option<Promise<Option<Vec<Pos2>>>> = None;
-
         let mut x = f64::MIN;
         let mut y = f64::MIN;
         if let Some(point) = plot_ui.pointer_coordinate() {
@@ -709,49 +593,56 @@ impl DemoPanel {
                 }
             });
 
-            plot_ui.ctx().input(|ui| unsafe {
+            plot_ui.ctx().input(|ui| {
                 if ui.pointer.primary_clicked() {
-                    let entts = crate::ecs::entity::get_entities_from_xy(x, y);
-                    if entts.len() > 0 {
-                        if !ui.raw.modifiers.ctrl {
-                            crate::panel::scene_hierarchy_panel::unselect_all();
-                        }
-
-                        for e in entts.iter() {
-                            crate::panel::scene_hierarchy_panel::select((*e).get_id());
-                        }
-                    } else {
-                        self.navigate(x, y, ui);
-                    }
+                    self.clicks.push(Click {
+                        x,
+                        y,
+                        ctrl: ui.raw.modifiers.ctrl,
+                    });
                 }
             });
 
-            unsafe {
-                let selected = get_selected();
-                for s in selected.iter() {
-                    if let Some(path_promise) = self.path_map.get_mut(s) {
-                        if let Some(promise) = &mut path_promise.0 {
-                            if let Some(path_result) = promise.ready_mut() {
-                                if let Some(path) = path_result.take() {
-                                    self.current_paths.insert(*s, path);
-                                    self.path_map.remove(s);
-                                } else {
-                                    self.current_paths.remove(s);
-                                    self.path_map.remove(s);
-                                }
-                                PATH_PROMISE = None;
-                            }
+            plot_ui.ctx().input(|ui| {
+                if ui.pointer.primary_pressed() && ui.modifiers.shift {
+                    self.box_start = Some([point.x, point.y]);
+                }
+                if ui.pointer.primary_released() {
+                    if let Some(start) = self.box_start.take() {
+                        let area = Aabb::new(start, [point.x, point.y]);
+                        // smaller ones are clicks
+                        if area.max[0] - area.min[0] >= 1. || area.max[1] - area.min[1] >= 1. {
+                            self.box_selects.push(BoxSelect {
+                                area,
+                                ctrl: ui.raw.modifiers.ctrl,
+                            });
                         }
-                    } else {
-                        // Handle the case where the key is not found in path_map if needed.
                     }
                 }
-            }
+            });
         } else {
         }
         (x, y)
     }
 
+    fn draw_selection_box(&self, plot_ui: &mut egui_plot::PlotUi) {
+        let (Some(start), Some(end)) = (self.box_start, plot_ui.pointer_coordinate()) else {
+            return;
+        };
+        let area = Aabb::new(start, [end.x, end.y]);
+        let col = egui::Color32::from_rgba_unmultiplied(120, 170, 255, 40);
+        plot_ui.polygon(
+            egui_plot::Polygon::new(egui_plot::PlotPoints::new(vec![
+                area.min,
+                [area.max[0], area.min[1]],
+                area.max,
+                [area.min[0], area.max[1]],
+            ]))
+            .fill_color(col)
+            .stroke(egui::Stroke::new(1., egui::Color32::LIGHT_BLUE)),
+        );
+    }
+
     fn update_marker_size(&mut self, plot_ui: &egui_plot::PlotUi) {
         let dist = plot_ui
             .screen_from_plot(egui_plot::PlotPoint::new(0.0, 0.0))
@@ -760,89 +651,17 @@ impl DemoPanel {
         // 1.8 for overlap
         self.marker_size = dist / (100. * 2.2);
     }
-}
 
-impl DemoPanel {
-    fn navigate(&mut self, x: f64, y: f64, _ui: &egui::InputState) {
-        self.start.x = x as i64;
-        self.start.y = y as i64;
-
-        unsafe {
-            let selected = get_selected();
-            if selected.len() > 0 {
-                self.queued_points.push(self.start);
-            }
-
-            for s in selected.iter() {
-                if let Some(path_promise) = self.path_map.get_mut(s) {
-                    // handle the Option
-                    if path_promise.0.is_none() {
-                        // check the inner Option of PathPromise
-                        let e = get_entity_from_id(*s);
-                        let mut pos = pos2::Pos2::default();
-                        for c in e.components.iter() {
-                            match c {
-                                Component::Transform2(tc) => {
-                                    pos = tc.get().pos;
-                                }
-                                _ => {}
-                            }
-                        }
-
-                        path_promise.0 = self.navmesh.async_a_star(pos, self.start);
-
-                        log::info!(
-                            "{} ({}, {}) wants to go to ({}, {})",
-                            s,
-                            pos.x,
-                            pos.y,
-                            self.start.x,
-                            self.start.y
-                        );
-                    }
-                } else {
-                    let e = get_entity_from_id(*s);
-                    let mut pos = pos2::Pos2::default();
-                    for c in e.components.iter() {
-                        match c {
-                            Component::Transform2(tc) => {
-                                pos = tc.get().pos;
-                            }
-                            _ => {}
-                        }
-                    }
-
-                    log::info!("{}", self.queued_points.len());
-                    let some_path_promise = self
-                        .navmesh
-                        .async_waypointed_a_star(pos, self.queued_points.clone());
-
-                    log::info!(
-                        "{} ({}, {}) wants to go to ({}, {})",
-                        s,
-                        pos.x,
-                        pos.y,
-                        self.start.x,
-                        self.start.y
-                    );
-                    self.path_map
-                        .insert(e.get_id(), PathPromise(some_path_promise));
-                }
-            }
-            self.queued_points.clear();
+    fn draw_formation_slots(&self, plot_ui: &mut egui_plot::PlotUi) {
+        if self.extract.slot_points.is_empty() {
+            return;
         }
-    }
-}
-
-impl DemoPanel {
-    pub fn generate(&mut self) {
-        self.generate = true;
-    }
-    pub fn swap_to_a_star_stage(&mut self) {
-        self.space_lut.clear();
-        fill_lut_with_rectangle(&mut self.space_lut, 39., 37., 1., 12.); // mid
-        fill_lut_with_rectangle(&mut self.space_lut, 30., 40., 19., 0.); // bot
-        fill_lut_with_rectangle(&mut self.space_lut, 30., 49., 19., 0.); // top
-        self.navmesh.set_space_lut(self.space_lut.clone());
+        plot_ui.points(
+            egui_plot::Points::new(self.extract.slot_points.clone())
+                .filled(false)
+                .radius(self.marker_size * 0.6)
+                .color(egui::Color32::from_rgba_unmultiplied(255, 165, 0, 160))
+                .shape(egui_plot::MarkerShape::Circle),
+        );
     }
 }
@@ -1,10 +1,15 @@
+use crate::pathfinding::formation::FormationShape;
+use crate::pathfinding::replan::ReplanStrategy;
+use crate::pathfinding::sampling::{CollisionModel, SamplingAlgorithm};
 use crate::{
-    panel::demo_panel::EnvironmentSettings, panel::demo_panel::Generated,
-    panel::demo_panel::Obstacle, panel::demo_panel::Stage,
+    pathfinding::visibility_graph::GraphSearch, simulation::settings::EnvironmentSettings,
+    simulation::settings::Generated, simulation::settings::Obstacle, simulation::settings::Planner,
+    simulation::settings::Stage,
 };
 
 use super::Panel;
 use super::MAX_WRAP;
+use crate::ecs::world::World;
 
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)]
@@ -55,7 +60,7 @@ impl Default for DemoSettingsPanel {
 
 impl Panel for DemoSettingsPanel {
     #[allow(unused_variables)]
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame, world: &mut World) {
         let mut open = self.open;
         egui::Window::new("Demo Settings")
             .fixed_pos((
@@ -239,6 +244,213 @@ impl Panel for DemoSettingsPanel {
                         ui.group(|ui| {
                             ui.vertical(|ui| {
                                 ui.checkbox(&mut self.is_waypoint, "Show Path");
+                                ui.add(
+                                    egui::Slider::new(
+                                        &mut self.env_settings.sim_speed,
+                                        0f32..=4f32,
+                                    )
+                                    .text("simulation speed"),
+                                );
+                                ui.add(
+                                    egui::Slider::new(
+                                        &mut self.env_settings.orca.max_speed,
+                                        1f64..=20f64,
+                                    )
+                                    .text("speed"),
+                                );
+                                ui.checkbox(
+                                    &mut self.env_settings.local_avoidance,
+                                    "Local Avoidance",
+                                );
+                                ui.scope(|ui| {
+                                    ui.set_enabled(self.env_settings.local_avoidance);
+                                    ui.add(
+                                        egui::Slider::new(
+                                            &mut self.env_settings.orca.radius,
+                                            0.2f64..=1f64,
+                                        )
+                                        .text("agent radius"),
+                                    );
+                                });
+                                let formation = &mut self.env_settings.formation;
+                                egui::ComboBox::from_label("formation")
+                                    .selected_text(format!("{:?}", formation.shape))
+                                    .show_ui(ui, |ui| {
+                                        for shape in [
+                                            FormationShape::None,
+                                            FormationShape::Line,
+                                            FormationShape::Column,
+                                            FormationShape::Wedge,
+                                            FormationShape::Box,
+                                            FormationShape::Custom,
+                                        ] {
+                                            ui.selectable_value(
+                                                &mut formation.shape,
+                                                shape,
+                                                format!("{:?}", shape),
+                                            );
+                                        }
+                                    });
+                                ui.add(
+                                    egui::Slider::new(&mut formation.spacing, 1f64..=5f64)
+                                        .text("spacing")
+                                        .step_by(0.5),
+                                );
+                                let replan = &mut self.env_settings.replan;
+                                egui::ComboBox::from_label("when blocked")
+                                    .selected_text(format!("{:?}", replan.strategy))
+                                    .show_ui(ui, |ui| {
+                                        for strategy in [
+                                            ReplanStrategy::Full,
+                                            ReplanStrategy::LocalRepair,
+                                            ReplanStrategy::WaitAndRetry,
+                                        ] {
+                                            ui.selectable_value(
+                                                &mut replan.strategy,
+                                                strategy,
+                                                format!("{:?}", strategy),
+                                            );
+                                        }
+                                    });
+                                ui.add(
+                                    egui::Slider::new(&mut replan.lookahead, 1..=30)
+                                        .text("lookahead"),
+                                );
+                                ui.scope(|ui| {
+                                    ui.set_enabled(replan.strategy == ReplanStrategy::WaitAndRetry);
+                                    ui.add(
+                                        egui::Slider::new(&mut replan.retry_delay, 0.1f32..=5f32)
+                                            .text("retry delay"),
+                                    );
+                                    ui.add(
+                                        egui::Slider::new(&mut replan.max_retries, 0..=10)
+                                            .text("retries"),
+                                    );
+                                });
+                                ui.radio_value(
+                                    &mut self.env_settings.planner,
+                                    Planner::Grid,
+                                    "Grid A*",
+                                );
+                                ui.radio_value(
+                                    &mut self.env_settings.planner,
+                                    Planner::PolygonMesh,
+                                    "NavMesh",
+                                );
+                                ui.radio_value(
+                                    &mut self.env_settings.planner,
+                                    Planner::VisibilityGraph,
+                                    "Visibility Graph",
+                                );
+                                ui.scope(|ui| {
+                                    ui.set_enabled(
+                                        self.env_settings.planner == Planner::VisibilityGraph,
+                                    );
+                                    ui.horizontal(|ui| {
+                                        ui.radio_value(
+                                            &mut self.env_settings.graph_search,
+                                            GraphSearch::AStar,
+                                            "A*",
+                                        );
+                                        ui.radio_value(
+                                            &mut self.env_settings.graph_search,
+                                            GraphSearch::Dijkstra,
+                                            "Dijkstra",
+                                        );
+                                    });
+                                    ui.add(
+                                        egui::Slider::new(
+                                            &mut self.env_settings.agent_radius,
+                                            0f32..=3f32,
+                                        )
+                                        .text("radius")
+                                        .step_by(0.5),
+                                    );
+                                });
+                                ui.radio_value(
+                                    &mut self.env_settings.planner,
+                                    Planner::Sampling,
+                                    "Sampling",
+                                );
+                                ui.scope(|ui| {
+                                    ui.set_enabled(self.env_settings.planner == Planner::Sampling);
+                                    let sampling = &mut self.env_settings.sampling;
+                                    egui::ComboBox::from_label("algorithm")
+                                        .selected_text(format!("{:?}", sampling.algorithm))
+                                        .show_ui(ui, |ui| {
+                                            for algorithm in [
+                                                SamplingAlgorithm::Prm,
+                                                SamplingAlgorithm::Rrt,
+                                                SamplingAlgorithm::RrtStar,
+                                                SamplingAlgorithm::RrtConnect,
+                                            ] {
+                                                ui.selectable_value(
+                                                    &mut sampling.algorithm,
+                                                    algorithm,
+                                                    format!("{:?}", algorithm),
+                                                );
+                                            }
+                                        });
+                                    ui.horizontal(|ui| {
+                                        ui.radio_value(
+                                            &mut sampling.collision,
+                                            CollisionModel::Shapes,
+                                            "Shapes",
+                                        );
+                                        ui.radio_value(
+                                            &mut sampling.collision,
+                                            CollisionModel::SpaceLut,
+                                            "LUT",
+                                        );
+                                    });
+                                    ui.horizontal(|ui| {
+                                        ui.label("seed");
+                                        ui.add(egui::DragValue::new(&mut sampling.seed));
+                                    });
+                                });
+                                ui.radio_value(
+                                    &mut self.env_settings.planner,
+                                    Planner::HybridAStar,
+                                    "Hybrid A*",
+                                );
+                                ui.scope(|ui| {
+                                    ui.set_enabled(
+                                        self.env_settings.planner == Planner::HybridAStar,
+                                    );
+                                    let hybrid = &mut self.env_settings.hybrid;
+                                    ui.add(
+                                        egui::Slider::new(&mut hybrid.turning_radius, 1f64..=10f64)
+                                            .text("turning radius")
+                                            .step_by(0.5),
+                                    );
+                                    ui.checkbox(&mut hybrid.allow_reverse, "Reverse");
+                                });
+                                ui.radio_value(
+                                    &mut self.env_settings.planner,
+                                    Planner::Sipp,
+                                    "SIPP",
+                                );
+                                ui.scope(|ui| {
+                                    ui.set_enabled(self.env_settings.planner == Planner::Sipp);
+                                    ui.add(
+                                        egui::Slider::new(
+                                            &mut self.env_settings.sipp.tick,
+                                            0.05f64..=1f64,
+                                        )
+                                        .text("tick (s)"),
+                                    );
+                                });
+                                ui.checkbox(&mut self.env_settings.show_navmesh, "Show NavMesh");
+                                let influence = &mut self.env_settings.influence;
+                                ui.checkbox(&mut influence.show_heatmap, "Show Influence");
+                                ui.add(
+                                    egui::Slider::new(&mut influence.radius, 1f64..=20f64)
+                                        .text("influence radius"),
+                                );
+                                ui.add(
+                                    egui::Slider::new(&mut influence.decay, 0.5f64..=0.99f64)
+                                        .text("decay"),
+                                );
                             });
                         });
                     });
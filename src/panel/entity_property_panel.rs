@@ -1,9 +1,11 @@
 
 
 //use crate::ecs::component2::Component3;
-use crate::ecs::{component::*};
 
 use super::Panel;
+use crate::ecs::entity::EntityId;
+use crate::ecs::prefab::PrefabInstance;
+use crate::ecs::world::World;
 
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)]
@@ -32,7 +34,7 @@ impl Default for EntityPropertyPanel {
 
 impl Panel for EntityPropertyPanel {
     #[allow(unused_variables)]
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame, world: &mut World) {
         if self.open {
             egui::Window::new(
                 egui::RichText::new("Inspector").size(self.font_size * self.font_scale),
@@ -41,7 +43,7 @@ impl Panel for EntityPropertyPanel {
             .fixed_size(self.dimensions)
             .collapsible(false)
             .show(ctx, |ui| {
-                self.entity_property_ui(ui);
+                self.entity_property_ui(ui, world);
             });
         }
     }
@@ -50,83 +52,103 @@ impl Panel for EntityPropertyPanel {
 }
 
 impl EntityPropertyPanel {
-    pub fn entity_property_ui(&mut self, ui: &mut egui::Ui) {
-        unsafe {
-            ui.scope(|ui| {
-                let mut style = (*ui.ctx().style()).clone();
-                if let Some(text_style) = style.text_styles.get_mut(&egui::TextStyle::Button) {
-                    *text_style = egui::FontId::new(
-                        self.font_size * self.font_scale,
-                        egui::FontFamily::Proportional,
-                    );
+    pub fn entity_property_ui(&mut self, ui: &mut egui::Ui, world: &mut World) {
+        ui.scope(|ui| {
+            let mut style = (*ui.ctx().style()).clone();
+            if let Some(text_style) = style.text_styles.get_mut(&egui::TextStyle::Button) {
+                *text_style = egui::FontId::new(
+                    self.font_size * self.font_scale,
+                    egui::FontFamily::Proportional,
+                );
+            }
+            if let Some(text_style) = style.text_styles.get_mut(&egui::TextStyle::Body) {
+                *text_style = egui::FontId::new(
+                    self.font_size * self.font_scale,
+                    egui::FontFamily::Proportional,
+                );
+            }
+            ui.style_mut().text_styles = style.text_styles;
+            //egui::Resize::default().
+            let _layout = egui::Layout::left_to_right(egui::Align::Center)
+                .with_cross_align(egui::Align::Center)
+                .with_cross_justify(false)
+                .with_main_wrap(true)
+                .with_main_align(egui::Align::Center);
+            for id in world.selected() {
+                if id == world.root() {
+                    continue;
                 }
-                if let Some(text_style) = style.text_styles.get_mut(&egui::TextStyle::Body) {
-                    *text_style = egui::FontId::new(
-                        self.font_size * self.font_scale,
-                        egui::FontFamily::Proportional,
-                    );
-                }
-                ui.style_mut().text_styles = style.text_styles;
-                //egui::Resize::default().
-                let _layout = egui::Layout::left_to_right(egui::Align::Center)
-                    .with_cross_align(egui::Align::Center)
-                    .with_cross_justify(false)
-                    .with_main_wrap(true)
-                    .with_main_align(egui::Align::Center);
-                let selected = super::scene_hierarchy_panel::get_selected();
-                for entt in selected.iter() {
-                    if *entt != 0 {}
+                if let Some(e) = world.entity_mut(id) {
+                    let mut edata = e.data.get_ui_drawer();
+                    edata(ui);
                 }
+                self.parent_ui(ui, world, id);
+                self.prefab_ui(ui, world, id);
 
-                let mut selected_entities = crate::ecs::entity::get_selected_entities();
-                for e in selected_entities.iter_mut() {
-                    if e.get_id() != 0_usize {
-                        let mut edata = e.data.get_ui_drawer();
-                        edata(ui);
+                ui.separator();
 
-                        ui.separator();
+                for (type_id, name) in world.inspectable(id) {
+                    ui.vertical_centered(|ui| {
+                        ui.label(
+                            egui::RichText::new(name).size(self.font_size * self.font_scale * 0.8),
+                        )
+                    });
+                    world.inspect(type_id, id, ui);
+                    ui.separator();
+                }
 
-                        for c in &mut e.components {
-                            match c {
-                                Component::Transform2(tc) => {
-                                    //ui.vertical_centered(|ui| ui.label(tc.component_data.name.to_string()));
-                                    ui.vertical_centered(|ui| {
-                                        ui.label(
-                                            egui::RichText::new("Transform")
-                                                .size(self.font_size * self.font_scale * 0.8),
-                                        )
-                                    });
-                                    let mut drawer = tc.get_mut().get_ui_drawer();
-                                    drawer(ui);
-                                }
-                                Component::Color(cc) => {
-                                    ui.vertical_centered(|ui| {
-                                        ui.label(
-                                            egui::RichText::new("Color")
-                                                .size(self.font_size * self.font_scale * 0.8),
-                                        )
-                                    });
+                for (type_id, name) in world.addable(id) {
+                    if ui.button(format!("Add {}", name)).clicked() {
+                        world.add_default(type_id, id);
+                    }
+                }
+            }
+        });
+    }
 
-                                    let mut drawer = cc.get_mut().get_ui_drawer();
-                                    drawer(ui);
-                                }
-                                Component::Mesh(mc) => {
-                                    ui.vertical_centered(|ui| {
-                                        ui.label(
-                                            egui::RichText::new("Mesh")
-                                                .size(self.font_size * self.font_scale * 0.8),
-                                        )
-                                    });
+    /// Which prefab the entity was spawned from and the components it
+    /// overrides, to revert them or make them part of the prefab.
+    fn prefab_ui(&mut self, ui: &mut egui::Ui, world: &mut World, id: EntityId) {
+        let Some(instance) = world.get::<PrefabInstance>(id) else {
+            return;
+        };
+        ui.label(format!("prefab: {}", instance.prefab));
+        let overrides = world.overrides(id);
+        for name in overrides.iter() {
+            ui.horizontal(|ui| {
+                ui.label(format!("{} overridden", name));
+                if ui.button("revert").clicked() {
+                    world.revert(id, name);
+                }
+            });
+        }
+        if !overrides.is_empty() && ui.button("apply to prefab").clicked() {
+            world.apply_overrides(id);
+        }
+    }
 
-                                    let mut drawer = mc.get_mut().get_ui_drawer();
-                                    drawer(ui);
-                                }
-                            }
-                            ui.separator();
-                        }
+    /// Picks the entity to hang the selected one below, keeping where it is
+    /// in the world.
+    fn parent_ui(&mut self, ui: &mut egui::Ui, world: &mut World, id: EntityId) {
+        let name = |world: &World, id: Option<EntityId>| {
+            id.and_then(|id| world.entity(id))
+                .map_or(String::new(), |e| e.data.name.clone())
+        };
+        let current = world.parent(id);
+        let mut parent = current;
+        egui::ComboBox::new(ui.next_auto_id(), "parent")
+            .selected_text(name(world, current))
+            .show_ui(ui, |ui| {
+                for candidate in world.ids() {
+                    // an entity can't hang below itself
+                    if candidate == id || world.is_descendant(candidate, id) {
+                        continue;
                     }
+                    ui.selectable_value(&mut parent, Some(candidate), name(world, Some(candidate)));
                 }
             });
+        if let Some(parent) = parent.filter(|parent| Some(*parent) != current) {
+            world.set_parent(id, parent);
         }
     }
 }
@@ -5,10 +5,12 @@ pub mod entity_property_panel;
 pub mod panel;
 pub mod scene_hierarchy_panel;
 pub mod top_panel;
+use crate::ecs::world::World;
+
 const MAX_WRAP: f32 = 1000.0;
 
 pub trait Panel {
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame);
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame, world: &mut World);
     fn ui(&mut self, ui: &mut egui::Ui, frame: &mut eframe::Frame);
 }
 
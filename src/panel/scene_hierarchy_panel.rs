@@ -1,10 +1,13 @@
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::collections::{BTreeSet, HashMap};
+use std::rc::Rc;
 
-use crate::ecs::entity::{get_entity_from_id, Entity, ENTITY_MANAGER};
-use once_cell::sync::Lazy;
-static mut ENTITY_HIERARCHY_OPEN: Lazy<HashMap<usize, bool>> = Lazy::new(|| HashMap::new());
-static mut ENTITY_HIERARCHY_SELECTED: Lazy<HashMap<usize, bool>> = Lazy::new(|| HashMap::new());
+use crate::ecs::entity::{Entity, EntityId};
+use crate::ecs::event::{
+    EntityDespawned, EntitySpawned, EventReader, PathCompleted, PathFailed, SelectionChanged,
+};
 
+#[derive(Clone, Copy)]
 pub enum SelectionEvent {
     Add,
     Change,
@@ -44,54 +47,77 @@ pub enum Action {
     Delete,
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
+/// Draws the entities below the root as they hang below each other through
+/// their `Parent` and `Children` components. All it keeps of its own is
+/// which entities are collapsed and how their last paths went.
+#[derive(Default, serde::Deserialize, serde::Serialize)]
 #[serde(default)]
 pub struct Tree {
     #[serde(skip)]
-    nodes: Vec<Tree>,
+    collapsed: BTreeSet<EntityId>,
+    /// Whether the last path of each entity was walked to the end.
     #[serde(skip)]
-    pub open: bool,
+    path_results: HashMap<EntityId, bool>,
+    #[serde(skip)]
+    spawned: EventReader<EntitySpawned>,
+    #[serde(skip)]
+    despawned: EventReader<EntityDespawned>,
+    #[serde(skip)]
+    selection: EventReader<SelectionChanged>,
     #[serde(skip)]
-    pub id: usize,
+    completed: EventReader<PathCompleted>,
     #[serde(skip)]
-    pub name: String,
+    failed: EventReader<PathFailed>,
 }
 
-impl Default for Tree {
-    fn default() -> Self {
-        let e = Entity::default();
-        let id = e.get_id();
-        unsafe {
-            ENTITY_HIERARCHY_OPEN.insert(id, true);
-            ENTITY_HIERARCHY_SELECTED.insert(id, false);
-            ENTITY_MANAGER.insert(id, e);
+impl Tree {
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        world: &mut World,
+        font_size: f32,
+        font_scale: f32,
+    ) -> Action {
+        self.handle_events(world);
+        let root = world.root();
+        self.ui_impl(root, ui, world, 0, font_size * font_scale)
+    }
+
+    /// Opens up the tree down to new and selected entities and forgets
+    /// despawned ones.
+    fn handle_events(&mut self, world: &World) {
+        let mut shown: Vec<EntityId> = self.spawned.read(world).map(|e| e.0).collect();
+        if self.selection.read(world).count() > 0 {
+            shown.extend(world.selected());
         }
-        let name = if id == 0_usize {
-            "Scene".to_string()
-        } else {
-            format!("Entity {id}")
-        };
-        Self {
-            nodes: Vec::default(),
-            open: true,
-            id: id,
-            name: name,
+        for id in shown {
+            let mut current = world.parent(id);
+            while let Some(parent) = current {
+                self.collapsed.remove(&parent);
+                current = world.parent(parent);
+            }
         }
-    }
-}
 
-impl Tree {
-    pub fn ui(&mut self, ui: &mut egui::Ui, font_size: f32, font_scale: f32) -> Action {
-        self.ui_impl(ui, 0, "Hierarchy", font_size, font_scale)
+        for event in self.completed.read(world) {
+            self.path_results.insert(event.0, true);
+        }
+        for event in self.failed.read(world) {
+            self.path_results.insert(event.0, false);
+        }
+
+        for event in self.despawned.read(world) {
+            self.collapsed.remove(&event.0);
+            self.path_results.remove(&event.0);
+        }
     }
 
     fn ui_impl(
         &mut self,
+        id: EntityId,
         ui: &mut egui::Ui,
+        world: &mut World,
         depth: usize,
-        _name: &str,
-        font_size: f32,
-        font_scale: f32,
+        text_size: f32,
     ) -> Action {
         let cursor_pos = ui.cursor().left_top();
 
@@ -106,31 +132,28 @@ impl Tree {
             },
         };
 
-        let mut name_str = self.name.clone();
-        if self.id != 0_usize {
-            unsafe {
-                name_str = get_entity_from_id(self.id).data.name.clone();
-            }
+        let mut name_str = world
+            .entity(id)
+            .map_or(format!("Entity {id}"), |e| e.data.name.clone());
+        match self.path_results.get(&id) {
+            Some(true) => name_str.push_str(" ✔"),
+            Some(false) => name_str.push_str(" ✖"),
+            None => {}
         }
-        let eid = self.id;
-
-        let act = egui::CollapsingHeader::new(egui::RichText::new("").size(font_size * font_scale))
-            .id_source(self.id)
-            .default_open(self.open)
-            .open(Some(self.open))
+        let open = !self.collapsed.contains(&id);
+        let selected = world.is_selected(id);
+        // the icon closure has to be 'static, so the event is applied after
+        let selection = Rc::new(Cell::new(None));
+        let selection_event = selection.clone();
+
+        let act = egui::CollapsingHeader::new(egui::RichText::new("").size(text_size))
+            .id_source(id)
+            .default_open(open)
+            .open(Some(open))
             .icon(move |ui, openness, response| {
                 egui::collapsing_header::paint_default_icon(ui, openness, &response);
                 if response.clicked() {
-                    unsafe {
-                        if let Some(state) = ENTITY_HIERARCHY_OPEN.get_mut(&eid) {
-                            *state = !*state;
-                        }
-                    }
-                }
-                let mut selected = false;
-                // use move keyword
-                unsafe {
-                    selected = *ENTITY_HIERARCHY_SELECTED.get(&eid).unwrap();
+                    selection_event.set(Some(SelectionEvent::ToggleCollapse));
                 }
 
                 let id = ui.next_auto_id();
@@ -140,39 +163,34 @@ impl Tree {
                         label_rect,
                         egui::SelectableLabel::new(
                             selected,
-                            egui::RichText::new(&name_str).size(font_size * font_scale),
+                            egui::RichText::new(&name_str).size(text_size),
                         ),
                     )
                 }) {
-                    Some(event) => match event {
-                        SelectionEvent::Add => unsafe {
-                            select(eid);
-                        },
-                        SelectionEvent::Change => unsafe {
-                            let state = query_select(eid);
-                            unselect_all();
-                            set_select(eid, !state)
-                        },
-                        SelectionEvent::ToggleCollapse => unsafe {
-                            if let Some(state) = ENTITY_HIERARCHY_OPEN.get_mut(&eid) {
-                                *state = !*state;
-                            }
-                        },
-                        SelectionEvent::Hovered => ui.ctx().highlight_widget(id),
-                    },
+                    Some(SelectionEvent::Hovered) => ui.ctx().highlight_widget(id),
+                    Some(event) => selection_event.set(Some(event)),
                     _ => {}
                 }
             })
             .show(ui, |ui| {
-                self.children_ui(ui, depth, cursor_pos, font_size, font_scale)
+                self.children_ui(id, ui, world, depth, cursor_pos, text_size)
             })
             .body_returned
             .unwrap_or(Action::Keep);
 
-        unsafe {
-            if let Some(state) = ENTITY_HIERARCHY_OPEN.get_mut(&eid) {
-                self.open = *state;
+        match selection.get() {
+            Some(SelectionEvent::Add) => world.select(id),
+            Some(SelectionEvent::Change) => {
+                world.unselect_all();
+                world.set_selected(id, !selected);
+            }
+            Some(SelectionEvent::ToggleCollapse) if open => {
+                self.collapsed.insert(id);
+            }
+            Some(SelectionEvent::ToggleCollapse) => {
+                self.collapsed.remove(&id);
             }
+            _ => {}
         }
 
         act
@@ -180,11 +198,12 @@ impl Tree {
 
     fn children_ui(
         &mut self,
+        id: EntityId,
         ui: &mut egui::Ui,
+        world: &mut World,
         depth: usize,
         cursor_pos: egui::Pos2,
-        font_size: f32,
-        font_scale: f32,
+        text_size: f32,
     ) -> Action {
         let close_rect = egui::Rect {
             min: egui::Pos2 {
@@ -218,34 +237,41 @@ impl Tree {
             );
         }
 
-        self.nodes = std::mem::take(&mut self.nodes)
-            .into_iter()
-            .enumerate()
-            .filter_map(|(i, mut tree)| {
-                if tree.ui_impl(
-                    ui,
-                    depth + 1,
-                    &format!("Entity #{i}"),
-                    font_size,
-                    font_scale,
-                ) == Action::Keep
-                {
-                    Some(tree)
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        if ui.button("+").clicked() {
-            self.nodes.push(Tree::default());
+        for child in world.children(id) {
+            if self.ui_impl(child, ui, world, depth + 1, text_size) == Action::Delete {
+                // takes the entities of the whole subtree along
+                world.despawn(child);
+            }
         }
 
+        ui.menu_button("+", |ui| {
+            let names: Vec<String> = world.prefabs().iter().map(|p| p.name.clone()).collect();
+            for name in names {
+                if ui.button(&name).clicked() {
+                    world.instantiate(&name, id);
+                    ui.close_menu();
+                }
+            }
+            if ui.button("Empty").clicked() {
+                world.spawn_child(id, Entity::default());
+                ui.close_menu();
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                ui.separator();
+                if ui.button("Reload prefabs").clicked() {
+                    world.reload_prefabs();
+                    ui.close_menu();
+                }
+            }
+        });
+
         Action::Keep
     }
 }
 
 use super::Panel;
+use crate::ecs::world::World;
 
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)]
@@ -281,7 +307,7 @@ impl Default for SceneHierarchyPanel {
 
 impl Panel for SceneHierarchyPanel {
     #[allow(unused_variables)]
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame, world: &mut World) {
         if self.open {
             egui::Window::new(
                 egui::RichText::new("Hierarchy").size(self.font_size * self.font_scale),
@@ -290,7 +316,7 @@ impl Panel for SceneHierarchyPanel {
             .fixed_size(self.dimensions)
             .collapsible(false)
             .show(ctx, |ui| {
-                self.scene_hierarchy_ui(ui);
+                self.scene_hierarchy_ui(ui, world);
             });
 
             if self.first_frame {
@@ -303,64 +329,8 @@ impl Panel for SceneHierarchyPanel {
 }
 
 impl SceneHierarchyPanel {
-    fn scene_hierarchy_ui(&mut self, ui: &mut egui::Ui) {
-        self.scene_hierarchy.ui(ui, self.font_size, self.font_scale);
-    }
-}
-
-unsafe fn toggle_collapse(id: usize) {
-    if let Some(state) = ENTITY_HIERARCHY_OPEN.get_mut(&id) {
-        *state = !*state;
-    }
-}
-
-pub unsafe fn toggle_select(id: usize) {
-    if let Some(state) = ENTITY_HIERARCHY_SELECTED.get_mut(&id) {
-        *state = !*state;
+    fn scene_hierarchy_ui(&mut self, ui: &mut egui::Ui, world: &mut World) {
+        self.scene_hierarchy
+            .ui(ui, world, self.font_size, self.font_scale);
     }
 }
-
-unsafe fn set_select(id: usize, state: bool) {
-    if let Some(state_) = ENTITY_HIERARCHY_SELECTED.get_mut(&id) {
-        *state_ = state;
-    }
-}
-
-pub unsafe fn select(id: usize) {
-    set_select(id, true);
-}
-
-unsafe fn unselect(id: usize) {
-    set_select(id, false);
-}
-
-unsafe fn select_all() {
-    for (_key, val) in ENTITY_HIERARCHY_SELECTED.iter_mut() {
-        *val = true;
-    }
-}
-
-pub unsafe fn unselect_all() {
-    for (_key, val) in ENTITY_HIERARCHY_SELECTED.iter_mut() {
-        *val = false;
-    }
-}
-
-pub unsafe fn get_selected() -> Vec<usize> {
-    let mut selected = Vec::new();
-    for (key, val) in ENTITY_HIERARCHY_SELECTED.iter_mut() {
-        if *val {
-            selected.push(*key);
-        }
-    }
-    selected
-}
-
-pub unsafe fn is_selected(id: usize) -> bool {
-    let selected = get_selected();
-    selected.contains(&id)
-}
-
-unsafe fn query_select(id: usize) -> bool {
-    *ENTITY_HIERARCHY_SELECTED.get(&id).unwrap()
-}
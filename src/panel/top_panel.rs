@@ -1,4 +1,5 @@
 use super::Panel;
+use crate::ecs::world::World;
 use super::MAX_WRAP;
 use super::fixed_demo_label;
 
@@ -69,7 +70,7 @@ impl Default for TopPanel {
 
 impl Panel for TopPanel {
     #[allow(unused_variables)]
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame, world: &mut World) {
         egui::TopBottomPanel::top("top_panel")
             .exact_height(self.calc_button_height())
             .show(ctx, |ui| {
@@ -1,12 +1,73 @@
 extern crate proc_macro;
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{parse_macro_input, DeriveInput, Fields};
+use syn::parse::ParseStream;
+use syn::{parse_macro_input, Attribute, DeriveInput, Fields, Ident, LitInt, Path, Token};
 
-#[proc_macro_derive(GenerateUI)]
+/// What the `#[ui(...)]` attributes of a struct or field ask for.
+#[derive(Default)]
+struct UiArgs {
+    /// `angle`: an `f32` in radians, shown as degrees.
+    angle: bool,
+    /// `choices`: an enum picked from its associated `ALL`.
+    choices: bool,
+    /// `points`: a list of points, each one editable and removable.
+    points: bool,
+    /// `clearable`: a `String` that may be left empty, others keep their
+    /// last text when cleared.
+    clearable: bool,
+    /// `range = 0..=100`: the bounds the points are kept in.
+    range: Option<(LitInt, LitInt)>,
+    /// `inspect = path::to::Inspect`: the trait implemented with the drawer.
+    inspect: Option<Path>,
+}
+
+impl UiArgs {
+    fn from_attrs(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut args = Self::default();
+        for attr in attrs.iter().filter(|a| a.path.is_ident("ui")) {
+            attr.parse_args_with(|input: ParseStream| {
+                while !input.is_empty() {
+                    let key: Ident = input.parse()?;
+                    match key.to_string().as_str() {
+                        "angle" => args.angle = true,
+                        "choices" => args.choices = true,
+                        "points" => args.points = true,
+                        "clearable" => args.clearable = true,
+                        "range" => {
+                            input.parse::<Token![=]>()?;
+                            let min = input.parse()?;
+                            input.parse::<Token![..=]>()?;
+                            args.range = Some((min, input.parse()?));
+                        }
+                        "inspect" => {
+                            input.parse::<Token![=]>()?;
+                            args.inspect = Some(input.parse()?);
+                        }
+                        _ => return Err(syn::Error::new(key.span(), "unknown ui attribute")),
+                    }
+                    if !input.is_empty() {
+                        input.parse::<Token![,]>()?;
+                    }
+                }
+                Ok(())
+            })?;
+        }
+        Ok(args)
+    }
+}
+
+/// Derives `get_ui_drawer`, and the trait named by `#[ui(inspect = ...)]`
+/// on top of it. Fields are drawn by their type unless a `#[ui(...)]`
+/// attribute says otherwise.
+#[proc_macro_derive(GenerateUI, attributes(ui))]
 pub fn generate_property_ui(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let name = &input.ident;
+    let struct_args = match UiArgs::from_attrs(&input.attrs) {
+        Ok(args) => args,
+        Err(e) => return e.to_compile_error().into(),
+    };
 
     let ui_code = match &input.data {
         syn::Data::Struct(s) => {
@@ -15,16 +76,81 @@ pub fn generate_property_ui(input: TokenStream) -> TokenStream {
                     fields.named.iter().map(|f| {
                         let field_name = &f.ident;
                         let field_type = &f.ty;
-                        if format!("{}", quote!(#field_type)) == format!("{}", quote!(f32)) {
+                        let args = UiArgs::from_attrs(&f.attrs)?;
+                        let code = if args.angle {
+                            quote! {
+                                let mut val = self.#field_name;
+                                ui.horizontal(|ui| {
+                                    ui.label(stringify!(#field_name));
+                                    ui.drag_angle(&mut val);
+                                });
+                                if self.#field_name != val {
+                                    log::info!("{} has changed from {} to {}", stringify!(#field_name), self.#field_name, val);
+                                    changed = true;
+                                }
+                                self.#field_name = val;
+                            }
+                        }
+                        // enums list their variants in an associated `ALL`
+                        else if args.choices {
+                            quote! {
+                                egui::ComboBox::new(ui.next_auto_id(), stringify!(#field_name))
+                                    .selected_text(format!("{:?}", self.#field_name))
+                                    .show_ui(ui, |ui| {
+                                        for mode in <#field_type>::ALL {
+                                            changed |= ui.selectable_value(&mut self.#field_name, mode, format!("{:?}", mode)).changed();
+                                        }
+                                    });
+                            }
+                        }
+                        else if args.points {
+                            let drag = match &args.range {
+                                Some((min, max)) => quote!(clamp_range(#min..=#max)),
+                                None => quote!(speed(0.1)),
+                            };
+                            quote! {
+                                ui.label(stringify!(#field_name));
+                                let mut remove = None;
+                                for (i, p) in self.#field_name.iter_mut().enumerate() {
+                                    ui.horizontal(|ui| {
+                                        ui.label(format!("{}", i + 1));
+                                        changed |= ui.add(egui::DragValue::new(&mut p.x).#drag.prefix("x: ")).changed();
+                                        changed |= ui.add(egui::DragValue::new(&mut p.y).#drag.prefix("y: ")).changed();
+                                        if ui.small_button("-").clicked() {
+                                            remove = Some(i);
+                                        }
+                                    });
+                                }
+                                if let Some(i) = remove {
+                                    log::info!("{} lost point {}", stringify!(#field_name), i + 1);
+                                    self.#field_name.remove(i);
+                                    changed = true;
+                                }
+                                if ui.small_button("+").clicked() {
+                                    let p = self.#field_name.last().copied().unwrap_or_default();
+                                    self.#field_name.push(p);
+                                    changed = true;
+                                }
+                            }
+                        }
+                        else if format!("{}", quote!(#field_type)) == format!("{}", quote!(f32)) {
                             quote! {
                                 let mut val = self.#field_name;
                                 ui.add(egui::Slider::new(&mut val, 0.0..=100.0).text(stringify!(#field_name)).integer());
                                 if self.#field_name != val {
                                     log::info!("{} has changed from {} to {}", stringify!(#field_name), self.#field_name, val);
+                                    changed = true;
                                 }
                                 self.#field_name = val;
                             }
                         } else if format!("{}", quote!(#field_type)) == format!("{}", quote!(String)) {
+                            let mut apply = quote! {
+                                changed |= self.#field_name != s;
+                                self.#field_name = s;
+                            };
+                            if !args.clearable {
+                                apply = quote!(if !s.is_empty() { #apply });
+                            }
                             quote! {
                                 let mut s = self.#field_name.clone();
                                 ui.horizontal(|ui| {
@@ -34,9 +160,7 @@ pub fn generate_property_ui(input: TokenStream) -> TokenStream {
                                         .cursor_at_end(true)
                                         .hint_text(stringify!(#field_name)));
                                 });
-                                if s != "".to_string() {
-                                    self.#field_name = s;
-                                } 
+                                #apply
                             }
                         } else if format!("{}", quote!(#field_type)) == format!("{}", quote!(Pos2)) {
                             quote! {
@@ -55,41 +179,73 @@ pub fn generate_property_ui(input: TokenStream) -> TokenStream {
                                             if self.#field_name.x != val_x {
                                                 log::info!("{} has changed from {} to {}", stringify!(#field_name.x), self.#field_name.x, val_x);
                                                 self.#field_name.x = val_x;
+                                                changed = true;
                                             }
                                             if self.#field_name.y != val_y {
                                                 log::info!("{} has changed from {} to {}", stringify!(#field_name.y), self.#field_name.y, val_y);
                                                 self.#field_name.y = val_y;
+                                                changed = true;
                                             }
                                         }));
                                 //});
                             }
                         }
+                        else if format!("{}", quote!(#field_type)) == format!("{}", quote!(egui::Vec2)) {
+                            quote! {
+                                ui.horizontal(|ui| {
+                                    ui.label(stringify!(#field_name));
+                                    changed |= ui.add(egui::DragValue::new(&mut self.#field_name.x).speed(0.1).prefix("x: ")).changed();
+                                    changed |= ui.add(egui::DragValue::new(&mut self.#field_name.y).speed(0.1).prefix("y: ")).changed();
+                                });
+                            }
+                        }
                         else if format!("{}", quote!(#field_type)) == format!("{}", quote!(egui::Color32)) {
                             quote! {
                                 let mut val = self.#field_name;
                                 ui.color_edit_button_srgba(&mut val);
+                                changed |= self.#field_name != val;
                                 self.#field_name = val;
                             }
                         }
                         else {
                             quote!()
-                        }
-                    }).collect::<Vec<_>>()
+                        };
+                        Ok(code)
+                    }).collect::<syn::Result<Vec<_>>>()
                 }
-                _ => vec![],
+                _ => Ok(vec![]),
             }
         }
-        _ => vec![],
+        _ => Ok(vec![]),
     };
+    let ui_code = match ui_code {
+        Ok(code) => code,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let inspect = struct_args.inspect.map(|path| {
+        quote! {
+            impl #path for #name {
+                fn inspect(&mut self, ui: &mut egui::Ui) -> bool {
+                    let mut drawer = self.get_ui_drawer();
+                    drawer(ui)
+                }
+            }
+        }
+    });
 
     let expanded = quote! {
         impl #name {
-            pub fn get_ui_drawer(&mut self) -> Box<dyn FnMut(&mut egui::Ui) + '_> {
+            /// The drawer returns whether any field was edited.
+            pub fn get_ui_drawer(&mut self) -> Box<dyn FnMut(&mut egui::Ui) -> bool + '_> {
                 Box::new(move |ui: &mut egui::Ui| {
+                    let mut changed = false;
                     #(#ui_code)*
+                    changed
                 })
             }
         }
+
+        #inspect
     };
 
     expanded.into()
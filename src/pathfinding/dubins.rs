@@ -0,0 +1,255 @@
+use std::f64::consts::TAU;
+
+/// `[x, y, heading]`, heading in radians counter-clockwise from +x.
+pub type Pose = [f64; 3];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Steer {
+    Left,
+    Straight,
+    Right,
+}
+
+/// One piece of a curve. Negative lengths are driven in reverse.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Segment {
+    pub steer: Steer,
+    pub length: f64,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Curve {
+    pub start: Pose,
+    pub radius: f64,
+    pub segments: Vec<Segment>,
+}
+
+impl Curve {
+    pub fn length(&self) -> f64 {
+        self.segments.iter().map(|s| s.length.abs()).sum()
+    }
+
+    /// Poses along the curve roughly every `step`, including both ends.
+    pub fn sample(&self, step: f64) -> Vec<Pose> {
+        let mut poses = vec![self.start];
+        let mut pose = self.start;
+        for s in self.segments.iter() {
+            let n = (s.length.abs() / step).ceil() as usize;
+            for i in 1..=n {
+                poses.push(advance(
+                    pose,
+                    s.steer,
+                    self.radius,
+                    s.length * i as f64 / n as f64,
+                ));
+            }
+            pose = advance(pose, s.steer, self.radius, s.length);
+        }
+        poses
+    }
+}
+
+pub fn mod2pi(a: f64) -> f64 {
+    a.rem_euclid(TAU)
+}
+
+/// Drives `length` (negative for reverse) from `pose` with a fixed steering.
+pub fn advance(pose: Pose, steer: Steer, radius: f64, length: f64) -> Pose {
+    let [x, y, h] = pose;
+    match steer {
+        Steer::Straight => [x + length * h.cos(), y + length * h.sin(), h],
+        Steer::Left => {
+            let turn = length / radius;
+            [
+                x + radius * ((h + turn).sin() - h.sin()),
+                y + radius * (h.cos() - (h + turn).cos()),
+                mod2pi(h + turn),
+            ]
+        }
+        Steer::Right => {
+            let turn = length / radius;
+            [
+                x + radius * (h.sin() - (h - turn).sin()),
+                y + radius * ((h - turn).cos() - h.cos()),
+                mod2pi(h - turn),
+            ]
+        }
+    }
+}
+
+/// Shortest forward-only path between two poses for a car with a minimum
+/// turning radius (the six Dubins words).
+pub fn dubins(start: Pose, end: Pose, radius: f64) -> Option<Curve> {
+    let (dx, dy) = (end[0] - start[0], end[1] - start[1]);
+    let d = (dx * dx + dy * dy).sqrt() / radius;
+    let theta = mod2pi(dy.atan2(dx));
+    let alpha = mod2pi(start[2] - theta);
+    let beta = mod2pi(end[2] - theta);
+
+    use Steer::*;
+    [
+        ([Left, Straight, Left], lsl(alpha, beta, d)),
+        ([Right, Straight, Right], rsr(alpha, beta, d)),
+        ([Left, Straight, Right], lsr(alpha, beta, d)),
+        ([Right, Straight, Left], rsl(alpha, beta, d)),
+        ([Right, Left, Right], rlr(alpha, beta, d)),
+        ([Left, Right, Left], lrl(alpha, beta, d)),
+    ]
+    .into_iter()
+    .filter_map(|(word, lengths)| lengths.map(|l| (word, l)))
+    .min_by(|a, b| (a.1.iter().sum::<f64>()).total_cmp(&b.1.iter().sum::<f64>()))
+    .map(|(word, lengths)| Curve {
+        start,
+        radius,
+        segments: (0..3)
+            .map(|i| Segment {
+                steer: word[i],
+                length: lengths[i] * radius,
+            })
+            .collect(),
+    })
+}
+
+/// Reeds-Shepp curves restricted to the words that don't change direction
+/// halfway: the shorter of the forward Dubins path and the Dubins path driven
+/// entirely in reverse.
+pub fn reeds_shepp(start: Pose, end: Pose, radius: f64) -> Option<Curve> {
+    // driving a forward path from `end` to `start` backwards gets us from `start` to `end`
+    let reverse = dubins(end, start, radius).map(|c| Curve {
+        start,
+        radius,
+        segments: c
+            .segments
+            .iter()
+            .rev()
+            .map(|s| Segment {
+                steer: s.steer,
+                length: -s.length,
+            })
+            .collect(),
+    });
+    match (dubins(start, end, radius), reverse) {
+        (Some(f), Some(r)) if r.length() < f.length() => Some(r),
+        (Some(f), _) => Some(f),
+        (None, r) => r,
+    }
+}
+
+fn lsl(alpha: f64, beta: f64, d: f64) -> Option<[f64; 3]> {
+    let (sa, sb, ca, cb) = (alpha.sin(), beta.sin(), alpha.cos(), beta.cos());
+    let p_sq = 2. + d * d - 2. * (alpha - beta).cos() + 2. * d * (sa - sb);
+    if p_sq < 0. {
+        return None;
+    }
+    let tmp = (cb - ca).atan2(d + sa - sb);
+    Some([mod2pi(tmp - alpha), p_sq.sqrt(), mod2pi(beta - tmp)])
+}
+
+fn rsr(alpha: f64, beta: f64, d: f64) -> Option<[f64; 3]> {
+    let (sa, sb, ca, cb) = (alpha.sin(), beta.sin(), alpha.cos(), beta.cos());
+    let p_sq = 2. + d * d - 2. * (alpha - beta).cos() + 2. * d * (sb - sa);
+    if p_sq < 0. {
+        return None;
+    }
+    let tmp = (ca - cb).atan2(d - sa + sb);
+    Some([mod2pi(alpha - tmp), p_sq.sqrt(), mod2pi(tmp - beta)])
+}
+
+fn lsr(alpha: f64, beta: f64, d: f64) -> Option<[f64; 3]> {
+    let (sa, sb, ca, cb) = (alpha.sin(), beta.sin(), alpha.cos(), beta.cos());
+    let p_sq = -2. + d * d + 2. * (alpha - beta).cos() + 2. * d * (sa + sb);
+    if p_sq < 0. {
+        return None;
+    }
+    let p = p_sq.sqrt();
+    let tmp = (-ca - cb).atan2(d + sa + sb) - (-2f64).atan2(p);
+    Some([mod2pi(tmp - alpha), p, mod2pi(tmp - beta)])
+}
+
+fn rsl(alpha: f64, beta: f64, d: f64) -> Option<[f64; 3]> {
+    let (sa, sb, ca, cb) = (alpha.sin(), beta.sin(), alpha.cos(), beta.cos());
+    let p_sq = -2. + d * d + 2. * (alpha - beta).cos() - 2. * d * (sa + sb);
+    if p_sq < 0. {
+        return None;
+    }
+    let p = p_sq.sqrt();
+    let tmp = (ca + cb).atan2(d - sa - sb) - 2f64.atan2(p);
+    Some([mod2pi(alpha - tmp), p, mod2pi(beta - tmp)])
+}
+
+fn rlr(alpha: f64, beta: f64, d: f64) -> Option<[f64; 3]> {
+    let (sa, sb, ca, cb) = (alpha.sin(), beta.sin(), alpha.cos(), beta.cos());
+    let tmp = (6. - d * d + 2. * (alpha - beta).cos() + 2. * d * (sa - sb)) / 8.;
+    if tmp.abs() > 1. {
+        return None;
+    }
+    let phi = (ca - cb).atan2(d - sa + sb);
+    let p = mod2pi(TAU - tmp.acos());
+    let t = mod2pi(alpha - phi + mod2pi(p / 2.));
+    Some([t, p, mod2pi(alpha - beta - t + mod2pi(p))])
+}
+
+fn lrl(alpha: f64, beta: f64, d: f64) -> Option<[f64; 3]> {
+    let (sa, sb, ca, cb) = (alpha.sin(), beta.sin(), alpha.cos(), beta.cos());
+    let tmp = (6. - d * d + 2. * (alpha - beta).cos() + 2. * d * (sb - sa)) / 8.;
+    if tmp.abs() > 1. {
+        return None;
+    }
+    let phi = (ca - cb).atan2(d + sa - sb);
+    let p = mod2pi(TAU - tmp.acos());
+    let t = mod2pi(-alpha - phi + p / 2.);
+    Some([t, p, mod2pi(mod2pi(beta) - alpha - t + mod2pi(p))])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn end_of(curve: &Curve) -> Pose {
+        curve.segments.iter().fold(curve.start, |pose, s| {
+            advance(pose, s.steer, curve.radius, s.length)
+        })
+    }
+
+    fn assert_same_pose(a: Pose, b: Pose) {
+        let heading = mod2pi(a[2] - b[2]);
+        assert!(
+            (a[0] - b[0]).abs() < 1e-6
+                && (a[1] - b[1]).abs() < 1e-6
+                && heading.min(TAU - heading) < 1e-6,
+            "{:?} is not {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn curves_end_on_the_goal_pose() {
+        let start = [2., 3., 0.5];
+        for end in [
+            [10., 3., 0.],
+            [2., 3., std::f64::consts::PI],
+            [-4., 8., 4.],
+            [3., 2., 2.],
+        ] {
+            let forward = dubins(start, end, 2.).unwrap();
+            assert_same_pose(end_of(&forward), end);
+            assert!(forward.segments.iter().all(|s| s.length >= 0.));
+            assert_same_pose(*forward.sample(0.1).last().unwrap(), end);
+
+            let either = reeds_shepp(start, end, 2.).unwrap();
+            assert_same_pose(end_of(&either), end);
+            assert!(either.length() <= forward.length() + 1e-9);
+        }
+    }
+
+    #[test]
+    fn straight_ahead_is_a_straight_line() {
+        let curve = dubins([0., 0., 0.], [10., 0., 0.], 3.).unwrap();
+        assert!((curve.length() - 10.).abs() < 1e-9);
+        // behind it, driving back beats going round
+        let back = reeds_shepp([10., 0., 0.], [0., 0., 0.], 3.).unwrap();
+        assert!((back.length() - 10.).abs() < 1e-9);
+        assert!(back.segments.iter().all(|s| s.length <= 0.));
+    }
+}
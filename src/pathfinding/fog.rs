@@ -0,0 +1,184 @@
+use crate::ecs::pos2::Pos2;
+use std::collections::{HashMap, HashSet};
+
+/// Transforms from the first octant to each of the eight: (xx, xy, yx, yy).
+const OCTANTS: [[i64; 4]; 8] = [
+    [1, 0, 0, 1],
+    [0, 1, 1, 0],
+    [0, -1, 1, 0],
+    [-1, 0, 0, 1],
+    [-1, 0, 0, -1],
+    [0, -1, -1, 0],
+    [0, 1, -1, 0],
+    [1, 0, 0, -1],
+];
+
+/// Cells visible from `origin` within `radius`, by recursive shadow casting.
+/// Opaque cells are visible themselves but hide what is behind them.
+pub fn shadow_cast(origin: Pos2, radius: i64, is_opaque: impl Fn(&Pos2) -> bool) -> HashSet<Pos2> {
+    let mut caster = ShadowCaster {
+        origin,
+        radius,
+        is_opaque,
+        visible: HashSet::from([origin]),
+    };
+    for octant in OCTANTS.iter() {
+        caster.cast_light(1, 1., 0., octant);
+    }
+    caster.visible
+}
+
+struct ShadowCaster<F> {
+    origin: Pos2,
+    radius: i64,
+    is_opaque: F,
+    visible: HashSet<Pos2>,
+}
+
+impl<F: Fn(&Pos2) -> bool> ShadowCaster<F> {
+    /// Scans one octant row by row, between the slopes `start` and `end`, and
+    /// recurses past every run of opaque cells with the part of the view they
+    /// leave open.
+    fn cast_light(&mut self, row: i64, mut start: f64, end: f64, octant: &[i64; 4]) {
+        if start < end {
+            return;
+        }
+        let [xx, xy, yx, yy] = *octant;
+        let mut next_start = start;
+        for j in row..=self.radius {
+            let dy = -j;
+            let mut blocked = false;
+            for dx in -j..=0 {
+                let cell = Pos2::new(
+                    self.origin.x + dx * xx + dy * xy,
+                    self.origin.y + dx * yx + dy * yy,
+                );
+                let left = (dx as f64 - 0.5) / (dy as f64 + 0.5);
+                let right = (dx as f64 + 0.5) / (dy as f64 - 0.5);
+                if start < right {
+                    continue;
+                } else if end > left {
+                    break;
+                }
+                if dx * dx + dy * dy <= self.radius * self.radius {
+                    self.visible.insert(cell);
+                }
+                let opaque = (self.is_opaque)(&cell);
+                if blocked {
+                    if opaque {
+                        next_start = right;
+                    } else {
+                        blocked = false;
+                        start = next_start;
+                    }
+                } else if opaque && j < self.radius {
+                    blocked = true;
+                    self.cast_light(j + 1, start, left, octant);
+                    next_start = right;
+                }
+            }
+            if blocked {
+                break;
+            }
+        }
+    }
+}
+
+/// What one agent has found out about the map. Cells it has never seen are
+/// missing, so planners treat them as free.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct KnownMap {
+    /// Every cell seen so far and whether it was blocked back then.
+    known: HashMap<(i64, i64), bool>,
+    pub visible: HashSet<Pos2>,
+    /// Where and how far the agent last looked, to only look again once it
+    /// has moved.
+    origin: Option<(Pos2, i64)>,
+}
+
+impl KnownMap {
+    /// Looks around from `origin`, unless it already did from there. Returns
+    /// the blocked cells it didn't know were blocked.
+    pub fn update(
+        &mut self,
+        origin: Pos2,
+        radius: i64,
+        is_blocked: impl Fn(&Pos2) -> bool,
+    ) -> Vec<Pos2> {
+        if self.origin == Some((origin, radius)) {
+            return Vec::new();
+        }
+        self.origin = Some((origin, radius));
+        self.visible = shadow_cast(origin, radius, &is_blocked);
+
+        let mut discovered = Vec::new();
+        for cell in self.visible.iter() {
+            let blocked = is_blocked(cell);
+            if self.known.insert(cell.to_tuple(), blocked) != Some(true) && blocked {
+                discovered.push(*cell);
+            }
+        }
+        discovered
+    }
+
+    /// Makes the next update look again, after the map itself changed.
+    pub fn invalidate(&mut self) {
+        self.origin = None;
+    }
+
+    pub fn is_known(&self, cell: &Pos2) -> bool {
+        self.known.contains_key(&cell.to_tuple())
+    }
+
+    pub fn is_blocked(&self, cell: &Pos2) -> bool {
+        self.known.get(&cell.to_tuple()) == Some(&true)
+    }
+
+    /// The blocked cells it knows of, shaped like `space_lut`.
+    pub fn obstacles(&self) -> HashMap<(i64, i64), bool> {
+        self.known
+            .iter()
+            .filter(|(_, blocked)| **blocked)
+            .map(|(cell, _)| (*cell, true))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walls_are_seen_but_hide_what_is_behind_them() {
+        // a wall along x = 3 from y = -2 to y = 2
+        let wall = |p: &Pos2| p.x == 3 && p.y.abs() <= 2;
+        let visible = shadow_cast(Pos2::new(0, 0), 6, wall);
+        assert!(visible.contains(&Pos2::new(0, 0)));
+        assert!(visible.contains(&Pos2::new(3, 0)));
+        assert!(!visible.contains(&Pos2::new(5, 0)));
+        assert!(visible.contains(&Pos2::new(-5, 0)));
+        assert!(visible.contains(&Pos2::new(0, 6)));
+        assert!(!visible.contains(&Pos2::new(0, 7)));
+        assert!(visible.iter().all(|p| p.x * p.x + p.y * p.y <= 36));
+    }
+
+    #[test]
+    fn known_map_only_reports_new_obstacles_once() {
+        let wall = |p: &Pos2| p.x == 3 && p.y.abs() <= 2;
+        let mut known = KnownMap::default();
+        let discovered = known.update(Pos2::new(0, 0), 6, wall);
+        assert_eq!(discovered.len(), 5);
+        assert!(known.is_blocked(&Pos2::new(3, 0)));
+        assert!(!known.is_known(&Pos2::new(5, 0)));
+
+        // looking again from the same spot does nothing, even when asked to
+        assert!(known.update(Pos2::new(0, 0), 6, wall).is_empty());
+        known.invalidate();
+        assert!(known.update(Pos2::new(0, 0), 6, wall).is_empty());
+
+        // walking round the wall reveals what it hid
+        known.update(Pos2::new(5, 4), 6, wall);
+        assert!(known.is_known(&Pos2::new(5, 0)));
+        assert_eq!(known.obstacles().len(), 5);
+    }
+}
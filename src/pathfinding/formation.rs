@@ -0,0 +1,253 @@
+use super::shape::distance;
+use crate::ecs::entity::EntityId;
+use std::collections::HashMap;
+
+/// Slots on top of one per follower, so a blocked slot can be swapped for a free one.
+const SPARE_SLOTS: usize = 4;
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum FormationShape {
+    None,
+    Line,
+    Column,
+    Wedge,
+    Box,
+    /// Offsets come from each follower's `FormationSlot` component.
+    Custom,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct FormationSettings {
+    pub shape: FormationShape,
+    pub spacing: f64,
+}
+
+impl Default for FormationSettings {
+    fn default() -> Self {
+        Self {
+            shape: FormationShape::Wedge,
+            spacing: 2.,
+        }
+    }
+}
+
+/// Slot offsets around a leader at the origin facing +x, +y being its left.
+/// Custom formations have no generated slots.
+pub fn slot_offsets(shape: FormationShape, count: usize, spacing: f64) -> Vec<[f64; 2]> {
+    if matches!(shape, FormationShape::None | FormationShape::Custom) {
+        return Vec::new();
+    }
+    if shape == FormationShape::Box {
+        // rows as wide as the square would be, the leader in the middle of the first
+        let width = ((count + 1) as f64).sqrt().ceil() as usize;
+        let middle = (width - 1) / 2;
+        return (0..)
+            .map(|k| (k / width, k % width))
+            .filter(|cell| *cell != (0, middle))
+            .take(count)
+            .map(|(row, column)| {
+                [
+                    -(row as f64) * spacing,
+                    (column as f64 - middle as f64) * spacing,
+                ]
+            })
+            .collect();
+    }
+    // alternates sides: 1st left, 1st right, 2nd left, ...
+    (0..count)
+        .map(|i| {
+            let (rank, sign) = ((i / 2 + 1) as f64, if i % 2 == 1 { -1. } else { 1. });
+            match shape {
+                FormationShape::Line => [0., sign * rank * spacing],
+                FormationShape::Column => [-((i + 1) as f64) * spacing, 0.],
+                _ => [-rank * spacing, sign * rank * spacing],
+            }
+        })
+        .collect()
+}
+
+/// `offset` in the frame of a leader at `origin` facing `heading`.
+pub fn to_world(origin: [f64; 2], heading: f64, offset: [f64; 2]) -> [f64; 2] {
+    let (sin, cos) = heading.sin_cos();
+    [
+        origin[0] + offset[0] * cos - offset[1] * sin,
+        origin[1] + offset[0] * sin + offset[1] * cos,
+    ]
+}
+
+/// A group moving behind one leader. The leader follows a planned path, the
+/// followers head for the slot they are assigned to around it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Formation {
+    pub leader: EntityId,
+    pub followers: Vec<EntityId>,
+    offsets: Vec<[f64; 2]>,
+    assignment: HashMap<EntityId, usize>,
+    /// How fast the leader may go, lowered while followers lag behind.
+    pub pace: f64,
+}
+
+impl Formation {
+    /// Generated shapes get a few spare slots, `custom` offsets are used one
+    /// per follower in the same order.
+    pub fn new(
+        leader: EntityId,
+        followers: Vec<EntityId>,
+        settings: &FormationSettings,
+        custom: Vec<[f64; 2]>,
+    ) -> Self {
+        let offsets = if settings.shape == FormationShape::Custom {
+            custom
+        } else {
+            slot_offsets(
+                settings.shape,
+                followers.len() + SPARE_SLOTS,
+                settings.spacing,
+            )
+        };
+        Self {
+            leader,
+            followers,
+            offsets,
+            assignment: HashMap::new(),
+            pace: 1.,
+        }
+    }
+
+    pub fn slot_positions(&self, origin: [f64; 2], heading: f64) -> Vec<[f64; 2]> {
+        self.offsets
+            .iter()
+            .map(|offset| to_world(origin, heading, *offset))
+            .collect()
+    }
+
+    pub fn slot_of(&self, follower: EntityId) -> Option<usize> {
+        self.assignment.get(&follower).copied()
+    }
+
+    /// Gives every follower a slot, closest pairs first. Followers that
+    /// already have a free slot keep it, so only those whose slot got blocked
+    /// (or who never had one) move to the nearest unused free slot. Returns
+    /// the followers that changed slots.
+    pub fn assign(
+        &mut self,
+        positions: &HashMap<EntityId, [f64; 2]>,
+        slots: &[[f64; 2]],
+        free: &[bool],
+    ) -> Vec<EntityId> {
+        self.assignment
+            .retain(|follower, slot| free[*slot] && positions.contains_key(follower));
+        let mut taken: Vec<bool> = vec![false; slots.len()];
+        for slot in self.assignment.values() {
+            taken[*slot] = true;
+        }
+
+        let mut pairs = Vec::new();
+        for follower in self.followers.iter() {
+            let Some(position) = positions.get(follower) else {
+                continue;
+            };
+            if self.assignment.contains_key(follower) {
+                continue;
+            }
+            for (slot, p) in slots.iter().enumerate() {
+                if free[slot] && !taken[slot] {
+                    pairs.push((distance(*position, *p), *follower, slot));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut changed = Vec::new();
+        for (_, follower, slot) in pairs.into_iter() {
+            if taken[slot] || self.assignment.contains_key(&follower) {
+                continue;
+            }
+            taken[slot] = true;
+            self.assignment.insert(follower, slot);
+            changed.push(follower);
+        }
+        changed
+    }
+}
+
+/// Moves `slot` towards `origin` until `is_free` accepts it, for followers
+/// left without a free slot.
+pub fn pull_in(origin: [f64; 2], slot: [f64; 2], is_free: impl Fn([f64; 2]) -> bool) -> [f64; 2] {
+    for t in [1., 0.75, 0.5, 0.25] {
+        let p = [
+            origin[0] + t * (slot[0] - origin[0]),
+            origin[1] + t * (slot[1] - origin[1]),
+        ];
+        if is_free(p) {
+            return p;
+        }
+    }
+    origin
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::entity::Entity;
+    use crate::ecs::world::World;
+
+    #[test]
+    fn generated_slots_are_apart_and_off_the_leader() {
+        for shape in [
+            FormationShape::Line,
+            FormationShape::Column,
+            FormationShape::Wedge,
+            FormationShape::Box,
+        ] {
+            let slots = slot_offsets(shape, 7, 2.);
+            assert_eq!(slots.len(), 7);
+            for (i, a) in slots.iter().enumerate() {
+                assert!(distance(*a, [0., 0.]) >= 2. - 1e-9, "{:?} {:?}", shape, a);
+                for b in slots.iter().skip(i + 1) {
+                    assert!(distance(*a, *b) >= 2. - 1e-9, "{:?} {:?} {:?}", shape, a, b);
+                }
+            }
+            // none of them ahead of the leader
+            assert!(slots.iter().all(|s| s[0] <= 0.));
+        }
+        assert!(slot_offsets(FormationShape::Custom, 3, 2.).is_empty());
+    }
+
+    #[test]
+    fn slots_turn_with_the_leader() {
+        let p = to_world([10., 10.], std::f64::consts::FRAC_PI_2, [-2., 1.]);
+        assert!(distance(p, [9., 8.]) < 1e-9);
+    }
+
+    #[test]
+    fn only_followers_on_blocked_slots_move() {
+        let mut world = World::default();
+        let leader = world.spawn(Entity::default());
+        let followers: Vec<EntityId> = (0..2).map(|_| world.spawn(Entity::default())).collect();
+        let settings = FormationSettings {
+            shape: FormationShape::Line,
+            spacing: 2.,
+        };
+        let mut formation = Formation::new(leader, followers.clone(), &settings, Vec::new());
+        let slots = formation.slot_positions([0., 0.], 0.);
+        let positions: HashMap<EntityId, [f64; 2]> =
+            [(followers[0], [0., 2.]), (followers[1], [0., -2.])]
+                .into_iter()
+                .collect();
+        let mut free = vec![true; slots.len()];
+        assert_eq!(formation.assign(&positions, &slots, &free), followers);
+        assert_eq!(formation.slot_of(followers[0]), Some(0));
+        assert_eq!(formation.slot_of(followers[1]), Some(1));
+
+        free[0] = false;
+        assert_eq!(
+            formation.assign(&positions, &slots, &free),
+            vec![followers[0]]
+        );
+        assert_eq!(formation.slot_of(followers[1]), Some(1));
+        let slot = formation.slot_of(followers[0]).unwrap();
+        assert!(slot > 1 && free[slot]);
+    }
+}
@@ -0,0 +1,208 @@
+use super::search::a_star;
+use super::NavMesh;
+use crate::ecs::pos2::Pos2;
+use std::collections::{BTreeMap, HashMap};
+
+/// World state as named facts, anything not listed counts as false.
+pub type Facts = BTreeMap<String, bool>;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Action {
+    pub name: String,
+    pub preconditions: Facts,
+    pub effects: Facts,
+    pub cost: f64,
+    /// Where the action is carried out. Walking there adds the path length
+    /// from wherever the plan has the agent at that point.
+    pub target: Option<Pos2>,
+    /// Seconds spent on the action once there.
+    pub duration: f32,
+}
+
+impl Action {
+    pub fn new(name: &str, preconditions: &[(&str, bool)], effects: &[(&str, bool)]) -> Self {
+        Self {
+            name: name.to_string(),
+            preconditions: facts(preconditions),
+            effects: facts(effects),
+            cost: 1.,
+            target: None,
+            duration: 0.,
+        }
+    }
+
+    pub fn at(mut self, target: Pos2) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    pub fn costing(mut self, cost: f64, duration: f32) -> Self {
+        self.cost = cost;
+        self.duration = duration;
+        self
+    }
+
+    pub fn is_applicable(&self, state: &Facts) -> bool {
+        satisfies(state, &self.preconditions)
+    }
+
+    pub fn apply(&self, state: &mut Facts) {
+        for (fact, value) in self.effects.iter() {
+            state.insert(fact.clone(), *value);
+        }
+    }
+}
+
+pub fn facts(list: &[(&str, bool)]) -> Facts {
+    list.iter()
+        .map(|(fact, value)| (fact.to_string(), *value))
+        .collect()
+}
+
+pub fn satisfies(state: &Facts, conditions: &Facts) -> bool {
+    conditions
+        .iter()
+        .all(|(fact, value)| state.get(fact).copied().unwrap_or(false) == *value)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Plan {
+    pub steps: Vec<Action>,
+    pub cost: f64,
+}
+
+/// A* over action space: nodes are the facts together with where the agent
+/// stands, edges the applicable actions. `path_length` prices the walk to an
+/// action's target and rules the action out when it returns `None`; lengths
+/// are cached for the duration of the search.
+pub fn plan(
+    start: Pos2,
+    state: &Facts,
+    goal: &Facts,
+    actions: &[Action],
+    mut path_length: impl FnMut(Pos2, Pos2) -> Option<f64>,
+) -> Option<Plan> {
+    // a single action may set every missing fact at once, so the cheapest
+    // action is all that can be promised while any is missing
+    let cheapest = actions
+        .iter()
+        .map(|a| a.cost)
+        .fold(f64::INFINITY, f64::min)
+        .max(0.);
+    let mut lengths: HashMap<(Pos2, Pos2), Option<f64>> = HashMap::new();
+    let mut step = |(s, at): &(Facts, Pos2), action: &Action| {
+        if !action.is_applicable(s) {
+            return None;
+        }
+        let mut cost = action.cost;
+        let mut to = *at;
+        if let Some(target) = action.target {
+            cost += (*lengths
+                .entry((*at, target))
+                .or_insert_with(|| path_length(*at, target)))?;
+            to = target;
+        }
+        let mut s = s.clone();
+        action.apply(&mut s);
+        Some(((s, to), cost))
+    };
+
+    let (nodes, cost) = a_star(
+        (state.clone(), start),
+        |node: &(Facts, Pos2)| {
+            actions
+                .iter()
+                .filter_map(|action| step(node, action))
+                .collect::<Vec<_>>()
+        },
+        |(s, _)| if satisfies(s, goal) { 0. } else { cheapest },
+        |(s, _)| satisfies(s, goal),
+    )?;
+
+    // the cheapest action leading from each node to the next
+    let mut steps = Vec::new();
+    for pair in nodes.windows(2) {
+        let action = actions
+            .iter()
+            .filter_map(|action| {
+                let (next, cost) = step(&pair[0], action)?;
+                (next == pair[1]).then_some((action, cost))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))?;
+        steps.push(action.0.clone());
+    }
+    Some(Plan { steps, cost })
+}
+
+impl NavMesh {
+    /// Length of the grid A* path between two cells, diagonal steps counting √2.
+    pub fn path_length(&self, from: Pos2, to: Pos2) -> Option<f64> {
+        let path = self.a_star(from, to)?;
+        Some(
+            path.windows(2)
+                .map(|w| {
+                    if w[0].x != w[1].x && w[0].y != w[1].y {
+                        std::f64::consts::SQRT_2
+                    } else {
+                        1.
+                    }
+                })
+                .sum(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_lengths_count_diagonal_steps_as_root_two() {
+        let mut navmesh = NavMesh::default();
+        navmesh.set_grid_boundaries(Pos2::new(0, 0), Pos2::new(10, 10));
+        let length = navmesh
+            .path_length(Pos2::new(0, 0), Pos2::new(3, 5))
+            .unwrap();
+        assert!((length - (2. + 3. * std::f64::consts::SQRT_2)).abs() < 1e-9);
+        navmesh.space_lut.insert((3, 5), true);
+        assert_eq!(navmesh.path_length(Pos2::new(0, 0), Pos2::new(3, 5)), None);
+    }
+
+    #[test]
+    fn plans_go_for_the_resource_with_the_shortest_walk() {
+        let mut navmesh = NavMesh::default();
+        navmesh.set_grid_boundaries(Pos2::new(0, 0), Pos2::new(20, 20));
+        // a wall just past the nearer tree, so getting there is a long way round
+        for y in 0..15 {
+            navmesh.space_lut.insert((3, y), true);
+        }
+        let near = Pos2::new(4, 2);
+        let far = Pos2::new(0, 9);
+        let actions = vec![
+            Action::new("chop near", &[], &[("wood", true)]).at(near),
+            Action::new("chop far", &[], &[("wood", true)]).at(far),
+            Action::new("store", &[("wood", true)], &[("stored", true)]).at(Pos2::new(0, 0)),
+        ];
+        let chosen = plan(
+            Pos2::new(1, 2),
+            &Facts::new(),
+            &facts(&[("stored", true)]),
+            &actions,
+            |from, to| navmesh.path_length(from, to),
+        )
+        .unwrap();
+        let names: Vec<&str> = chosen.steps.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, vec!["chop far", "store"]);
+
+        // nothing reachable, no plan
+        navmesh.space_lut.insert((0, 0), true);
+        assert!(plan(
+            Pos2::new(1, 2),
+            &Facts::new(),
+            &facts(&[("stored", true)]),
+            &actions,
+            |from, to| navmesh.path_length(from, to),
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,363 @@
+use super::dubins::{advance, dubins, mod2pi, reeds_shepp, Curve, Pose, Segment, Steer};
+use super::sampling::{CollisionChecker, CollisionModel};
+use super::search::Cost;
+use super::shape::distance;
+use super::{cell_center, ContinuousPlanner, Reverse};
+use crate::ecs::pos2::Pos2;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::f64::consts::TAU;
+
+/// Spacing of the poses checked for collisions along a motion.
+const COLLISION_STEP: f64 = 0.25;
+/// How many expansions between two attempts to reach the goal analytically.
+const ANALYTIC_INTERVAL: usize = 5;
+/// Final headings tried by the analytic expansion, the goal heading is free.
+const GOAL_HEADINGS: usize = 16;
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct HybridSettings {
+    pub turning_radius: f64,
+    pub step: f64,
+    pub heading_bins: usize,
+    pub allow_reverse: bool,
+    pub reverse_penalty: f64,
+    pub steer_penalty: f64,
+    pub switch_penalty: f64,
+    pub max_expansions: usize,
+    pub collision: CollisionModel,
+}
+
+impl Default for HybridSettings {
+    fn default() -> Self {
+        Self {
+            turning_radius: 3.,
+            step: 1.5,
+            heading_bins: 72,
+            allow_reverse: false,
+            reverse_penalty: 2.,
+            steer_penalty: 0.1,
+            switch_penalty: 5.,
+            max_expansions: 20000,
+            collision: CollisionModel::Shapes,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct HybridNode {
+    pose: Pose,
+    g: f64,
+    parent: Option<usize>,
+    /// The primitive that led here from the parent.
+    motion: Segment,
+}
+
+/// Hybrid A*: searches continuous `(x, y, heading)` states reached with
+/// car-like motion primitives, while pruning on a discretised grid of cells
+/// and heading bins. The goal is connected with a Dubins (or, when reversing
+/// is allowed, Reeds-Shepp) curve as soon as one fits between the obstacles.
+#[derive(Clone, Debug)]
+pub struct HybridAStar {
+    pub settings: HybridSettings,
+    pub start_heading: f64,
+    checker: CollisionChecker,
+}
+
+impl HybridAStar {
+    pub fn new(checker: CollisionChecker, settings: HybridSettings) -> Self {
+        Self {
+            settings,
+            start_heading: 0.,
+            checker,
+        }
+    }
+
+    pub fn with_start_heading(mut self, heading: f64) -> Self {
+        self.start_heading = heading;
+        self
+    }
+
+    fn key(&self, pose: Pose) -> (i64, i64, usize) {
+        let bins = self.settings.heading_bins.max(1);
+        (
+            pose[0].floor() as i64,
+            pose[1].floor() as i64,
+            ((mod2pi(pose[2]) / TAU * bins as f64) as usize) % bins,
+        )
+    }
+
+    fn motion_free(&self, poses: &[Pose]) -> bool {
+        poses.windows(2).all(|w| {
+            self.checker
+                .segment_free([w[0][0], w[0][1]], [w[1][0], w[1][1]])
+        })
+    }
+
+    fn curve_to_goal(&self, pose: Pose, end: [f64; 2]) -> Option<Curve> {
+        let mut curves: Vec<Curve> = (0..GOAL_HEADINGS)
+            .filter_map(|i| {
+                let goal = [end[0], end[1], TAU * i as f64 / GOAL_HEADINGS as f64];
+                if self.settings.allow_reverse {
+                    reeds_shepp(pose, goal, self.settings.turning_radius)
+                } else {
+                    dubins(pose, goal, self.settings.turning_radius)
+                }
+            })
+            .collect();
+        curves.sort_by(|a, b| a.length().total_cmp(&b.length()));
+        curves
+            .into_iter()
+            .find(|c| self.motion_free(&c.sample(COLLISION_STEP)))
+    }
+
+    /// Obstacle-aware distance to the goal ignoring the vehicle's kinematics,
+    /// from a Dijkstra flood over the grid cells.
+    fn holonomic_heuristic(&self, end: [f64; 2]) -> HashMap<(i64, i64), f64> {
+        let (min, max) = self.checker.bounds();
+        let in_bounds = |c: (i64, i64)| {
+            c.0 as f64 >= min[0]
+                && c.0 as f64 + 1. <= max[0]
+                && c.1 as f64 >= min[1]
+                && c.1 as f64 + 1. <= max[1]
+        };
+        let goal = (end[0].floor() as i64, end[1].floor() as i64);
+        let mut dist: HashMap<(i64, i64), f64> = HashMap::new();
+        let mut open: BinaryHeap<Reverse<(Cost, (i64, i64))>> = BinaryHeap::new();
+        dist.insert(goal, 0.);
+        open.push(Reverse((Cost(0.), goal)));
+        while let Some(Reverse((Cost(d), cell))) = open.pop() {
+            if d > dist[&cell] {
+                continue;
+            }
+            for (dx, dy) in [
+                (1, 0),
+                (-1, 0),
+                (0, 1),
+                (0, -1),
+                (1, 1),
+                (1, -1),
+                (-1, 1),
+                (-1, -1),
+            ] {
+                let next = (cell.0 + dx, cell.1 + dy);
+                let center = cell_center(Pos2::new(next.0, next.1));
+                if !in_bounds(next) || !self.checker.is_free(center) {
+                    continue;
+                }
+                let nd = d + ((dx * dx + dy * dy) as f64).sqrt();
+                if !dist.get(&next).is_some_and(|old| *old <= nd) {
+                    dist.insert(next, nd);
+                    open.push(Reverse((Cost(nd), next)));
+                }
+            }
+        }
+        dist
+    }
+
+    /// Plans from a full pose to a position, returning poses roughly every
+    /// `COLLISION_STEP` along the way.
+    pub fn find_poses(&self, start: Pose, end: [f64; 2]) -> Option<Vec<Pose>> {
+        let start_xy = [start[0], start[1]];
+        if !self.checker.is_free(start_xy) || !self.checker.is_free(end) {
+            return None;
+        }
+        let holonomic = self.holonomic_heuristic(end);
+        let heuristic = |pose: Pose| {
+            let euclidean = distance([pose[0], pose[1]], end);
+            holonomic
+                .get(&(pose[0].floor() as i64, pose[1].floor() as i64))
+                .map_or(euclidean, |h| euclidean.max(*h - 1.))
+        };
+
+        let mut directions = vec![false];
+        if self.settings.allow_reverse {
+            directions.push(true);
+        }
+
+        let mut nodes = vec![HybridNode {
+            pose: start,
+            g: 0.,
+            parent: None,
+            motion: Segment {
+                steer: Steer::Straight,
+                length: 0.,
+            },
+        }];
+        let mut best_g: HashMap<(i64, i64, usize), f64> = HashMap::new();
+        let mut closed: HashSet<(i64, i64, usize)> = HashSet::new();
+        let mut open: BinaryHeap<Reverse<(Cost, usize)>> = BinaryHeap::new();
+        open.push(Reverse((Cost(heuristic(start)), 0)));
+
+        let mut expansions = 0;
+        while let Some(Reverse((_, current))) = open.pop() {
+            let node = nodes[current];
+            if !closed.insert(self.key(node.pose)) {
+                continue;
+            }
+
+            if expansions % ANALYTIC_INTERVAL == 0 {
+                if let Some(curve) = self.curve_to_goal(node.pose, end) {
+                    return Some(self.trace(&nodes, current, curve));
+                }
+            }
+            expansions += 1;
+            if expansions > self.settings.max_expansions {
+                log::info!("hybrid a* gave up after {} expansions", expansions);
+                return None;
+            }
+
+            for reverse in directions.iter() {
+                for steer in [Steer::Left, Steer::Straight, Steer::Right] {
+                    let length = if *reverse {
+                        -self.settings.step
+                    } else {
+                        self.settings.step
+                    };
+                    let motion = Segment { steer, length };
+                    if !self.motion_free(&self.primitive(node.pose, motion).sample(COLLISION_STEP))
+                    {
+                        continue;
+                    }
+                    let pose = advance(node.pose, steer, self.settings.turning_radius, length);
+                    let key = self.key(pose);
+                    if closed.contains(&key) {
+                        continue;
+                    }
+
+                    let mut cost = self.settings.step;
+                    if *reverse {
+                        cost *= self.settings.reverse_penalty;
+                    }
+                    if steer != Steer::Straight {
+                        cost += self.settings.steer_penalty * self.settings.step;
+                    }
+                    if current != 0 && *reverse != (node.motion.length < 0.) {
+                        cost += self.settings.switch_penalty;
+                    }
+                    let g = node.g + cost;
+                    if best_g.get(&key).is_some_and(|old| *old <= g) {
+                        continue;
+                    }
+                    best_g.insert(key, g);
+                    nodes.push(HybridNode {
+                        pose,
+                        g,
+                        parent: Some(current),
+                        motion,
+                    });
+                    open.push(Reverse((Cost(g + heuristic(pose)), nodes.len() - 1)));
+                }
+            }
+        }
+        None
+    }
+
+    /// Plans through every waypoint in order, each leg starting with the
+    /// heading the previous one arrived with.
+    pub fn waypointed_poses(&self, start: Pos2, waypoints: Vec<Pos2>) -> Option<Vec<Pose>> {
+        let [x, y] = cell_center(start);
+        let mut poses = vec![[x, y, self.start_heading]];
+        for end in waypoints.into_iter() {
+            let leg = self.find_poses(*poses.last().unwrap(), cell_center(end))?;
+            poses.extend(leg.into_iter().skip(1));
+        }
+        Some(poses)
+    }
+
+    fn primitive(&self, start: Pose, motion: Segment) -> Curve {
+        Curve {
+            start,
+            radius: self.settings.turning_radius,
+            segments: vec![motion],
+        }
+    }
+
+    /// Poses from the start through the expanded primitives and the final curve.
+    fn trace(&self, nodes: &[HybridNode], mut i: usize, curve: Curve) -> Vec<Pose> {
+        let mut chain = vec![i];
+        while let Some(parent) = nodes[i].parent {
+            chain.push(parent);
+            i = parent;
+        }
+        chain.reverse();
+
+        let mut poses = vec![nodes[chain[0]].pose];
+        for w in chain.windows(2) {
+            let motion = self.primitive(nodes[w[0]].pose, nodes[w[1]].motion);
+            poses.extend(motion.sample(COLLISION_STEP).into_iter().skip(1));
+        }
+        poses.extend(curve.sample(COLLISION_STEP).into_iter().skip(1));
+        poses
+    }
+}
+
+impl ContinuousPlanner for HybridAStar {
+    fn find_path(&self, start: [f64; 2], end: [f64; 2]) -> Option<Vec<[f64; 2]>> {
+        self.find_poses([start[0], start[1], self.start_heading], end)
+            .map(|poses| poses.into_iter().map(|p| [p[0], p[1]]).collect())
+    }
+
+    fn name(&self) -> &'static str {
+        "hybrid_a_star"
+    }
+
+    fn waypointed_polyline(&self, start: Pos2, waypoints: Vec<Pos2>) -> Option<Vec<[f64; 2]>> {
+        let poses = self.waypointed_poses(start, waypoints)?;
+        Some(poses.into_iter().map(|p| [p[0], p[1]]).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pathfinding::shape::{RectParams, ShapeParams};
+
+    /// A wall between x = 18 and x = 22 up to y = 30, in a 40 by 40 grid.
+    fn planner(settings: HybridSettings) -> HybridAStar {
+        let wall = ShapeParams::Rectangle(RectParams {
+            center_x: 18.,
+            center_y: 0.,
+            width: 4.,
+            height: 30.,
+        });
+        let checker = CollisionChecker::new(
+            Pos2::new(0, 0),
+            Pos2::new(40, 40),
+            CollisionModel::Shapes,
+            HashMap::new(),
+            vec![wall],
+        );
+        HybridAStar::new(checker, settings)
+    }
+
+    #[test]
+    fn poses_turn_no_tighter_than_the_turning_radius() {
+        let settings = HybridSettings::default();
+        let planner = planner(settings).with_start_heading(0.);
+        let poses = planner
+            .waypointed_poses(Pos2::new(5, 10), vec![Pos2::new(35, 10)])
+            .unwrap();
+        assert_eq!(poses[0], [5.5, 10.5, 0.]);
+        let last = poses.last().unwrap();
+        assert!(distance([last[0], last[1]], [35.5, 10.5]) < 1e-6);
+        assert!(poses.iter().any(|p| p[1] > 30.));
+        for w in poses.windows(2) {
+            assert!(planner
+                .checker
+                .segment_free([w[0][0], w[0][1]], [w[1][0], w[1][1]]));
+            // an arc of length d turns by at most d / r, its chord is a bit shorter
+            let step = distance([w[0][0], w[0][1]], [w[1][0], w[1][1]]);
+            let turn = mod2pi(w[1][2] - w[0][2]);
+            let turn = turn.min(TAU - turn);
+            assert!(turn <= 1.01 * step / settings.turning_radius, "{:?}", w);
+        }
+    }
+
+    #[test]
+    fn goals_in_obstacles_are_not_reached() {
+        let planner = planner(HybridSettings::default());
+        assert!(planner.find_poses([5.5, 10.5, 0.], [20., 10.]).is_none());
+        assert!(planner.find_poses([5.5, 10.5, 0.], [45., 10.]).is_none());
+    }
+}
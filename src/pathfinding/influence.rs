@@ -0,0 +1,165 @@
+use super::search::Cost;
+use super::{rasterize_polyline, Reverse};
+use crate::ecs::pos2::Pos2;
+use std::collections::{BinaryHeap, HashMap};
+
+/// Cheapest a cell can get, however much it is preferred. Keeps the grid
+/// heuristics admissible.
+pub const MIN_FACTOR: f64 = 0.25;
+
+/// Influence in `[0, 1]` per cell, cells without any left out.
+pub type InfluenceMap = HashMap<(i64, i64), f64>;
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct InfluenceSettings {
+    /// How far influence spreads from a source, in walked cells.
+    pub radius: f64,
+    /// Share of the influence left after each cell walked.
+    pub decay: f64,
+    pub show_heatmap: bool,
+}
+
+impl Default for InfluenceSettings {
+    fn default() -> Self {
+        Self {
+            radius: 8.,
+            decay: 0.8,
+            show_heatmap: false,
+        }
+    }
+}
+
+/// Spreads influence from every source over the free cells, decaying with the
+/// walked distance so it doesn't leak through walls. Overlapping sources keep
+/// the strongest value.
+pub fn propagate(
+    sources: &[Pos2],
+    settings: &InfluenceSettings,
+    is_blocked: impl Fn(&Pos2) -> bool,
+) -> InfluenceMap {
+    let mut map = InfluenceMap::new();
+    for source in sources.iter() {
+        let mut walked: HashMap<Pos2, f64> = HashMap::new();
+        let mut open = BinaryHeap::new();
+        walked.insert(*source, 0.);
+        open.push(Reverse((Cost(0.), *source)));
+        while let Some(Reverse((Cost(d), cell))) = open.pop() {
+            if d > walked[&cell] {
+                continue;
+            }
+            let value = settings.decay.powf(d);
+            let entry = map.entry(cell.to_tuple()).or_insert(0.);
+            *entry = entry.max(value);
+            for neighbor in cell.neighbors() {
+                if is_blocked(&neighbor) {
+                    continue;
+                }
+                let step = if neighbor.x != cell.x && neighbor.y != cell.y {
+                    std::f64::consts::SQRT_2
+                } else {
+                    1.
+                };
+                let next = d + step;
+                if next <= settings.radius && next < *walked.get(&neighbor).unwrap_or(&f64::MAX) {
+                    walked.insert(neighbor, next);
+                    open.push(Reverse((Cost(next), neighbor)));
+                }
+            }
+        }
+    }
+    map
+}
+
+/// Multiplier on the cost of entering each cell, built from weighted
+/// influence maps. Cells it has nothing on cost as usual.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct CostLayer {
+    extra: HashMap<(i64, i64), f64>,
+}
+
+impl CostLayer {
+    /// Adds `weight` times the influence to every cell of `map`. Positive
+    /// weights make the cells more expensive, negative ones cheaper.
+    pub fn add(&mut self, map: &InfluenceMap, weight: f64) {
+        for (cell, value) in map.iter() {
+            *self.extra.entry(*cell).or_insert(0.) += weight * value;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.extra.is_empty()
+    }
+
+    pub fn factor(&self, cell: &Pos2) -> f64 {
+        self.extra
+            .get(&cell.to_tuple())
+            .map_or(1., |extra| (1. + extra).max(MIN_FACTOR))
+    }
+
+    /// Mean factor of the cells a straight segment crosses, for planners
+    /// that don't move cell by cell.
+    pub fn segment_factor(&self, a: [f64; 2], b: [f64; 2]) -> f64 {
+        if self.is_empty() {
+            return 1.;
+        }
+        let cells = rasterize_polyline(&[a, b]);
+        cells.iter().map(|c| self.factor(c)).sum::<f64>() / cells.len().max(1) as f64
+    }
+
+    pub fn factors(&self) -> impl Iterator<Item = ((i64, i64), f64)> + '_ {
+        self.extra
+            .iter()
+            .map(|(cell, extra)| (*cell, (1. + extra).max(MIN_FACTOR)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pathfinding::NavMesh;
+
+    #[test]
+    fn influence_decays_with_the_walk_and_stops_at_walls() {
+        let settings = InfluenceSettings::default();
+        // a wall along x = 2 with a gap at y = 5
+        let wall = |p: &Pos2| p.x == 2 && p.y != 5;
+        let map = propagate(&[Pos2::new(0, 0)], &settings, wall);
+        assert_eq!(map[&(0, 0)], 1.);
+        assert!((map[&(1, 0)] - settings.decay).abs() < 1e-9);
+        assert!(map[&(1, 1)] < map[&(1, 0)]);
+        assert!(!map.contains_key(&(2, 0)));
+        // right behind the wall it only gets what came round through the gap
+        let around = map.get(&(3, 0)).copied().unwrap_or(0.);
+        assert!(around < settings.decay.powi(3));
+        assert!(map
+            .keys()
+            .all(|(x, y)| ((x * x + y * y) as f64).sqrt() <= settings.radius));
+    }
+
+    #[test]
+    fn planners_walk_round_avoided_cells_and_through_preferred_ones() {
+        let mut navmesh = NavMesh::default();
+        navmesh.set_grid_boundaries(Pos2::new(0, 0), Pos2::new(20, 20));
+        let threat = propagate(&[Pos2::new(10, 10)], &InfluenceSettings::default(), |_| {
+            false
+        });
+        let mut costs = CostLayer::default();
+        costs.add(&threat, 3.);
+        let path = navmesh
+            .clone()
+            .with_costs(costs)
+            .a_star(Pos2::new(2, 10), Pos2::new(18, 10))
+            .unwrap();
+        let straight = navmesh.a_star(Pos2::new(2, 10), Pos2::new(18, 10)).unwrap();
+        assert!(straight.contains(&Pos2::new(10, 10)));
+        assert!(path
+            .iter()
+            .all(|p| (p.y - 10).abs() >= 2 || (p.x - 10).abs() >= 2));
+
+        let mut preferred = CostLayer::default();
+        preferred.add(&threat, -10.);
+        assert_eq!(preferred.factor(&Pos2::new(10, 10)), MIN_FACTOR);
+        assert_eq!(preferred.factor(&Pos2::new(0, 0)), 1.);
+    }
+}
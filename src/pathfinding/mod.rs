@@ -0,0 +1,481 @@
+use crate::ecs::pos2::Pos2;
+use influence::CostLayer;
+use poll_promise::Promise;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+pub mod dubins;
+pub mod fog;
+pub mod formation;
+pub mod goap;
+pub mod hybrid_a_star;
+pub mod influence;
+pub mod orca;
+pub mod polygon_mesh;
+pub mod replan;
+pub mod sampling;
+pub mod search;
+pub mod shape;
+pub mod sipp;
+pub mod visibility_graph;
+
+static THREAD_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Eq, PartialEq)]
+pub(crate) struct Reverse<T>(pub T);
+
+impl<T: Ord> Ord for Reverse<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.cmp(&self.0)
+    }
+}
+
+impl<T: PartialOrd> PartialOrd for Reverse<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        other.0.partial_cmp(&self.0)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, serde::Deserialize, serde::Serialize)]
+pub struct NavMesh {
+    pub space_lut: HashMap<(i64, i64), bool>,
+    pub min: Pos2,
+    pub max: Pos2,
+    /// Tactical costs on top of the distance walked.
+    #[serde(skip)]
+    pub costs: CostLayer,
+}
+
+impl Default for NavMesh {
+    fn default() -> Self {
+        Self {
+            space_lut: HashMap::default(),
+            min: Pos2::default(),
+            max: Pos2::default(),
+            costs: CostLayer::default(),
+        }
+    }
+}
+
+impl NavMesh {
+    pub fn set_grid_boundaries(&mut self, min: Pos2, max: Pos2) {
+        self.min = min;
+        self.max = max;
+    }
+
+    fn is_in_bounds(&self, pos: &Pos2) -> bool {
+        pos.x >= self.min.x && pos.x <= self.max.x && pos.y >= self.min.y && pos.y <= self.max.y
+    }
+
+    pub fn set_space_lut(&mut self, space_lut: HashMap<(i64, i64), bool>) {
+        self.space_lut = space_lut;
+    }
+
+    pub fn with_costs(mut self, costs: CostLayer) -> Self {
+        self.costs = costs;
+        self
+    }
+
+    /// Cost of stepping from `from` onto the neighbouring cell `to`.
+    fn step_cost(&self, from: &Pos2, to: &Pos2) -> i64 {
+        let base = if (to.x != from.x) && (to.y != from.y) {
+            14
+        } else {
+            10
+        };
+        if self.costs.is_empty() {
+            return base;
+        }
+        (base as f64 * self.costs.factor(to)).round() as i64
+    }
+
+    fn heuristic(&self, a: &Pos2, b: &Pos2) -> i64 {
+        ((a.x - b.x).abs() + (a.y - b.y).abs()) as i64
+    }
+
+    pub fn async_a_star(&self, start: Pos2, end: Pos2) -> Option<Promise<Option<Vec<Pos2>>>> {
+        let navmesh_clone = self.clone();
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let thread_id = THREAD_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let thread_name = format!("a_star_{}", thread_id);
+            return Some(Promise::spawn_thread(&thread_name, move || {
+                navmesh_clone.a_star(start, end)
+            }));
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        return Some(Promise::spawn_local(async move {
+            navmesh_clone.a_star(start, end)
+        }));
+    }
+
+    pub fn async_waypointed_a_star(
+        &self,
+        start: Pos2,
+        waypoints: Vec<Pos2>,
+    ) -> Option<Promise<Option<Vec<Pos2>>>> {
+        let navmesh_clone = self.clone();
+
+        // Spawn a new thread or async task depending on the target architecture
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let thread_id = THREAD_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let thread_name = format!("waypointed_a_star_{}", thread_id);
+            Some(Promise::spawn_thread(&thread_name, move || {
+                // Call the waypointed_a_star function with the cloned navmesh, start, and waypoints
+                navmesh_clone.waypointed_a_star(start, waypoints)
+            }))
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            Some(Promise::spawn_local(async move {
+                // Since spawn_local expects a Future, we have to use async block here.
+                // The waypointed_a_star call is still synchronous, but we are in an async block.
+                navmesh_clone.waypointed_a_star(start, waypoints)
+            }))
+        }
+    }
+
+    pub fn a_star(&self, start: Pos2, end: Pos2) -> Option<Vec<Pos2>> {
+        if !self.space_lut.contains_key(&start.to_tuple())
+            && !self.space_lut.contains_key(&end.to_tuple())
+        {
+            let mut open_set: BinaryHeap<Reverse<(i64, Pos2)>> = BinaryHeap::new();
+            let mut came_from: HashMap<Pos2, Pos2> = HashMap::new();
+            let mut g_score: HashMap<Pos2, i64> = HashMap::new();
+            let mut f_score: HashMap<Pos2, i64> = HashMap::new();
+
+            open_set.push(Reverse((0, start)));
+            g_score.insert(start, 0);
+            f_score.insert(start, self.heuristic(&start, &end));
+
+            while let Some(Reverse((_, current))) = open_set.pop() {
+                if current == end {
+                    let mut path = vec![end];
+                    while let Some(neighbor) = came_from.get(&path[path.len() - 1]) {
+                        path.push(*neighbor);
+                    }
+                    path.reverse();
+                    return Some(path);
+                }
+
+                for neighbor in current.neighbors() {
+                    if !self.is_in_bounds(&neighbor)
+                        || self.space_lut.contains_key(&neighbor.to_tuple())
+                    {
+                        continue;
+                    }
+                    // 14 and 10 approximate 1.4 and 1, scaled by the cost layer
+                    let movement_cost = self.step_cost(&current, &neighbor);
+
+                    let tentative_g_score =
+                        g_score.get(&current).unwrap_or(&i64::MAX) + movement_cost;
+
+                    if tentative_g_score < *g_score.get(&neighbor).unwrap_or(&i64::MAX) {
+                        came_from.insert(neighbor, current);
+                        g_score.insert(neighbor, tentative_g_score);
+                        f_score.insert(
+                            neighbor,
+                            tentative_g_score + self.heuristic(&neighbor, &end),
+                        );
+                        open_set.push(Reverse((f_score[&neighbor], neighbor)));
+                    }
+                }
+            }
+            None
+        } else {
+            None
+        }
+    }
+    pub fn waypointed_a_star(&self, start: Pos2, waypoints: Vec<Pos2>) -> Option<Vec<Pos2>> {
+        let mut total_path = Vec::new();
+        let mut current_start = start;
+
+        for end in waypoints.into_iter() {
+            if self.space_lut.contains_key(&current_start.to_tuple())
+                || self.space_lut.contains_key(&end.to_tuple())
+            {
+                // If any point is not traversable, return None
+                return None;
+            }
+
+            let mut open_set: BinaryHeap<Reverse<(i64, Pos2)>> = BinaryHeap::new();
+            let mut came_from: HashMap<Pos2, Pos2> = HashMap::new();
+            let mut g_score: HashMap<Pos2, i64> = HashMap::new();
+            let mut f_score: HashMap<Pos2, i64> = HashMap::new();
+
+            open_set.push(Reverse((0, current_start)));
+            g_score.insert(current_start, 0);
+            f_score.insert(current_start, self.heuristic(&current_start, &end));
+
+            while let Some(Reverse((_, current))) = open_set.pop() {
+                if current == end {
+                    let mut path = Vec::new();
+                    let mut current_pos = current;
+                    while let Some(&came_from_pos) = came_from.get(&current_pos) {
+                        path.push(current_pos);
+                        current_pos = came_from_pos;
+                    }
+                    path.push(current_start); // Add the start position to the path
+                    path.reverse(); // Reverse the path to start->end order
+
+                    if !total_path.is_empty() {
+                        path.remove(0); // Remove the first point to avoid duplication
+                    }
+
+                    total_path.extend(path);
+                    break;
+                }
+
+                for neighbor in current.neighbors() {
+                    if !self.is_in_bounds(&neighbor)
+                        || self.space_lut.contains_key(&neighbor.to_tuple())
+                    {
+                        continue;
+                    }
+                    let movement_cost = self.step_cost(&current, &neighbor);
+
+                    let tentative_g_score =
+                        g_score.get(&current).unwrap_or(&i64::MAX) + movement_cost;
+
+                    if tentative_g_score < *g_score.get(&neighbor).unwrap_or(&i64::MAX) {
+                        came_from.insert(neighbor, current);
+                        g_score.insert(neighbor, tentative_g_score);
+                        f_score.insert(
+                            neighbor,
+                            tentative_g_score + self.heuristic(&neighbor, &end),
+                        );
+                        open_set.push(Reverse((f_score[&neighbor], neighbor)));
+                    }
+                }
+            }
+
+            current_start = end; // The next start point is the current end point
+        }
+
+        if total_path.is_empty() {
+            None // No path found
+        } else {
+            Some(total_path) // Return the concatenated path
+        }
+    }
+
+    /// Goes around every run of blocked cells in `path` on the grid, from the
+    /// free cell before it to the free one after. None if the path starts or
+    /// ends blocked or there is no way around.
+    pub fn detour_blocked(&self, path: Vec<Pos2>) -> Option<Vec<Pos2>> {
+        let blocked = |p: &Pos2| self.space_lut.contains_key(&p.to_tuple());
+        let mut detoured = Vec::with_capacity(path.len());
+        let mut i = 0;
+        while i < path.len() {
+            if !blocked(&path[i]) {
+                detoured.push(path[i]);
+                i += 1;
+                continue;
+            }
+            let from = *detoured.last()?;
+            let after = i + path[i..].iter().take_while(|p| blocked(p)).count();
+            let detour = self.a_star(from, *path.get(after)?)?;
+            detoured.extend(detour.into_iter().skip(1));
+            i = after + 1;
+        }
+        Some(detoured)
+    }
+}
+
+/// Planners that work in continuous space and hand back straight-line paths
+/// between cell centers. Entities still walk cells, so the results are rasterized.
+pub trait ContinuousPlanner: Clone + Send + 'static {
+    fn find_path(&self, start: [f64; 2], end: [f64; 2]) -> Option<Vec<[f64; 2]>>;
+
+    fn name(&self) -> &'static str;
+
+    /// Plans through every waypoint in order and returns the full polyline.
+    fn waypointed_polyline(&self, start: Pos2, waypoints: Vec<Pos2>) -> Option<Vec<[f64; 2]>> {
+        let mut polyline = vec![cell_center(start)];
+        let mut current_start = start;
+        for end in waypoints.into_iter() {
+            let leg = self.find_path(cell_center(current_start), cell_center(end))?;
+            polyline.extend(leg.into_iter().skip(1));
+            current_start = end;
+        }
+        Some(polyline)
+    }
+
+    /// Same as [`ContinuousPlanner::waypointed_polyline`], as the grid cells
+    /// the path passes over. Where those are blocked on `grid`, e.g. where a
+    /// shortcut clips the corner of an obstacle, it goes around them on the
+    /// grid instead.
+    fn waypointed_path(
+        &self,
+        grid: &NavMesh,
+        start: Pos2,
+        waypoints: Vec<Pos2>,
+    ) -> Option<Vec<Pos2>> {
+        let polyline = self.waypointed_polyline(start, waypoints)?;
+        grid.detour_blocked(rasterize_polyline(&polyline))
+    }
+
+    fn async_waypointed_path(
+        &self,
+        grid: &NavMesh,
+        start: Pos2,
+        waypoints: Vec<Pos2>,
+    ) -> Option<Promise<Option<Vec<Pos2>>>> {
+        let planner_clone = self.clone();
+        let grid = grid.clone();
+        spawn_path_promise(self.name(), move || {
+            planner_clone.waypointed_path(&grid, start, waypoints)
+        })
+    }
+}
+
+/// Center of a grid cell in plot coordinates.
+pub fn cell_center(pos: Pos2) -> [f64; 2] {
+    [pos.x as f64 + 0.5, pos.y as f64 + 0.5]
+}
+
+/// Turns a continuous polyline into the sequence of grid cells it passes over,
+/// every one of them however little of it a segment clips. Segments running
+/// exactly through a corner step diagonally, like grid paths do.
+pub fn rasterize_polyline(points: &[[f64; 2]]) -> Vec<Pos2> {
+    let mut cells: Vec<Pos2> = Vec::new();
+    if let Some(first) = points.first() {
+        cells.push(cell_of(*first));
+    }
+    for pair in points.windows(2) {
+        traverse_segment(pair[0], pair[1], |cell| {
+            if cells.last() != Some(&cell) {
+                cells.push(cell);
+            }
+        });
+    }
+    cells
+}
+
+fn cell_of(p: [f64; 2]) -> Pos2 {
+    Pos2::new(p[0].floor() as i64, p[1].floor() as i64)
+}
+
+/// Walks the cells from `a` to `b` in the order the segment enters them
+/// (Amanatides & Woo), calling `visit` on each but the first.
+fn traverse_segment(a: [f64; 2], b: [f64; 2], mut visit: impl FnMut(Pos2)) {
+    let (mut cell, end) = (cell_of(a), cell_of(b));
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    let step = |d: f64| if d > 0. { 1 } else { -1 };
+    // distance along the segment, in fractions of it, to the next vertical
+    // and horizontal grid line, and between two of them
+    let first_crossing = |p: f64, d: f64| {
+        if d > 0. {
+            (p.floor() + 1. - p) / d
+        } else if d < 0. {
+            (p - p.floor()) / -d
+        } else {
+            f64::INFINITY
+        }
+    };
+    let (mut next_x, mut next_y) = (first_crossing(a[0], dx), first_crossing(a[1], dy));
+    let (delta_x, delta_y) = (1. / dx.abs(), 1. / dy.abs());
+    while cell != end {
+        // never past the end on an axis, whatever rounding says
+        let (x_done, y_done) = (cell.x == end.x, cell.y == end.y);
+        if !x_done && !y_done && (next_x - next_y).abs() < 1e-9 {
+            cell = Pos2::new(cell.x + step(dx), cell.y + step(dy));
+            next_x += delta_x;
+            next_y += delta_y;
+        } else if !x_done && (y_done || next_x < next_y) {
+            cell.x += step(dx);
+            next_x += delta_x;
+        } else {
+            cell.y += step(dy);
+            next_y += delta_y;
+        }
+        visit(cell);
+    }
+}
+
+/// Runs a planner on its own thread natively, or as a local task on the web.
+pub(crate) fn spawn_path_promise<T: Send + 'static>(
+    name: &str,
+    planner: impl FnOnce() -> T + Send + 'static,
+) -> Option<Promise<T>> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let thread_id = THREAD_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let thread_name = format!("{}_{}", name, thread_id);
+        Some(Promise::spawn_thread(&thread_name, planner))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = name;
+        Some(Promise::spawn_local(async move { planner() }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(points: &[(i64, i64)]) -> Vec<Pos2> {
+        points.iter().map(|(x, y)| Pos2::new(*x, *y)).collect()
+    }
+
+    fn grid(blocked: &[(i64, i64)]) -> NavMesh {
+        let mut navmesh = NavMesh::default();
+        navmesh.set_grid_boundaries(Pos2::new(0, 0), Pos2::new(10, 10));
+        navmesh.set_space_lut(blocked.iter().map(|cell| (*cell, true)).collect());
+        navmesh
+    }
+
+    #[test]
+    fn rasterizing_keeps_cells_a_segment_barely_clips() {
+        // enters (1, 0) for less than a thirtieth of a cell
+        assert_eq!(
+            rasterize_polyline(&[[0.5, 0.5], [1.5, 1.45]]),
+            cells(&[(0, 0), (1, 0), (1, 1)])
+        );
+        assert_eq!(
+            rasterize_polyline(&[[0.5, 0.5], [3.5, 1.3], [3.5, 0.5]]),
+            cells(&[(0, 0), (1, 0), (2, 0), (2, 1), (3, 1), (3, 0)])
+        );
+    }
+
+    #[test]
+    fn rasterizing_steps_diagonally_through_corners() {
+        assert_eq!(
+            rasterize_polyline(&[[0.5, 0.5], [2.5, 2.5]]),
+            cells(&[(0, 0), (1, 1), (2, 2)])
+        );
+    }
+
+    #[test]
+    fn blocked_runs_are_walked_around() {
+        let navmesh = grid(&[(2, 1), (3, 1)]);
+        let path = cells(&[(0, 1), (1, 1), (2, 1), (3, 1), (4, 1), (5, 1)]);
+        let detoured = navmesh.detour_blocked(path).unwrap();
+        assert_eq!(detoured.first(), Some(&Pos2::new(0, 1)));
+        assert_eq!(detoured.last(), Some(&Pos2::new(5, 1)));
+        assert!(detoured
+            .iter()
+            .all(|p| !navmesh.space_lut.contains_key(&p.to_tuple())));
+        assert!(detoured
+            .windows(2)
+            .all(|w| (w[0].x - w[1].x).abs() <= 1 && (w[0].y - w[1].y).abs() <= 1));
+    }
+
+    #[test]
+    fn paths_ending_blocked_are_not_detoured() {
+        let navmesh = grid(&[(2, 1)]);
+        assert_eq!(
+            navmesh.detour_blocked(cells(&[(0, 1), (1, 1), (2, 1)])),
+            None
+        );
+        assert_eq!(navmesh.detour_blocked(cells(&[(2, 1), (3, 1)])), None);
+    }
+}
@@ -0,0 +1,455 @@
+use super::shape::distance;
+use std::collections::HashMap;
+
+const EPSILON: f64 = 1e-5;
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct OrcaSettings {
+    pub radius: f64,
+    pub max_speed: f64,
+    pub neighbor_dist: f64,
+    pub max_neighbors: usize,
+    pub time_horizon: f64,
+    pub obstacle_time_horizon: f64,
+}
+
+impl Default for OrcaSettings {
+    fn default() -> Self {
+        Self {
+            radius: 0.45,
+            max_speed: 10.,
+            neighbor_dist: 6.,
+            max_neighbors: 10,
+            time_horizon: 2.,
+            obstacle_time_horizon: 0.5,
+        }
+    }
+}
+
+/// State of one agent as seen by the avoidance, in grid units per second.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct OrcaAgent {
+    pub position: [f64; 2],
+    pub velocity: [f64; 2],
+    pub preferred_velocity: [f64; 2],
+}
+
+/// Half-plane of allowed velocities: everything left of `direction` through `point`.
+#[derive(Debug, Clone, Copy)]
+struct Line {
+    point: [f64; 2],
+    direction: [f64; 2],
+}
+
+fn add(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    [a[0] + b[0], a[1] + b[1]]
+}
+
+fn sub(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+fn scale(a: [f64; 2], s: f64) -> [f64; 2] {
+    [a[0] * s, a[1] * s]
+}
+
+fn dot(a: [f64; 2], b: [f64; 2]) -> f64 {
+    a[0] * b[0] + a[1] * b[1]
+}
+
+fn det(a: [f64; 2], b: [f64; 2]) -> f64 {
+    a[0] * b[1] - a[1] * b[0]
+}
+
+fn length(a: [f64; 2]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+fn normalize(a: [f64; 2]) -> [f64; 2] {
+    let l = length(a);
+    if l < EPSILON {
+        [0., 0.]
+    } else {
+        scale(a, 1. / l)
+    }
+}
+
+/// A static edge agents can't cross, from one end to the other.
+pub type Wall = ([f64; 2], [f64; 2]);
+
+/// Boundary edges of a cell in a `space_lut`, i.e. every side of it that
+/// faces a free cell, none if it is free itself. They only depend on the
+/// cell and its four neighbours.
+pub fn cell_edges(space_lut: &HashMap<(i64, i64), bool>, (x, y): (i64, i64)) -> Vec<Wall> {
+    let blocked = |x: i64, y: i64| space_lut.get(&(x, y)).copied().unwrap_or(false);
+    let mut edges = Vec::new();
+    if !blocked(x, y) {
+        return edges;
+    }
+    let (x0, y0, x1, y1) = (x as f64, y as f64, x as f64 + 1., y as f64 + 1.);
+    if !blocked(x - 1, y) {
+        edges.push(([x0, y0], [x0, y1]));
+    }
+    if !blocked(x + 1, y) {
+        edges.push(([x1, y0], [x1, y1]));
+    }
+    if !blocked(x, y - 1) {
+        edges.push(([x0, y0], [x1, y0]));
+    }
+    if !blocked(x, y + 1) {
+        edges.push(([x0, y1], [x1, y1]));
+    }
+    edges
+}
+
+/// The four sides of the area from `min` to `max`, which agents can't
+/// leave.
+pub fn boundary_walls(min: [f64; 2], max: [f64; 2]) -> Vec<Wall> {
+    vec![
+        (min, [max[0], min[1]]),
+        ([max[0], min[1]], max),
+        (max, [min[0], max[1]]),
+        ([min[0], max[1]], min),
+    ]
+}
+
+fn closest_point(p: [f64; 2], a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    let ab = sub(b, a);
+    let t = (dot(sub(p, a), ab) / dot(ab, ab)).clamp(0., 1.);
+    add(a, scale(ab, t))
+}
+
+/// Computes a collision-free velocity for `agents[index]` that stays as close
+/// as possible to its preferred velocity (Optimal Reciprocal Collision
+/// Avoidance, following RVO2). Static `edges` are not handled with full
+/// obstacle velocity obstacles: each nearby edge only forbids moving towards
+/// it faster than the gap can close within `obstacle_time_horizon`.
+/// Neighbours are picked from the agents at the indices in `nearby`.
+pub fn compute_velocity(
+    index: usize,
+    agents: &[OrcaAgent],
+    nearby: &[usize],
+    edges: &[([f64; 2], [f64; 2])],
+    settings: &OrcaSettings,
+    dt: f64,
+) -> [f64; 2] {
+    let agent = agents[index];
+    let r = settings.radius;
+    let mut lines: Vec<Line> = Vec::new();
+
+    let obstacle_range = r + settings.max_speed * settings.obstacle_time_horizon;
+    for (a, b) in edges.iter() {
+        let c = closest_point(agent.position, *a, *b);
+        let d = distance(agent.position, c);
+        if d > obstacle_range || d < EPSILON {
+            continue;
+        }
+        let n = scale(sub(agent.position, c), 1. / d);
+        // allowed: velocity . n >= k
+        let k = if d > r {
+            -(d - r) / settings.obstacle_time_horizon
+        } else {
+            (r - d) / dt
+        };
+        lines.push(Line {
+            point: scale(n, k),
+            direction: [n[1], -n[0]],
+        });
+    }
+    let obstacle_lines = lines.len();
+
+    let mut neighbors: Vec<(f64, usize)> = nearby
+        .iter()
+        .filter(|i| **i != index)
+        .map(|i| (distance(agent.position, agents[*i].position), *i))
+        .filter(|(d, _)| *d < settings.neighbor_dist)
+        .collect();
+    neighbors.sort_by(|a, b| a.0.total_cmp(&b.0));
+    neighbors.truncate(settings.max_neighbors);
+
+    let inv_time_horizon = 1. / settings.time_horizon;
+    let combined_radius = 2. * r;
+    let combined_radius_sq = combined_radius * combined_radius;
+    for (_, i) in neighbors.into_iter() {
+        let other = agents[i];
+        let relative_position = sub(other.position, agent.position);
+        let relative_velocity = sub(agent.velocity, other.velocity);
+        let dist_sq = dot(relative_position, relative_position);
+
+        let (direction, u);
+        if dist_sq > combined_radius_sq {
+            // vector from cutoff center to relative velocity
+            let w = sub(
+                relative_velocity,
+                scale(relative_position, inv_time_horizon),
+            );
+            let w_length_sq = dot(w, w);
+            let dot_product = dot(w, relative_position);
+            if dot_product < 0. && dot_product * dot_product > combined_radius_sq * w_length_sq {
+                // project on cut-off circle
+                let w_length = w_length_sq.sqrt();
+                let unit_w = scale(w, 1. / w_length);
+                direction = [unit_w[1], -unit_w[0]];
+                u = scale(unit_w, combined_radius * inv_time_horizon - w_length);
+            } else {
+                // project on legs
+                let leg = (dist_sq - combined_radius_sq).sqrt();
+                let [px, py] = relative_position;
+                direction = if det(relative_position, w) > 0. {
+                    scale(
+                        [
+                            px * leg - py * combined_radius,
+                            px * combined_radius + py * leg,
+                        ],
+                        1. / dist_sq,
+                    )
+                } else {
+                    scale(
+                        [
+                            px * leg + py * combined_radius,
+                            -px * combined_radius + py * leg,
+                        ],
+                        -1. / dist_sq,
+                    )
+                };
+                u = sub(
+                    scale(direction, dot(relative_velocity, direction)),
+                    relative_velocity,
+                );
+            }
+        } else {
+            // already overlapping, resolve within one step
+            let w = sub(relative_velocity, scale(relative_position, 1. / dt));
+            let w_length = length(w);
+            let unit_w = normalize(w);
+            direction = [unit_w[1], -unit_w[0]];
+            u = scale(unit_w, combined_radius / dt - w_length);
+        }
+        lines.push(Line {
+            point: add(agent.velocity, scale(u, 0.5)),
+            direction,
+        });
+    }
+
+    let mut result = [0., 0.];
+    let fail = linear_program2(
+        &lines,
+        settings.max_speed,
+        agent.preferred_velocity,
+        false,
+        &mut result,
+    );
+    if fail < lines.len() {
+        linear_program3(
+            &lines,
+            obstacle_lines,
+            fail,
+            settings.max_speed,
+            &mut result,
+        );
+    }
+    result
+}
+
+fn linear_program1(
+    lines: &[Line],
+    line_no: usize,
+    radius: f64,
+    opt_velocity: [f64; 2],
+    direction_opt: bool,
+    result: &mut [f64; 2],
+) -> bool {
+    let line = lines[line_no];
+    let dot_product = dot(line.point, line.direction);
+    let discriminant = dot_product * dot_product + radius * radius - dot(line.point, line.point);
+    if discriminant < 0. {
+        // max speed circle fully invalidates this line
+        return false;
+    }
+    let sqrt_discriminant = discriminant.sqrt();
+    let mut t_left = -dot_product - sqrt_discriminant;
+    let mut t_right = -dot_product + sqrt_discriminant;
+
+    for other in lines.iter().take(line_no) {
+        let denominator = det(line.direction, other.direction);
+        let numerator = det(other.direction, sub(line.point, other.point));
+        if denominator.abs() <= EPSILON {
+            // parallel lines
+            if numerator < 0. {
+                return false;
+            }
+            continue;
+        }
+        let t = numerator / denominator;
+        if denominator >= 0. {
+            t_right = t_right.min(t);
+        } else {
+            t_left = t_left.max(t);
+        }
+        if t_left > t_right {
+            return false;
+        }
+    }
+
+    let t = if direction_opt {
+        if dot(opt_velocity, line.direction) > 0. {
+            t_right
+        } else {
+            t_left
+        }
+    } else {
+        dot(line.direction, sub(opt_velocity, line.point)).clamp(t_left, t_right)
+    };
+    *result = add(line.point, scale(line.direction, t));
+    true
+}
+
+fn linear_program2(
+    lines: &[Line],
+    radius: f64,
+    opt_velocity: [f64; 2],
+    direction_opt: bool,
+    result: &mut [f64; 2],
+) -> usize {
+    *result = if direction_opt {
+        // opt_velocity is a unit direction here
+        scale(opt_velocity, radius)
+    } else if dot(opt_velocity, opt_velocity) > radius * radius {
+        scale(normalize(opt_velocity), radius)
+    } else {
+        opt_velocity
+    };
+
+    for i in 0..lines.len() {
+        if det(lines[i].direction, sub(lines[i].point, *result)) > 0. {
+            let previous = *result;
+            if !linear_program1(lines, i, radius, opt_velocity, direction_opt, result) {
+                *result = previous;
+                return i;
+            }
+        }
+    }
+    lines.len()
+}
+
+/// Used when the constraints are infeasible: finds the velocity that violates
+/// the agent lines the least, never giving up on the obstacle lines.
+fn linear_program3(
+    lines: &[Line],
+    obstacle_lines: usize,
+    begin_line: usize,
+    radius: f64,
+    result: &mut [f64; 2],
+) {
+    let mut dist = 0.;
+    for i in begin_line..lines.len() {
+        if det(lines[i].direction, sub(lines[i].point, *result)) <= dist {
+            continue;
+        }
+        let mut projected: Vec<Line> = lines[..obstacle_lines].to_vec();
+        for j in obstacle_lines..i {
+            let determinant = det(lines[i].direction, lines[j].direction);
+            let point = if determinant.abs() <= EPSILON {
+                if dot(lines[i].direction, lines[j].direction) > 0. {
+                    // same direction
+                    continue;
+                }
+                scale(add(lines[i].point, lines[j].point), 0.5)
+            } else {
+                add(
+                    lines[i].point,
+                    scale(
+                        lines[i].direction,
+                        det(lines[j].direction, sub(lines[i].point, lines[j].point)) / determinant,
+                    ),
+                )
+            };
+            projected.push(Line {
+                point,
+                direction: normalize(sub(lines[j].direction, lines[i].direction)),
+            });
+        }
+
+        let previous = *result;
+        let direction = [-lines[i].direction[1], lines[i].direction[0]];
+        if linear_program2(&projected, radius, direction, true, result) < projected.len() {
+            // can only fail because of rounding, keep the previous result
+            *result = previous;
+        }
+        dist = det(lines[i].direction, sub(lines[i].point, *result));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Moves every agent by its ORCA velocity for `steps` steps of `dt`,
+    /// returning the smallest distance between the two first ones.
+    fn simulate(agents: &mut [OrcaAgent], walls: &[Wall], steps: usize, dt: f64) -> f64 {
+        let settings = OrcaSettings::default();
+        let nearby: Vec<usize> = (0..agents.len()).collect();
+        let mut closest = f64::MAX;
+        for _ in 0..steps {
+            let velocities: Vec<[f64; 2]> = (0..agents.len())
+                .map(|i| compute_velocity(i, agents, &nearby, walls, &settings, dt))
+                .collect();
+            for (agent, velocity) in agents.iter_mut().zip(velocities) {
+                agent.velocity = velocity;
+                agent.position = add(agent.position, scale(velocity, dt));
+            }
+            if agents.len() > 1 {
+                closest = closest.min(distance(agents[0].position, agents[1].position));
+            }
+        }
+        closest
+    }
+
+    #[test]
+    fn unhindered_agents_keep_their_preferred_velocity() {
+        let agents = [OrcaAgent {
+            position: [5., 5.],
+            velocity: [0., 0.],
+            preferred_velocity: [3., 4.],
+        }];
+        let v = compute_velocity(0, &agents, &[0], &[], &OrcaSettings::default(), 0.05);
+        assert!(distance(v, [3., 4.]) < 1e-9);
+    }
+
+    #[test]
+    fn agents_walking_at_each_other_pass_without_touching() {
+        let mut agents = [
+            OrcaAgent {
+                position: [0., 0.],
+                velocity: [2., 0.],
+                preferred_velocity: [2., 0.],
+            },
+            OrcaAgent {
+                position: [10., 0.1],
+                velocity: [-2., 0.],
+                preferred_velocity: [-2., 0.],
+            },
+        ];
+        let closest = simulate(&mut agents, &[], 200, 0.05);
+        let r = OrcaSettings::default().radius;
+        assert!(closest >= 2. * r - 1e-3, "came within {}", closest);
+        // and got past each other
+        assert!(agents[0].position[0] > 15. && agents[1].position[0] < -5.);
+    }
+
+    #[test]
+    fn agents_stop_short_of_blocked_cells() {
+        let space_lut: HashMap<(i64, i64), bool> = [((5, 0), true)].into_iter().collect();
+        let walls = cell_edges(&space_lut, (5, 0));
+        let mut agents = [OrcaAgent {
+            position: [2.5, 0.5],
+            velocity: [0., 0.],
+            preferred_velocity: [4., 0.],
+        }];
+        simulate(&mut agents, &walls, 100, 0.05);
+        let r = OrcaSettings::default().radius;
+        assert!(agents[0].position[0] <= 5. - r + 1e-3);
+        assert!(agents[0].position[0] > 4.);
+    }
+}
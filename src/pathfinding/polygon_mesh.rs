@@ -0,0 +1,566 @@
+use super::influence::{CostLayer, MIN_FACTOR};
+use super::search::a_star;
+use super::shape::{
+    clip_polygon, cross, distance, point_in_polygon, segment_intersection, ShapeParams,
+};
+use super::ContinuousPlanner;
+use crate::ecs::pos2::Pos2;
+use std::collections::{HashMap, VecDeque};
+
+const EPSILON: f64 = 1e-9;
+const SNAP: f64 = 1e6;
+/// Constraint segments shorter than this are not split any further.
+const MIN_SPLIT_LENGTH: f64 = 1e-2;
+const MAX_RECOVERY_PASSES: usize = 16;
+
+/// A free-space triangle. `neighbors[i]` is the triangle across the edge
+/// `vertices[i] -> vertices[(i + 1) % 3]`. Vertices are counter-clockwise.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NavTriangle {
+    pub vertices: [usize; 3],
+    pub neighbors: [Option<usize>; 3],
+}
+
+/// Polygonal navigation mesh: a constrained Delaunay triangulation of the free
+/// space between the grid boundaries and the obstacle shapes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PolygonNavMesh {
+    pub vertices: Vec<[f64; 2]>,
+    pub triangles: Vec<NavTriangle>,
+    /// Tactical costs, weighing the corridor search between centroids.
+    pub costs: CostLayer,
+}
+
+impl PolygonNavMesh {
+    pub fn from_obstacles(min: Pos2, max: Pos2, obstacles: &[ShapeParams]) -> Self {
+        let min = [min.x as f64, min.y as f64];
+        let max = [max.x as f64, max.y as f64];
+        if max[0] - min[0] <= EPSILON || max[1] - min[1] <= EPSILON {
+            return Self::default();
+        }
+
+        let mut outlines: Vec<Vec<[f64; 2]>> =
+            vec![vec![min, [max[0], min[1]], max, [min[0], max[1]]]];
+        let mut holes: Vec<Vec<[f64; 2]>> = Vec::new();
+        for obs in obstacles.iter() {
+            let clipped = clip_polygon(&obs.outline(), min, max);
+            if clipped.len() >= 3 {
+                outlines.push(clipped.clone());
+                holes.push(clipped);
+            }
+        }
+
+        let mut segments: Vec<([f64; 2], [f64; 2])> = Vec::new();
+        for outline in outlines.iter() {
+            for i in 0..outline.len() {
+                let (a, b) = (outline[i], outline[(i + 1) % outline.len()]);
+                if distance(a, b) > EPSILON {
+                    segments.push((a, b));
+                }
+            }
+        }
+
+        let mut triangulation = Triangulation::new(min, max);
+        let mut constraints: Vec<(usize, usize)> = Vec::new();
+        for (a, b) in split_segments(&segments) {
+            let ia = triangulation.insert(a);
+            let ib = triangulation.insert(b);
+            if ia != ib {
+                constraints.push((ia, ib));
+            }
+        }
+        triangulation.recover_constraints(constraints);
+
+        Self::from_triangulation(&triangulation, min, max, &holes)
+    }
+
+    fn from_triangulation(
+        triangulation: &Triangulation,
+        min: [f64; 2],
+        max: [f64; 2],
+        holes: &[Vec<[f64; 2]>],
+    ) -> Self {
+        let mut remap: HashMap<usize, usize> = HashMap::new();
+        let mut vertices = Vec::new();
+        let mut kept: Vec<[usize; 3]> = Vec::new();
+
+        for tri in triangulation.triangles.iter().flatten() {
+            if tri.iter().any(|v| *v < 3) {
+                continue;
+            }
+            let [a, b, c] = tri.map(|v| triangulation.points[v]);
+            let centroid = [(a[0] + b[0] + c[0]) / 3., (a[1] + b[1] + c[1]) / 3.];
+            if centroid[0] < min[0]
+                || centroid[0] > max[0]
+                || centroid[1] < min[1]
+                || centroid[1] > max[1]
+                || holes.iter().any(|h| point_in_polygon(centroid, h))
+            {
+                continue;
+            }
+            kept.push(tri.map(|v| {
+                *remap.entry(v).or_insert_with(|| {
+                    vertices.push(triangulation.points[v]);
+                    vertices.len() - 1
+                })
+            }));
+        }
+
+        let mut edge_owner: HashMap<(usize, usize), usize> = HashMap::new();
+        for (i, tri) in kept.iter().enumerate() {
+            for k in 0..3 {
+                edge_owner.insert((tri[k], tri[(k + 1) % 3]), i);
+            }
+        }
+        let triangles = kept
+            .iter()
+            .map(|tri| NavTriangle {
+                vertices: *tri,
+                neighbors: [0, 1, 2].map(|k| {
+                    let twin = (tri[(k + 1) % 3], tri[k]);
+                    edge_owner.get(&twin).copied()
+                }),
+            })
+            .collect();
+
+        Self {
+            vertices,
+            triangles,
+            costs: CostLayer::default(),
+        }
+    }
+
+    pub fn with_costs(mut self, costs: CostLayer) -> Self {
+        self.costs = costs;
+        self
+    }
+
+    pub fn triangle_points(&self, t: usize) -> [[f64; 2]; 3] {
+        self.triangles[t].vertices.map(|v| self.vertices[v])
+    }
+
+    fn centroid(&self, t: usize) -> [f64; 2] {
+        let [a, b, c] = self.triangle_points(t);
+        [(a[0] + b[0] + c[0]) / 3., (a[1] + b[1] + c[1]) / 3.]
+    }
+
+    /// Index of the triangle containing `p`, if `p` is in free space.
+    pub fn locate(&self, p: [f64; 2]) -> Option<usize> {
+        (0..self.triangles.len()).find(|t| {
+            let [a, b, c] = self.triangle_points(*t);
+            cross(a, b, p) >= -EPSILON && cross(b, c, p) >= -EPSILON && cross(c, a, p) >= -EPSILON
+        })
+    }
+
+    /// Triangle corridor from `start` to `end` over the adjacency graph.
+    pub fn triangle_path(&self, start: [f64; 2], end: [f64; 2]) -> Option<Vec<usize>> {
+        let start_tri = self.locate(start)?;
+        let end_tri = self.locate(end)?;
+        let min_factor = if self.costs.is_empty() {
+            1.
+        } else {
+            MIN_FACTOR
+        };
+        a_star(
+            start_tri,
+            |t| {
+                let from = self.centroid(*t);
+                self.triangles[*t]
+                    .neighbors
+                    .iter()
+                    .flatten()
+                    .map(|n| {
+                        let to = self.centroid(*n);
+                        (*n, distance(from, to) * self.costs.segment_factor(from, to))
+                    })
+                    .collect::<Vec<_>>()
+            },
+            |t| distance(self.centroid(*t), end) * min_factor,
+            |t| *t == end_tri,
+        )
+        .map(|(corridor, _)| corridor)
+    }
+}
+
+impl ContinuousPlanner for PolygonNavMesh {
+    /// Straight-line path from `start` to `end`, pulled taut through the
+    /// triangle corridor with the funnel algorithm.
+    fn find_path(&self, start: [f64; 2], end: [f64; 2]) -> Option<Vec<[f64; 2]>> {
+        let corridor = self.triangle_path(start, end)?;
+
+        // (left, right) portal pairs, as seen when walking along the corridor.
+        let mut portals = vec![(start, start)];
+        for pair in corridor.windows(2) {
+            let tri = &self.triangles[pair[0]];
+            let k = tri.neighbors.iter().position(|n| *n == Some(pair[1]))?;
+            let right = self.vertices[tri.vertices[k]];
+            let left = self.vertices[tri.vertices[(k + 1) % 3]];
+            portals.push((left, right));
+        }
+        portals.push((end, end));
+
+        Some(string_pull(&portals))
+    }
+
+    fn name(&self) -> &'static str {
+        "polygon_mesh"
+    }
+}
+
+/// Simple stupid funnel algorithm over (left, right) portals.
+fn string_pull(portals: &[([f64; 2], [f64; 2])]) -> Vec<[f64; 2]> {
+    let same = |a: [f64; 2], b: [f64; 2]| distance(a, b) < EPSILON;
+
+    let mut path = vec![portals[0].0];
+    let (mut apex, mut left, mut right) = (portals[0].0, portals[0].0, portals[0].1);
+    let (mut left_i, mut right_i) = (0, 0);
+
+    let mut i = 1;
+    while i < portals.len() {
+        let (l, r) = portals[i];
+
+        // Tighten the right side of the funnel.
+        if cross(apex, right, r) >= 0. {
+            if same(apex, right) || cross(apex, left, r) < 0. {
+                right = r;
+                right_i = i;
+            } else {
+                // Right crossed over left, left becomes the new apex.
+                path.push(left);
+                apex = left;
+                (right, right_i) = (left, left_i);
+                i = left_i + 1;
+                continue;
+            }
+        }
+
+        // Tighten the left side of the funnel.
+        if cross(apex, left, l) <= 0. {
+            if same(apex, left) || cross(apex, right, l) > 0. {
+                left = l;
+                left_i = i;
+            } else {
+                path.push(right);
+                apex = right;
+                (left, left_i) = (right, right_i);
+                i = right_i + 1;
+                continue;
+            }
+        }
+        i += 1;
+    }
+
+    let end = portals[portals.len() - 1].0;
+    if !same(path[path.len() - 1], end) {
+        path.push(end);
+    }
+    path
+}
+
+/// Splits segments at every crossing and at every endpoint that lies on another
+/// segment, so the constraint set handed to the triangulation never overlaps.
+fn split_segments(segments: &[([f64; 2], [f64; 2])]) -> Vec<([f64; 2], [f64; 2])> {
+    let mut result = Vec::new();
+    for (i, (a, b)) in segments.iter().enumerate() {
+        let len = distance(*a, *b);
+        let mut cuts: Vec<f64> = vec![0., 1.];
+        for (j, (c, d)) in segments.iter().enumerate() {
+            if i == j {
+                continue;
+            }
+            if let Some(p) = segment_intersection(*a, *b, *c, *d) {
+                cuts.push(distance(*a, p) / len);
+            }
+            for p in [*c, *d] {
+                if cross(*a, *b, p).abs() < EPSILON * len.max(1.) {
+                    let t = ((p[0] - a[0]) * (b[0] - a[0]) + (p[1] - a[1]) * (b[1] - a[1]))
+                        / (len * len);
+                    if t > EPSILON && t < 1. - EPSILON {
+                        cuts.push(t);
+                    }
+                }
+            }
+        }
+        cuts.sort_by(|x, y| x.total_cmp(y));
+        cuts.dedup_by(|x, y| (*x - *y).abs() * len < EPSILON);
+        for w in cuts.windows(2) {
+            let p = |t: f64| [a[0] + t * (b[0] - a[0]), a[1] + t * (b[1] - a[1])];
+            result.push((p(w[0]), p(w[1])));
+        }
+    }
+    result
+}
+
+/// Incremental Bowyer-Watson triangulation. The first three points form a
+/// super triangle that encloses everything and is discarded afterwards.
+struct Triangulation {
+    points: Vec<[f64; 2]>,
+    triangles: Vec<Option<[usize; 3]>>,
+    edges: HashMap<(usize, usize), usize>,
+    lookup: HashMap<(i64, i64), usize>,
+}
+
+impl Triangulation {
+    fn new(min: [f64; 2], max: [f64; 2]) -> Self {
+        let center = [(min[0] + max[0]) / 2., (min[1] + max[1]) / 2.];
+        let r = (max[0] - min[0]).max(max[1] - min[1]) * 20.;
+        let mut triangulation = Self {
+            points: vec![
+                [center[0] - 2. * r, center[1] - r],
+                [center[0] + 2. * r, center[1] - r],
+                [center[0], center[1] + 2. * r],
+            ],
+            triangles: Vec::new(),
+            edges: HashMap::new(),
+            lookup: HashMap::new(),
+        };
+        triangulation.add_triangle([0, 1, 2]);
+        triangulation
+    }
+
+    fn add_triangle(&mut self, tri: [usize; 3]) {
+        let index = self.triangles.len();
+        for k in 0..3 {
+            self.edges.insert((tri[k], tri[(k + 1) % 3]), index);
+        }
+        self.triangles.push(Some(tri));
+    }
+
+    fn remove_triangle(&mut self, index: usize) -> [usize; 3] {
+        let tri = self.triangles[index].take().unwrap();
+        for k in 0..3 {
+            self.edges.remove(&(tri[k], tri[(k + 1) % 3]));
+        }
+        tri
+    }
+
+    fn has_edge(&self, a: usize, b: usize) -> bool {
+        self.edges.contains_key(&(a, b)) || self.edges.contains_key(&(b, a))
+    }
+
+    fn in_circumcircle(&self, tri: [usize; 3], p: [f64; 2]) -> bool {
+        let [a, b, c] = tri.map(|v| self.points[v]);
+        let (adx, ady) = (a[0] - p[0], a[1] - p[1]);
+        let (bdx, bdy) = (b[0] - p[0], b[1] - p[1]);
+        let (cdx, cdy) = (c[0] - p[0], c[1] - p[1]);
+        let det = (adx * adx + ady * ady) * (bdx * cdy - cdx * bdy)
+            - (bdx * bdx + bdy * bdy) * (adx * cdy - cdx * ady)
+            + (cdx * cdx + cdy * cdy) * (adx * bdy - bdx * ady);
+        det > EPSILON
+    }
+
+    fn contains(&self, tri: [usize; 3], p: [f64; 2]) -> bool {
+        let [a, b, c] = tri.map(|v| self.points[v]);
+        cross(a, b, p) >= -EPSILON && cross(b, c, p) >= -EPSILON && cross(c, a, p) >= -EPSILON
+    }
+
+    /// Walks from the most recently added triangle towards `p`, falling back to
+    /// a full scan if the walk gets lost.
+    fn locate(&self, p: [f64; 2]) -> Option<usize> {
+        let mut current = self.triangles.iter().rposition(|t| t.is_some())?;
+        for _ in 0..self.triangles.len() {
+            let tri = self.triangles[current].unwrap();
+            let exit = (0..3).find(|k| {
+                let (a, b) = (tri[*k], tri[(*k + 1) % 3]);
+                cross(self.points[a], self.points[b], p) < -EPSILON
+            });
+            let Some(k) = exit else {
+                return Some(current);
+            };
+            match self.edges.get(&(tri[(k + 1) % 3], tri[k])) {
+                Some(next) => current = *next,
+                None => break,
+            }
+        }
+        self.triangles
+            .iter()
+            .position(|t| t.is_some_and(|tri| self.contains(tri, p)))
+    }
+
+    /// Inserts a point and returns its index. Points closer than the snapping
+    /// resolution to an existing point reuse that point.
+    fn insert(&mut self, p: [f64; 2]) -> usize {
+        let key = ((p[0] * SNAP).round() as i64, (p[1] * SNAP).round() as i64);
+        if let Some(existing) = self.lookup.get(&key) {
+            return *existing;
+        }
+
+        let Some(containing) = self.locate(p) else {
+            return usize::MAX;
+        };
+
+        let index = self.points.len();
+        self.points.push(p);
+        self.lookup.insert(key, index);
+
+        // Grow the cavity outwards from the containing triangle so it stays connected.
+        let mut cavity = vec![containing];
+        let mut queue = VecDeque::from([containing]);
+        let mut boundary: Vec<(usize, usize)> = Vec::new();
+        while let Some(t) = queue.pop_front() {
+            let tri = self.triangles[t].unwrap();
+            for k in 0..3 {
+                let (a, b) = (tri[k], tri[(k + 1) % 3]);
+                match self.edges.get(&(b, a)) {
+                    Some(n) if cavity.contains(n) => {}
+                    Some(n) if self.in_circumcircle(self.triangles[*n].unwrap(), p) => {
+                        cavity.push(*n);
+                        queue.push_back(*n);
+                    }
+                    _ => boundary.push((a, b)),
+                }
+            }
+        }
+        // Edges shared by two cavity triangles are interior; only keep the outer ring.
+        boundary.retain(|(a, b)| {
+            !self
+                .edges
+                .get(&(*b, *a))
+                .is_some_and(|n| cavity.contains(n))
+        });
+
+        for t in cavity {
+            self.remove_triangle(t);
+        }
+        for (a, b) in boundary {
+            if cross(self.points[a], self.points[b], p).abs() > EPSILON {
+                self.add_triangle([a, b, index]);
+            }
+        }
+        index
+    }
+
+    /// Makes every constraint an edge of the triangulation by inserting midpoints
+    /// (a conforming constrained Delaunay triangulation). Later insertions can
+    /// break earlier edges, so this repeats until nothing changes.
+    fn recover_constraints(&mut self, mut constraints: Vec<(usize, usize)>) {
+        for _ in 0..MAX_RECOVERY_PASSES {
+            let mut changed = false;
+            let mut next = Vec::with_capacity(constraints.len());
+            for (a, b) in constraints {
+                if a == usize::MAX || b == usize::MAX || self.has_edge(a, b) {
+                    next.push((a, b));
+                    continue;
+                }
+                let (pa, pb) = (self.points[a], self.points[b]);
+                if distance(pa, pb) < MIN_SPLIT_LENGTH {
+                    next.push((a, b));
+                    continue;
+                }
+                let m = self.insert([(pa[0] + pb[0]) / 2., (pa[1] + pb[1]) / 2.]);
+                if m == a || m == b || m == usize::MAX {
+                    next.push((a, b));
+                    continue;
+                }
+                next.push((a, m));
+                next.push((m, b));
+                changed = true;
+            }
+            constraints = next;
+            if !changed {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pathfinding::shape::{CircleParams, RectParams};
+
+    fn rect(x: f64, y: f64, width: f64, height: f64) -> ShapeParams {
+        ShapeParams::Rectangle(RectParams {
+            center_x: x,
+            center_y: y,
+            width,
+            height,
+        })
+    }
+
+    /// The inside of a hole, without its edges paths may run along.
+    fn inside(x: f64, y: f64, width: f64, height: f64) -> ShapeParams {
+        rect(x + 1e-6, y + 1e-6, width - 2e-6, height - 2e-6)
+    }
+
+    fn mesh(obstacles: &[ShapeParams]) -> PolygonNavMesh {
+        PolygonNavMesh::from_obstacles(Pos2::new(0, 0), Pos2::new(50, 50), obstacles)
+    }
+
+    fn crosses(path: &[[f64; 2]], hole: &ShapeParams) -> bool {
+        path.windows(2).any(|w| hole.intersects_segment(w[0], w[1]))
+    }
+
+    #[test]
+    fn no_triangle_lies_in_a_hole() {
+        let holes = [rect(10., 10., 10., 20.), rect(30., 5., 5., 40.)];
+        let navmesh = mesh(&holes);
+        assert!(!navmesh.triangles.is_empty());
+        for t in 0..navmesh.triangles.len() {
+            let c = navmesh.centroid(t);
+            assert!(holes.iter().all(|hole| !hole.contains(c)), "triangle {}", t);
+        }
+        assert_eq!(navmesh.locate([15., 20.]), None);
+        assert!(navmesh.locate([5., 5.]).is_some());
+    }
+
+    #[test]
+    fn straight_line_when_nothing_is_in_the_way() {
+        let navmesh = mesh(&[rect(10., 30., 30., 10.)]);
+        let path = navmesh.find_path([5., 5.], [45., 20.]).unwrap();
+        assert_eq!(path, vec![[5., 5.], [45., 20.]]);
+    }
+
+    #[test]
+    fn funnel_paths_bend_around_holes() {
+        let navmesh = mesh(&[rect(20., 0., 10., 40.)]);
+        let path = navmesh.find_path([10., 20.], [40., 20.]).unwrap();
+        assert_eq!(path.first(), Some(&[10., 20.]));
+        assert_eq!(path.last(), Some(&[40., 20.]));
+        assert!(!crosses(&path, &inside(20., 0., 10., 40.)));
+        // over the top, the only way past, pulled taut to its two corners
+        assert_eq!(path, vec![[10., 20.], [20., 40.], [30., 40.], [40., 20.]]);
+    }
+
+    #[test]
+    fn funnel_paths_keep_out_of_every_hole_on_the_way() {
+        let holes = [
+            (10., 10., 10., 20.),
+            (25., 20., 5., 30.),
+            (35., 0., 5., 35.),
+        ];
+        let obstacles: Vec<ShapeParams> = holes
+            .iter()
+            .map(|(x, y, w, h)| rect(*x, *y, *w, *h))
+            .collect();
+        let navmesh = mesh(&obstacles);
+        let path = navmesh.find_path([2., 20.], [48., 10.]).unwrap();
+        assert!(path.len() > 2);
+        for (x, y, w, h) in holes {
+            assert!(!crosses(&path, &inside(x, y, w, h)), "crosses {:?}", (x, y));
+        }
+    }
+
+    #[test]
+    fn no_path_into_or_across_a_hole() {
+        let circle = ShapeParams::Circle(CircleParams {
+            center_x: 25.,
+            center_y: 25.,
+            radius_x: 5.,
+            radius_y: 5.,
+        });
+        // a wall across the whole mesh
+        let navmesh = mesh(&[circle, rect(0., 40., 50., 2.)]);
+        assert!(navmesh.find_path([5., 5.], [25., 25.]).is_none());
+        assert!(navmesh.find_path([5., 5.], [5., 45.]).is_none());
+        let path = navmesh.find_path([5., 25.], [45., 25.]).unwrap();
+        // around the outline, which circumscribes the circle
+        let inner = ShapeParams::Circle(CircleParams {
+            center_x: 25.,
+            center_y: 25.,
+            radius_x: 4.9,
+            radius_y: 4.9,
+        });
+        assert!(!crosses(&path, &inner));
+    }
+}
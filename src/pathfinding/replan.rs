@@ -0,0 +1,98 @@
+use super::NavMesh;
+use crate::ecs::pos2::Pos2;
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum ReplanStrategy {
+    /// Plans again from the current cell with the selected planner.
+    Full,
+    /// Detours around the blocked stretch with grid A* and keeps the rest.
+    LocalRepair,
+    /// Stops and checks again later, replanning fully if it stays blocked.
+    WaitAndRetry,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct ReplanSettings {
+    pub strategy: ReplanStrategy,
+    /// How many upcoming path cells are checked every tick.
+    pub lookahead: usize,
+    /// Seconds between two checks while waiting.
+    pub retry_delay: f32,
+    pub max_retries: u32,
+}
+
+impl Default for ReplanSettings {
+    fn default() -> Self {
+        Self {
+            strategy: ReplanStrategy::LocalRepair,
+            lookahead: 8,
+            retry_delay: 1.,
+            max_retries: 3,
+        }
+    }
+}
+
+/// Index of the first of the next `lookahead` cells of `path` that is blocked.
+pub fn first_blocked(
+    path: &[Pos2],
+    lookahead: usize,
+    is_blocked: impl Fn(&Pos2) -> bool,
+) -> Option<usize> {
+    path.iter().take(lookahead).position(is_blocked)
+}
+
+impl NavMesh {
+    /// Replaces the blocked stretch starting at `path[blocked]` with a grid A*
+    /// detour from `from` to the first free cell after it. `None` when the path
+    /// is blocked all the way to its end or there is no way around.
+    pub fn repair_path(
+        &self,
+        from: Pos2,
+        path: &[Pos2],
+        blocked: usize,
+        is_blocked: impl Fn(&Pos2) -> bool,
+    ) -> Option<Vec<Pos2>> {
+        let rejoin = blocked + path[blocked..].iter().position(|p| !is_blocked(p))?;
+        let mut repaired = self.a_star(from, path[rejoin])?;
+        repaired.extend_from_slice(&path[rejoin + 1..]);
+        Some(repaired)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_next_cells_are_checked() {
+        let path: Vec<Pos2> = (1..10).map(|x| Pos2::new(x, 0)).collect();
+        let blocked = |p: &Pos2| p.x == 6;
+        assert_eq!(first_blocked(&path, 5, blocked), None);
+        assert_eq!(first_blocked(&path, 6, blocked), Some(5));
+    }
+
+    #[test]
+    fn repairs_rejoin_the_path_after_the_blocked_stretch() {
+        let mut navmesh = NavMesh::default();
+        navmesh.set_grid_boundaries(Pos2::new(0, 0), Pos2::new(10, 10));
+        for x in [4, 5] {
+            navmesh.space_lut.insert((x, 5), true);
+        }
+        let path: Vec<Pos2> = (1..10).map(|x| Pos2::new(x, 5)).collect();
+        let is_blocked = |p: &Pos2| navmesh.space_lut.contains_key(&p.to_tuple());
+        let index = first_blocked(&path, 8, is_blocked).unwrap();
+        let repaired = navmesh
+            .repair_path(Pos2::new(1, 5), &path, index, is_blocked)
+            .unwrap();
+        assert!(!repaired.iter().any(is_blocked));
+        assert!(repaired.ends_with(&path[5..]));
+        assert!(repaired
+            .windows(2)
+            .all(|w| (w[0].x - w[1].x).abs() <= 1 && (w[0].y - w[1].y).abs() <= 1));
+        // blocked up to its end, there is nothing to rejoin
+        assert!(navmesh
+            .repair_path(Pos2::new(1, 5), &path[..5], index, is_blocked)
+            .is_none());
+    }
+}
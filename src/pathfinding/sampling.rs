@@ -0,0 +1,554 @@
+use super::search::a_star;
+use super::shape::{distance, ShapeParams};
+use super::ContinuousPlanner;
+use crate::ecs::pos2::Pos2;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, HashSet};
+use std::f64::consts::PI;
+use std::sync::mpsc;
+
+/// Resolution used when walking a segment over the `space_lut`.
+const LUT_CHECK_STEP: f64 = 0.1;
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum SamplingAlgorithm {
+    Prm,
+    Rrt,
+    RrtStar,
+    RrtConnect,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum CollisionModel {
+    SpaceLut,
+    Shapes,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct SamplingSettings {
+    pub algorithm: SamplingAlgorithm,
+    pub collision: CollisionModel,
+    pub seed: u64,
+    pub max_samples: usize,
+    pub step_size: f64,
+    pub goal_bias: f64,
+    pub prm_neighbors: usize,
+}
+
+impl Default for SamplingSettings {
+    fn default() -> Self {
+        Self {
+            algorithm: SamplingAlgorithm::Rrt,
+            collision: CollisionModel::Shapes,
+            seed: 0,
+            max_samples: 2000,
+            step_size: 3.,
+            goal_bias: 0.05,
+            prm_neighbors: 10,
+        }
+    }
+}
+
+/// Growth of a planner's tree (or PRM roadmap), streamed so it can be animated.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TreeEvent {
+    Add {
+        from: [f64; 2],
+        to: [f64; 2],
+        tree: usize,
+    },
+    Remove {
+        from: [f64; 2],
+        to: [f64; 2],
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct CollisionChecker {
+    min: [f64; 2],
+    max: [f64; 2],
+    model: CollisionModel,
+    space_lut: HashMap<(i64, i64), bool>,
+    obstacles: Vec<ShapeParams>,
+}
+
+impl CollisionChecker {
+    pub fn new(
+        min: Pos2,
+        max: Pos2,
+        model: CollisionModel,
+        space_lut: HashMap<(i64, i64), bool>,
+        obstacles: Vec<ShapeParams>,
+    ) -> Self {
+        Self {
+            min: [min.x as f64, min.y as f64],
+            max: [max.x as f64, max.y as f64],
+            model,
+            space_lut,
+            obstacles,
+        }
+    }
+
+    pub fn is_free(&self, p: [f64; 2]) -> bool {
+        if p[0] < self.min[0] || p[0] > self.max[0] || p[1] < self.min[1] || p[1] > self.max[1] {
+            return false;
+        }
+        match self.model {
+            CollisionModel::SpaceLut => !self
+                .space_lut
+                .contains_key(&(p[0].floor() as i64, p[1].floor() as i64)),
+            CollisionModel::Shapes => !self.obstacles.iter().any(|o| o.contains(p)),
+        }
+    }
+
+    pub fn segment_free(&self, a: [f64; 2], b: [f64; 2]) -> bool {
+        if !self.is_free(a) || !self.is_free(b) {
+            return false;
+        }
+        match self.model {
+            CollisionModel::SpaceLut => {
+                let steps = (distance(a, b) / LUT_CHECK_STEP).ceil() as usize;
+                (1..steps).all(|i| {
+                    let t = i as f64 / steps as f64;
+                    self.is_free([a[0] + t * (b[0] - a[0]), a[1] + t * (b[1] - a[1])])
+                })
+            }
+            CollisionModel::Shapes => !self.obstacles.iter().any(|o| o.intersects_segment(a, b)),
+        }
+    }
+
+    fn sample(&self, rng: &mut StdRng) -> [f64; 2] {
+        [
+            rng.gen_range(self.min[0]..=self.max[0]),
+            rng.gen_range(self.min[1]..=self.max[1]),
+        ]
+    }
+
+    fn area(&self) -> f64 {
+        (self.max[0] - self.min[0]) * (self.max[1] - self.min[1])
+    }
+
+    pub fn bounds(&self) -> ([f64; 2], [f64; 2]) {
+        (self.min, self.max)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TreeNode {
+    pos: [f64; 2],
+    parent: Option<usize>,
+    cost: f64,
+}
+
+enum Extend {
+    Trapped,
+    Advanced(usize),
+    Reached(usize),
+}
+
+/// PRM and the RRT family, sampling the grid bounds. Every query reseeds the
+/// rng from the settings, so the same query always produces the same path.
+#[derive(Clone, Debug)]
+pub struct SamplingPlanner {
+    pub settings: SamplingSettings,
+    checker: CollisionChecker,
+    tree_sender: Option<mpsc::Sender<TreeEvent>>,
+}
+
+impl SamplingPlanner {
+    pub fn new(checker: CollisionChecker, settings: SamplingSettings) -> Self {
+        Self {
+            settings,
+            checker,
+            tree_sender: None,
+        }
+    }
+
+    pub fn with_tree_sender(mut self, sender: mpsc::Sender<TreeEvent>) -> Self {
+        self.tree_sender = Some(sender);
+        self
+    }
+
+    fn emit(&self, event: TreeEvent) {
+        if let Some(sender) = &self.tree_sender {
+            // nobody listening anymore is fine, the path is what matters
+            let _ = sender.send(event);
+        }
+    }
+
+    fn steer(&self, from: [f64; 2], towards: [f64; 2]) -> [f64; 2] {
+        let d = distance(from, towards);
+        if d <= self.settings.step_size {
+            return towards;
+        }
+        let t = self.settings.step_size / d;
+        [
+            from[0] + t * (towards[0] - from[0]),
+            from[1] + t * (towards[1] - from[1]),
+        ]
+    }
+
+    fn nearest(nodes: &[TreeNode], p: [f64; 2]) -> usize {
+        (0..nodes.len())
+            .min_by(|a, b| distance(nodes[*a].pos, p).total_cmp(&distance(nodes[*b].pos, p)))
+            .unwrap()
+    }
+
+    fn trace(nodes: &[TreeNode], mut i: usize) -> Vec<[f64; 2]> {
+        let mut path = vec![nodes[i].pos];
+        while let Some(parent) = nodes[i].parent {
+            path.push(nodes[parent].pos);
+            i = parent;
+        }
+        path.reverse();
+        path
+    }
+
+    /// Probabilistic roadmap with lazy collision checking: only nodes are checked
+    /// while building, edges are checked when a search wants to use them.
+    fn prm(&self, rng: &mut StdRng, start: [f64; 2], end: [f64; 2]) -> Option<Vec<[f64; 2]>> {
+        let mut nodes = vec![start, end];
+        for _ in 0..self.settings.max_samples * 10 {
+            if nodes.len() >= self.settings.max_samples {
+                break;
+            }
+            let p = self.checker.sample(rng);
+            if self.checker.is_free(p) {
+                nodes.push(p);
+            }
+        }
+
+        let mut adjacency: Vec<Vec<usize>> = vec![Vec::new(); nodes.len()];
+        for i in 0..nodes.len() {
+            let mut by_distance: Vec<(f64, usize)> = (0..nodes.len())
+                .filter(|j| *j != i)
+                .map(|j| (distance(nodes[i], nodes[j]), j))
+                .collect();
+            let k = self.settings.prm_neighbors.min(by_distance.len());
+            if k < by_distance.len() {
+                by_distance.select_nth_unstable_by(k, |a, b| a.0.total_cmp(&b.0));
+            }
+            for (_, j) in by_distance.into_iter().take(k) {
+                if !adjacency[i].contains(&j) {
+                    adjacency[i].push(j);
+                    adjacency[j].push(i);
+                    self.emit(TreeEvent::Add {
+                        from: nodes[i],
+                        to: nodes[j],
+                        tree: 0,
+                    });
+                }
+            }
+        }
+
+        let key = |a: usize, b: usize| (a.min(b), a.max(b));
+        let mut valid: HashSet<(usize, usize)> = HashSet::new();
+        let mut invalid: HashSet<(usize, usize)> = HashSet::new();
+        loop {
+            let (path, _) = a_star(
+                0,
+                |i| {
+                    adjacency[*i]
+                        .iter()
+                        .filter(|j| !invalid.contains(&key(*i, **j)))
+                        .map(|j| (*j, distance(nodes[*i], nodes[*j])))
+                        .collect::<Vec<_>>()
+                },
+                |i| distance(nodes[*i], end),
+                |i| *i == 1,
+            )?;
+
+            let blocked = path.windows(2).find(|w| {
+                let k = key(w[0], w[1]);
+                if valid.contains(&k) {
+                    return false;
+                }
+                if self.checker.segment_free(nodes[w[0]], nodes[w[1]]) {
+                    valid.insert(k);
+                    false
+                } else {
+                    true
+                }
+            });
+            match blocked {
+                Some(w) => {
+                    invalid.insert(key(w[0], w[1]));
+                    self.emit(TreeEvent::Remove {
+                        from: nodes[w[0]],
+                        to: nodes[w[1]],
+                    });
+                }
+                None => return Some(path.into_iter().map(|i| nodes[i]).collect()),
+            }
+        }
+    }
+
+    /// RRT, or RRT* when `star` is set: new nodes pick the cheapest parent in
+    /// their neighbourhood and rewire neighbours through themselves.
+    fn rrt(
+        &self,
+        rng: &mut StdRng,
+        start: [f64; 2],
+        end: [f64; 2],
+        star: bool,
+    ) -> Option<Vec<[f64; 2]>> {
+        let mut nodes = vec![TreeNode {
+            pos: start,
+            parent: None,
+            cost: 0.,
+        }];
+        let mut children: Vec<Vec<usize>> = vec![Vec::new()];
+        let mut goal_candidates: Vec<usize> = Vec::new();
+        // gamma from the RRT* paper for two dimensions
+        let gamma = 2. * (1.5 * self.checker.area() / PI).sqrt();
+
+        for _ in 0..self.settings.max_samples {
+            let target = if rng.gen_bool(self.settings.goal_bias) {
+                end
+            } else {
+                self.checker.sample(rng)
+            };
+            let nearest = Self::nearest(&nodes, target);
+            let new = self.steer(nodes[nearest].pos, target);
+            if !self.checker.segment_free(nodes[nearest].pos, new) {
+                continue;
+            }
+
+            let mut parent = nearest;
+            let mut cost = nodes[nearest].cost + distance(nodes[nearest].pos, new);
+            let near: Vec<usize> = if star {
+                let n = nodes.len() as f64 + 1.;
+                let radius = (gamma * (n.ln() / n).sqrt()).min(self.settings.step_size);
+                (0..nodes.len())
+                    .filter(|i| distance(nodes[*i].pos, new) <= radius)
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            for j in near.iter() {
+                let c = nodes[*j].cost + distance(nodes[*j].pos, new);
+                if c < cost && self.checker.segment_free(nodes[*j].pos, new) {
+                    parent = *j;
+                    cost = c;
+                }
+            }
+
+            let index = nodes.len();
+            nodes.push(TreeNode {
+                pos: new,
+                parent: Some(parent),
+                cost,
+            });
+            children.push(Vec::new());
+            children[parent].push(index);
+            self.emit(TreeEvent::Add {
+                from: nodes[parent].pos,
+                to: new,
+                tree: 0,
+            });
+
+            for j in near.into_iter().filter(|j| *j != parent) {
+                let c = cost + distance(new, nodes[j].pos);
+                if c >= nodes[j].cost || !self.checker.segment_free(new, nodes[j].pos) {
+                    continue;
+                }
+                let old_parent = nodes[j].parent.unwrap();
+                children[old_parent].retain(|k| *k != j);
+                children[index].push(j);
+                self.emit(TreeEvent::Remove {
+                    from: nodes[old_parent].pos,
+                    to: nodes[j].pos,
+                });
+                self.emit(TreeEvent::Add {
+                    from: new,
+                    to: nodes[j].pos,
+                    tree: 0,
+                });
+                // the whole subtree gets cheaper by the same amount
+                let delta = nodes[j].cost - c;
+                nodes[j].parent = Some(index);
+                let mut stack = vec![j];
+                while let Some(k) = stack.pop() {
+                    nodes[k].cost -= delta;
+                    stack.extend(children[k].iter().copied());
+                }
+            }
+
+            if distance(new, end) <= self.settings.step_size && self.checker.segment_free(new, end)
+            {
+                goal_candidates.push(index);
+                if !star {
+                    break;
+                }
+            }
+        }
+
+        let best = goal_candidates.into_iter().min_by(|a, b| {
+            (nodes[*a].cost + distance(nodes[*a].pos, end))
+                .total_cmp(&(nodes[*b].cost + distance(nodes[*b].pos, end)))
+        })?;
+        let mut path = Self::trace(&nodes, best);
+        if distance(path[path.len() - 1], end) > 0. {
+            self.emit(TreeEvent::Add {
+                from: nodes[best].pos,
+                to: end,
+                tree: 0,
+            });
+            path.push(end);
+        }
+        Some(path)
+    }
+
+    fn extend(&self, nodes: &mut Vec<TreeNode>, tree: usize, target: [f64; 2]) -> Extend {
+        let nearest = Self::nearest(nodes, target);
+        let new = self.steer(nodes[nearest].pos, target);
+        if !self.checker.segment_free(nodes[nearest].pos, new) {
+            return Extend::Trapped;
+        }
+        nodes.push(TreeNode {
+            pos: new,
+            parent: Some(nearest),
+            cost: nodes[nearest].cost + distance(nodes[nearest].pos, new),
+        });
+        self.emit(TreeEvent::Add {
+            from: nodes[nearest].pos,
+            to: new,
+            tree,
+        });
+        if new == target {
+            Extend::Reached(nodes.len() - 1)
+        } else {
+            Extend::Advanced(nodes.len() - 1)
+        }
+    }
+
+    /// RRT-Connect: grows a tree from each end and greedily connects them.
+    fn rrt_connect(
+        &self,
+        rng: &mut StdRng,
+        start: [f64; 2],
+        end: [f64; 2],
+    ) -> Option<Vec<[f64; 2]>> {
+        let root = |pos| {
+            vec![TreeNode {
+                pos,
+                parent: None,
+                cost: 0.,
+            }]
+        };
+        let mut trees = [root(start), root(end)];
+
+        for i in 0..self.settings.max_samples {
+            let (a, b) = if i % 2 == 0 { (0, 1) } else { (1, 0) };
+            let target = self.checker.sample(rng);
+            let new = match self.extend(&mut trees[a], a, target) {
+                Extend::Trapped => continue,
+                Extend::Advanced(new) | Extend::Reached(new) => new,
+            };
+            let new_pos = trees[a][new].pos;
+            loop {
+                match self.extend(&mut trees[b], b, new_pos) {
+                    Extend::Advanced(_) => continue,
+                    Extend::Trapped => break,
+                    Extend::Reached(other) => {
+                        let mut from_a = Self::trace(&trees[a], new);
+                        let mut from_b = Self::trace(&trees[b], other);
+                        from_b.pop();
+                        from_b.reverse();
+                        from_a.extend(from_b);
+                        if a == 1 {
+                            from_a.reverse();
+                        }
+                        return Some(from_a);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+impl ContinuousPlanner for SamplingPlanner {
+    fn find_path(&self, start: [f64; 2], end: [f64; 2]) -> Option<Vec<[f64; 2]>> {
+        if !self.checker.is_free(start) || !self.checker.is_free(end) {
+            return None;
+        }
+        if self.checker.segment_free(start, end) {
+            return Some(vec![start, end]);
+        }
+        let mut rng = StdRng::seed_from_u64(self.settings.seed);
+        match self.settings.algorithm {
+            SamplingAlgorithm::Prm => self.prm(&mut rng, start, end),
+            SamplingAlgorithm::Rrt => self.rrt(&mut rng, start, end, false),
+            SamplingAlgorithm::RrtStar => self.rrt(&mut rng, start, end, true),
+            SamplingAlgorithm::RrtConnect => self.rrt_connect(&mut rng, start, end),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "sampling"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pathfinding::shape::RectParams;
+
+    /// A wall from the bottom up to y = 30 between x = 18 and x = 22, the way
+    /// round it is over the top.
+    fn checker(model: CollisionModel) -> CollisionChecker {
+        let wall = ShapeParams::Rectangle(RectParams {
+            // the corner, despite the name
+            center_x: 18.,
+            center_y: 0.,
+            width: 4.,
+            height: 30.,
+        });
+        let space_lut = (18..22)
+            .flat_map(|x| (0..30).map(move |y| ((x, y), true)))
+            .collect();
+        CollisionChecker::new(
+            Pos2::new(0, 0),
+            Pos2::new(40, 40),
+            model,
+            space_lut,
+            vec![wall],
+        )
+    }
+
+    #[test]
+    fn every_algorithm_finds_a_free_path_and_repeats_it() {
+        for model in [CollisionModel::SpaceLut, CollisionModel::Shapes] {
+            for algorithm in [
+                SamplingAlgorithm::Prm,
+                SamplingAlgorithm::Rrt,
+                SamplingAlgorithm::RrtStar,
+                SamplingAlgorithm::RrtConnect,
+            ] {
+                let settings = SamplingSettings {
+                    algorithm,
+                    collision: model,
+                    seed: 7,
+                    ..Default::default()
+                };
+                let planner = SamplingPlanner::new(checker(model), settings);
+                let path = planner.find_path([5., 10.], [35., 10.]).unwrap();
+                assert_eq!(path.first(), Some(&[5., 10.]));
+                assert_eq!(path.last(), Some(&[35., 10.]));
+                assert!(
+                    path.windows(2)
+                        .all(|w| planner.checker.segment_free(w[0], w[1])),
+                    "{:?} with {:?} went through the wall",
+                    algorithm,
+                    model
+                );
+                assert!(path.iter().any(|p| p[1] > 30.));
+                // the rng is reseeded for every query
+                assert_eq!(planner.find_path([5., 10.], [35., 10.]), Some(path));
+            }
+        }
+    }
+}
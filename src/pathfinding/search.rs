@@ -0,0 +1,90 @@
+use super::Reverse;
+use std::cmp::Ordering;
+use std::collections::hash_map::Entry;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::Hash;
+
+/// Floating point cost that can live in a `BinaryHeap`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cost(pub f64);
+
+impl Eq for Cost {}
+
+impl Ord for Cost {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl PartialOrd for Cost {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Generic A* over any graph given by a successor function. Returns the node
+/// sequence from `start` to the first node accepted by `is_goal`, and its cost.
+/// Passing a heuristic that always returns 0 turns this into Dijkstra.
+pub fn a_star<N, I>(
+    start: N,
+    mut successors: impl FnMut(&N) -> I,
+    mut heuristic: impl FnMut(&N) -> f64,
+    mut is_goal: impl FnMut(&N) -> bool,
+) -> Option<(Vec<N>, f64)>
+where
+    N: Eq + Hash + Clone,
+    I: IntoIterator<Item = (N, f64)>,
+{
+    let mut nodes: Vec<N> = vec![start.clone()];
+    let mut index: HashMap<N, usize> = HashMap::new();
+    let mut g_score: Vec<f64> = vec![0.];
+    let mut came_from: Vec<Option<usize>> = vec![None];
+    let mut closed: Vec<bool> = vec![false];
+    let mut open_set: BinaryHeap<Reverse<(Cost, usize)>> = BinaryHeap::new();
+
+    index.insert(start.clone(), 0);
+    open_set.push(Reverse((Cost(heuristic(&start)), 0)));
+
+    while let Some(Reverse((_, current))) = open_set.pop() {
+        if closed[current] {
+            continue;
+        }
+        closed[current] = true;
+
+        if is_goal(&nodes[current]) {
+            let cost = g_score[current];
+            let mut path = vec![nodes[current].clone()];
+            let mut i = current;
+            while let Some(prev) = came_from[i] {
+                path.push(nodes[prev].clone());
+                i = prev;
+            }
+            path.reverse();
+            return Some((path, cost));
+        }
+
+        let current_node = nodes[current].clone();
+        for (neighbor, step_cost) in successors(&current_node) {
+            let tentative_g_score = g_score[current] + step_cost;
+            let neighbor_index = match index.entry(neighbor) {
+                Entry::Occupied(o) => *o.get(),
+                Entry::Vacant(v) => {
+                    let i = nodes.len();
+                    nodes.push(v.key().clone());
+                    v.insert(i);
+                    g_score.push(f64::INFINITY);
+                    came_from.push(None);
+                    closed.push(false);
+                    i
+                }
+            };
+            if !closed[neighbor_index] && tentative_g_score < g_score[neighbor_index] {
+                g_score[neighbor_index] = tentative_g_score;
+                came_from[neighbor_index] = Some(current);
+                let f = tentative_g_score + heuristic(&nodes[neighbor_index]);
+                open_set.push(Reverse((Cost(f), neighbor_index)));
+            }
+        }
+    }
+    None
+}
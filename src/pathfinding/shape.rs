@@ -0,0 +1,282 @@
+use std::f64::consts::TAU;
+
+/// Number of segments used when a circle has to be treated as a polygon.
+pub const CIRCLE_SEGMENTS: usize = 16;
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub struct CircleParams {
+    pub center_x: f64,
+    pub center_y: f64,
+    pub radius_x: f64,
+    pub radius_y: f64,
+}
+
+/// Axis aligned rectangle. Despite the field names, (`center_x`, `center_y`) is the
+/// min corner, which is how the demo has always generated and drawn them.
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub struct RectParams {
+    pub center_x: f64,
+    pub center_y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+#[derive(Debug, PartialEq, Clone, serde::Deserialize, serde::Serialize)]
+pub enum ShapeParams {
+    Circle(CircleParams),
+    Rectangle(RectParams),
+    /// Corners in any order. Planned around as their convex hull, which is
+    /// what the continuous planners can handle.
+    Polygon(Vec<[f64; 2]>),
+}
+
+impl ShapeParams {
+    /// Counter-clockwise outline of the shape. Circles are approximated by a
+    /// polygon that circumscribes the circle so the outline never cuts into it.
+    pub fn outline(&self) -> Vec<[f64; 2]> {
+        self.outline_with(CIRCLE_SEGMENTS)
+    }
+
+    /// Same as [`ShapeParams::outline`] with a custom number of circle segments.
+    /// Every edge of the circle polygon touches the circle at its midpoint.
+    pub fn outline_with(&self, segments: usize) -> Vec<[f64; 2]> {
+        match self {
+            ShapeParams::Circle(cp) => {
+                let scale = 1. / (std::f64::consts::PI / segments as f64).cos();
+                (0..segments)
+                    .map(|i| {
+                        let t = TAU * i as f64 / segments as f64;
+                        [
+                            cp.center_x + cp.radius_x * scale * t.cos(),
+                            cp.center_y + cp.radius_y * scale * t.sin(),
+                        ]
+                    })
+                    .collect()
+            }
+            ShapeParams::Rectangle(rp) => vec![
+                [rp.center_x, rp.center_y],
+                [rp.center_x + rp.width, rp.center_y],
+                [rp.center_x + rp.width, rp.center_y + rp.height],
+                [rp.center_x, rp.center_y + rp.height],
+            ],
+            ShapeParams::Polygon(points) => convex_hull(points),
+        }
+    }
+
+    /// Exact containment test against the shape itself (not its outline).
+    pub fn contains(&self, p: [f64; 2]) -> bool {
+        match self {
+            ShapeParams::Circle(cp) => {
+                let dx = (p[0] - cp.center_x) / cp.radius_x;
+                let dy = (p[1] - cp.center_y) / cp.radius_y;
+                dx * dx + dy * dy <= 1.
+            }
+            ShapeParams::Rectangle(rp) => {
+                p[0] >= rp.center_x
+                    && p[0] <= rp.center_x + rp.width
+                    && p[1] >= rp.center_y
+                    && p[1] <= rp.center_y + rp.height
+            }
+            ShapeParams::Polygon(_) => {
+                let hull = self.outline();
+                hull.len() >= 3 && point_in_polygon(p, &hull)
+            }
+        }
+    }
+
+    /// Exact test whether the segment `a b` passes through the shape. Ellipses
+    /// are treated as circles of their larger radius.
+    pub fn intersects_segment(&self, a: [f64; 2], b: [f64; 2]) -> bool {
+        match self {
+            ShapeParams::Circle(cp) => {
+                distance_to_segment([cp.center_x, cp.center_y], a, b)
+                    <= cp.radius_x.max(cp.radius_y)
+            }
+            ShapeParams::Rectangle(_) | ShapeParams::Polygon(_) => {
+                self.contains(a) || self.contains(b) || segment_enters_convex(a, b, &self.outline())
+            }
+        }
+    }
+
+    /// The shape grown by `r` on every side, e.g. to account for an agent's radius.
+    /// Rectangles keep square corners and polygons get a circumscribed octagon
+    /// around every corner, which is conservative.
+    pub fn inflated(&self, r: f64) -> ShapeParams {
+        match self {
+            ShapeParams::Circle(cp) => ShapeParams::Circle(CircleParams {
+                radius_x: cp.radius_x + r,
+                radius_y: cp.radius_y + r,
+                ..*cp
+            }),
+            ShapeParams::Rectangle(rp) => ShapeParams::Rectangle(RectParams {
+                center_x: rp.center_x - r,
+                center_y: rp.center_y - r,
+                width: rp.width + 2. * r,
+                height: rp.height + 2. * r,
+            }),
+            ShapeParams::Polygon(points) => {
+                let r = r / (std::f64::consts::PI / 8.).cos();
+                ShapeParams::Polygon(
+                    points
+                        .iter()
+                        .flat_map(|p| {
+                            (0..8).map(move |i| {
+                                let t = TAU * i as f64 / 8.;
+                                [p[0] + r * t.cos(), p[1] + r * t.sin()]
+                            })
+                        })
+                        .collect(),
+                )
+            }
+        }
+    }
+}
+
+/// Counter-clockwise convex hull of the points (monotone chain), without
+/// collinear points.
+pub fn convex_hull(points: &[[f64; 2]]) -> Vec<[f64; 2]> {
+    let mut sorted = points.to_vec();
+    sorted.sort_by(|a, b| a[0].total_cmp(&b[0]).then(a[1].total_cmp(&b[1])));
+    sorted.dedup();
+    if sorted.len() < 3 {
+        return sorted;
+    }
+    let mut hull: Vec<[f64; 2]> = Vec::new();
+    for pass in [sorted.clone(), sorted.into_iter().rev().collect()] {
+        let start = hull.len();
+        for p in pass {
+            while hull.len() >= start + 2
+                && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0.
+            {
+                hull.pop();
+            }
+            hull.push(p);
+        }
+        // the last point of a pass starts the next one
+        hull.pop();
+    }
+    hull
+}
+
+pub fn cross(o: [f64; 2], a: [f64; 2], b: [f64; 2]) -> f64 {
+    (a[0] - o[0]) * (b[1] - o[1]) - (a[1] - o[1]) * (b[0] - o[0])
+}
+
+pub fn distance(a: [f64; 2], b: [f64; 2]) -> f64 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)).sqrt()
+}
+
+pub fn distance_to_segment(p: [f64; 2], a: [f64; 2], b: [f64; 2]) -> f64 {
+    let len_sq = (b[0] - a[0]).powi(2) + (b[1] - a[1]).powi(2);
+    if len_sq == 0. {
+        return distance(p, a);
+    }
+    let t =
+        (((p[0] - a[0]) * (b[0] - a[0]) + (p[1] - a[1]) * (b[1] - a[1])) / len_sq).clamp(0., 1.);
+    distance(p, [a[0] + t * (b[0] - a[0]), a[1] + t * (b[1] - a[1])])
+}
+
+pub fn point_in_polygon(p: [f64; 2], polygon: &[[f64; 2]]) -> bool {
+    let mut inside = false;
+    let mut j = polygon.len() - 1;
+    for i in 0..polygon.len() {
+        let (a, b) = (polygon[i], polygon[j]);
+        if (a[1] > p[1]) != (b[1] > p[1])
+            && p[0] < (b[0] - a[0]) * (p[1] - a[1]) / (b[1] - a[1]) + a[0]
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Intersection point of the open segments `a1 a2` and `b1 b2`, if they properly cross.
+pub fn segment_intersection(
+    a1: [f64; 2],
+    a2: [f64; 2],
+    b1: [f64; 2],
+    b2: [f64; 2],
+) -> Option<[f64; 2]> {
+    let d1 = cross(b1, b2, a1);
+    let d2 = cross(b1, b2, a2);
+    let d3 = cross(a1, a2, b1);
+    let d4 = cross(a1, a2, b2);
+    if ((d1 > 0. && d2 < 0.) || (d1 < 0. && d2 > 0.))
+        && ((d3 > 0. && d4 < 0.) || (d3 < 0. && d4 > 0.))
+    {
+        let t = d1 / (d1 - d2);
+        Some([a1[0] + t * (a2[0] - a1[0]), a1[1] + t * (a2[1] - a1[1])])
+    } else {
+        None
+    }
+}
+
+/// Whether the segment `a b` passes through the interior of a counter-clockwise
+/// convex polygon (Cyrus-Beck clipping). Touching the boundary does not count.
+pub fn segment_enters_convex(a: [f64; 2], b: [f64; 2], polygon: &[[f64; 2]]) -> bool {
+    const EPSILON: f64 = 1e-9;
+    let (mut t_enter, mut t_exit) = (0f64, 1f64);
+    for i in 0..polygon.len() {
+        let (p, q) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+        let f0 = cross(p, q, a);
+        let den = cross(p, q, b) - f0;
+        if den.abs() < EPSILON {
+            if f0 <= EPSILON {
+                return false;
+            }
+            continue;
+        }
+        let t = (EPSILON - f0) / den;
+        if den > 0. {
+            t_enter = t_enter.max(t);
+        } else {
+            t_exit = t_exit.min(t);
+        }
+        if t_enter >= t_exit {
+            return false;
+        }
+    }
+    true
+}
+
+/// Clips a polygon against an axis aligned box (Sutherland-Hodgman).
+pub fn clip_polygon(polygon: &[[f64; 2]], min: [f64; 2], max: [f64; 2]) -> Vec<[f64; 2]> {
+    let mut output = polygon.to_vec();
+    for (axis, bound, keep_greater) in [
+        (0, min[0], true),
+        (0, max[0], false),
+        (1, min[1], true),
+        (1, max[1], false),
+    ] {
+        let input = std::mem::take(&mut output);
+        if input.is_empty() {
+            break;
+        }
+        let inside = |p: &[f64; 2]| {
+            if keep_greater {
+                p[axis] >= bound
+            } else {
+                p[axis] <= bound
+            }
+        };
+        let mut prev = input[input.len() - 1];
+        for cur in input {
+            let (cur_in, prev_in) = (inside(&cur), inside(&prev));
+            if cur_in != prev_in {
+                let t = (bound - prev[axis]) / (cur[axis] - prev[axis]);
+                let mut p = [
+                    prev[0] + t * (cur[0] - prev[0]),
+                    prev[1] + t * (cur[1] - prev[1]),
+                ];
+                p[axis] = bound;
+                output.push(p);
+            }
+            if cur_in {
+                output.push(cur);
+            }
+            prev = cur;
+        }
+    }
+    output
+}
@@ -0,0 +1,362 @@
+use super::search::Cost;
+use super::{NavMesh, Reverse};
+use crate::ecs::pos2::Pos2;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// Open-ended end of a safe interval.
+const FOREVER: u64 = u64::MAX;
+
+/// States a search closes before giving up, so a goal walled in for good
+/// doesn't make it wait through every interval.
+const MAX_EXPANSIONS: usize = 50_000;
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct SippSettings {
+    /// Seconds a tick of a SIPP path lasts, i.e. a step to a neighbouring
+    /// cell or a wait on one.
+    pub tick: f64,
+}
+
+impl Default for SippSettings {
+    fn default() -> Self {
+        Self {
+            // a diagonal step at 10 cells per second
+            tick: std::f64::consts::SQRT_2 / 10.,
+        }
+    }
+}
+
+/// Other agents' committed trajectories, indexed by tick. An agent occupies
+/// `path[t]` at tick `t` and stays on its last cell once the path runs out.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DynamicObstacles {
+    occupied: HashMap<Pos2, Vec<u64>>,
+    parked: HashMap<Pos2, u64>,
+    moves: HashSet<(Pos2, Pos2, u64)>,
+}
+
+impl DynamicObstacles {
+    pub fn add_trajectory(&mut self, path: &[Pos2]) {
+        let Some(last) = path.last() else {
+            return;
+        };
+        for (t, pos) in path.iter().enumerate() {
+            self.occupied.entry(*pos).or_default().push(t as u64);
+            if let Some(next) = path.get(t + 1) {
+                if next != pos {
+                    self.moves.insert((*pos, *next, t as u64));
+                }
+            }
+        }
+        let end = (path.len() - 1) as u64;
+        let parked = self.parked.entry(*last).or_insert(end);
+        *parked = (*parked).min(end);
+    }
+
+    /// An agent standing still at `pos` from the start on.
+    pub fn add_stationary(&mut self, pos: Pos2) {
+        self.parked.insert(pos, 0);
+    }
+
+    /// Maximal time ranges (inclusive) in which `pos` is not occupied.
+    pub fn safe_intervals(&self, pos: &Pos2) -> Vec<(u64, u64)> {
+        let parked = self.parked.get(pos).copied().unwrap_or(FOREVER);
+        let mut times = self.occupied.get(pos).cloned().unwrap_or_default();
+        times.sort_unstable();
+        times.dedup();
+
+        let mut intervals = Vec::new();
+        let mut start = 0;
+        for t in times.into_iter().take_while(|t| *t < parked) {
+            if t > start {
+                intervals.push((start, t - 1));
+            }
+            start = t + 1;
+        }
+        if parked == FOREVER {
+            intervals.push((start, FOREVER));
+        } else if parked > start {
+            intervals.push((start, parked - 1));
+        }
+        intervals
+    }
+
+    /// Whether an agent could follow `path`, one cell per tick from tick 0,
+    /// and then stay on its last cell without running into anybody.
+    pub fn is_clear(&self, path: &[Pos2]) -> bool {
+        path.iter().enumerate().all(|(t, pos)| {
+            let t = t as u64;
+            let Some((_, until)) = self
+                .safe_intervals(pos)
+                .into_iter()
+                .find(|(s, e)| *s <= t && t <= *e)
+            else {
+                return false;
+            };
+            match path.get(t as usize + 1) {
+                Some(next) => next == pos || !self.is_swap(*pos, *next, t),
+                None => until == FOREVER,
+            }
+        })
+    }
+
+    /// Whether moving `from` -> `to` between ticks `t` and `t + 1` swaps
+    /// places with another agent.
+    fn is_swap(&self, from: Pos2, to: Pos2, t: u64) -> bool {
+        self.moves.contains(&(to, from, t))
+    }
+}
+
+impl NavMesh {
+    /// Safe Interval Path Planning: A* over (cell, safe interval) states where
+    /// the cost is the arrival tick, so waiting in place to let other agents
+    /// pass is part of the search. Every move, diagonal or not, takes one tick.
+    /// The waypoints are reached in order within the one search, so when an
+    /// agent gets to one matters for the legs after it. Only the last one has
+    /// to be safe to stay on. Returns each visited cell with its arrival tick,
+    /// counted from `start_time`.
+    pub fn sipp(
+        &self,
+        start: Pos2,
+        waypoints: &[Pos2],
+        start_time: u64,
+        obstacles: &DynamicObstacles,
+    ) -> Option<Vec<(Pos2, u64)>> {
+        if std::iter::once(&start)
+            .chain(waypoints)
+            .any(|p| self.space_lut.contains_key(&p.to_tuple()))
+        {
+            return None;
+        }
+        let Some(last) = waypoints.len().checked_sub(1) else {
+            return Some(vec![(start, start_time)]);
+        };
+        let chebyshev = |a: &Pos2, b: &Pos2| (a.x - b.x).abs().max((a.y - b.y).abs()) as u64;
+        // ticks from each waypoint on through the ones after it
+        let mut rest = vec![0; waypoints.len()];
+        for leg in (0..last).rev() {
+            rest[leg] = rest[leg + 1] + chebyshev(&waypoints[leg], &waypoints[leg + 1]);
+        }
+        let heuristic = |p: &Pos2, leg: usize| chebyshev(p, &waypoints[leg]) + rest[leg];
+
+        let mut intervals: HashMap<Pos2, Vec<(u64, u64)>> = HashMap::new();
+        let mut intervals_of = |p: Pos2| -> Vec<(u64, u64)> {
+            intervals
+                .entry(p)
+                .or_insert_with(|| obstacles.safe_intervals(&p))
+                .clone()
+        };
+
+        let start_interval = intervals_of(start)
+            .iter()
+            .position(|(s, e)| *s <= start_time && start_time <= *e)?;
+
+        // (cell, interval index, waypoint heading for, arrival tick, parent)
+        let mut states: Vec<(Pos2, usize, usize, u64, Option<usize>)> =
+            vec![(start, start_interval, 0, start_time, None)];
+        let mut best: HashMap<(Pos2, usize, usize), u64> = HashMap::new();
+        let mut closed: HashSet<(Pos2, usize, usize)> = HashSet::new();
+        let mut open: BinaryHeap<Reverse<(Cost, usize)>> = BinaryHeap::new();
+        open.push(Reverse((
+            Cost((start_time + heuristic(&start, 0)) as f64),
+            0,
+        )));
+
+        while let Some(Reverse((_, current))) = open.pop() {
+            let (pos, interval, leg, t, _) = states[current];
+            if !closed.insert((pos, interval, leg)) {
+                continue;
+            }
+            if closed.len() > MAX_EXPANSIONS {
+                return None;
+            }
+            let interval_end = intervals_of(pos)[interval].1;
+            if pos == waypoints[leg] {
+                if leg < last {
+                    // heads on for the next waypoint from here and now
+                    states.push((pos, interval, leg + 1, t, Some(current)));
+                    open.push(Reverse((
+                        Cost((t + heuristic(&pos, leg + 1)) as f64),
+                        states.len() - 1,
+                    )));
+                    continue;
+                }
+                if interval_end == FOREVER {
+                    let mut path: Vec<(Pos2, u64)> = Vec::new();
+                    let mut i = Some(current);
+                    while let Some(s) = i {
+                        let (pos, _, _, t, parent) = states[s];
+                        if path.last() != Some(&(pos, t)) {
+                            path.push((pos, t));
+                        }
+                        i = parent;
+                    }
+                    path.reverse();
+                    return Some(path);
+                }
+            }
+
+            for neighbor in pos.neighbors() {
+                if !self.is_in_bounds(&neighbor)
+                    || self.space_lut.contains_key(&neighbor.to_tuple())
+                {
+                    continue;
+                }
+                for (j, (s, e)) in intervals_of(neighbor).into_iter().enumerate() {
+                    let mut arrival = (t + 1).max(s);
+                    while arrival <= e
+                        && arrival - 1 <= interval_end
+                        && obstacles.is_swap(pos, neighbor, arrival - 1)
+                    {
+                        arrival += 1;
+                    }
+                    if arrival > e || arrival - 1 > interval_end {
+                        continue;
+                    }
+                    if closed.contains(&(neighbor, j, leg))
+                        || best.get(&(neighbor, j, leg)).is_some_and(|b| *b <= arrival)
+                    {
+                        continue;
+                    }
+                    best.insert((neighbor, j, leg), arrival);
+                    states.push((neighbor, j, leg, arrival, Some(current)));
+                    open.push(Reverse((
+                        Cost((arrival + heuristic(&neighbor, leg)) as f64),
+                        states.len() - 1,
+                    )));
+                }
+            }
+        }
+        None
+    }
+
+    /// SIPP through every waypoint, returned one cell per tick so waits show
+    /// up as repeated cells. Only the last waypoint has to be safe to stay on.
+    pub fn waypointed_sipp(
+        &self,
+        start: Pos2,
+        waypoints: Vec<Pos2>,
+        obstacles: &DynamicObstacles,
+    ) -> Option<Vec<Pos2>> {
+        let timed = self.sipp(start, &waypoints, 0, obstacles)?;
+        Some(expand_timed_path(&timed))
+    }
+}
+
+/// Turns (cell, arrival tick) pairs into one cell per tick.
+pub fn expand_timed_path(timed: &[(Pos2, u64)]) -> Vec<Pos2> {
+    let mut path = Vec::new();
+    for w in timed.windows(2) {
+        for _ in w[0].1..w[1].1 {
+            path.push(w[0].0);
+        }
+    }
+    if let Some((last, _)) = timed.last() {
+        path.push(*last);
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(points: &[(i64, i64)]) -> Vec<Pos2> {
+        points.iter().map(|(x, y)| Pos2::new(*x, *y)).collect()
+    }
+
+    /// A corridor one cell high from (0, 0) to (6, 0).
+    fn corridor() -> NavMesh {
+        let mut navmesh = NavMesh::default();
+        navmesh.set_grid_boundaries(Pos2::new(0, 0), Pos2::new(6, 0));
+        navmesh
+    }
+
+    /// Crosses the corridor at (3, 0) on tick 3.
+    fn crossing() -> DynamicObstacles {
+        let mut obstacles = DynamicObstacles::default();
+        obstacles.add_trajectory(&cells(&[(3, 3), (3, 2), (3, 1), (3, 0), (3, -1)]));
+        obstacles
+    }
+
+    #[test]
+    fn safe_intervals_leave_out_the_ticks_a_cell_is_taken() {
+        let obstacles = crossing();
+        assert_eq!(
+            obstacles.safe_intervals(&Pos2::new(3, 0)),
+            vec![(0, 2), (4, FOREVER)]
+        );
+        // the last cell is taken for good once it is reached
+        assert_eq!(obstacles.safe_intervals(&Pos2::new(3, -1)), vec![(0, 3)]);
+        assert_eq!(
+            obstacles.safe_intervals(&Pos2::new(0, 0)),
+            vec![(0, FOREVER)]
+        );
+    }
+
+    #[test]
+    fn waits_for_a_crossing_trajectory_to_pass() {
+        let navmesh = corridor();
+        let obstacles = crossing();
+        let path = navmesh
+            .waypointed_sipp(Pos2::new(0, 0), vec![Pos2::new(6, 0)], &obstacles)
+            .unwrap();
+        // walking straight through would meet it on (3, 0)
+        let straight = cells(&[(0, 0), (1, 0), (2, 0), (3, 0), (4, 0), (5, 0), (6, 0)]);
+        assert!(!obstacles.is_clear(&straight));
+        assert_eq!(path.len(), straight.len() + 1);
+        assert_eq!(path.first(), Some(&Pos2::new(0, 0)));
+        assert_eq!(path.last(), Some(&Pos2::new(6, 0)));
+        assert_ne!(path[3], Pos2::new(3, 0));
+        assert!(obstacles.is_clear(&path));
+    }
+
+    #[test]
+    fn swapping_places_is_not_clear() {
+        let mut obstacles = DynamicObstacles::default();
+        obstacles.add_trajectory(&cells(&[(1, 0), (0, 0), (0, 1)]));
+        assert!(!obstacles.is_clear(&cells(&[(0, 0), (1, 0), (2, 0)])));
+        assert!(obstacles.is_clear(&cells(&[(1, 1), (2, 1), (2, 2)])));
+    }
+
+    #[test]
+    fn paths_ending_where_somebody_comes_later_are_not_clear() {
+        let obstacles = crossing();
+        // (3, 0) is free for as long as this takes, but not after
+        assert!(!obstacles.is_clear(&cells(&[(2, 0), (3, 0)])));
+        assert!(obstacles.is_clear(&cells(&[(2, 0), (3, 0), (4, 0)])));
+        let mut parked = DynamicObstacles::default();
+        parked.add_stationary(Pos2::new(4, 0));
+        assert!(!parked.is_clear(&cells(&[(2, 0), (3, 0), (4, 0)])));
+    }
+
+    #[test]
+    fn waits_before_a_waypoint_it_could_not_get_away_from() {
+        let navmesh = corridor();
+        let mut obstacles = DynamicObstacles::default();
+        // (1, 0) is taken on ticks 2 to 5 and the dead end (0, 0) on tick 4
+        obstacles.add_trajectory(&cells(&[
+            (1, 2),
+            (1, 1),
+            (1, 0),
+            (1, 0),
+            (1, 0),
+            (1, 0),
+            (1, -1),
+        ]));
+        obstacles.add_trajectory(&cells(&[(0, 4), (0, 3), (0, 2), (0, 1), (0, 0), (0, -1)]));
+        let path = navmesh
+            .waypointed_sipp(
+                Pos2::new(2, 0),
+                vec![Pos2::new(0, 0), Pos2::new(6, 0)],
+                &obstacles,
+            )
+            .unwrap();
+        // getting to (0, 0) by tick 2 would leave it stuck there on tick 4
+        let waypoint = path.iter().position(|p| *p == Pos2::new(0, 0)).unwrap();
+        assert!(waypoint > 4);
+        assert_eq!(path.last(), Some(&Pos2::new(6, 0)));
+        assert!(obstacles.is_clear(&path));
+    }
+}
@@ -0,0 +1,497 @@
+use super::influence::{CostLayer, MIN_FACTOR};
+use super::search::a_star;
+use super::shape::{cross, distance, segment_enters_convex, ShapeParams};
+use super::ContinuousPlanner;
+use crate::ecs::pos2::Pos2;
+use std::collections::HashMap;
+
+const EPSILON: f64 = 1e-9;
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum GraphSearch {
+    Dijkstra,
+    AStar,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct InflatedObstacle {
+    polygon: Vec<[f64; 2]>,
+    min: [f64; 2],
+    max: [f64; 2],
+}
+
+impl InflatedObstacle {
+    fn new(polygon: Vec<[f64; 2]>) -> Self {
+        let mut min = [f64::MAX; 2];
+        let mut max = [f64::MIN; 2];
+        for p in polygon.iter() {
+            min = [min[0].min(p[0]), min[1].min(p[1])];
+            max = [max[0].max(p[0]), max[1].max(p[1])];
+        }
+        Self { polygon, min, max }
+    }
+
+    fn strictly_contains(&self, p: [f64; 2]) -> bool {
+        let n = self.polygon.len();
+        (0..n).all(|i| cross(self.polygon[i], self.polygon[(i + 1) % n], p) > EPSILON)
+    }
+
+    fn blocks(&self, a: [f64; 2], b: [f64; 2]) -> bool {
+        a[0].max(b[0]) >= self.min[0]
+            && a[0].min(b[0]) <= self.max[0]
+            && a[1].max(b[1]) >= self.min[1]
+            && a[1].min(b[1]) <= self.max[1]
+            && segment_enters_convex(a, b, &self.polygon)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Corner {
+    pos: [f64; 2],
+    prev: [f64; 2],
+    next: [f64; 2],
+    /// Key of the obstacle the corner belongs to.
+    obstacle: usize,
+    active: bool,
+}
+
+/// Visibility graph over the corners of the (inflated) obstacles. Shortest
+/// paths around convex obstacles only ever bend at these corners, so searching
+/// this graph gives exact shortest paths for the approximated shapes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VisibilityGraph {
+    pub agent_radius: f64,
+    pub search: GraphSearch,
+    min: [f64; 2],
+    max: [f64; 2],
+    /// Indexed by the keys `insert_obstacle` hands out, removed ones are
+    /// `None` so the others keep theirs.
+    obstacles: Vec<Option<InflatedObstacle>>,
+    corners: Vec<Corner>,
+    edges: Vec<Vec<(usize, f64)>>,
+    /// How many corners belong to removed obstacles, see `compact`.
+    dead: usize,
+    /// Tactical costs, scaling the length of every edge by the cells it crosses.
+    pub costs: CostLayer,
+}
+
+impl Default for VisibilityGraph {
+    fn default() -> Self {
+        Self::new(Pos2::new(0, 0), Pos2::new(0, 0), 0., GraphSearch::AStar)
+    }
+}
+
+impl VisibilityGraph {
+    pub fn new(min: Pos2, max: Pos2, agent_radius: f64, search: GraphSearch) -> Self {
+        Self {
+            agent_radius,
+            search,
+            min: [min.x as f64, min.y as f64],
+            max: [max.x as f64, max.y as f64],
+            obstacles: Vec::new(),
+            corners: Vec::new(),
+            edges: Vec::new(),
+            dead: 0,
+            costs: CostLayer::default(),
+        }
+    }
+
+    pub fn with_costs(mut self, costs: CostLayer) -> Self {
+        self.costs = costs;
+        self
+    }
+
+    /// Adds one obstacle without rebuilding the graph: edges and corners it
+    /// covers are dropped, and only its own corners are connected to the rest.
+    /// Returns the key to remove it with.
+    pub fn insert_obstacle(&mut self, shape: &ShapeParams) -> usize {
+        // circles become polygons whose edges touch the circle at tangent points
+        let obstacle = InflatedObstacle::new(shape.inflated(self.agent_radius).outline());
+
+        for corner in self.corners.iter_mut() {
+            if corner.active && obstacle.strictly_contains(corner.pos) {
+                corner.active = false;
+            }
+        }
+        for i in 0..self.corners.len() {
+            if !self.corners[i].active {
+                self.edges[i].clear();
+                continue;
+            }
+            let a = self.corners[i].pos;
+            let corners = &self.corners;
+            self.edges[i]
+                .retain(|(j, _)| corners[*j].active && !obstacle.blocks(a, corners[*j].pos));
+        }
+
+        let first_new = self.corners.len();
+        let n = obstacle.polygon.len();
+        for k in 0..n {
+            let pos = obstacle.polygon[k];
+            let active = self.is_free(pos);
+            self.corners.push(Corner {
+                pos,
+                prev: obstacle.polygon[(k + n - 1) % n],
+                next: obstacle.polygon[(k + 1) % n],
+                obstacle: self.obstacles.len(),
+                active,
+            });
+            self.edges.push(Vec::new());
+        }
+        self.obstacles.push(Some(obstacle));
+
+        for i in first_new..self.corners.len() {
+            if self.corners[i].active {
+                self.connect_corner(i, |j| j < i);
+            }
+        }
+        self.obstacles.len() - 1
+    }
+
+    /// Takes an obstacle out again without rebuilding the graph: its corners
+    /// and their edges are dropped, the corners it covered come back and
+    /// edges it blocked between the remaining corners are added.
+    pub fn remove_obstacle(&mut self, key: usize) {
+        let Some(removed) = self.obstacles.get_mut(key).and_then(Option::take) else {
+            return;
+        };
+        for i in 0..self.corners.len() {
+            if self.corners[i].obstacle == key {
+                self.corners[i].active = false;
+                self.edges[i].clear();
+                self.dead += 1;
+            }
+        }
+        let corners = &self.corners;
+        for edges in self.edges.iter_mut() {
+            edges.retain(|(j, _)| corners[*j].active);
+        }
+
+        let revived: Vec<usize> = (0..self.corners.len())
+            .filter(|i| {
+                let c = &self.corners[*i];
+                !c.active
+                    && self.obstacles[c.obstacle].is_some()
+                    && removed.strictly_contains(c.pos)
+                    && self.is_free(c.pos)
+            })
+            .collect();
+        // corners that were active all along, each pair once
+        let positions: Vec<[f64; 2]> = self.corners.iter().map(|c| c.pos).collect();
+        for i in 0..self.corners.len() {
+            if self.corners[i].active {
+                self.connect_corner(i, |j| j < i && removed.blocks(positions[i], positions[j]));
+            }
+        }
+        for i in revived.iter() {
+            self.corners[*i].active = true;
+        }
+        for i in revived.iter() {
+            self.connect_corner(*i, |j| !revived.contains(&j) || j < *i);
+        }
+
+        if self.dead > self.corners.len() / 2 {
+            self.compact();
+        }
+    }
+
+    /// Adds the edges from the active corner `i` to the active corners
+    /// `candidate` picks that it has a tangent line of sight to.
+    fn connect_corner(&mut self, i: usize, candidate: impl Fn(usize) -> bool) {
+        let a = self.corners[i].pos;
+        for j in 0..self.corners.len() {
+            if j == i || !self.corners[j].active || !candidate(j) {
+                continue;
+            }
+            let b = self.corners[j].pos;
+            if self.is_tangent(i, b) && self.is_tangent(j, a) && self.is_visible(a, b) {
+                let d = distance(a, b);
+                self.edges[i].push((j, d));
+                self.edges[j].push((i, d));
+            }
+        }
+    }
+
+    /// Drops the corners of removed obstacles once they make up half of the
+    /// graph. Obstacle keys stay as they are.
+    fn compact(&mut self) {
+        let kept: Vec<usize> = (0..self.corners.len())
+            .filter(|i| self.obstacles[self.corners[*i].obstacle].is_some())
+            .collect();
+        let mut remap = vec![None; self.corners.len()];
+        for (new, old) in kept.iter().enumerate() {
+            remap[*old] = Some(new);
+        }
+        self.edges = kept
+            .iter()
+            .map(|i| {
+                self.edges[*i]
+                    .iter()
+                    .filter_map(|(j, d)| Some((remap[*j]?, *d)))
+                    .collect()
+            })
+            .collect();
+        self.corners = kept.iter().map(|i| self.corners[*i].clone()).collect();
+        self.dead = 0;
+    }
+
+    fn in_bounds(&self, p: [f64; 2]) -> bool {
+        p[0] >= self.min[0] && p[0] <= self.max[0] && p[1] >= self.min[1] && p[1] <= self.max[1]
+    }
+
+    pub fn is_free(&self, p: [f64; 2]) -> bool {
+        self.in_bounds(p)
+            && !self
+                .obstacles
+                .iter()
+                .flatten()
+                .any(|o| o.strictly_contains(p))
+    }
+
+    pub fn is_visible(&self, a: [f64; 2], b: [f64; 2]) -> bool {
+        !self.obstacles.iter().flatten().any(|o| o.blocks(a, b))
+    }
+
+    /// A shortest path can only leave a corner along a line that keeps both of
+    /// the corner's neighbours on the same side.
+    fn is_tangent(&self, corner: usize, towards: [f64; 2]) -> bool {
+        let c = &self.corners[corner];
+        cross(c.pos, towards, c.prev) * cross(c.pos, towards, c.next) >= -EPSILON
+    }
+
+    /// Every visibility edge once, for drawing.
+    pub fn edges(&self) -> Vec<([f64; 2], [f64; 2])> {
+        let mut edges = Vec::new();
+        for (i, adjacent) in self.edges.iter().enumerate() {
+            for (j, _) in adjacent.iter().filter(|(j, _)| *j > i) {
+                edges.push((self.corners[i].pos, self.corners[*j].pos));
+            }
+        }
+        edges
+    }
+
+    /// Corners and edges that connect `p` to the graph for a single query.
+    fn connect(&self, p: [f64; 2]) -> Vec<(usize, f64)> {
+        (0..self.corners.len())
+            .filter(|i| {
+                self.corners[*i].active
+                    && self.is_tangent(*i, p)
+                    && self.is_visible(p, self.corners[*i].pos)
+            })
+            .map(|i| (i, distance(p, self.corners[i].pos)))
+            .collect()
+    }
+}
+
+impl ContinuousPlanner for VisibilityGraph {
+    fn find_path(&self, start: [f64; 2], end: [f64; 2]) -> Option<Vec<[f64; 2]>> {
+        if !self.is_free(start) || !self.is_free(end) {
+            return None;
+        }
+        if self.costs.is_empty() && self.is_visible(start, end) {
+            return Some(vec![start, end]);
+        }
+
+        // Start and end are temporary nodes past the last corner.
+        let (start_node, end_node) = (self.corners.len(), self.corners.len() + 1);
+        let from_start = self.connect(start);
+        let mut to_end: HashMap<usize, f64> = self.connect(end).into_iter().collect();
+        let pos = |i: usize| match i {
+            i if i == start_node => start,
+            i if i == end_node => end,
+            i => self.corners[i].pos,
+        };
+        // with tactical costs the direct line is just one more candidate
+        if !self.costs.is_empty() && self.is_visible(start, end) {
+            to_end.insert(start_node, distance(start, end));
+        }
+        let cost = |from: usize, (to, d): (usize, f64)| {
+            (to, d * self.costs.segment_factor(pos(from), pos(to)))
+        };
+        let min_factor = if self.costs.is_empty() {
+            1.
+        } else {
+            MIN_FACTOR
+        };
+
+        let (nodes, _) = a_star(
+            start_node,
+            |i| {
+                let mut successors = if *i == start_node {
+                    from_start.clone()
+                } else {
+                    self.edges[*i].clone()
+                };
+                if let Some(d) = to_end.get(i) {
+                    successors.push((end_node, *d));
+                }
+                if !self.costs.is_empty() {
+                    successors = successors.into_iter().map(|s| cost(*i, s)).collect();
+                }
+                successors
+            },
+            |i| match self.search {
+                GraphSearch::AStar => distance(pos(*i), end) * min_factor,
+                GraphSearch::Dijkstra => 0.,
+            },
+            |i| *i == end_node,
+        )?;
+        Some(nodes.into_iter().map(pos).collect())
+    }
+
+    fn name(&self) -> &'static str {
+        "visibility_graph"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pathfinding::shape::{CircleParams, RectParams};
+
+    fn rect(x: f64, y: f64, width: f64, height: f64) -> ShapeParams {
+        ShapeParams::Rectangle(RectParams {
+            center_x: x,
+            center_y: y,
+            width,
+            height,
+        })
+    }
+
+    fn graph(obstacles: &[ShapeParams]) -> VisibilityGraph {
+        let mut graph = VisibilityGraph::new(
+            Pos2::new(0, 0),
+            Pos2::new(100, 100),
+            0.5,
+            GraphSearch::AStar,
+        );
+        for obstacle in obstacles {
+            graph.insert_obstacle(obstacle);
+        }
+        graph
+    }
+
+    fn sorted_edges(graph: &VisibilityGraph) -> Vec<[i64; 4]> {
+        let round = |v: f64| (v * 1e6).round() as i64;
+        let mut edges: Vec<[i64; 4]> = graph
+            .edges()
+            .into_iter()
+            .map(|(a, b)| {
+                let (a, b) = if (a[0], a[1]) <= (b[0], b[1]) {
+                    (a, b)
+                } else {
+                    (b, a)
+                };
+                [round(a[0]), round(a[1]), round(b[0]), round(b[1])]
+            })
+            .collect();
+        edges.sort();
+        edges
+    }
+
+    fn obstacles() -> Vec<ShapeParams> {
+        vec![
+            rect(20., 20., 10., 30.),
+            rect(25., 40., 30., 5.),
+            ShapeParams::Circle(CircleParams {
+                center_x: 60.,
+                center_y: 60.,
+                radius_x: 8.,
+                radius_y: 8.,
+            }),
+            rect(70., 10., 5., 50.),
+            // inside the first one, its corners only come out once that goes
+            rect(22., 25., 3., 3.),
+        ]
+    }
+
+    #[test]
+    fn straight_line_when_nothing_is_in_the_way() {
+        let graph = graph(&obstacles());
+        let path = graph.find_path([5., 5.], [5., 90.]).unwrap();
+        assert_eq!(path, vec![[5., 5.], [5., 90.]]);
+    }
+
+    #[test]
+    fn paths_bend_at_corners_around_obstacles() {
+        let graph = graph(&[rect(40., 0., 10., 80.)]);
+        let path = graph.find_path([20., 40.], [80., 40.]).unwrap();
+        assert_eq!(path.len(), 4);
+        assert!(path.windows(2).all(|w| graph.is_visible(w[0], w[1])));
+        // around the top end, the only way past
+        assert!(path[1..3].iter().all(|p| p[1] > 80.));
+        assert!(graph.find_path([20., 40.], [45., 40.]).is_none());
+    }
+
+    #[test]
+    fn removing_an_obstacle_matches_never_adding_it() {
+        let all = obstacles();
+        for removed in 0..all.len() {
+            let mut incremental = graph(&all);
+            incremental.remove_obstacle(removed);
+            let mut rest = all.clone();
+            rest.remove(removed);
+            assert_eq!(
+                sorted_edges(&incremental),
+                sorted_edges(&graph(&rest)),
+                "removing obstacle {}",
+                removed
+            );
+        }
+    }
+
+    #[test]
+    fn keys_survive_compaction() {
+        let all = obstacles();
+        let mut incremental = graph(&all);
+        for key in [0, 2, 3] {
+            incremental.remove_obstacle(key);
+        }
+        // most of the corners were dead at some point, so they were dropped
+        assert!(incremental.corners.len() < graph(&all).corners.len());
+        incremental.remove_obstacle(1);
+        let key = incremental.insert_obstacle(&all[0]);
+        assert_eq!(key, all.len());
+        assert_eq!(
+            sorted_edges(&incremental),
+            sorted_edges(&graph(&[all[4].clone(), all[0].clone()]))
+        );
+    }
+
+    #[test]
+    fn dijkstra_and_a_star_agree_and_keep_the_agent_radius() {
+        let circle = ShapeParams::Circle(CircleParams {
+            center_x: 50.,
+            center_y: 50.,
+            radius_x: 10.,
+            radius_y: 10.,
+        });
+        let length = |path: &[[f64; 2]]| path.windows(2).map(|w| distance(w[0], w[1])).sum();
+        let mut lengths: Vec<f64> = Vec::new();
+        for search in [GraphSearch::Dijkstra, GraphSearch::AStar] {
+            let mut graph = VisibilityGraph::new(Pos2::new(0, 0), Pos2::new(100, 100), 2., search);
+            graph.insert_obstacle(&circle);
+            let path = graph.find_path([20., 50.], [80., 50.]).unwrap();
+            // every point along the way stays a radius clear of the circle
+            for w in path.windows(2) {
+                for i in 0..=20 {
+                    let t = i as f64 / 20.;
+                    let p = [
+                        w[0][0] + t * (w[1][0] - w[0][0]),
+                        w[0][1] + t * (w[1][1] - w[0][1]),
+                    ];
+                    assert!(
+                        distance(p, [50., 50.]) >= 12. - 1e-6,
+                        "{:?} is too close",
+                        p
+                    );
+                }
+            }
+            lengths.push(length(&path));
+        }
+        assert!((lengths[0] - lengths[1]).abs() < 1e-9);
+        // longer than the straight line, shorter than round the square
+        // the inflated circle fits in
+        assert!(lengths[0] > 60. && lengths[0] < 84.);
+    }
+}
@@ -0,0 +1,483 @@
+use super::Simulation;
+use crate::ecs::behaviour::{Actuator, Status, Value};
+use crate::ecs::component::*;
+use crate::ecs::entity::EntityId;
+use crate::ecs::goap::{GoalMode, Tags};
+use crate::ecs::patrol;
+use crate::ecs::pos2::Pos2;
+use crate::ecs::query::{With, Without};
+use crate::ecs::world::World;
+use crate::pathfinding::formation;
+use crate::pathfinding::influence::{self, CostLayer};
+use crate::pathfinding::shape::distance;
+use crate::pathfinding::{cell_center, rasterize_polyline};
+use std::collections::HashMap;
+
+impl Simulation {
+    /// Ticks the behaviour tree of every entity that has one, rebuilding it
+    /// first when its preset was changed in the inspector.
+    pub(super) fn update_behaviours(&mut self, world: &mut World, dt: f32) {
+        self.bt_patrolling.clear();
+        let ids: Vec<EntityId> = world
+            .query_filtered::<EntityId, With<Behaviour>>()
+            .collect();
+        for id in ids {
+            // taken out while ticking, the leaves look the entity up again;
+            // the tree is the component's own state, so this isn't a change
+            let Some((mode, post, threat, mut tree)) =
+                world.get_mut_untracked::<Behaviour>(id).map(|b| {
+                let tree = std::mem::take(&mut b.tree);
+                (b.mode, b.post, b.threat.clone(), tree)
+            }) else {
+                continue;
+            };
+
+            let mut actuator = SimActuator { sim: self, world };
+            if tree.mode != Some(mode) {
+                log::info!("{} now behaves as {:?}", id, mode);
+                tree.rebuild(id, mode, &mut actuator);
+            }
+            if let Some(pos) = actuator.position(id) {
+                tree.blackboard
+                    .entry("home".to_string())
+                    .or_insert(Value::Cell(pos));
+            }
+            tree.blackboard
+                .insert("post".to_string(), Value::Cell(post));
+            tree.blackboard
+                .insert("threat".to_string(), Value::Name(threat));
+            tree.tick(id, dt, &mut actuator);
+
+            if let Some(bc) = world.get_mut_untracked::<Behaviour>(id) {
+                bc.tree = tree;
+            }
+        }
+    }
+
+    /// Lets every entity with a goal plan towards it and carry out its plan.
+    pub(super) fn update_goals(&mut self, world: &mut World, dt: f32) {
+        let ids: Vec<EntityId> = world.query_filtered::<EntityId, With<Goap>>().collect();
+        for id in ids {
+            // the agent is taken out while ticking, like behaviour trees
+            let Some((goal, resource, stockpile, tool, mut agent)) =
+                world.get_mut_untracked::<Goap>(id).map(|g| {
+                    let agent = std::mem::take(&mut g.agent);
+                    (
+                        g.goal,
+                        g.resource.clone(),
+                        g.stockpile.clone(),
+                        g.tool.clone(),
+                        agent,
+                    )
+                })
+            else {
+                continue;
+            };
+
+            let tags = Tags {
+                resource: &resource,
+                stockpile: &stockpile,
+                tool: &tool,
+            };
+            let mut actuator = SimActuator { sim: self, world };
+            agent.tick(id, dt, goal, &tags, &mut actuator);
+
+            if let Some(goap) = world.get_mut_untracked::<Goap>(id) {
+                goap.agent = agent;
+            }
+        }
+    }
+
+    /// Lets every entity with a sensor look around from where it stands and
+    /// plans its path again as soon as it sees the path is blocked.
+    pub(super) fn update_sensors(&mut self, world: &mut World) {
+        let sensors: Vec<(EntityId, i64)> = world
+            .query::<(EntityId, &Sensor)>()
+            .map(|(id, sc)| (id, sc.radius.round() as i64))
+            .collect();
+        // forgets what entities that lost their sensor knew
+        for id in world.query_filtered::<EntityId, Without<Sensor>>() {
+            self.known_maps.remove(&id);
+        }
+
+        for (id, radius) in sensors {
+            let Some((pos, _)) = self.entity_transform(world, id) else {
+                continue;
+            };
+            let mut known = self.known_maps.remove(&id).unwrap_or_default();
+            let discovered = known.update(pos, radius, |p| {
+                !self.grid.contains(egui::Pos2::new(p.x as f32, p.y as f32))
+                    || self.space_lut.contains_key(&p.to_tuple())
+            });
+            self.known_maps.insert(id, known);
+            if discovered.is_empty() {
+                continue;
+            }
+
+            let blocked = self
+                .current_paths
+                .get(&id)
+                .is_some_and(|path| path.iter().any(|p| discovered.contains(p)));
+            if blocked {
+                log::info!("{} saw its path is blocked", id);
+                if let Some(path) = self.current_paths.remove(&id) {
+                    self.replan(world, id, &path);
+                }
+            }
+        }
+    }
+
+    /// Spreads the influence maps again whenever a tagged entity moved, was
+    /// (un)tagged or the obstacles or influence settings changed.
+    pub(super) fn update_influence(&mut self, world: &World) {
+        let mut sources: Vec<(String, Pos2)> = world
+            .iter()
+            .filter(|(id, e)| *id != world.root() && !e.data.tag.is_empty())
+            .filter_map(|(id, e)| {
+                let (pos, _) = self.entity_transform(world, id)?;
+                Some((e.data.tag.clone(), pos))
+            })
+            .collect();
+        sources.sort();
+        if sources == self.influence_sources {
+            return;
+        }
+
+        let mut by_tag: HashMap<String, Vec<Pos2>> = HashMap::new();
+        for (tag, pos) in sources.iter() {
+            by_tag.entry(tag.clone()).or_default().push(*pos);
+        }
+        self.influence_maps = by_tag
+            .into_iter()
+            .map(|(tag, cells)| {
+                let map = influence::propagate(&cells, &self.env_settings.influence, |p| {
+                    !self.grid.contains(egui::Pos2::new(p.x as f32, p.y as f32))
+                        || self.space_lut.contains_key(&p.to_tuple())
+                });
+                (tag, map)
+            })
+            .collect();
+        self.influence_sources = sources;
+    }
+
+    /// The influence maps weighted by the entity's `Tactics`, empty without.
+    pub(super) fn cost_layer(&self, world: &World, id: EntityId) -> CostLayer {
+        let mut costs = CostLayer::default();
+        let Some(tactics) = world.get::<Tactics>(id) else {
+            return costs;
+        };
+        if let Some(map) = self.influence_maps.get(&tactics.avoid) {
+            costs.add(map, tactics.avoid_weight as f64 / 10.);
+        }
+        if let Some(map) = self.influence_maps.get(&tactics.prefer) {
+            costs.add(map, -tactics.prefer_weight as f64 / 10.);
+        }
+        costs
+    }
+
+    /// Stops entities from walking to a patrol waypoint that was edited away,
+    /// so they go for the next one instead.
+    pub(super) fn update_patrol_targets(&mut self, world: &mut World) {
+        let edited: Vec<EntityId> = self
+            .changes
+            .read(world)
+            .filter(|event| event.is::<Patrol>())
+            .map(|event| event.id)
+            .collect();
+        for id in edited {
+            let Some(target) = self.patrol_targets.get(&id).copied() else {
+                continue;
+            };
+            let kept = world
+                .get::<Patrol>(id)
+                .is_some_and(|p| p.waypoints.contains(&target));
+            let heading_there = self
+                .current_paths
+                .get(&id)
+                .is_some_and(|p| p.last() == Some(&target));
+            if !kept && heading_there {
+                log::info!(
+                    "{} patrol waypoint ({}, {}) is gone",
+                    id,
+                    target.x,
+                    target.y
+                );
+                self.current_paths.remove(&id);
+                self.path_map.remove(&id);
+                self.timed_paths.remove(&id);
+                self.pose_paths.remove(&id);
+                self.patrol_targets.remove(&id);
+            }
+        }
+    }
+
+    /// Sends entities with a patrol on to their next waypoint whenever they are
+    /// idle: no path to walk, no plan pending and not held up or in formation.
+    pub(super) fn update_patrols(&mut self, world: &mut World) {
+        let mut orders = Vec::new();
+        for id in world.ids() {
+            // behaviour trees decide themselves when to patrol, goals
+            // keep the entity busy with their own plans
+            let held_back = (world.has::<Behaviour>(id) && !self.bt_patrolling.contains(&id))
+                || world
+                    .get::<Goap>(id)
+                    .is_some_and(|gc| gc.goal != GoalMode::Idle);
+            let busy = held_back
+                || self.current_paths.get(&id).is_some_and(|p| !p.is_empty())
+                || self.path_map.contains_key(&id)
+                || self.waiting_paths.contains_key(&id)
+                || self
+                    .formations
+                    .iter()
+                    .any(|f| f.leader == id || f.followers.contains(&id));
+            if busy {
+                continue;
+            }
+            let Some((pos, heading)) = world.get::<Transform2>(id).map(|t| (t.pos, t.heading))
+            else {
+                continue;
+            };
+            let Some(patrol) = world.get_mut_untracked::<Patrol>(id) else {
+                continue;
+            };
+            let was = (patrol.next, patrol.returning);
+            patrol::restart_if_off_route(patrol);
+            match patrol::current_waypoint(patrol) {
+                Some(target) if target == pos => patrol::advance(patrol),
+                Some(target) if self.patrol_targets.get(&id) == Some(&target) => {
+                    // already went for it and ended up somewhere else
+                    log::info!(
+                        "{} can't reach patrol waypoint ({}, {}), skipping it",
+                        id,
+                        target.x,
+                        target.y
+                    );
+                    patrol::advance(patrol);
+                }
+                _ => {}
+            }
+            let next = patrol::current_waypoint(patrol);
+            if (patrol.next, patrol.returning) != was {
+                world.mark_changed::<Patrol>(id);
+            }
+            if let Some(next) = next {
+                orders.push((id, pos, heading, next));
+            }
+        }
+        for (id, pos, heading, target) in orders {
+            if target == pos {
+                continue;
+            }
+            let path_promise = self.plan_path(world, id, pos, heading, vec![target]);
+            self.path_map.insert(id, path_promise);
+            self.patrol_targets.insert(id, target);
+        }
+    }
+
+    /// Takes `ids` out of their formations, breaking up the ones they lead.
+    pub(super) fn leave_formations(&mut self, ids: &[EntityId]) {
+        self.formations.retain_mut(|f| {
+            f.followers.retain(|id| !ids.contains(id));
+            !ids.contains(&f.leader) && !f.followers.is_empty()
+        });
+        self.formation_targets.retain(|id, _| !ids.contains(id));
+    }
+
+    /// Lays the slots out around every leader's current pose, moves followers
+    /// off slots that became blocked and replans towards slots that moved.
+    /// The leader slows down while its followers lag behind.
+    pub(super) fn update_formations(&mut self, world: &World) {
+        let mut formations = std::mem::take(&mut self.formations);
+        formations.retain_mut(|f| {
+            f.followers.retain(|id| world.contains(*id));
+            world.contains(f.leader) && !f.followers.is_empty()
+        });
+        let spacing = self.env_settings.formation.spacing;
+        self.slot_points.clear();
+
+        for formation in formations.iter_mut() {
+            let Some((origin, mut heading)) = self.entity_pose(world, formation.leader) else {
+                continue;
+            };
+            // face where the leader is about to go rather than where it points now
+            if let Some(path) = self.current_paths.get(&formation.leader) {
+                if let Some(ahead) = path.get(3.min(path.len().saturating_sub(1))) {
+                    let [x, y] = cell_center(*ahead);
+                    if distance([x, y], origin) > 0.5 {
+                        heading = (y - origin[1]).atan2(x - origin[0]);
+                    }
+                }
+            }
+
+            let slots = formation.slot_positions(origin, heading);
+            let free: Vec<bool> = slots
+                .iter()
+                .map(|p| self.is_clear_line(origin, *p))
+                .collect();
+            let positions: HashMap<EntityId, [f64; 2]> = formation
+                .followers
+                .iter()
+                .filter_map(|id| Some((*id, self.entity_pose(world, *id)?.0)))
+                .collect();
+            for id in formation.assign(&positions, &slots, &free) {
+                log::info!("{} takes a new slot behind {}", id, formation.leader);
+            }
+
+            let mut lag = 0f64;
+            for (id, position) in positions.iter() {
+                let target = match formation.slot_of(*id) {
+                    Some(slot) => slots[slot],
+                    None => formation::pull_in(
+                        origin,
+                        formation::to_world(origin, heading, [-spacing, 0.]),
+                        |p| self.is_clear_line(origin, p),
+                    ),
+                };
+                lag = lag.max(distance(*position, target));
+                self.slot_points.push(target);
+
+                let cell = Pos2::new(target[0].floor() as i64, target[1].floor() as i64);
+                if self.formation_targets.get(id) == Some(&cell) {
+                    continue;
+                }
+                self.formation_targets.insert(*id, cell);
+                let from = Pos2::new(position[0].floor() as i64, position[1].floor() as i64);
+                let line = rasterize_polyline(&[*position, target]);
+                let mut path = if line.iter().all(|c| self.is_free_cell(*c)) {
+                    line
+                } else {
+                    self.navmesh.a_star(from, cell).unwrap_or_default()
+                };
+                if path.len() > 1 {
+                    path.remove(0);
+                }
+                self.current_paths.insert(*id, path);
+            }
+            formation.pace = ((3. * spacing - lag) / (2. * spacing)).clamp(0.25, 1.);
+        }
+        self.formations = formations;
+    }
+}
+
+/// The sim's entities as behaviour trees and goals see and move them.
+struct SimActuator<'a> {
+    sim: &'a mut Simulation,
+    world: &'a World,
+}
+
+impl Actuator for SimActuator<'_> {
+    fn position(&self, id: EntityId) -> Option<Pos2> {
+        self.sim
+            .entity_transform(self.world, id)
+            .map(|(pos, _)| pos)
+    }
+
+    fn find(&self, name: &str) -> Option<(EntityId, Pos2)> {
+        self.world
+            .iter()
+            .filter(|(id, e)| *id != self.world.root() && e.data.name == name)
+            .find_map(|(id, _)| Some((id, self.position(id)?)))
+    }
+
+    fn tagged(&self, tag: &str) -> Vec<(EntityId, String, Pos2)> {
+        self.world
+            .iter()
+            .filter(|(id, e)| *id != self.world.root() && !tag.is_empty() && e.data.tag == tag)
+            .filter_map(|(id, e)| Some((id, e.data.name.clone(), self.position(id)?)))
+            .collect()
+    }
+
+    fn is_free(&self, cell: Pos2) -> bool {
+        self.sim.is_free_cell(cell)
+    }
+
+    fn path_length(&mut self, from: Pos2, to: Pos2) -> Option<f64> {
+        let sim = &mut *self.sim;
+        *sim.path_lengths
+            .entry((from, to))
+            .or_insert_with(|| sim.navmesh.path_length(from, to))
+    }
+
+    fn move_to(&mut self, id: EntityId, target: Pos2) -> Status {
+        let Some((pos, heading)) = self.sim.entity_transform(self.world, id) else {
+            return Status::Failure;
+        };
+        let sim = &mut *self.sim;
+        if pos == target {
+            sim.move_requests.remove(&id);
+            return Status::Success;
+        }
+        let on_the_way = sim.path_map.contains_key(&id)
+            || sim.waiting_paths.contains_key(&id)
+            || sim
+                .current_paths
+                .get(&id)
+                .is_some_and(|p| p.last() == Some(&target));
+        if on_the_way {
+            return Status::Running;
+        }
+        // already went for it and ended up somewhere else
+        if sim.move_requests.get(&id) == Some(&target) {
+            sim.move_requests.remove(&id);
+            return Status::Failure;
+        }
+        let path_promise = sim.plan_path(self.world, id, pos, heading, vec![target]);
+        sim.path_map.insert(id, path_promise);
+        sim.move_requests.insert(id, target);
+        Status::Running
+    }
+
+    fn patrol(&mut self, id: EntityId) -> Status {
+        let patrol = self.world.get::<Patrol>(id);
+        match patrol {
+            Some(p) if p.waypoints.is_empty() => Status::Failure,
+            Some(p) if p.mode == PatrolMode::Once && patrol::current_waypoint(&p).is_none() => {
+                Status::Success
+            }
+            Some(_) => {
+                self.sim.bt_patrolling.insert(id);
+                Status::Running
+            }
+            None => Status::Failure,
+        }
+    }
+
+    fn stop(&mut self, id: EntityId) {
+        let sim = &mut *self.sim;
+        sim.current_paths.remove(&id);
+        sim.path_map.remove(&id);
+        sim.waiting_paths.remove(&id);
+        sim.timed_paths.remove(&id);
+        sim.pose_paths.remove(&id);
+        sim.move_requests.remove(&id);
+        sim.patrol_targets.remove(&id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::entity::Entity;
+
+    #[test]
+    fn path_lengths_are_worked_out_again_once_cells_are_blocked() {
+        let mut sim = Simulation::default();
+        let mut world = World::default();
+        let (from, to) = (Pos2::new(20, 20), Pos2::new(30, 20));
+        let length =
+            |sim: &mut Simulation, world: &World| SimActuator { sim, world }.path_length(from, to);
+        assert_eq!(length(&mut sim, &world), Some(10.));
+
+        let wall = world.spawn(Entity::default());
+        world.add(
+            wall,
+            Transform2 {
+                pos: Pos2::new(24, 18),
+                heading: 0.,
+            },
+        );
+        world.add(wall, Collider::default());
+        sim.rasterize_colliders(&world);
+        assert!(length(&mut sim, &world).unwrap() > 10.);
+    }
+}
@@ -0,0 +1,245 @@
+use super::settings::Planner;
+use super::Simulation;
+use crate::ecs::component::*;
+use crate::ecs::entity::EntityId;
+use crate::ecs::pos2::Pos2;
+use crate::ecs::query::Without;
+use crate::ecs::world::World;
+use crate::pathfinding::cell_center;
+use crate::pathfinding::influence;
+use crate::pathfinding::shape::ShapeParams;
+use std::collections::HashMap;
+
+/// What `draw_entities` needs of an entity.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Sprite {
+    pub cell: Pos2,
+    pub center: [f64; 2],
+    pub heading: f64,
+    pub col: egui::Color32,
+    pub name: String,
+    pub selected: bool,
+}
+
+/// A collider as it was last rasterised.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ObstacleShape {
+    pub shape: ShapeParams,
+    pub selected: bool,
+}
+
+/// What the selected entity with a sensor knows, by cell.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct KnownCells {
+    pub unknown: Vec<[f64; 2]>,
+    pub visible: Vec<[f64; 2]>,
+    pub remembered: Vec<[f64; 2]>,
+}
+
+/// A cell of the heatmap with its strength in [0, 1], preferred ones
+/// cheaper than the rest.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct HeatCell {
+    pub cell: (i64, i64),
+    pub strength: f64,
+    pub preferred: bool,
+}
+
+/// A patrol route through its waypoints.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PatrolRoute {
+    pub points: Vec<[f64; 2]>,
+    pub col: egui::Color32,
+    pub looped: bool,
+}
+
+/// Everything the demo panel draws, extracted after the simulation ran so
+/// panels never read the simulation or the world themselves.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Extract {
+    /// Centers of the cells colliders cover.
+    pub blocked: Vec<[f64; 2]>,
+    pub paths: Vec<Vec<Pos2>>,
+    /// The SIPP paths, one cell per tick, with the tick of their first cell.
+    pub timed_paths: Vec<(u64, Vec<Pos2>)>,
+    pub slot_points: Vec<[f64; 2]>,
+    pub obstacles: Vec<ObstacleShape>,
+    pub known_cells: Option<KnownCells>,
+    /// Empty unless the heatmap is shown.
+    pub heat: Vec<HeatCell>,
+    /// Lines of the planner's navigation structure, empty unless shown.
+    pub overlay: Vec<Vec<[f64; 2]>>,
+    pub tree_edges: Vec<([f64; 2], [f64; 2], usize)>,
+    pub patrols: Vec<PatrolRoute>,
+    pub sprites: Vec<Sprite>,
+}
+
+impl Simulation {
+    pub(super) fn extract(&mut self, world: &World) {
+        let blocked = self
+            .space_lut
+            .iter()
+            .filter(|(_, covered)| **covered)
+            .map(|((x, y), _)| [*x as f64 + 0.5, *y as f64 + 0.5])
+            .collect();
+        let timed_paths = self
+            .timed_paths
+            .iter()
+            .filter_map(|(id, start)| Some((*start, self.current_paths.get(id)?.clone())))
+            .collect();
+        let obstacles = self
+            .colliders
+            .iter()
+            .map(|(id, (shape, _))| ObstacleShape {
+                shape: shape.clone(),
+                selected: world.is_selected(*id),
+            })
+            .collect();
+        let tree_edges = if self.env_settings.planner == Planner::Sampling {
+            self.tree_edges.clone()
+        } else {
+            Vec::new()
+        };
+        self.extract = Extract {
+            blocked,
+            paths: self.current_paths.values().cloned().collect(),
+            timed_paths,
+            slot_points: self.slot_points.clone(),
+            obstacles,
+            known_cells: self.extract_known_cells(world),
+            heat: self.extract_heat(world),
+            overlay: self.extract_overlay(),
+            tree_edges,
+            patrols: extract_patrols(world),
+            sprites: self.extract_sprites(world),
+        };
+    }
+
+    /// Unknown cells are fogged, the ones seen right now lit and obstacles
+    /// remembered marked.
+    fn extract_known_cells(&self, world: &World) -> Option<KnownCells> {
+        let selected = world.selected();
+        let known = selected.iter().find_map(|id| self.known_maps.get(id))?;
+        let mut cells = KnownCells::default();
+        let (min, max) = (Pos2::from_min(&self.grid), Pos2::from_max(&self.grid));
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                let cell = Pos2::new(x, y);
+                let point = [x as f64 + 0.5, y as f64 + 0.5];
+                if known.visible.contains(&cell) {
+                    cells.visible.push(point);
+                } else if !known.is_known(&cell) {
+                    cells.unknown.push(point);
+                } else if known.is_blocked(&cell) {
+                    cells.remembered.push(point);
+                }
+            }
+        }
+        Some(cells)
+    }
+
+    /// The cost layer of the selected entity when it has tactics, otherwise
+    /// the strongest influence of any tag per cell.
+    fn extract_heat(&self, world: &World) -> Vec<HeatCell> {
+        if !self.env_settings.influence.show_heatmap {
+            return Vec::new();
+        }
+        let costs = world
+            .selected()
+            .iter()
+            .map(|id| self.cost_layer(world, *id))
+            .find(|costs| !costs.is_empty());
+
+        let mut cells = Vec::new();
+        if let Some(costs) = costs {
+            for (cell, factor) in costs.factors() {
+                if factor > 1. {
+                    cells.push(HeatCell {
+                        cell,
+                        strength: (factor - 1.).min(1.),
+                        preferred: false,
+                    });
+                } else if factor < 1. {
+                    let floor = influence::MIN_FACTOR;
+                    cells.push(HeatCell {
+                        cell,
+                        strength: (1. - factor) / (1. - floor),
+                        preferred: true,
+                    });
+                }
+            }
+        } else {
+            let mut strongest: HashMap<(i64, i64), f64> = HashMap::new();
+            for map in self.influence_maps.values() {
+                for (cell, value) in map.iter() {
+                    let entry = strongest.entry(*cell).or_insert(0.);
+                    *entry = entry.max(*value);
+                }
+            }
+            cells.extend(strongest.into_iter().map(|(cell, strength)| HeatCell {
+                cell,
+                strength,
+                preferred: false,
+            }));
+        }
+        cells
+    }
+
+    fn extract_overlay(&self) -> Vec<Vec<[f64; 2]>> {
+        if !self.env_settings.show_navmesh {
+            return Vec::new();
+        }
+        match self.env_settings.planner {
+            Planner::PolygonMesh => (0..self.polygon_navmesh.triangles.len())
+                .map(|t| {
+                    let [a, b, c] = self.polygon_navmesh.triangle_points(t);
+                    vec![a, b, c, a]
+                })
+                .collect(),
+            Planner::VisibilityGraph => self
+                .visibility_graph
+                .edges()
+                .into_iter()
+                .map(|(a, b)| vec![a, b])
+                .collect(),
+            // the sampling tree is extracted as it grows
+            Planner::Grid | Planner::Sampling | Planner::HybridAStar | Planner::Sipp => Vec::new(),
+        }
+    }
+
+    /// Where entities are drawn: over their continuous position where they
+    /// have one, the center of their cell otherwise.
+    fn extract_sprites(&self, world: &World) -> Vec<Sprite> {
+        world
+            .query_filtered::<(EntityId, &Transform2, Option<&Color>), Without<Collider>>()
+            .map(|(id, transform, color)| Sprite {
+                cell: transform.pos,
+                center: self
+                    .agents
+                    .get(&id)
+                    .map_or(cell_center(transform.pos), |a| a.position),
+                heading: transform.heading as f64,
+                col: color.map_or(egui::Color32::default(), |c| c.col),
+                name: world
+                    .entity(id)
+                    .map_or(String::new(), |e| e.data.name.clone()),
+                selected: world.is_selected(id),
+            })
+            .collect()
+    }
+}
+
+fn extract_patrols(world: &World) -> Vec<PatrolRoute> {
+    world
+        .query::<(&Patrol, Option<&Color>)>()
+        .filter(|(patrol, _)| !patrol.waypoints.is_empty())
+        .map(|(patrol, color)| {
+            let col = color.map_or(egui::Color32::default(), |c| c.col);
+            PatrolRoute {
+                points: patrol.waypoints.iter().map(|p| cell_center(*p)).collect(),
+                col: egui::Color32::from_rgba_unmultiplied(col.r(), col.g(), col.b(), 120),
+                looped: patrol.mode == PatrolMode::Loop,
+            }
+        })
+        .collect()
+}
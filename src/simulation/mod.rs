@@ -0,0 +1,486 @@
+use crate::ecs::component::{Collider, ShapeMode, Transform2};
+use crate::ecs::entity::EntityId;
+use crate::ecs::event::{ComponentChanged, EntityDespawned, EventReader};
+use crate::ecs::pos2::Pos2;
+use crate::ecs::schedule::{Schedule, Stage as SystemStage};
+use crate::ecs::spatial::Aabb;
+use crate::ecs::world::World;
+use crate::pathfinding::dubins::Pose;
+use crate::pathfinding::fog::KnownMap;
+use crate::pathfinding::formation::Formation;
+use crate::pathfinding::influence::InfluenceMap;
+use crate::pathfinding::orca::{self, OrcaAgent};
+use crate::pathfinding::polygon_mesh::PolygonNavMesh;
+use crate::pathfinding::sampling::TreeEvent;
+use crate::pathfinding::shape::ShapeParams;
+use crate::pathfinding::visibility_graph::VisibilityGraph;
+use crate::pathfinding::{cell_center, rasterize_polyline, NavMesh};
+use poll_promise::Promise;
+use settings::{EnvironmentSettings, Planner, Stage};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::mpsc;
+
+mod ai;
+pub mod extract;
+mod movement;
+mod navigation;
+mod planning;
+pub mod settings;
+
+pub use extract::Extract;
+
+/// The cells of a path and, for Hybrid A* only, the poses it drives
+/// through them.
+type PlannedPath = (Vec<Pos2>, Vec<Pose>);
+
+/// A path being planned. Hybrid A* plans come with the poses to drive
+/// through, the cells they pass over are what everything else works with.
+enum PathPromise {
+    Cells(Option<Promise<Option<Vec<Pos2>>>>),
+    Poses(Option<Promise<Option<PlannedPath>>>),
+}
+
+impl PathPromise {
+    /// Whether planning never started.
+    fn is_none(&self) -> bool {
+        match self {
+            PathPromise::Cells(p) => p.is_none(),
+            PathPromise::Poses(p) => p.is_none(),
+        }
+    }
+
+    /// The cells of the finished plan, `None` while it is still running.
+    fn ready(&self) -> Option<Option<&Vec<Pos2>>> {
+        match self {
+            PathPromise::Cells(Some(p)) => p.ready().map(|r| r.as_ref()),
+            PathPromise::Poses(Some(p)) => p.ready().map(|r| r.as_ref().map(|(c, _)| c)),
+            _ => Some(None),
+        }
+    }
+
+    /// Takes the finished plan, `None` while it is still running.
+    fn take_ready(&mut self) -> Option<Option<PlannedPath>> {
+        match self {
+            PathPromise::Cells(p) => {
+                let path = p.as_mut()?.ready_mut()?.take();
+                Some(path.map(|cells| (cells, Vec::new())))
+            }
+            PathPromise::Poses(p) => p.as_mut()?.ready_mut().map(|r| r.take()),
+        }
+    }
+}
+
+impl std::fmt::Debug for PathPromise {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Your custom logic here
+        write!(f, "DebuggablePromise(...)")
+    }
+}
+impl PartialEq for PathPromise {
+    fn eq(&self, _other: &Self) -> bool {
+        false
+    }
+}
+impl Clone for PathPromise {
+    fn clone(&self) -> Self {
+        Self::Cells(Option::None)
+    }
+}
+
+/// A primary click on the grid, handled in the input stage.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Click {
+    pub x: f64,
+    pub y: f64,
+    pub ctrl: bool,
+}
+
+/// A box dragged out on the grid with shift held, selecting what is in it
+/// in the input stage.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct BoxSelect {
+    pub area: Aabb,
+    pub ctrl: bool,
+}
+
+/// What the user did on the grid since the simulation last ran.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Input {
+    pub clicks: Vec<Click>,
+    pub box_selects: Vec<BoxSelect>,
+    /// Cells middle clicked, which the next order goes through first.
+    pub waypoints: Vec<Pos2>,
+}
+
+/// A path put on hold by [`ReplanStrategy::WaitAndRetry`].
+///
+/// [`ReplanStrategy::WaitAndRetry`]: crate::pathfinding::replan::ReplanStrategy::WaitAndRetry
+#[derive(Debug, PartialEq, Clone)]
+struct WaitingPath {
+    path: Vec<Pos2>,
+    /// Seconds until the next check.
+    timer: f32,
+    retries: u32,
+}
+
+/// Seconds entities move by at a time, however long frames take.
+const MOVEMENT_STEP: f32 = 1. / 60.;
+/// Minimum number of tree events revealed per frame while animating.
+const TREE_EVENTS_PER_FRAME: usize = 8;
+
+/// Everything the demo's systems keep between frames: the navigation built
+/// from the colliders, the paths being planned and walked, and what the
+/// agents know and are up to. The app owns it next to the `Schedule` that
+/// runs over it, panels only hand it their input and draw the `Extract` it
+/// leaves behind.
+pub struct Simulation {
+    grid: egui::Rect,
+    env_settings: EnvironmentSettings,
+    generate: bool,
+    /// Obstacles to replace the generated ones with, spawned in the input
+    /// stage.
+    pending_obstacles: Option<Vec<(Pos2, Collider)>>,
+    /// The entity the generated obstacles hang below.
+    obstacle_group: Option<EntityId>,
+    /// Shape and cells of every collider as last rasterised.
+    colliders: HashMap<EntityId, (ShapeParams, Vec<(i64, i64)>)>,
+    /// How many colliders cover each cell of the `space_lut`.
+    cover: HashMap<(i64, i64), u32>,
+    space_lut: HashMap<(i64, i64), bool>,
+
+    navmesh: NavMesh,
+    /// Lengths of the grid paths goal planning asked for, kept until cells
+    /// are blocked or freed or the grid bounds change.
+    path_lengths: HashMap<(Pos2, Pos2), Option<f64>>,
+    polygon_navmesh: PolygonNavMesh,
+    /// Whether colliders changed since the polygon mesh was triangulated.
+    polygon_stale: bool,
+    visibility_graph: VisibilityGraph,
+    /// The key of every collider's obstacle in the visibility graph.
+    visibility_keys: HashMap<EntityId, usize>,
+
+    tree_receiver: Option<mpsc::Receiver<TreeEvent>>,
+    tree_events: VecDeque<TreeEvent>,
+    tree_edges: Vec<([f64; 2], [f64; 2], usize)>,
+
+    start: Pos2,
+
+    path_map: HashMap<EntityId, PathPromise>,
+    current_paths: HashMap<EntityId, Vec<Pos2>>,
+    /// The entities planning or walking a SIPP path, with the tick the
+    /// first cell of it is at. Together with their `current_paths` they
+    /// are the reservations later plans avoid.
+    timed_paths: HashMap<EntityId, u64>,
+    /// SIPP ticks since the simulation started, which reservations count in.
+    sipp_clock: f64,
+    /// The poses left to drive through of the entities on a Hybrid A*
+    /// path, their `current_paths` are the cells those pass over.
+    pose_paths: HashMap<EntityId, Vec<Pose>>,
+    agents: HashMap<EntityId, OrcaAgent>,
+    /// The ORCA walls: the boundary edges of every blocked cell, and the
+    /// grid boundaries.
+    wall_edges: Vec<orca::Wall>,
+    cell_walls: HashMap<(i64, i64), Vec<orca::Wall>>,
+    /// The grid boundaries, kept apart from the cell walls as they only
+    /// change with the grid.
+    boundary_walls: Vec<orca::Wall>,
+    formations: Vec<Formation>,
+    formation_targets: HashMap<EntityId, Pos2>,
+    slot_points: Vec<[f64; 2]>,
+
+    waypoint_goals: HashMap<EntityId, Vec<Pos2>>,
+    waiting_paths: HashMap<EntityId, WaitingPath>,
+    patrol_targets: HashMap<EntityId, Pos2>,
+    move_requests: HashMap<EntityId, Pos2>,
+    bt_patrolling: HashSet<EntityId>,
+    influence_maps: HashMap<String, InfluenceMap>,
+    /// Tag and cell of every source the maps were spread from.
+    influence_sources: Vec<(String, Pos2)>,
+    known_maps: HashMap<EntityId, KnownMap>,
+
+    input: Input,
+    extract: Extract,
+    despawned: EventReader<EntityDespawned>,
+    changes: EventReader<ComponentChanged>,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        let mut sim = Self {
+            grid: egui::Rect::from_min_max(
+                egui::Pos2 { x: 0., y: 0. },
+                egui::Pos2 { x: 100., y: 100. },
+            ),
+            env_settings: EnvironmentSettings::default(),
+            generate: false,
+            pending_obstacles: None,
+            obstacle_group: None,
+            colliders: HashMap::new(),
+            cover: HashMap::new(),
+            space_lut: HashMap::default(),
+
+            navmesh: NavMesh::default(),
+            path_lengths: HashMap::new(),
+            polygon_navmesh: PolygonNavMesh::default(),
+            polygon_stale: true,
+            visibility_graph: VisibilityGraph::default(),
+            visibility_keys: HashMap::new(),
+            tree_receiver: None,
+            tree_events: VecDeque::new(),
+            tree_edges: Vec::new(),
+            start: Pos2::default(),
+            path_map: HashMap::default(),
+            current_paths: HashMap::default(),
+            timed_paths: HashMap::default(),
+            sipp_clock: 0.,
+            pose_paths: HashMap::default(),
+            agents: HashMap::default(),
+            wall_edges: Vec::new(),
+            cell_walls: HashMap::new(),
+            boundary_walls: Vec::new(),
+            formations: Vec::new(),
+            formation_targets: HashMap::default(),
+            slot_points: Vec::new(),
+            waypoint_goals: HashMap::default(),
+            waiting_paths: HashMap::default(),
+            patrol_targets: HashMap::default(),
+            move_requests: HashMap::default(),
+            bt_patrolling: HashSet::default(),
+            influence_maps: HashMap::default(),
+            influence_sources: Vec::new(),
+            known_maps: HashMap::default(),
+            input: Input::default(),
+            extract: Extract::default(),
+            despawned: EventReader::default(),
+            changes: EventReader::default(),
+        };
+        sim.update_bounds();
+        sim
+    }
+}
+
+impl Simulation {
+    pub fn set_env_settings(&mut self, new_settings: EnvironmentSettings) {
+        let swap_stage =
+            (self.env_settings.stage != Stage::AStar) && (new_settings.stage == Stage::AStar);
+        let radius_changed = self.env_settings.agent_radius != new_settings.agent_radius;
+        if self.env_settings.influence != new_settings.influence {
+            self.influence_sources.clear();
+        }
+        self.env_settings = new_settings;
+        if swap_stage {
+            self.swap_to_a_star_stage();
+        } else if radius_changed {
+            self.rebuild_visibility_graph();
+        }
+        self.visibility_graph.search = new_settings.graph_search;
+    }
+
+    /// Takes over the bounds of the grid shown.
+    pub fn set_grid(&mut self, grid: egui::Rect) {
+        if self.grid != grid {
+            self.grid = grid;
+            self.update_bounds();
+        }
+    }
+
+    /// Queues up what the user did, for the input stage to handle.
+    pub fn push_input(&mut self, input: Input) {
+        self.input.clicks.extend(input.clicks);
+        self.input.box_selects.extend(input.box_selects);
+        self.input.waypoints.extend(input.waypoints);
+    }
+
+    /// What the last run left for the panels to draw.
+    pub fn take_extract(&mut self) -> Extract {
+        std::mem::take(&mut self.extract)
+    }
+
+    pub fn generate(&mut self) {
+        self.generate = true;
+    }
+    pub fn swap_to_a_star_stage(&mut self) {
+        let walls = [
+            (39, 37, 2., 13.), // mid
+            (30, 40, 20., 1.), // bot
+            (30, 49, 20., 1.), // top
+        ];
+        self.pending_obstacles = Some(
+            walls
+                .into_iter()
+                .map(|(x, y, width, height)| {
+                    let collider = Collider {
+                        shape: ShapeMode::Rect,
+                        size: egui::vec2(width, height),
+                        ..Default::default()
+                    };
+                    (Pos2::new(x, y), collider)
+                })
+                .collect(),
+        );
+    }
+}
+
+impl Simulation {
+    /// The simulation as systems, which the app runs every frame whether or
+    /// not the demo panel is drawn.
+    pub fn schedule() -> Schedule<Simulation> {
+        let mut schedule = Schedule::<Simulation>::default().with_fixed_step(MOVEMENT_STEP);
+        schedule.add_system("forget_despawned", SystemStage::Input, |sim, world, _| {
+            sim.forget_despawned(world)
+        });
+        schedule.add_system("spawn_obstacles", SystemStage::Input, |sim, world, _| {
+            sim.spawn_obstacles(world)
+        });
+        schedule
+            .add_system("handle_clicks", SystemStage::Input, |sim, world, _| {
+                sim.handle_clicks(world)
+            })
+            .after("forget_despawned");
+
+        // paths are checked against the obstacles as they are this frame
+        schedule
+            .add_system(
+                "rasterize_colliders",
+                SystemStage::Planning,
+                |sim, world, _| sim.rasterize_colliders(world),
+            )
+            .before("update_sensors")
+            .before("monitor_paths");
+        schedule.add_system("collect_paths", SystemStage::Planning, |sim, world, _| {
+            sim.collect_paths(world)
+        });
+        schedule.add_system("grow_sampling_tree", SystemStage::Planning, |sim, _, _| {
+            if sim.env_settings.planner == Planner::Sampling {
+                sim.grow_sampling_tree();
+            }
+        });
+        schedule
+            .add_system("update_sensors", SystemStage::Planning, |sim, world, _| {
+                sim.update_sensors(world)
+            })
+            .after("collect_paths");
+        schedule
+            .add_system(
+                "update_influence",
+                SystemStage::Planning,
+                |sim, world, _| sim.update_influence(world),
+            )
+            .before("monitor_paths");
+        schedule
+            .add_system("monitor_paths", SystemStage::Planning, |sim, world, dt| {
+                sim.monitor_paths(world, dt)
+            })
+            .after("update_sensors");
+        schedule
+            .add_system(
+                "update_behaviours",
+                SystemStage::Planning,
+                |sim, world, dt| sim.update_behaviours(world, dt),
+            )
+            .after("monitor_paths");
+        schedule
+            .add_system("update_goals", SystemStage::Planning, |sim, world, dt| {
+                sim.update_goals(world, dt)
+            })
+            .after("update_behaviours");
+        schedule
+            .add_system(
+                "update_patrol_targets",
+                SystemStage::Planning,
+                |sim, world, _| sim.update_patrol_targets(world),
+            )
+            .before("update_patrols");
+        // behaviour trees say which of their entities patrol this frame
+        schedule
+            .add_system("update_patrols", SystemStage::Planning, |sim, world, _| {
+                sim.update_patrols(world)
+            })
+            .after("update_behaviours")
+            .after("update_goals");
+        schedule
+            .add_system(
+                "update_formations",
+                SystemStage::Planning,
+                |sim, world, _| sim.update_formations(world),
+            )
+            .after("update_patrols");
+
+        schedule
+            .add_system("move_entities", SystemStage::Movement, |sim, world, dt| {
+                sim.move_entities(world, dt as f64)
+            })
+            .fixed();
+        schedule
+            .add_system("complete_paths", SystemStage::Movement, |sim, world, _| {
+                sim.complete_paths(world)
+            })
+            .after("move_entities");
+
+        schedule.add_system(
+            "propagate_transforms",
+            SystemStage::TransformPropagation,
+            |_, world, _| world.propagate_entity_changes(),
+        );
+
+        schedule.add_system("extract", SystemStage::RenderExtract, |sim, world, _| {
+            sim.extract(world)
+        });
+        schedule
+    }
+
+    /// Drops whatever is kept per entity for entities that were despawned, so
+    /// they stop being drawn, planned for or moved.
+    fn forget_despawned(&mut self, world: &World) {
+        if self.despawned.read(world).count() == 0 {
+            return;
+        }
+        let alive = |id: &EntityId| world.contains(*id);
+        self.path_map.retain(|id, _| alive(id));
+        self.current_paths.retain(|id, _| alive(id));
+        self.timed_paths.retain(|id, _| alive(id));
+        self.pose_paths.retain(|id, _| alive(id));
+        self.agents.retain(|id, _| alive(id));
+        self.waypoint_goals.retain(|id, _| alive(id));
+        self.waiting_paths.retain(|id, _| alive(id));
+        self.patrol_targets.retain(|id, _| alive(id));
+        self.move_requests.retain(|id, _| alive(id));
+        self.bt_patrolling.retain(alive);
+        self.known_maps.retain(|id, _| alive(id));
+        let gone: Vec<EntityId> = self
+            .formations
+            .iter()
+            .flat_map(|f| f.followers.iter().chain([&f.leader]))
+            .filter(|id| !alive(id))
+            .copied()
+            .collect();
+        self.leave_formations(&gone);
+    }
+
+    fn entity_transform(&self, world: &World, id: EntityId) -> Option<(Pos2, f32)> {
+        world.get::<Transform2>(id).map(|tc| (tc.pos, tc.heading))
+    }
+
+    /// Continuous position and heading of an entity.
+    fn entity_pose(&self, world: &World, id: EntityId) -> Option<([f64; 2], f64)> {
+        let (pos, heading) = self.entity_transform(world, id)?;
+        let position = self
+            .agents
+            .get(&id)
+            .map_or(cell_center(pos), |a| a.position);
+        Some((position, heading as f64))
+    }
+
+    fn is_free_cell(&self, cell: Pos2) -> bool {
+        let center = cell_center(cell);
+        self.grid
+            .contains(egui::pos2(center[0] as f32, center[1] as f32))
+            && !self.space_lut.contains_key(&cell.to_tuple())
+    }
+
+    fn is_clear_line(&self, from: [f64; 2], to: [f64; 2]) -> bool {
+        rasterize_polyline(&[from, to])
+            .into_iter()
+            .all(|c| self.is_free_cell(c))
+    }
+}
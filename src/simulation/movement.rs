@@ -0,0 +1,509 @@
+use super::Simulation;
+use crate::ecs::component::*;
+use crate::ecs::entity::EntityId;
+use crate::ecs::event::PathCompleted;
+use crate::ecs::pos2::Pos2;
+use crate::ecs::query::Without;
+use crate::ecs::steering;
+use crate::ecs::world::World;
+use crate::pathfinding::cell_center;
+use crate::pathfinding::orca::{self, OrcaAgent};
+use crate::pathfinding::shape::distance;
+use std::collections::HashMap;
+
+impl Simulation {
+    /// Moves every entity along its path, avoiding the others if local
+    /// avoidance is on, and the ones without a path by their steering
+    /// behaviours either way.
+    pub(super) fn move_entities(&mut self, world: &mut World, dt: f64) {
+        self.sipp_clock += dt / self.env_settings.sipp.tick;
+        if self.env_settings.local_avoidance {
+            self.steer_entities(world, dt);
+        } else {
+            self.follow_paths(world, dt);
+        }
+    }
+
+    /// Walks every entity along its path at its own speed, through continuous
+    /// positions so entities glide between cells and diagonal steps take √2 as
+    /// long. SIPP and Hybrid A* paths are kept to the way they were planned.
+    fn follow_paths(&mut self, world: &mut World, dt: f64) {
+        if dt <= 0. {
+            return;
+        }
+        let mut moved = Vec::new();
+        steering::apply_steering(world, dt as f32, &self.agent_positions());
+        for (id, transform, steering, velocity) in world.query_filtered::<(
+            EntityId,
+            &Transform2,
+            Option<&Steering>,
+            Option<&Velocity>,
+        ), Without<Collider>>()
+        {
+            let pos = transform.pos;
+            let mut agent = *self.agents.entry(id).or_insert(OrcaAgent {
+                position: cell_center(pos),
+                ..Default::default()
+            });
+            // moved by something else, e.g. the inspector
+            if Pos2::new(
+                agent.position[0].floor() as i64,
+                agent.position[1].floor() as i64,
+            ) != pos
+            {
+                agent.position = cell_center(pos);
+                // off its schedule, it just walks the rest
+                self.timed_paths.remove(&id);
+                self.pose_paths.remove(&id);
+            }
+            let start = agent.position;
+            let mut heading = None;
+            let speed = self.entity_speed(world, id) * self.pace(id);
+
+            if self.keeps_to_plan(id) {
+                heading = self.follow_plan(id, &mut agent, speed * dt);
+            } else if let Some(path) = self.current_paths.get_mut(&id) {
+                let mut budget = speed * dt;
+                while let Some(next) = path.first() {
+                    let target = cell_center(*next);
+                    let d = distance(agent.position, target);
+                    if d > budget {
+                        let t = budget / d;
+                        agent.position = [
+                            agent.position[0] + t * (target[0] - agent.position[0]),
+                            agent.position[1] + t * (target[1] - agent.position[1]),
+                        ];
+                        break;
+                    }
+                    agent.position = target;
+                    budget -= d;
+                    path.remove(0);
+                }
+            } else if let Some(v) = velocity
+                .filter(|_| steering.is_some_and(steering::is_steering))
+                .map(|v| v.linear)
+            {
+                // steering behaviours drive the entity whenever it has no path
+                // to follow, up to the walls since nothing else avoids them
+                let next = [
+                    agent.position[0] + v.x as f64 * dt,
+                    agent.position[1] + v.y as f64 * dt,
+                ];
+                if self.is_free_cell(Pos2::new(next[0].floor() as i64, next[1].floor() as i64)) {
+                    agent.position = next;
+                }
+            }
+            agent.velocity = [
+                (agent.position[0] - start[0]) / dt,
+                (agent.position[1] - start[1]) / dt,
+            ];
+            agent.preferred_velocity = agent.velocity;
+            moved.push((id, agent, heading));
+        }
+
+        self.agents
+            .retain(|id, _| moved.iter().any(|(m, _, _)| m == id));
+        for (id, agent, heading) in moved.into_iter() {
+            self.agents.insert(id, agent);
+            write_agent(world, id, &agent, heading);
+        }
+    }
+
+    /// Moves every agent continuously along its path. The path only gives the
+    /// preferred velocity, ORCA picks the actual one so agents avoid each other
+    /// and the walls of the `space_lut`. Entities keeping to a SIPP or Hybrid
+    /// A* path don't give way, the others avoid them.
+    fn steer_entities(&mut self, world: &mut World, dt: f64) {
+        if dt <= 0. {
+            return;
+        }
+        let mut ids = Vec::new();
+        let mut agents = Vec::new();
+        // where the entities keeping to their plan end up, and facing which way
+        let mut planned = Vec::new();
+        steering::apply_steering(world, dt as f32, &self.agent_positions());
+        for (id, transform, steering, velocity) in world.query_filtered::<(
+            EntityId,
+            &Transform2,
+            Option<&Steering>,
+            Option<&Velocity>,
+        ), Without<Collider>>()
+        {
+            let pos = transform.pos;
+            let mut agent = *self.agents.entry(id).or_insert(OrcaAgent {
+                position: cell_center(pos),
+                ..Default::default()
+            });
+            // moved by something else, e.g. the inspector
+            if Pos2::new(
+                agent.position[0].floor() as i64,
+                agent.position[1].floor() as i64,
+            ) != pos
+            {
+                agent.position = cell_center(pos);
+                agent.velocity = [0., 0.];
+                // off its schedule, it just walks the rest
+                self.timed_paths.remove(&id);
+                self.pose_paths.remove(&id);
+            }
+            // SIPP and Hybrid A* paths are kept to, it is up to the others to
+            // avoid the entity
+            if self.keeps_to_plan(id) {
+                let mut next = agent;
+                let speed = self.entity_speed(world, id) * self.pace(id);
+                let heading = self.follow_plan(id, &mut next, speed * dt);
+                agent.velocity = [
+                    (next.position[0] - agent.position[0]) / dt,
+                    (next.position[1] - agent.position[1]) / dt,
+                ];
+                agent.preferred_velocity = agent.velocity;
+                planned.push(Some((next.position, heading)));
+                ids.push(id);
+                agents.push(agent);
+                continue;
+            }
+            // steering behaviours drive the entity whenever it has no path to follow
+            let steered = steering.is_some_and(steering::is_steering);
+            let velocity = velocity.map(|v| v.linear);
+            let has_path = self.current_paths.get(&id).is_some_and(|p| !p.is_empty());
+            agent.preferred_velocity = match velocity {
+                Some(v) if steered && !has_path => [v.x as f64, v.y as f64],
+                _ => self.preferred_velocity(world, id, agent.position),
+            };
+            planned.push(None);
+            ids.push(id);
+            agents.push(agent);
+        }
+        self.agents.retain(|id, _| ids.contains(id));
+
+        // the spatial index knows the cells agents stood on, so it is asked
+        // for a little more than the settings want and ORCA picks from that;
+        // colliders are walls to ORCA, not neighbours
+        let settings = &self.env_settings.orca;
+        let slots: HashMap<EntityId, usize> =
+            ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+        let velocities: Vec<[f64; 2]> = agents
+            .iter()
+            .enumerate()
+            .map(|(i, agent)| {
+                if planned[i].is_some() {
+                    return agent.velocity;
+                }
+                let nearby: Vec<usize> = world
+                    .spatial()
+                    .nearest(
+                        agent.position,
+                        settings.max_neighbors + 1,
+                        settings.neighbor_dist + std::f64::consts::SQRT_2,
+                        |id| slots.contains_key(&id),
+                    )
+                    .into_iter()
+                    .filter_map(|id| slots.get(&id).copied())
+                    .collect();
+                orca::compute_velocity(i, &agents, &nearby, &self.wall_edges, settings, dt)
+            })
+            .collect();
+
+        let moves = ids.into_iter().zip(agents).zip(velocities).zip(planned);
+        for (((id, mut agent), velocity), planned) in moves {
+            let heading = match planned {
+                Some((position, heading)) => {
+                    agent.position = position;
+                    heading
+                }
+                None => {
+                    agent.velocity = velocity;
+                    agent.position = [
+                        agent.position[0] + velocity[0] * dt,
+                        agent.position[1] + velocity[1] * dt,
+                    ];
+                    None
+                }
+            };
+            self.agents.insert(id, agent);
+            write_agent(world, id, &agent, heading);
+        }
+    }
+
+    /// Whether the entity walks a SIPP or Hybrid A* path, which it keeps to
+    /// rather than heading from cell to cell.
+    fn keeps_to_plan(&self, id: EntityId) -> bool {
+        self.current_paths.get(&id).is_some_and(|p| !p.is_empty())
+            && (self.timed_paths.contains_key(&id) || self.pose_paths.contains_key(&id))
+    }
+
+    /// Moves an entity along the path it keeps to. A SIPP path puts it where
+    /// the SIPP clock says, one cell per tick whatever its speed, so the
+    /// reservations hold. A Hybrid A* path is driven through its poses,
+    /// `budget` further, and gives the heading of the pose driven to, which
+    /// can face away from the way it moves when reversing.
+    fn follow_plan(&mut self, id: EntityId, agent: &mut OrcaAgent, budget: f64) -> Option<f64> {
+        let path = self.current_paths.get_mut(&id)?;
+        if let Some(start) = self.timed_paths.get_mut(&id) {
+            // waits on the first cell until the path starts
+            let mut t = (self.sipp_clock - *start as f64).max(0.);
+            while t >= 1. && path.len() > 1 {
+                path.remove(0);
+                *start += 1;
+                t -= 1.;
+            }
+            let [x0, y0] = cell_center(*path.first()?);
+            match path.get(1) {
+                Some(next) => {
+                    let [x1, y1] = cell_center(*next);
+                    agent.position = [x0 + t * (x1 - x0), y0 + t * (y1 - y0)];
+                }
+                None => {
+                    agent.position = [x0, y0];
+                    path.clear();
+                }
+            }
+            return None;
+        }
+        let poses = self.pose_paths.get_mut(&id)?;
+        let mut budget = budget;
+        let mut heading = None;
+        while let Some(next) = poses.first() {
+            let target = [next[0], next[1]];
+            heading = Some(next[2]);
+            let d = distance(agent.position, target);
+            if d > budget {
+                let t = budget / d;
+                agent.position = [
+                    agent.position[0] + t * (target[0] - agent.position[0]),
+                    agent.position[1] + t * (target[1] - agent.position[1]),
+                ];
+                break;
+            }
+            agent.position = target;
+            budget -= d;
+            poses.remove(0);
+        }
+        // the cells behind it are passed
+        let cell = Pos2::new(
+            agent.position[0].floor() as i64,
+            agent.position[1].floor() as i64,
+        );
+        if let Some(i) = path.iter().position(|c| *c == cell) {
+            path.drain(..i);
+        }
+        if poses.is_empty() {
+            path.clear();
+        }
+        heading
+    }
+
+    /// Drops the paths walked to the end.
+    pub(super) fn complete_paths(&mut self, world: &mut World) {
+        let completed: Vec<EntityId> = self
+            .current_paths
+            .iter()
+            .filter(|(_, path)| path.is_empty())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in completed {
+            self.current_paths.remove(&id);
+            self.timed_paths.remove(&id);
+            self.pose_paths.remove(&id);
+            world.send(PathCompleted(id));
+        }
+    }
+
+    /// Where the agents are between cells, for the steering behaviours.
+    fn agent_positions(&self) -> HashMap<EntityId, egui::Pos2> {
+        self.agents
+            .iter()
+            .map(|(id, a)| {
+                let p = egui::Pos2::new(a.position[0] as f32, a.position[1] as f32);
+                (*id, p)
+            })
+            .collect()
+    }
+
+    /// Cells per second from the entity's `Kinematics`, the global speed otherwise.
+    fn entity_speed(&self, world: &World, id: EntityId) -> f64 {
+        world
+            .get::<Kinematics>(id)
+            .map_or(self.env_settings.orca.max_speed, |k| k.max_speed as f64)
+    }
+
+    /// Below 1 while `id` leads a formation whose followers lag behind.
+    fn pace(&self, id: EntityId) -> f64 {
+        self.formations
+            .iter()
+            .find(|f| f.leader == id)
+            .map_or(1., |f| f.pace)
+    }
+
+    /// Heads for the next cell of the entity's path, dropping cells once they
+    /// are close enough, and slows down on the last one.
+    fn preferred_velocity(&mut self, world: &World, id: EntityId, position: [f64; 2]) -> [f64; 2] {
+        let max_speed = self
+            .entity_speed(world, id)
+            .min(self.env_settings.orca.max_speed)
+            * self.pace(id);
+        let Some(path) = self.current_paths.get_mut(&id) else {
+            return [0., 0.];
+        };
+        let distance_to = |p: Pos2| {
+            let c = cell_center(p);
+            ((c[0] - position[0]).powi(2) + (c[1] - position[1]).powi(2)).sqrt()
+        };
+        while path.len() > 1 && distance_to(path[0]) < 0.75 {
+            path.remove(0);
+        }
+        if path.len() == 1 && distance_to(path[0]) < 0.05 {
+            path.clear();
+        }
+        let Some(next) = path.first() else {
+            return [0., 0.];
+        };
+        let target = cell_center(*next);
+        let to = [target[0] - position[0], target[1] - position[1]];
+        let d = (to[0] * to[0] + to[1] * to[1]).sqrt();
+        let speed = if path.len() > 1 {
+            max_speed
+        } else {
+            max_speed.min(d * 4.)
+        };
+        if d < 1e-9 {
+            [0., 0.]
+        } else {
+            [to[0] / d * speed, to[1] / d * speed]
+        }
+    }
+}
+
+/// Puts an agent's continuous state back on its entity: the cell it is over,
+/// the direction it is heading, `heading` if given and the way it moves
+/// otherwise, and, if it has one, its `Velocity`.
+/// Only what differs is written, so agents standing still don't show up as
+/// changed.
+fn write_agent(world: &mut World, id: EntityId, agent: &OrcaAgent, heading: Option<f64>) {
+    let velocity = agent.velocity;
+    if let Some(mut tc) = world.get::<Transform2>(id).copied() {
+        tc.pos = Pos2::new(
+            agent.position[0].floor() as i64,
+            agent.position[1].floor() as i64,
+        );
+        if let Some(heading) = heading {
+            tc.heading = heading as f32;
+        } else if velocity[0].abs() + velocity[1].abs() > 0.1 {
+            tc.heading = velocity[1].atan2(velocity[0]) as f32;
+        }
+        world.set(id, tc);
+    }
+    world.set(
+        id,
+        Velocity {
+            linear: egui::vec2(velocity[0] as f32, velocity[1] as f32),
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::entity::Entity;
+    use crate::pathfinding::hybrid_a_star::HybridAStar;
+    use crate::simulation::planning::pose_cells;
+
+    /// An entity standing on `cell`.
+    fn spawn_at(world: &mut World, cell: Pos2) -> EntityId {
+        let id = world.spawn(Entity::default());
+        world.add(
+            id,
+            Transform2 {
+                pos: cell,
+                heading: 0.,
+            },
+        );
+        id
+    }
+
+    /// Runs the movement stage for `ticks` SIPP ticks.
+    fn run(sim: &mut Simulation, world: &mut World, ticks: f64) {
+        let dt = sim.env_settings.sipp.tick * ticks;
+        sim.move_entities(world, dt);
+    }
+
+    fn assert_at(sim: &Simulation, id: EntityId, position: [f64; 2]) {
+        let at = sim.agents[&id].position;
+        assert!(
+            distance(at, position) < 1e-9,
+            "{:?} is not {:?}",
+            at,
+            position
+        );
+    }
+
+    #[test]
+    fn timed_paths_wait_on_their_wait_cells_with_local_avoidance() {
+        let mut sim = Simulation::default();
+        assert!(sim.env_settings.local_avoidance);
+        let mut world = World::default();
+        let id = spawn_at(&mut world, Pos2::new(5, 5));
+        // waits a tick on its first cell, then steps east
+        let path = vec![Pos2::new(5, 5), Pos2::new(5, 5), Pos2::new(6, 5)];
+        sim.current_paths.insert(id, path);
+        sim.timed_paths.insert(id, 0);
+
+        run(&mut sim, &mut world, 0.5);
+        assert_at(&sim, id, [5.5, 5.5]);
+        run(&mut sim, &mut world, 0.5);
+        assert_at(&sim, id, [5.5, 5.5]);
+        run(&mut sim, &mut world, 0.5);
+        assert_at(&sim, id, [6., 5.5]);
+        run(&mut sim, &mut world, 1.);
+        assert_at(&sim, id, [6.5, 5.5]);
+        assert_eq!(world.get::<Transform2>(id).unwrap().pos, Pos2::new(6, 5));
+    }
+
+    #[test]
+    fn timed_paths_wait_for_their_start_tick() {
+        let mut sim = Simulation::default();
+        let mut world = World::default();
+        let id = spawn_at(&mut world, Pos2::new(5, 5));
+        sim.current_paths
+            .insert(id, vec![Pos2::new(5, 5), Pos2::new(5, 6)]);
+        sim.timed_paths.insert(id, 2);
+
+        run(&mut sim, &mut world, 1.5);
+        assert_at(&sim, id, [5.5, 5.5]);
+        run(&mut sim, &mut world, 1.);
+        assert_at(&sim, id, [5.5, 6.]);
+    }
+
+    #[test]
+    fn pose_paths_keep_their_heading_with_local_avoidance() {
+        let mut sim = Simulation::default();
+        assert!(sim.env_settings.local_avoidance);
+        let mut world = World::default();
+        let id = spawn_at(&mut world, Pos2::new(20, 20));
+        // has to turn round to reach a goal behind it
+        let poses = HybridAStar::new(
+            sim.collision_checker(sim.env_settings.hybrid.collision),
+            sim.env_settings.hybrid,
+        )
+        .waypointed_poses(Pos2::new(20, 20), vec![Pos2::new(14, 24)])
+        .unwrap();
+        let cells = pose_cells(&sim.navmesh, &poses).unwrap();
+        sim.current_paths.insert(id, cells);
+        sim.pose_paths.insert(id, poses);
+
+        let mut heading = 0.;
+        for _ in 0..2000 {
+            if sim.current_paths.get(&id).map_or(true, |p| p.is_empty()) {
+                break;
+            }
+            run(&mut sim, &mut world, 0.1);
+            let next = world.get::<Transform2>(id).unwrap().heading as f64;
+            let turn = (next - heading).sin().abs();
+            assert!(turn < 0.1, "turned from {} to {}", heading, next);
+            heading = next;
+        }
+        assert!(sim.current_paths[&id].is_empty());
+        assert_at(&sim, id, [14.5, 24.5]);
+        assert!(heading.cos() < 0.);
+    }
+}
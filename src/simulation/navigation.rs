@@ -0,0 +1,414 @@
+use super::settings::{Generated, Obstacle, Planner};
+use super::{Simulation, TREE_EVENTS_PER_FRAME};
+use crate::ecs::component::*;
+use crate::ecs::entity::{Entity, EntityId};
+use crate::ecs::pos2::Pos2;
+use crate::ecs::world::World;
+use crate::pathfinding::orca;
+use crate::pathfinding::polygon_mesh::PolygonNavMesh;
+use crate::pathfinding::sampling::{CollisionChecker, CollisionModel, SamplingPlanner, TreeEvent};
+use crate::pathfinding::shape::ShapeParams;
+use crate::pathfinding::visibility_graph::VisibilityGraph;
+use rand::Rng;
+use std::collections::HashMap;
+
+impl Simulation {
+    /// Replaces the generated obstacles with the pending ones, as entities
+    /// below an "Obstacles" entity.
+    pub(super) fn spawn_obstacles(&mut self, world: &mut World) {
+        if std::mem::take(&mut self.generate) {
+            self.generate_obstacles();
+        }
+        let Some(obstacles) = self.pending_obstacles.take() else {
+            return;
+        };
+        if let Some(group) = self.obstacle_group.take() {
+            world.despawn(group);
+        }
+        let mut group = Entity::default();
+        group.data.name = "Obstacles".to_string();
+        let group = world.spawn(group);
+        for (i, (pos, collider)) in obstacles.into_iter().enumerate() {
+            let mut obstacle = Entity::default();
+            obstacle.data.name = format!("Obstacle {}", i + 1);
+            obstacle.data.tag = "obstacle".to_string();
+            let id = world.spawn_child(group, obstacle);
+            world.add(id, Transform2 { pos, heading: 0. });
+            world.add(id, collider);
+        }
+        self.obstacle_group = Some(group);
+    }
+
+    fn generate_obstacles(&mut self) {
+        let mut obstacles = Vec::new();
+        match self.env_settings.n {
+            Generated::N(n) => {
+                for _ in 0..n {
+                    let x = rand::thread_rng()
+                        .gen_range((self.grid.min.x as i32)..(self.grid.max.x as i32))
+                        as i64;
+                    let y = rand::thread_rng().gen_range(0..100) as i64;
+                    let collider = match self.env_settings.obstacle {
+                        Obstacle::Circular => {
+                            let radius = rand::thread_rng().gen_range(
+                                self.env_settings.circle_radius_min
+                                    ..self.env_settings.circle_radius_max,
+                            ) as f32;
+                            Collider {
+                                shape: ShapeMode::Circle,
+                                size: egui::vec2(radius, radius),
+                                ..Default::default()
+                            }
+                        }
+                        Obstacle::Rectangular => {
+                            let width = rand::thread_rng().gen_range(
+                                self.env_settings.rect_side_min..self.env_settings.rect_side_max,
+                            ) as f32;
+                            let height = rand::thread_rng().gen_range(
+                                self.env_settings.rect_side_min..self.env_settings.rect_side_max,
+                            ) as f32;
+                            Collider {
+                                shape: ShapeMode::Rect,
+                                size: egui::vec2(width, height),
+                                ..Default::default()
+                            }
+                        }
+                    };
+                    obstacles.push((Pos2::new(x, y), collider));
+                }
+            }
+        }
+        self.pending_obstacles = Some(obstacles);
+    }
+
+    /// Rasterises the colliders that were added, moved, edited or removed
+    /// since they were last rasterised into the `space_lut`, touching only
+    /// the cells they covered before and cover now, and updates the
+    /// navigation around them. Shapes are compared against the cached ones
+    /// rather than going by change ticks, which are cleared before a
+    /// collider moved along with its parent gets here. Paths running through
+    /// newly blocked cells are replanned by `monitor_paths`.
+    pub(super) fn rasterize_colliders(&mut self, world: &World) {
+        let mut shapes: HashMap<EntityId, ShapeParams> = world
+            .query::<(EntityId, &Transform2, &Collider)>()
+            .map(|(id, transform, collider)| (id, collider.shape_at(transform.pos)))
+            .collect();
+        let mut dirty: Vec<EntityId> = self
+            .colliders
+            .keys()
+            .filter(|id| !shapes.contains_key(*id))
+            .copied()
+            .collect();
+        dirty.extend(shapes.keys().copied());
+
+        // cells that were blocked or freed
+        let mut flipped = Vec::new();
+        for id in dirty {
+            let shape = shapes.remove(&id);
+            if self.colliders.get(&id).map(|(shape, _)| shape) == shape.as_ref() {
+                continue;
+            }
+            self.polygon_stale = true;
+            if let Some((_, cells)) = self.colliders.remove(&id) {
+                for cell in cells {
+                    if let Some(count) = self.cover.get_mut(&cell) {
+                        *count -= 1;
+                        if *count == 0 {
+                            self.cover.remove(&cell);
+                            self.space_lut.remove(&cell);
+                            self.navmesh.space_lut.remove(&cell);
+                            flipped.push(cell);
+                        }
+                    }
+                }
+            }
+            if let Some(key) = self.visibility_keys.remove(&id) {
+                self.visibility_graph.remove_obstacle(key);
+            }
+            if let Some(shape) = shape {
+                let cells = rasterize(&shape);
+                for cell in cells.iter() {
+                    let count = self.cover.entry(*cell).or_insert(0);
+                    *count += 1;
+                    if *count == 1 {
+                        self.space_lut.insert(*cell, true);
+                        self.navmesh.space_lut.insert(*cell, true);
+                        flipped.push(*cell);
+                    }
+                }
+                let key = self.visibility_graph.insert_obstacle(&shape);
+                self.visibility_keys.insert(id, key);
+                self.colliders.insert(id, (shape, cells));
+            }
+        }
+        if !flipped.is_empty() {
+            self.update_navigation(&flipped);
+        }
+        self.refresh_polygon_navmesh();
+    }
+
+    /// Brings what depends on the `space_lut` up to date with the cells that
+    /// were blocked or freed: the ORCA walls around them and the maps of the
+    /// entities that saw them, which look again. Path lengths and influence
+    /// maps are worked out again as they are needed.
+    fn update_navigation(&mut self, flipped: &[(i64, i64)]) {
+        self.path_lengths.clear();
+        self.influence_sources.clear();
+        for known in self.known_maps.values_mut() {
+            if flipped
+                .iter()
+                .any(|(x, y)| known.visible.contains(&Pos2::new(*x, *y)))
+            {
+                known.invalidate();
+            }
+        }
+
+        for (x, y) in flipped.iter() {
+            for cell in [(*x, *y), (x - 1, *y), (x + 1, *y), (*x, y - 1), (*x, y + 1)] {
+                let edges = orca::cell_edges(&self.space_lut, cell);
+                if edges.is_empty() {
+                    self.cell_walls.remove(&cell);
+                } else {
+                    self.cell_walls.insert(cell, edges);
+                }
+            }
+        }
+        self.collect_walls();
+    }
+
+    /// Brings what depends on the grid bounds up to date: the bounds of the
+    /// `NavMesh` and the walls along them. Path lengths are worked out again.
+    pub(super) fn update_bounds(&mut self) {
+        self.path_lengths.clear();
+        self.navmesh
+            .set_grid_boundaries(Pos2::from_min(&self.grid), Pos2::from_max(&self.grid));
+        self.boundary_walls = orca::boundary_walls(
+            [self.grid.min.x as f64, self.grid.min.y as f64],
+            [self.grid.max.x as f64, self.grid.max.y as f64],
+        );
+        self.collect_walls();
+    }
+
+    /// The ORCA walls from the cell walls and the grid boundaries.
+    fn collect_walls(&mut self) {
+        self.wall_edges = self
+            .cell_walls
+            .values()
+            .flatten()
+            .chain(self.boundary_walls.iter())
+            .copied()
+            .collect();
+    }
+
+    /// Triangulates the polygon mesh again if colliders changed since, but
+    /// only while it is the planner: unlike the grid and the visibility
+    /// graph it can't be patched where they changed, so it isn't kept up to
+    /// date for nothing.
+    pub(super) fn refresh_polygon_navmesh(&mut self) {
+        if !self.polygon_stale || self.env_settings.planner != Planner::PolygonMesh {
+            return;
+        }
+        self.polygon_stale = false;
+        self.polygon_navmesh = PolygonNavMesh::from_obstacles(
+            Pos2::from_min(&self.grid),
+            Pos2::from_max(&self.grid),
+            &self.obstacle_shapes(),
+        );
+    }
+
+    pub(super) fn rebuild_visibility_graph(&mut self) {
+        self.visibility_graph = VisibilityGraph::new(
+            Pos2::from_min(&self.grid),
+            Pos2::from_max(&self.grid),
+            self.env_settings.agent_radius as f64,
+            self.env_settings.graph_search,
+        );
+        self.visibility_keys = self
+            .colliders
+            .iter()
+            .map(|(id, (shape, _))| (*id, self.visibility_graph.insert_obstacle(shape)))
+            .collect();
+    }
+
+    /// The shape of every collider as last rasterised.
+    fn obstacle_shapes(&self) -> Vec<ShapeParams> {
+        self.colliders
+            .values()
+            .map(|(shape, _)| shape.clone())
+            .collect()
+    }
+
+    pub(super) fn collision_checker(&self, model: CollisionModel) -> CollisionChecker {
+        CollisionChecker::new(
+            Pos2::from_min(&self.grid),
+            Pos2::from_max(&self.grid),
+            model,
+            self.space_lut.clone(),
+            self.obstacle_shapes(),
+        )
+    }
+
+    pub(super) fn sampling_planner(&self) -> SamplingPlanner {
+        SamplingPlanner::new(
+            self.collision_checker(self.env_settings.sampling.collision),
+            self.env_settings.sampling,
+        )
+    }
+
+    /// Reveals a few more events of the last sampling query, faster when a lot
+    /// of them are still pending so big trees don't take forever.
+    pub(super) fn grow_sampling_tree(&mut self) {
+        if let Some(receiver) = &self.tree_receiver {
+            self.tree_events.extend(receiver.try_iter());
+        }
+        let n = TREE_EVENTS_PER_FRAME.max(self.tree_events.len() / 120);
+        for event in self.tree_events.drain(..n.min(self.tree_events.len())) {
+            match event {
+                TreeEvent::Add { from, to, tree } => self.tree_edges.push((from, to, tree)),
+                TreeEvent::Remove { from, to } => {
+                    if let Some(i) = self
+                        .tree_edges
+                        .iter()
+                        .position(|(a, b, _)| (*a == from && *b == to) || (*a == to && *b == from))
+                    {
+                        self.tree_edges.swap_remove(i);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Cells a shape blocks. Rects and polygons block the cells they overlap,
+/// circles the cells with a corner inside them.
+fn rasterize(shape: &ShapeParams) -> Vec<(i64, i64)> {
+    let mut lut = HashMap::new();
+    match shape {
+        ShapeParams::Circle(cp) => {
+            fill_lut_with_circle(&mut lut, cp.center_x, cp.center_y, cp.radius_x)
+        }
+        ShapeParams::Rectangle(rp) => {
+            for x in rp.center_x.floor() as i64..(rp.center_x + rp.width).ceil() as i64 {
+                for y in rp.center_y.floor() as i64..(rp.center_y + rp.height).ceil() as i64 {
+                    lut.insert((x, y), true);
+                }
+            }
+        }
+        ShapeParams::Polygon(points) => {
+            let hull = shape.outline();
+            let (mut min, mut max) = ([f64::MAX; 2], [f64::MIN; 2]);
+            for p in hull.iter() {
+                min = [min[0].min(p[0]), min[1].min(p[1])];
+                max = [max[0].max(p[0]), max[1].max(p[1])];
+            }
+            for x in min[0].floor() as i64..max[0].ceil() as i64 {
+                for y in min[1].floor() as i64..max[1].ceil() as i64 {
+                    let (px, py) = (x as f64, y as f64);
+                    let corners = [[px, py], [px + 1., py], [px, py + 1.], [px + 1., py + 1.]];
+                    if corners.iter().any(|c| shape.contains(*c)) {
+                        lut.insert((x, y), true);
+                    }
+                }
+            }
+            for p in points.iter() {
+                lut.insert((p[0].floor() as i64, p[1].floor() as i64), true);
+            }
+        }
+    }
+    lut.into_keys().collect()
+}
+
+fn fill_lut_with_circle(lut: &mut HashMap<(i64, i64), bool>, cx: f64, cy: f64, r: f64) {
+    let min_x = (cx - r).floor() as i64;
+    let max_x = (cx + r).ceil() as i64;
+    let min_y = (cy - r).floor() as i64;
+    let max_y = (cy + r).ceil() as i64;
+
+    for x in min_x..=max_x {
+        for y in min_y..=max_y {
+            if is_inside_circle(x, y, cx, cy, r) {
+                lut.insert((x, y), true);
+            }
+        }
+    }
+}
+
+fn is_inside_circle(x: i64, y: i64, cx: f64, cy: f64, r: f64) -> bool {
+    let corners = [
+        (x as f64, y as f64),
+        (x as f64 + 1.0, y as f64),
+        (x as f64, y as f64 + 1.0),
+        (x as f64 + 1.0, y as f64 + 1.0),
+    ];
+
+    corners.iter().any(|&(px, py)| {
+        let dx = px - cx;
+        let dy = py - cy;
+        dx * dx + dy * dy <= r * r
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawn_collider(world: &mut World, x: i64, y: i64, collider: Collider) -> EntityId {
+        let id = world.spawn(Entity::default());
+        world.add(
+            id,
+            Transform2 {
+                pos: Pos2::new(x, y),
+                heading: 0.,
+            },
+        );
+        world.add(id, collider);
+        id
+    }
+
+    fn blocked(sim: &Simulation, x: i64, y: i64) -> bool {
+        sim.navmesh.space_lut.contains_key(&(x, y))
+    }
+
+    #[test]
+    fn rects_reach_right_and_up_and_circles_round_their_position() {
+        let cells = rasterize(&Collider::default().shape_at(Pos2::new(10, 20)));
+        assert_eq!(cells.len(), 16);
+        assert!(cells
+            .iter()
+            .all(|(x, y)| (10..14).contains(x) && (20..24).contains(y)));
+
+        let circle = Collider {
+            shape: ShapeMode::Circle,
+            size: egui::vec2(2., 2.),
+            ..Default::default()
+        };
+        let cells = rasterize(&circle.shape_at(Pos2::new(10, 20)));
+        assert!(cells.contains(&(10, 20)));
+        assert!(cells.contains(&(12, 20)));
+        assert!(!cells.contains(&(12, 22)));
+    }
+
+    #[test]
+    fn moved_colliders_free_their_old_cells_but_not_shared_ones() {
+        let mut sim = Simulation::default();
+        let mut world = World::default();
+        let a = spawn_collider(&mut world, 10, 10, Collider::default());
+        let b = spawn_collider(&mut world, 12, 10, Collider::default());
+        sim.rasterize_colliders(&world);
+        assert!(blocked(&sim, 10, 10));
+        assert!(blocked(&sim, 15, 13));
+
+        // (12, 10) to (13, 13) stays covered by b
+        world.get_mut::<Transform2>(a).unwrap().pos = Pos2::new(30, 30);
+        sim.rasterize_colliders(&world);
+        assert!(!blocked(&sim, 10, 10));
+        assert!(blocked(&sim, 12, 10));
+        assert!(blocked(&sim, 30, 30));
+
+        world.despawn(b);
+        sim.rasterize_colliders(&world);
+        assert!(!blocked(&sim, 12, 10));
+        assert!(blocked(&sim, 30, 30));
+        assert_eq!(sim.navmesh.space_lut.len(), 16);
+    }
+}
@@ -0,0 +1,579 @@
+use super::settings::Planner;
+use super::{PathPromise, Simulation, WaitingPath};
+use crate::ecs::component::*;
+use crate::ecs::entity::EntityId;
+use crate::ecs::event::PathFailed;
+use crate::ecs::pos2::{self, Pos2};
+use crate::ecs::query::Without;
+use crate::ecs::world::World;
+use crate::pathfinding::dubins::Pose;
+use crate::pathfinding::formation::{Formation, FormationShape};
+use crate::pathfinding::hybrid_a_star::HybridAStar;
+use crate::pathfinding::replan::{first_blocked, ReplanStrategy};
+use crate::pathfinding::shape::distance;
+use crate::pathfinding::sipp::DynamicObstacles;
+use crate::pathfinding::{cell_center, spawn_path_promise, ContinuousPlanner, NavMesh};
+use std::collections::HashSet;
+use std::sync::mpsc;
+
+impl Simulation {
+    /// Selects what was clicked on or boxed in, ctrl adding to the
+    /// selection, or sends the selection to the clicked cell if there is
+    /// nothing.
+    pub(super) fn handle_clicks(&mut self, world: &mut World) {
+        for select in std::mem::take(&mut self.input.box_selects) {
+            if !select.ctrl {
+                world.unselect_all();
+            }
+            for id in world.entities_in(select.area) {
+                world.select(id);
+            }
+        }
+        for click in std::mem::take(&mut self.input.clicks) {
+            let entts: Vec<EntityId> = world.entities_at(click.x, click.y);
+            if !entts.is_empty() {
+                if !click.ctrl {
+                    world.unselect_all();
+                }
+
+                for id in entts {
+                    world.select(id);
+                }
+            } else {
+                self.navigate(world, click.x, click.y);
+            }
+        }
+    }
+
+    fn navigate(&mut self, world: &World, x: f64, y: f64) {
+        self.start.x = x as i64;
+        self.start.y = y as i64;
+
+        let selected = world.selected();
+        if selected.len() > 0 {
+            self.input.waypoints.push(self.start);
+        }
+        self.leave_formations(&selected);
+        if selected.len() > 1 && self.env_settings.formation.shape != FormationShape::None {
+            self.navigate_formation(world, &selected);
+            self.input.waypoints.clear();
+            return;
+        }
+
+        for s in selected.iter() {
+            if let Some(path_promise) = self.path_map.get(s) {
+                // handle the Option
+                if path_promise.is_none() {
+                    // check the inner Option of PathPromise
+                    if !world.contains(*s) {
+                        continue;
+                    }
+                    let (pos, heading) = world
+                        .get::<Transform2>(*s)
+                        .map_or((pos2::Pos2::default(), 0f32), |t| (t.pos, t.heading));
+
+                    let some_path_promise =
+                        self.plan_path(world, *s, pos, heading, vec![self.start]);
+                    self.path_map.insert(*s, some_path_promise);
+
+                    log::info!(
+                        "{} ({}, {}) wants to go to ({}, {})",
+                        s,
+                        pos.x,
+                        pos.y,
+                        self.start.x,
+                        self.start.y
+                    );
+                }
+            } else {
+                if !world.contains(*s) {
+                    continue;
+                }
+                let (pos, heading) = world
+                    .get::<Transform2>(*s)
+                    .map_or((pos2::Pos2::default(), 0f32), |t| (t.pos, t.heading));
+
+                log::info!("{}", self.input.waypoints.len());
+                let some_path_promise =
+                    self.plan_path(world, *s, pos, heading, self.input.waypoints.clone());
+
+                log::info!(
+                    "{} ({}, {}) wants to go to ({}, {})",
+                    s,
+                    pos.x,
+                    pos.y,
+                    self.start.x,
+                    self.start.y
+                );
+                self.path_map.insert(*s, some_path_promise);
+            }
+        }
+        self.input.waypoints.clear();
+    }
+
+    /// Sends the selection off as one group: the member closest to the goal
+    /// leads along a planned path, everyone else keeps to a slot around it.
+    fn navigate_formation(&mut self, world: &World, selected: &[EntityId]) {
+        let goal = cell_center(self.start);
+        let mut members: Vec<(EntityId, Pos2, f32)> = selected
+            .iter()
+            .filter_map(|s| {
+                let tc = world.get::<Transform2>(*s)?;
+                Some((*s, tc.pos, tc.heading))
+            })
+            .collect();
+        members.sort_by_key(|(id, _, _)| *id);
+        let Some(&(leader, pos, heading)) = members.iter().min_by(|a, b| {
+            let d = |p: Pos2| distance(cell_center(p), goal);
+            d(a.1).total_cmp(&d(b.1))
+        }) else {
+            return;
+        };
+
+        let spacing = self.env_settings.formation.spacing;
+        let mut followers = Vec::new();
+        let mut custom = Vec::new();
+        for (i, (id, _, _)) in members.iter().filter(|m| m.0 != leader).enumerate() {
+            followers.push(*id);
+            // followers without a slot of their own line up behind the leader
+            let offset = world
+                .get::<FormationSlot>(*id)
+                .map(|fc| fc.offset)
+                .map_or([-((i + 1) as f64) * spacing, 0.], |o| {
+                    [o.x as f64, o.y as f64]
+                });
+            custom.push(offset);
+            self.path_map.remove(id);
+            self.current_paths.remove(id);
+            self.timed_paths.remove(id);
+            self.pose_paths.remove(id);
+        }
+
+        let path_promise =
+            self.plan_path(world, leader, pos, heading, self.input.waypoints.clone());
+        self.path_map.insert(leader, path_promise);
+        log::info!(
+            "{} ({}, {}) leads {} entities to ({}, {}) in a {:?} formation",
+            leader,
+            pos.x,
+            pos.y,
+            followers.len(),
+            self.start.x,
+            self.start.y,
+            self.env_settings.formation.shape
+        );
+        self.formations.push(Formation::new(
+            leader,
+            followers,
+            &self.env_settings.formation,
+            custom,
+        ));
+    }
+
+    /// Hands every finished path over to its entity, selected or not. SIPP
+    /// plans that run into one picked up since they started are made
+    /// again.
+    pub(super) fn collect_paths(&mut self, world: &mut World) {
+        let ids: Vec<EntityId> = self.path_map.keys().copied().collect();
+        for s in ids.iter() {
+            let Some(path_result) = self.path_map.get_mut(s).and_then(|p| p.take_ready()) else {
+                continue;
+            };
+            self.path_map.remove(s);
+            if let Some((path, poses)) = path_result {
+                if self.timed_paths.contains_key(s) && !self.start_timed_path(world, *s, &path) {
+                    log::info!("{} SIPP plan is no longer clear, replanning", s);
+                    self.replan(world, *s, &path);
+                    continue;
+                }
+                if poses.is_empty() {
+                    self.pose_paths.remove(s);
+                } else {
+                    self.pose_paths.insert(*s, poses);
+                }
+                self.current_paths.insert(*s, path);
+            } else {
+                log::info!("{} found no path", s);
+                self.current_paths.remove(s);
+                world.send(PathFailed(*s));
+            }
+        }
+    }
+
+    /// Starts a SIPP path at the tick it was planned to, or the next one if
+    /// that has passed, as long as it stays clear of the reservations made
+    /// since it was planned.
+    fn start_timed_path(&mut self, world: &World, id: EntityId, path: &[Pos2]) -> bool {
+        let begin = self.timed_paths[&id].max(self.next_tick());
+        if !self.dynamic_obstacles(world, id, begin).is_clear(path) {
+            return false;
+        }
+        self.timed_paths.insert(id, begin);
+        true
+    }
+
+    /// The first whole SIPP tick that hasn't begun yet.
+    fn next_tick(&self) -> u64 {
+        self.sipp_clock.ceil() as u64
+    }
+
+    /// Checks the next few cells of every path against the current `NavMesh`
+    /// and reacts to the ones that got blocked after they were planned.
+    pub(super) fn monitor_paths(&mut self, world: &mut World, dt: f32) {
+        let settings = self.env_settings.replan;
+
+        let mut retry = Vec::new();
+        for (id, waiting) in self.waiting_paths.iter_mut() {
+            waiting.timer -= dt;
+            if waiting.timer <= 0. {
+                retry.push(*id);
+            }
+        }
+        for id in retry {
+            let Some(mut waiting) = self.waiting_paths.remove(&id) else {
+                continue;
+            };
+            if self.blocked_index(id, &waiting.path).is_none() {
+                log::info!("{} path is clear again", id);
+                self.current_paths.insert(id, waiting.path);
+            } else if waiting.retries >= settings.max_retries {
+                log::info!("{} gave up waiting after {} retries", id, waiting.retries);
+                self.replan(world, id, &waiting.path);
+            } else {
+                waiting.retries += 1;
+                waiting.timer = settings.retry_delay;
+                self.waiting_paths.insert(id, waiting);
+            }
+        }
+
+        let blocked: Vec<(EntityId, usize)> = self
+            .current_paths
+            .iter()
+            .filter_map(|(id, path)| Some((*id, self.blocked_index(*id, path)?)))
+            .collect();
+        for (id, index) in blocked {
+            let Some(path) = self.current_paths.remove(&id) else {
+                continue;
+            };
+            let cell = path[index];
+            // formation followers get a new path to their slot instead
+            if self.formations.iter().any(|f| f.followers.contains(&id)) {
+                log::info!("{} path blocked at ({}, {}), rejoining", id, cell.x, cell.y);
+                self.formation_targets.remove(&id);
+                continue;
+            }
+            log::info!(
+                "{} path blocked at ({}, {}), {:?}",
+                id,
+                cell.x,
+                cell.y,
+                settings.strategy
+            );
+            match settings.strategy {
+                ReplanStrategy::Full => self.replan(world, id, &path),
+                ReplanStrategy::LocalRepair => {
+                    let Some((from, _)) = self.entity_transform(world, id) else {
+                        continue;
+                    };
+                    let repaired = self
+                        .grid_for(world, id)
+                        .repair_path(from, &path, index, |p| self.knows_blocked(id, p));
+                    if let Some(repaired) = repaired {
+                        log::info!("{} detours around ({}, {})", id, cell.x, cell.y);
+                        self.timed_paths.remove(&id);
+                        self.pose_paths.remove(&id);
+                        self.current_paths.insert(id, repaired);
+                    } else {
+                        log::info!("{} found no detour", id);
+                        self.replan(world, id, &path);
+                    }
+                }
+                ReplanStrategy::WaitAndRetry => {
+                    self.waiting_paths.insert(
+                        id,
+                        WaitingPath {
+                            path,
+                            timer: settings.retry_delay,
+                            retries: 0,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    /// Whether the entity knows `p` is blocked: entities with a sensor only
+    /// know what they have seen, the rest know the whole map.
+    fn knows_blocked(&self, id: EntityId, p: &Pos2) -> bool {
+        match self.known_maps.get(&id) {
+            Some(known) => known.is_blocked(p),
+            None => self.navmesh.space_lut.contains_key(&p.to_tuple()),
+        }
+    }
+
+    fn blocked_index(&self, id: EntityId, path: &[Pos2]) -> Option<usize> {
+        first_blocked(path, self.env_settings.replan.lookahead, |p| {
+            self.knows_blocked(id, p)
+        })
+    }
+
+    /// Plans again from where the entity stands, through the waypoints of
+    /// its last order it hasn't passed yet.
+    pub(super) fn replan(&mut self, world: &mut World, id: EntityId, path: &[Pos2]) {
+        let (Some((pos, heading)), Some(goal)) = (self.entity_transform(world, id), path.last())
+        else {
+            return;
+        };
+        if self.knows_blocked(id, goal) {
+            log::info!("{} goal ({}, {}) is blocked, stopping", id, goal.x, goal.y);
+            world.send(PathFailed(id));
+            return;
+        }
+        let mut waypoints: Vec<Pos2> = self
+            .waypoint_goals
+            .get(&id)
+            .map(|w| w.iter().filter(|p| path.contains(p)).copied().collect())
+            .unwrap_or_default();
+        if waypoints.last() != Some(goal) {
+            waypoints.push(*goal);
+        }
+        log::info!("{} replans from ({}, {})", id, pos.x, pos.y);
+        let path_promise = self.plan_path(world, id, pos, heading, waypoints);
+        self.path_map.insert(id, path_promise);
+    }
+
+    /// The grid the entity plans on, weighted by its tactics. Entities with
+    /// a sensor only find blocked what they have seen.
+    fn grid_for(&self, world: &World, id: EntityId) -> NavMesh {
+        let mut navmesh = self.navmesh.clone().with_costs(self.cost_layer(world, id));
+        if let Some(known) = self.known_maps.get(&id) {
+            navmesh.set_space_lut(known.obstacles());
+        }
+        navmesh
+    }
+
+    pub(super) fn plan_path(
+        &mut self,
+        world: &World,
+        id: EntityId,
+        start: Pos2,
+        heading: f32,
+        waypoints: Vec<Pos2>,
+    ) -> PathPromise {
+        self.waypoint_goals.insert(id, waypoints.clone());
+        self.patrol_targets.remove(&id);
+        self.move_requests.remove(&id);
+        if self.env_settings.planner != Planner::Sipp {
+            self.timed_paths.remove(&id);
+        }
+        if self.known_maps.contains_key(&id) {
+            // on the grid whatever the planner
+            self.timed_paths.remove(&id);
+            return PathPromise::Cells(
+                self.grid_for(world, id)
+                    .async_waypointed_a_star(start, waypoints),
+            );
+        }
+        let costs = self.cost_layer(world, id);
+        self.refresh_polygon_navmesh();
+        let promise = match self.env_settings.planner {
+            Planner::Grid if costs.is_empty() && waypoints.len() == 1 => {
+                self.navmesh.async_a_star(start, waypoints[0])
+            }
+            Planner::Grid if costs.is_empty() => {
+                self.navmesh.async_waypointed_a_star(start, waypoints)
+            }
+            Planner::Grid => self
+                .navmesh
+                .clone()
+                .with_costs(costs)
+                .async_waypointed_a_star(start, waypoints),
+            Planner::PolygonMesh => self
+                .polygon_navmesh
+                .clone()
+                .with_costs(costs)
+                .async_waypointed_path(&self.navmesh, start, waypoints),
+            Planner::VisibilityGraph => self
+                .visibility_graph
+                .clone()
+                .with_costs(costs)
+                .async_waypointed_path(&self.navmesh, start, waypoints),
+            Planner::Sampling => {
+                // only the last query is animated
+                let (sender, receiver) = mpsc::channel();
+                self.tree_receiver = Some(receiver);
+                self.tree_events.clear();
+                self.tree_edges.clear();
+                self.sampling_planner()
+                    .with_tree_sender(sender)
+                    .async_waypointed_path(&self.navmesh, start, waypoints)
+            }
+            Planner::HybridAStar => {
+                let planner = HybridAStar::new(
+                    self.collision_checker(self.env_settings.hybrid.collision),
+                    self.env_settings.hybrid,
+                )
+                .with_start_heading(heading as f64);
+                let navmesh = self.navmesh.clone();
+                return PathPromise::Poses(spawn_path_promise(planner.name(), move || {
+                    let poses = planner.waypointed_poses(start, waypoints)?;
+                    let cells = pose_cells(&navmesh, &poses)?;
+                    Some((cells, poses))
+                }));
+            }
+            Planner::Sipp => {
+                // plans around a snapshot of the reservations, from the next
+                // tick on; it is checked against the ones made in the
+                // meantime when it is picked up
+                let begin = self.next_tick();
+                let obstacles = self.dynamic_obstacles(world, id, begin);
+                self.timed_paths.insert(id, begin);
+                let navmesh = self.navmesh.clone();
+                spawn_path_promise("sipp", move || {
+                    navmesh.waypointed_sipp(start, waypoints, &obstacles)
+                })
+            }
+        };
+        PathPromise::Cells(promise)
+    }
+
+    /// Every other agent's committed path from tick `now` on, including
+    /// finished plans that haven't been picked up yet. SIPP paths are where
+    /// their reservation puts them by then, the others are taken to start
+    /// now. Agents without a path stand still, except the ones whose SIPP
+    /// plan is still running: it is checked against this one when picked up.
+    fn dynamic_obstacles(&self, world: &World, id: EntityId, now: u64) -> DynamicObstacles {
+        let mut obstacles = DynamicObstacles::default();
+        let mut moving = HashSet::new();
+        let mut pending = HashSet::new();
+        let mut planned = Vec::new();
+        for (other, promise) in self.path_map.iter() {
+            match promise.ready() {
+                Some(Some(path)) => planned.push((other, path)),
+                None if self.timed_paths.contains_key(other) => {
+                    pending.insert(*other);
+                }
+                _ => {}
+            }
+        }
+        for (other, path) in self.current_paths.iter().chain(planned) {
+            if *other == id || path.is_empty() || !moving.insert(*other) {
+                continue;
+            }
+            match self.timed_paths.get(other) {
+                Some(start) => obstacles.add_trajectory(&trajectory_from(*start, path, now)),
+                None => obstacles.add_trajectory(path),
+            }
+        }
+        // colliders are in the `space_lut` already
+        for (other, tc) in world.query_filtered::<(EntityId, &Transform2), Without<Collider>>() {
+            if other == id || moving.contains(&other) || pending.contains(&other) {
+                continue;
+            }
+            obstacles.add_stationary(tc.pos);
+        }
+        obstacles
+    }
+}
+
+/// What is left at tick `now` of a path whose first cell is at tick `start`,
+/// waiting on that cell until then if it hasn't started yet.
+fn trajectory_from(start: u64, path: &[Pos2], now: u64) -> Vec<Pos2> {
+    if now >= start {
+        let skipped = ((now - start) as usize).min(path.len() - 1);
+        path[skipped..].to_vec()
+    } else {
+        std::iter::repeat(path[0])
+            .take((start - now) as usize)
+            .chain(path.iter().copied())
+            .collect()
+    }
+}
+
+/// The cells the poses of a Hybrid A* path are in, or `None` if one of them
+/// is blocked on `grid`: the poses were checked against the shapes, which the
+/// grid covers cell by cell, and the path couldn't be walked or monitored
+/// through such a cell.
+pub(super) fn pose_cells(grid: &NavMesh, poses: &[Pose]) -> Option<Vec<Pos2>> {
+    let mut cells: Vec<Pos2> = Vec::new();
+    for pose in poses {
+        let cell = Pos2::new(pose[0].floor() as i64, pose[1].floor() as i64);
+        if grid.space_lut.contains_key(&cell.to_tuple()) {
+            return None;
+        }
+        if cells.last() != Some(&cell) {
+            cells.push(cell);
+        }
+    }
+    Some(cells)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::entity::Entity;
+
+    /// An entity on (5, 5) walking east to (12, 5), with (8, 5) blocked
+    /// after the path was planned.
+    fn blocked_walk(sim: &mut Simulation, world: &mut World) -> EntityId {
+        let id = world.spawn(Entity::default());
+        world.add(
+            id,
+            Transform2 {
+                pos: Pos2::new(5, 5),
+                heading: 0.,
+            },
+        );
+        let path = (6..=12).map(|x| Pos2::new(x, 5)).collect();
+        sim.current_paths.insert(id, path);
+        sim.navmesh.space_lut.insert((8, 5), true);
+        id
+    }
+
+    #[test]
+    fn blocked_paths_are_repaired_around_the_new_obstacle() {
+        let mut sim = Simulation::default();
+        sim.env_settings.replan.strategy = ReplanStrategy::LocalRepair;
+        let mut world = World::default();
+        let id = blocked_walk(&mut sim, &mut world);
+
+        sim.monitor_paths(&mut world, 0.1);
+        let path = &sim.current_paths[&id];
+        assert!(!path.contains(&Pos2::new(8, 5)));
+        assert!(path.ends_with(&[Pos2::new(10, 5), Pos2::new(11, 5), Pos2::new(12, 5)]));
+    }
+
+    #[test]
+    fn waiting_paths_go_on_once_they_are_clear() {
+        let mut sim = Simulation::default();
+        sim.env_settings.replan.strategy = ReplanStrategy::WaitAndRetry;
+        let mut world = World::default();
+        let id = blocked_walk(&mut sim, &mut world);
+
+        sim.monitor_paths(&mut world, 0.1);
+        assert!(!sim.current_paths.contains_key(&id));
+        sim.navmesh.space_lut.remove(&(8, 5));
+        // not before the retry delay is up
+        sim.monitor_paths(&mut world, 0.1);
+        assert!(!sim.current_paths.contains_key(&id));
+        sim.monitor_paths(&mut world, sim.env_settings.replan.retry_delay);
+        assert_eq!(sim.current_paths[&id].len(), 7);
+    }
+
+    #[test]
+    fn pose_cells_fail_on_a_blocked_cell() {
+        let mut grid = NavMesh::default();
+        let poses = [
+            [0.5, 0.5, 0.],
+            [0.9, 0.5, 0.],
+            [1.5, 0.5, 0.],
+            [2.5, 0.5, 0.],
+        ];
+        assert_eq!(
+            pose_cells(&grid, &poses),
+            Some(vec![Pos2::new(0, 0), Pos2::new(1, 0), Pos2::new(2, 0)])
+        );
+        grid.space_lut.insert((1, 0), true);
+        assert_eq!(pose_cells(&grid, &poses), None);
+    }
+}
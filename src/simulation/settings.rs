@@ -0,0 +1,109 @@
+use crate::pathfinding::formation::FormationSettings;
+use crate::pathfinding::hybrid_a_star::HybridSettings;
+use crate::pathfinding::influence::InfluenceSettings;
+use crate::pathfinding::orca::OrcaSettings;
+use crate::pathfinding::replan::ReplanSettings;
+use crate::pathfinding::sampling::SamplingSettings;
+use crate::pathfinding::sipp::SippSettings;
+use crate::pathfinding::visibility_graph::GraphSearch;
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum Stage {
+    AStar,
+    Office,
+    Generated,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum Generated {
+    N(usize),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum Obstacle {
+    Rectangular,
+    Circular,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum Planner {
+    Grid,
+    PolygonMesh,
+    VisibilityGraph,
+    Sampling,
+    HybridAStar,
+    Sipp,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct AStarStageSettings {
+    pub red_notch_size: f32,
+    pub blue_notch_size: f32,
+}
+
+impl Default for AStarStageSettings {
+    fn default() -> Self {
+        Self {
+            red_notch_size: 3.,
+            blue_notch_size: 2.,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct EnvironmentSettings {
+    pub stage: Stage,
+    pub n: Generated,
+    pub obstacle: Obstacle,
+    pub planner: Planner,
+    pub show_navmesh: bool,
+    pub graph_search: GraphSearch,
+    pub agent_radius: f32,
+    pub sampling: SamplingSettings,
+    pub hybrid: HybridSettings,
+    pub sipp: SippSettings,
+    pub local_avoidance: bool,
+    /// Multiplies the frame time fed to every movement system, 0 pauses.
+    pub sim_speed: f32,
+    pub replan: ReplanSettings,
+    pub orca: OrcaSettings,
+    pub formation: FormationSettings,
+    pub influence: InfluenceSettings,
+
+    pub a_star_stage_settings: AStarStageSettings,
+
+    pub rect_side_min: f32,
+    pub rect_side_max: f32,
+    pub circle_radius_min: f32,
+    pub circle_radius_max: f32,
+}
+
+impl Default for EnvironmentSettings {
+    fn default() -> Self {
+        Self {
+            stage: Stage::Generated,
+            n: Generated::N(20 as usize),
+            obstacle: Obstacle::Rectangular,
+            planner: Planner::Grid,
+            show_navmesh: false,
+            graph_search: GraphSearch::AStar,
+            agent_radius: 0.,
+            sampling: SamplingSettings::default(),
+            hybrid: HybridSettings::default(),
+            sipp: SippSettings::default(),
+            local_avoidance: true,
+            sim_speed: 1.,
+            replan: ReplanSettings::default(),
+            orca: OrcaSettings::default(),
+            formation: FormationSettings::default(),
+            influence: InfluenceSettings::default(),
+            a_star_stage_settings: AStarStageSettings::default(),
+            rect_side_min: 4.,
+            rect_side_max: 5.,
+            circle_radius_min: 2.,
+            circle_radius_max: 3.,
+        }
+    }
+}